@@ -0,0 +1,64 @@
+// Compares the evaluators on the same programs: the tree-walker (lval_eval),
+// the pre-resolved AST evaluator and the bytecode VM. Run with `cargo bench`.
+
+use criterion::{criterion_group, criterion_main, Criterion};
+use rustlisp::ast::Evaluator;
+use rustlisp::eval::lval_eval;
+use rustlisp::types::{Lenv, Lval};
+use rustlisp::vm::Vm;
+use rustlisp::{add_builtins, eval_source, read_source};
+use std::cell::RefCell;
+use std::rc::Rc;
+
+const DEFINITIONS: &str = "
+    (defun fib (n) (cond ((eq n 0) 0) ((eq n 1) 1) (t (+ (fib (- n 1)) (fib (- n 2))))))
+    (defun count (n acc) (cond ((eq n 0) acc) (t (count (- n 1) (+ acc 1)))))
+    (defun len (l) (cond ((null l) 0) (t (+ 1 (len (cdr l))))))
+    (defun range (n) (cond ((eq n 0) '()) (t (cons n (range (- n 1))))))
+    (defun digit (n) (cond ((eq n 0) 'zero) ((eq n 1) 'one) ((eq n 2) 'two) ((eq n 3) 'three)
+                           ((eq n 4) 'four) ((eq n 5) 'five) ((eq n 6) 'six) ((eq n 7) 'seven)
+                           ((eq n 8) 'eight) ((eq n 9) 'nine) (t (digit (- n 10)))))
+    (defun nines (n acc) (cond ((eq n 0) acc) (t (nines (- n 1) (+ acc (cond ((eq (digit n) 'nine) 1) (t 0)))))))
+";
+
+/// name and expression of each benchmark
+const PROGRAMS: &[(&str, &str)] = &[
+    ("fib 18", "(fib 18)"),
+    ("count 500", "(count 500 0)"),
+    ("len of range 200", "(len (range 200))"),
+    // many calls of a function with a long body, which the ast and vm
+    // evaluators find in their caches by identity rather than comparing it
+    ("nines below 300", "(nines 300 0)"),
+];
+
+fn environment() -> Rc<RefCell<Lenv>> {
+    let env = Rc::new(RefCell::new(Lenv::new()));
+    add_builtins(env.clone());
+    eval_source(&env, DEFINITIONS, |_| {}).unwrap();
+    env
+}
+
+fn form(src: &str) -> Lval {
+    read_source(src).unwrap().remove(0)
+}
+
+fn evaluators(c: &mut Criterion) {
+    for (name, src) in PROGRAMS {
+        let expr = form(src);
+        let mut group = c.benchmark_group(*name);
+
+        let env = environment();
+        group.bench_function("tree", |b| b.iter(|| lval_eval(env.clone(), expr.clone())));
+
+        let evaluator = Evaluator::new(environment());
+        group.bench_function("ast", |b| b.iter(|| evaluator.eval(&expr)));
+
+        let mut vm = Vm::new(environment());
+        group.bench_function("vm", |b| b.iter(|| vm.eval(&expr)));
+
+        group.finish();
+    }
+}
+
+criterion_group!(benches, evaluators);
+criterion_main!(benches);
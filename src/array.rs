@@ -0,0 +1,282 @@
+// Arrays. Lval::Array holds an array on the gc heap that every copy of the
+// value shares, like a hash table, so (setf (aref v i) x) is seen through
+// every variable holding it. Elements are kept in row-major order with the
+// size of each dimension. A vector is an array of one dimension, and may
+// have a fill pointer marking how much of it is in use, which vector-push
+// advances; the sequence functions see only that part.
+//
+// Vectors print as #(1 2 3) and other arrays as #2A((1 2) (3 4)). The reader
+// reads both back as new arrays, which evaluate to themselves.
+
+use crate::character;
+use crate::cons;
+use crate::eval::lval_eval;
+use crate::gc::{self, Trace, Tracer};
+use crate::limits::{fits, track_cells};
+use crate::sequence;
+use crate::types::{Lenv, Lval};
+use std::cell::RefCell;
+use std::mem::size_of;
+use std::rc::Rc;
+
+pub struct Array {
+    /// the size of each dimension; a vector has one
+    dims: Vec<usize>,
+    /// the elements in row-major order
+    items: Vec<Lval>,
+    /// how many elements of a vector are in use, if it has a fill pointer
+    fill: Option<usize>,
+}
+
+impl Array {
+    pub fn vector(items: Vec<Lval>) -> Self {
+        Array { dims: vec![items.len()], items, fill: None }
+    }
+
+    pub fn dims(&self) -> &[usize] {
+        &self.dims
+    }
+
+    /// the elements in use: those before a vector's fill pointer, otherwise all
+    pub fn elements(&self) -> &[Lval] {
+        &self.items[..self.fill.unwrap_or(self.items.len())]
+    }
+
+    /// the elements in use as nested lists, one level for each dimension
+    /// after the first, as they are printed
+    pub fn rows(&self) -> Vec<Lval> {
+        fn nest(dims: &[usize], items: &[Lval]) -> Vec<Lval> {
+            let width = dims[1..].iter().product::<usize>();
+            (0..dims[0]).map(|i| Lval::Sexpr(match dims.len() {
+                2 => items[i * width..(i + 1) * width].to_vec(),
+                _ => nest(&dims[1..], &items[i * width..(i + 1) * width]),
+            })).collect()
+        }
+        match self.dims.len() {
+            1 => self.elements().to_vec(),
+            _ => nest(&self.dims, &self.items),
+        }
+    }
+
+    /// where the element at `indices` is kept in `items`
+    fn offset(&self, indices: &[Lval]) -> Result<usize, Lval> {
+        if indices.len() != self.dims.len() {
+            return Err(Lval::Err(format!("Array of rank {} accessed with {} indices", self.dims.len(), indices.len())));
+        }
+        let mut offset = 0;
+        for (index, &dim) in indices.iter().zip(&self.dims) {
+            let Lval::Num(i) = *index else { return Err(Lval::Err("Index given to aref must be a number".to_string())) };
+            let Some(i) = usize::try_from(i).ok().filter(|&i| i < dim) else {
+                return Err(Lval::Err(format!("Index {} is out of range", i)));
+            };
+            offset = offset * dim + i;
+        }
+        Ok(offset)
+    }
+
+    pub fn get(&self, indices: &[Lval]) -> Result<Lval, Lval> {
+        self.offset(indices).map(|i| self.items[i].clone())
+    }
+
+    pub fn set(&mut self, indices: &[Lval], v: Lval) -> Result<(), Lval> {
+        let i = self.offset(indices)?;
+        self.items[i] = v;
+        Ok(())
+    }
+}
+
+impl Trace for Array {
+    fn trace(&self, tracer: &mut Tracer) {
+        for v in &self.items { tracer.value(v); }
+    }
+
+    fn clear(&mut self) {
+        self.items.clear();
+        self.dims = vec![0];
+        self.fill = None;
+    }
+
+    fn bytes(&self) -> usize {
+        size_of::<Self>() + self.items.len() * size_of::<Lval>()
+    }
+}
+
+/// moves an array onto the heap
+pub fn new_array(array: Array) -> Lval {
+    Lval::Array(gc::alloc("arrays", array))
+}
+
+/// the array in a builtin's argument
+pub fn array_arg(v: &Lval, func: &str) -> Result<Rc<RefCell<Array>>, Lval> {
+    match v {
+        Lval::Array(array) => Ok(array.clone()),
+        _ => Err(Lval::Err(format!("Argument to {} must be an array", func))),
+    }
+}
+
+fn vector_arg(v: &Lval, func: &str) -> Result<Rc<RefCell<Array>>, Lval> {
+    match v {
+        Lval::Array(array) if array.borrow().dims.len() == 1 => Ok(array.clone()),
+        _ => Err(Lval::Err(format!("Argument to {} must be a vector", func))),
+    }
+}
+
+/// appends the elements of nested sequences `contents` shaped as `dims` to `out`
+fn flatten(contents: &Lval, dims: &[usize], out: &mut Vec<Lval>) -> Result<(), Lval> {
+    let Some((&dim, inner)) = dims.split_first() else {
+        out.push(contents.clone().unquote());
+        return Ok(());
+    };
+    let items = sequence::items(contents)
+        .filter(|items| items.len() == dim)
+        .ok_or_else(|| Lval::Err("Initial contents do not match the array's dimensions".to_string()))?;
+    for item in &items { flatten(item, inner, out)?; }
+    Ok(())
+}
+
+/// the vector read from #(...), given its elements
+pub fn read_vector(items: Vec<Lval>) -> Lval {
+    new_array(Array::vector(items))
+}
+
+/// the array read from #nA(...), given the rank and the nested lists of elements
+pub fn read_array(rank: usize, contents: &Lval) -> Lval {
+    // the dimensions are the lengths of the first list at each level
+    let mut dims = Vec::new();
+    let mut level = contents.clone();
+    while dims.len() < rank {
+        let Some(items) = sequence::items(&level) else { break };
+        dims.push(items.len());
+        level = items.into_iter().next().unwrap_or(Lval::NIL);
+    }
+    let mut items = Vec::new();
+    if rank == 0 || dims.len() < rank || flatten(contents, &dims, &mut items).is_err() {
+        return Lval::Err("Malformed array literal".to_string());
+    }
+    new_array(Array { dims, items, fill: None })
+}
+
+/// (make-array dims [:initial-element x] [:initial-contents seq] [:fill-pointer n])
+/// returns a new array. dims is a length or a list of them; :initial-contents
+/// gives every element as nested sequences, otherwise each is :initial-element
+/// (NIL by default). A vector may have a fill pointer, T meaning its length
+pub fn builtin_make_array(_e: Rc<RefCell<Lenv>>, args: Vec<Lval>) -> Lval {
+    let Some((dims, options)) = args.split_first() else {
+        return Lval::Err("Function 'make-array' passed incorrect number of arguments.".to_string());
+    };
+    let dims = match dims {
+        Lval::Num(n) => vec![Lval::Num(*n)],
+        other => sequence::items(other).unwrap_or_default(),
+    };
+    let dims: Vec<usize> = match dims.iter().map(|d| match d {
+        Lval::Num(n) => usize::try_from(*n).ok(),
+        _ => None,
+    }).collect::<Option<Vec<usize>>>() {
+        Some(dims) if !dims.is_empty() => dims,
+        _ => return Lval::Err("Array dimensions must be a number or a list of numbers, none negative".to_string()),
+    };
+    if !options.len().is_multiple_of(2) {
+        return Lval::Err("Function 'make-array' passed incorrect arguments.".to_string());
+    }
+
+    let (mut initial, mut contents, mut fill) = (Lval::NIL, None, Lval::NIL);
+    for pair in options.chunks(2) {
+        match &pair[0] {
+            Lval::Sym(k) if k.name() == ":initial-element" => initial = pair[1].clone().unquote(),
+            Lval::Sym(k) if k.name() == ":initial-contents" => contents = Some(&pair[1]),
+            Lval::Sym(k) if k.name() == ":fill-pointer" => fill = pair[1].clone(),
+            Lval::Sym(k) if k.is_keyword() => return Lval::Err(format!("Unknown keyword argument {} to make-array", k)),
+            _ => return Lval::Err("Function 'make-array' passed incorrect arguments.".to_string()),
+        }
+    }
+
+    let size = dims.iter().try_fold(1usize, |size, &d| size.checked_mul(d));
+    let Some(size) = size else { return Lval::Err("Array is too large".to_string()) };
+    // alloc charges the elements; checking first keeps a huge array from
+    // being built only to be refused
+    if let Err(err) = fits(size.saturating_mul(size_of::<Lval>())) { return err; }
+    // without a memory limit the size is only checked here, so a huge array
+    // is an error rather than an allocation failure that aborts the process
+    let mut items = Vec::new();
+    if items.try_reserve_exact(size).is_err() { return Lval::Err("Array is too large".to_string()); }
+    match contents {
+        Some(contents) => if let Err(err) = flatten(contents, &dims, &mut items) { return err; },
+        None => items.resize(size, initial),
+    }
+    let fill = match fill {
+        Lval::NIL => None,
+        _ if dims.len() != 1 => return Lval::Err("Only a vector can have a fill pointer".to_string()),
+        Lval::T => Some(size),
+        Lval::Num(n) if usize::try_from(n).is_ok_and(|n| n <= size) => Some(n as usize),
+        _ => return Lval::Err("Fill pointer must be T or a number from 0 to the vector's length".to_string()),
+    };
+    new_array(Array { dims, items, fill })
+}
+
+/// (vector x ...) returns a new vector of its arguments
+pub fn builtin_vector(_e: Rc<RefCell<Lenv>>, args: Vec<Lval>) -> Lval {
+    read_vector(args.into_iter().map(Lval::unquote).collect())
+}
+
+/// (aref array index ...) is the element at one index for each dimension,
+/// or the character at an index of a string
+pub fn builtin_aref(_e: Rc<RefCell<Lenv>>, args: Vec<Lval>) -> Lval {
+    let Some((array, indices)) = args.split_first() else {
+        return Lval::Err("Function 'aref' passed incorrect number of arguments.".to_string());
+    };
+    if let Lval::Str(s) = array { return character::char_at(s, indices, "aref"); }
+    let array = match array_arg(array, "aref") {
+        Ok(array) => array,
+        Err(err) => return err,
+    };
+    let v = array.borrow().get(indices);
+    v.unwrap_or_else(|err| err)
+}
+
+/// (array-dimensions array) lists the size of each dimension
+pub fn builtin_array_dimensions(_e: Rc<RefCell<Lenv>>, args: Vec<Lval>) -> Lval {
+    if args.len() != 1 { return Lval::Err("Function 'array-dimensions' passed incorrect number of arguments.".to_string()); }
+    match array_arg(&args[0], "array-dimensions") {
+        Ok(array) => cons::list(array.borrow().dims.iter().map(|&d| Lval::Num(d as i64)).collect()),
+        Err(err) => err,
+    }
+}
+
+pub fn builtin_vector_push(e: Rc<RefCell<Lenv>>, args: Vec<Lval>) -> Lval { push(e, args, "vector-push") }
+pub fn builtin_vector_push_extend(e: Rc<RefCell<Lenv>>, args: Vec<Lval>) -> Lval { push(e, args, "vector-push-extend") }
+
+/// (vector-push x vector) stores x at the fill pointer and advances it,
+/// returning the index x was stored at, or NIL when the vector is full.
+/// vector-push-extend grows a full vector instead
+fn push(_e: Rc<RefCell<Lenv>>, args: Vec<Lval>, name: &str) -> Lval {
+    if args.len() != 2 { return Lval::Err(format!("Function '{}' passed incorrect number of arguments.", name)); }
+    let vector = match vector_arg(&args[1], name) {
+        Ok(vector) => vector,
+        Err(err) => return err,
+    };
+    let mut vector = vector.borrow_mut();
+    let Some(fill) = vector.fill else { return Lval::Err("Vector has no fill pointer".to_string()) };
+    let item = args[0].clone().unquote();
+    if fill < vector.items.len() {
+        vector.items[fill] = item;
+    } else if name == "vector-push-extend" {
+        if let Err(err) = track_cells(1) { return err; }
+        vector.items.push(item);
+        vector.dims[0] += 1;
+    } else {
+        return Lval::NIL;
+    }
+    vector.fill = Some(fill + 1);
+    Lval::Num(fill as i64)
+}
+
+/// evaluates the array and indices of an (aref array index ...) place
+pub fn place_args(e: &Rc<RefCell<Lenv>>, array: &Lval, indices: &[Lval]) -> Result<(Rc<RefCell<Array>>, Vec<Lval>), Lval> {
+    let eval = |form: &Lval| match lval_eval(e.clone(), form.clone()) {
+        err @ Lval::Err(_) => Err(err),
+        v => Ok(v),
+    };
+    let array = array_arg(&eval(array)?, "aref")?;
+    let indices = indices.iter().map(eval).collect::<Result<_, _>>()?;
+    Ok((array, indices))
+}
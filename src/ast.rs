@@ -0,0 +1,451 @@
+// Pre-resolved evaluation. `analyze` turns a form into a Node tree once:
+// special forms get their own nodes, constants are set aside, and parameters
+// become (depth, index) addresses into vector scopes instead of names hashed
+// on every reference. A function's scope holds its arguments at depth 0; the
+// arguments a partial application already bound sit in the scope above it at
+// depth 1. Above those come the callers' scopes, since scoping is dynamic, so
+// free variables are still found by name at run time, walking the scopes and
+// then the global environment as Lenv::get would.
+//
+// Evaluation recurses over the nodes like lval_eval, with the same step,
+// depth and interrupt checks, and gives the same results as the tree-walker.
+
+use crate::capability::denied;
+use crate::cons;
+use crate::eval::{clear_interrupt, duplicate_formal, is_interrupted, lval_call, lval_eval, ENV_BUILTINS};
+use crate::limits::{begin_evaluation, count_step, end_evaluation, enter_call, exit_call};
+use crate::symbol::{self, Symbol};
+use crate::trace::{is_traced, traced_call};
+use crate::types::{Lenv, Lval};
+use crate::{builtin_profile, debug, read_forms};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+pub enum Node {
+    Const(Lval),
+    /// a quoted form, whose lists are made into new cells each time
+    Quote(Lval),
+    /// a parameter `depth` scopes up from the innermost, in slot `index`
+    Local { depth: usize, index: usize },
+    /// any other variable, found by name at run time
+    Dynamic(Symbol),
+    /// (setq name v)
+    Setq { target: Box<Node>, value: Box<Node> },
+    /// (defun ...), defining `name` as the lambda in the global environment
+    Defun { name: Symbol, lambda: Lval },
+    /// cond clauses in order; a malformed clause ends them with its error
+    Cond { clauses: Vec<(Node, Node)>, otherwise: Lval },
+    Call { name: Option<Symbol>, head: Box<Node>, args: Vec<Node>, needs_env: bool },
+    /// the special forms other than quote, setq, defun and cond, left to the tree-walker
+    Walk(Lval),
+}
+
+/// a vector environment: slots named for the variables they hold, followed
+/// by any variables a setq added during the call
+struct Scope {
+    vars: RefCell<Vec<(Symbol, Lval)>>,
+    parent: Option<Rc<Scope>>,
+}
+
+/// a lambda's body analyzed for its parameters
+struct Function {
+    /// the formals and body it was analyzed from, Qexprs shared with the lambda
+    formals: Rc<Lval>,
+    body_form: Rc<Lval>,
+    /// the remaining formals, in the scope at depth 0
+    params: Vec<Symbol>,
+    /// names bound by an earlier partial application, sorted, in the scope at depth 1
+    captured: Vec<Symbol>,
+    body: Node,
+}
+
+/// analyzes a top level form, evaluated outside any function
+pub fn analyze(form: &Lval) -> Node {
+    Analyzer { scopes: Vec::new() }.node(form)
+}
+
+/// analyzes a lambda's body with its remaining formals at depth 0 and the
+/// arguments already bound by partial application at depth 1
+fn analyze_function(formals: &Rc<Lval>, body: &Rc<Lval>, captured: Vec<Symbol>) -> Function {
+    let params: Vec<Symbol> = match &**formals {
+        Lval::Qexpr(cells) => cells.iter().map(|c| match c {
+            Lval::Sym(s) => *s,
+            // binding fails before the body runs, so the slot is never read
+            _ => Symbol::intern(""),
+        }).collect(),
+        _ => Vec::new(),
+    };
+    let form = Lval::Sexpr(match &**body {
+        Lval::Qexpr(cells) => cells.clone(),
+        _ => Vec::new(),
+    });
+    let node = Analyzer { scopes: vec![params.clone(), captured.clone()] }.node(&form);
+    Function { formals: formals.clone(), body_form: body.clone(), params, captured, body: node }
+}
+
+struct Analyzer {
+    /// parameter names, innermost scope first
+    scopes: Vec<Vec<Symbol>>,
+}
+
+impl Analyzer {
+    fn resolve(&self, s: Symbol) -> Node {
+        for (depth, names) in self.scopes.iter().enumerate() {
+            if let Some(index) = names.iter().position(|&n| n == s) {
+                return Node::Local { depth, index };
+            }
+        }
+        Node::Dynamic(s)
+    }
+
+    fn node(&self, v: &Lval) -> Node {
+        match v {
+            Lval::Sym(s) if s.is_t() => Node::Const(Lval::T),
+            Lval::Sym(s) if s.is_nil() => Node::Const(Lval::NIL),
+            Lval::Sym(s) if s.is_keyword() => Node::Const(v.clone()),
+            Lval::Sym(s) => self.resolve(*s),
+            Lval::Sexpr(cells) => self.list(v, cells),
+            Lval::Qexpr(_) => Node::Quote(v.clone()),
+            other => Node::Const(other.clone()),
+        }
+    }
+
+    fn list(&self, v: &Lval, cells: &[Lval]) -> Node {
+        let error = |message: &str| Node::Const(Lval::Err(message.to_string()));
+        let head = match cells.first() {
+            Some(Lval::Sym(s)) => Some(*s),
+            Some(_) => None,
+            None => return Node::Const(Lval::Sexpr(Vec::new())),
+        };
+        match head {
+            Some(symbol::QUOTE) => match cells {
+                [_, quoted] => Node::Quote(Lval::Qexpr(vec![quoted.clone()])),
+                _ => error("Function 'quote' passed incorrect number of arguments."),
+            },
+            Some(symbol::SETQ) => match cells {
+                [_, Lval::Sym(s), value] => Node::Setq { target: Box::new(self.resolve(*s)), value: Box::new(self.node(value)) },
+                [_, _, _] => error("First argument to setq must be a symbol"),
+                _ => error("Function 'setq' passed incorrect number of arguments."),
+            },
+            Some(symbol::DEFUN) => self.defun(cells),
+            Some(symbol::COND) => self.cond(&cells[1..]),
+            // every other special form is left to the tree-walker
+            Some(s) if s.special_form().is_some() => Node::Walk(v.clone()),
+            _ if cells.len() == 1 && head.is_none() => self.node(&cells[0]),
+            _ => Node::Call {
+                name: head,
+                head: Box::new(self.node(&cells[0])),
+                args: cells[1..].iter().map(|c| self.node(c)).collect(),
+                needs_env: head.is_some_and(|h| ENV_BUILTINS.contains(&h.name())),
+            },
+        }
+    }
+
+    fn defun(&self, cells: &[Lval]) -> Node {
+        let error = |message: &str| Node::Const(Lval::Err(message.to_string()));
+        if cells.len() != 4 { return error("Function 'defun' passed incorrect number of arguments."); }
+        let Lval::Sym(name) = cells[1] else {
+            return error("First argument to defun must be a symbol");
+        };
+        let formals = match &cells[2] {
+            Lval::Sexpr(v) | Lval::Qexpr(v) => Lval::Qexpr(v.clone()),
+            _ => return error("Second argument to defun must be a list"),
+        };
+        let body = match &cells[3] {
+            Lval::Sexpr(v) | Lval::Qexpr(v) => Lval::Qexpr(v.clone()),
+            _ => return error("Third argument to defun must be a list"),
+        };
+        if let Some(err) = duplicate_formal(&formals) { return Node::Const(err); }
+        Node::Defun { name, lambda: Lval::Lambda(Lenv::new(), Rc::new(formals), Rc::new(body)) }
+    }
+
+    fn cond(&self, clauses: &[Lval]) -> Node {
+        let mut analyzed = Vec::new();
+        for clause in clauses {
+            let mut cells = match clause {
+                Lval::Sexpr(c) | Lval::Qexpr(c) => c.as_slice(),
+                _ => return Node::Cond { clauses: analyzed, otherwise: Lval::Err("Cond branches must be Qexpr or Sexpr".to_string()) },
+            };
+            if let [Lval::Sexpr(inner)] = cells { cells = inner; }
+            if cells.len() < 2 {
+                return Node::Cond { clauses: analyzed, otherwise: Lval::Err("Cond branch too short".to_string()) };
+            }
+            analyzed.push((self.node(&cells[0]), self.node(&cells[1])));
+        }
+        Node::Cond { clauses: analyzed, otherwise: Lval::Sexpr(Vec::new()) }
+    }
+}
+
+/// evaluates analyzed forms against a global environment, keeping analyzed
+/// function bodies between calls
+pub struct Evaluator {
+    globals: Rc<RefCell<Lenv>>,
+    /// analyzed bodies by the name their function was last called under
+    cache: RefCell<HashMap<Symbol, Rc<Function>>>,
+}
+
+/// the function of a call, resolved before its arguments are evaluated
+enum Callee {
+    Lambda { function: Rc<Function>, captured: Lenv },
+    Value(Lval),
+}
+
+impl Evaluator {
+    pub fn new(globals: Rc<RefCell<Lenv>>) -> Self {
+        Evaluator { globals, cache: RefCell::new(HashMap::new()) }
+    }
+
+    /// analyzes and evaluates one top level form
+    pub fn eval(&self, form: &Lval) -> Lval {
+        self.node(&analyze(form), &None)
+    }
+
+    /// parses source text and evaluates each top level form in order, handing
+    /// every result to `on_result`, as eval_source does with the tree-walker
+    pub fn eval_source(&self, src: &str, mut on_result: impl FnMut(Lval)) -> Result<(), String> {
+        let forms = read_forms(src)?;
+        // stepping and breakpoints pause in lval_eval, which compiled code does not go through
+        if debug::is_hooked() {
+            debug::reset();
+            return Err("the debugger needs the tree backend".to_string());
+        }
+        clear_interrupt();
+        for form in forms {
+            begin_evaluation();
+            on_result(end_evaluation(self.eval(&form)));
+            if is_interrupted() { break; }
+        }
+        clear_interrupt();
+        debug::reset();
+        Ok(())
+    }
+
+    fn node(&self, node: &Node, scope: &Option<Rc<Scope>>) -> Lval {
+        if let Err(err) = count_step() { return err; }
+        if is_interrupted() { return Lval::Err("Evaluation interrupted".to_string()); }
+        match node {
+            Node::Const(v) => v.clone(),
+            Node::Quote(v) => cons::quoted(v),
+            Node::Local { depth, index } => {
+                let scope = ancestor(scope, *depth);
+                let vars = scope.vars.borrow();
+                vars[*index].1.clone()
+            },
+            Node::Dynamic(name) => self.lookup(scope, *name, Lval::clone),
+            Node::Setq { target, value } => {
+                let v = self.node(value, scope);
+                if let Lval::Err(_) = v { return v; }
+                match **target {
+                    Node::Local { depth, index } => ancestor(scope, depth).vars.borrow_mut()[index].1 = v,
+                    Node::Dynamic(name) => self.bind(scope, name, v),
+                    _ => {},
+                }
+                Lval::Sexpr(Vec::new())
+            },
+            Node::Defun { name, lambda } => {
+                self.globals.borrow_mut().def(*name, lambda.clone());
+                Lval::Sexpr(Vec::new())
+            },
+            Node::Cond { clauses, otherwise } => {
+                for (test, body) in clauses {
+                    match self.node(test, scope) {
+                        err @ Lval::Err(_) => return err,
+                        Lval::NIL | Lval::Num(0) => {},
+                        _ => return self.node(body, scope),
+                    }
+                }
+                otherwise.clone()
+            },
+            Node::Call { name, head, args, needs_env } => self.call(scope, *name, head, args, *needs_env),
+            Node::Walk(form) => {
+                let env = self.scope_env(scope);
+                let result = lval_eval(env.clone(), form.clone());
+                write_back(scope, &env);
+                result
+            },
+        }
+    }
+
+    fn call(&self, scope: &Option<Rc<Scope>>, name: Option<Symbol>, head: &Node, args: &[Node], needs_env: bool) -> Lval {
+        // a named function is resolved in place rather than copied out of its scope
+        let callee = match head {
+            Node::Dynamic(name) => {
+                if let Err(err) = count_step() { return err; }
+                self.lookup(scope, *name, |v| self.callee_of(Some(*name), v))
+            },
+            _ => self.callee_of(None, &self.node(head, scope)),
+        };
+        let args: Vec<Lval> = args.iter().map(|a| self.node(a, scope)).collect();
+
+        // the first error among the function and its arguments is the result
+        if let Callee::Value(err @ Lval::Err(_)) = callee { return err; }
+        if let Some(err) = args.iter().find(|a| matches!(a, Lval::Err(_))) { return err.clone(); }
+
+        let (function, captured) = match callee {
+            // (x) is x's value unless x is a function taking no arguments, as in lval_eval
+            Callee::Value(v) if args.is_empty() && !v.takes_no_arguments() => return v,
+            Callee::Lambda { function, captured } if args.is_empty() && !function.params.is_empty() => {
+                return Lval::Lambda(captured, function.formals.clone(), function.body_form.clone());
+            },
+            Callee::Value(Lval::Fun(func)) if needs_env => {
+                let env = self.scope_env(scope);
+                let result = func(env.clone(), args);
+                write_back(scope, &env);
+                return result;
+            },
+            Callee::Value(Lval::Fun(func)) => return func(self.globals.clone(), args),
+            // structure functions, or a value that is not a function
+            Callee::Value(f) => return lval_call(self.globals.clone(), f, args),
+            Callee::Lambda { function, captured } => (function, captured),
+        };
+
+        if let Some(name) = name.filter(|&n| is_traced(n)) {
+            let f = Lval::Lambda(captured, function.formals.clone(), function.body_form.clone());
+            let env = self.scope_env(scope);
+            let result = traced_call(env.clone(), name.name(), f, args);
+            write_back(scope, &env);
+            return result;
+        }
+
+        // bind the arguments as apply does, failing at the first one without a formal
+        let formals = match *function.formals {
+            Lval::Qexpr(ref cells) => cells.as_slice(),
+            _ => &[],
+        };
+        for i in 0..args.len() {
+            match formals.get(i) {
+                Some(Lval::Sym(_)) => {},
+                Some(_) => return Lval::Err("Formal should be a symbol".to_string()),
+                None => return Lval::Err(format!("Function passed too many arguments. Got {}, Expected {}.", args.len(), formals.len())),
+            }
+        }
+        if args.len() < formals.len() {
+            let rest = Lval::Qexpr(formals[args.len()..].to_vec());
+            let mut env = captured;
+            for (formal, arg) in formals.iter().zip(args) {
+                if let Lval::Sym(s) = formal { env.put(*s, arg); }
+            }
+            return Lval::Lambda(env, Rc::new(rest), function.body_form.clone());
+        }
+
+        // arguments bound earlier form the scope between the caller's and the call's
+        let parent = if function.captured.is_empty() {
+            scope.clone()
+        } else {
+            let mut data = captured.data;
+            let vars = function.captured.iter().map(|&n| (n, data.remove(&n).unwrap())).collect();
+            Some(Rc::new(Scope { vars: RefCell::new(vars), parent: scope.clone() }))
+        };
+        let params = function.params.iter().copied().zip(args).collect();
+        let call_scope = Some(Rc::new(Scope { vars: RefCell::new(params), parent }));
+
+        if let Err(err) = enter_call() { return err; }
+        let result = self.node(&function.body, &call_scope);
+        exit_call();
+        result
+    }
+
+    /// resolves a function value, analyzing a lambda's body unless the cache
+    /// holds it under the name, analyzed from the same formals and body
+    /// allocations the lambda shares with its copies and with the same
+    /// captured names
+    fn callee_of(&self, name: Option<Symbol>, v: &Lval) -> Callee {
+        let Lval::Lambda(env, formals, body) = v else {
+            return Callee::Value(v.clone());
+        };
+        let mut captured: Vec<Symbol> = env.data.keys().copied().collect();
+        captured.sort();
+        let mut cache = self.cache.borrow_mut();
+        let cached = name.and_then(|n| cache.get(&n)).filter(|f| {
+            Rc::ptr_eq(&f.formals, formals) && Rc::ptr_eq(&f.body_form, body) && f.captured == captured
+        });
+        let function = match cached {
+            Some(function) => function.clone(),
+            None => {
+                let function = Rc::new(analyze_function(formals, body, captured));
+                if let Some(n) = name { cache.insert(n, function.clone()); }
+                function
+            },
+        };
+        Callee::Lambda { function, captured: env.clone() }
+    }
+
+    /// finds a variable through the scopes and then the global environment,
+    /// handing it to `f` without copying it
+    fn lookup<R>(&self, scope: &Option<Rc<Scope>>, name: Symbol, f: impl FnOnce(&Lval) -> R) -> R {
+        let mut next = scope.as_ref();
+        while let Some(s) = next {
+            if let Some((_, v)) = s.vars.borrow().iter().find(|(n, _)| *n == name) {
+                return f(v);
+            }
+            next = s.parent.as_ref();
+        }
+        self.globals.borrow().with(name, |v| match v {
+            Some(v) => f(v),
+            None => match builtin_profile(name.name()) {
+                // a builtin that exists but was left out by the capability profile
+                Some(required) => f(&denied(name.name(), required)),
+                None => f(&Lval::Err(format!("Unbound symbol '{}'", name))),
+            },
+        })
+    }
+
+    /// binds a variable in the innermost scope, as Lenv::put does
+    fn bind(&self, scope: &Option<Rc<Scope>>, name: Symbol, v: Lval) {
+        match scope {
+            Some(s) => {
+                let mut vars = s.vars.borrow_mut();
+                match vars.iter_mut().find(|(n, _)| *n == name) {
+                    Some(var) => var.1 = v,
+                    None => vars.push((name, v)),
+                }
+            },
+            None => self.globals.borrow_mut().put(name, v),
+        }
+    }
+
+    /// the scopes as the Lenv chain the tree-walker would have built
+    fn scope_env(&self, scope: &Option<Rc<Scope>>) -> Rc<RefCell<Lenv>> {
+        let mut scopes = Vec::new();
+        let mut next = scope.as_ref();
+        while let Some(s) = next {
+            scopes.push(s);
+            next = s.parent.as_ref();
+        }
+        let mut env = self.globals.clone();
+        for s in scopes.into_iter().rev() {
+            let data = s.vars.borrow().iter().map(|(n, v)| (*n, v.clone())).collect();
+            env = Rc::new(RefCell::new(Lenv { par: Some(env), data }));
+        }
+        env
+    }
+}
+
+/// the scope `depth` levels above `scope`
+fn ancestor(scope: &Option<Rc<Scope>>, depth: usize) -> &Scope {
+    let mut s = scope.as_deref().unwrap();
+    for _ in 0..depth {
+        s = s.parent.as_deref().unwrap();
+    }
+    s
+}
+
+/// copies variables set through a scope_env chain back into the scopes
+fn write_back(scope: &Option<Rc<Scope>>, env: &Rc<RefCell<Lenv>>) {
+    let mut next = scope.as_ref();
+    let mut env = env.clone();
+    while let Some(s) = next {
+        let data = std::mem::take(&mut env.borrow_mut().data);
+        let mut vars = s.vars.borrow_mut();
+        for (name, v) in data {
+            match vars.iter_mut().find(|(n, _)| *n == name) {
+                Some(var) => var.1 = v,
+                None => vars.push((name, v)),
+            }
+        }
+        let par = env.borrow().par.clone().unwrap();
+        env = par;
+        next = s.parent.as_ref();
+    }
+}
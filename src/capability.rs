@@ -0,0 +1,45 @@
+// Capability profiles decide which builtins add_builtins_with installs, so
+// untrusted code can be run without file or shell access. Profiles are
+// ordered: every builtin allowed in `pure` is also allowed in `io-read`, and
+// `full` allows everything.
+
+use crate::types::Lval;
+use std::fmt;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Profile {
+    /// computation only; print still writes to the REPL's output
+    Pure,
+    /// adds reading files and loading source
+    IoRead,
+    /// adds writing files and running shell commands
+    #[default]
+    Full,
+}
+
+impl Profile {
+    pub const ALL: [Profile; 3] = [Profile::Pure, Profile::IoRead, Profile::Full];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Profile::Pure => "pure",
+            Profile::IoRead => "io-read",
+            Profile::Full => "full",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Profile> {
+        Profile::ALL.into_iter().find(|p| p.name() == name)
+    }
+}
+
+impl fmt::Display for Profile {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+/// error for a builtin that exists but was withheld by the active profile
+pub fn denied(name: &str, required: Profile) -> Lval {
+    Lval::Err(format!("Capability denied: '{}' requires the '{}' profile", name, required))
+}
@@ -0,0 +1,155 @@
+// Characters. #\a reads as the character a, and #\Space, #\Newline and #\Tab
+// as the characters they name, written in any case. Characters print the same
+// way, except that princ shows the character itself. A string is indexed to
+// its characters with char or aref, counting characters rather than bytes.
+
+use crate::types::{Lenv, Lval};
+use std::cell::RefCell;
+use std::rc::Rc;
+
+/// the characters written by name rather than as themselves
+const NAMES: &[(&str, char)] = &[("Space", ' '), ("Newline", '\n'), ("Tab", '\t')];
+
+/// the name a character is written with, if it has one
+pub fn name(c: char) -> Option<&'static str> {
+    NAMES.iter().find(|(_, n)| *n == c).map(|(name, _)| *name)
+}
+
+/// the character read from #\x, given the text after #\
+pub fn read_char(text: &str) -> Lval {
+    let mut chars = text.chars();
+    if let (Some(c), None) = (chars.next(), chars.next()) { return Lval::Char(c); }
+    match NAMES.iter().find(|(name, _)| name.eq_ignore_ascii_case(text)) {
+        Some((_, c)) => Lval::Char(*c),
+        None => Lval::Err(format!("Unknown character name {}", text)),
+    }
+}
+
+/// the character in a builtin's argument
+fn char_arg(v: &Lval, func: &str) -> Result<char, Lval> {
+    match v {
+        Lval::Char(c) => Ok(*c),
+        _ => Err(Lval::Err(format!("Argument to {} must be a character", func))),
+    }
+}
+
+/// the one character argument of a builtin
+fn one_char(args: &[Lval], func: &str) -> Result<char, Lval> {
+    match args {
+        [v] => char_arg(v, func),
+        _ => Err(Lval::Err(format!("Function '{}' passed incorrect number of arguments.", func))),
+    }
+}
+
+fn boolean(b: bool) -> Lval {
+    if b { Lval::T } else { Lval::NIL }
+}
+
+/// the character at a string index, as char and aref read it
+pub fn char_at(s: &str, indices: &[Lval], func: &str) -> Lval {
+    let [index] = indices else { return Lval::Err(format!("String accessed with {} indices", indices.len())) };
+    let Lval::Num(i) = *index else { return Lval::Err(format!("Index given to {} must be a number", func)) };
+    match usize::try_from(i).ok().and_then(|i| s.chars().nth(i)) {
+        Some(c) => Lval::Char(c),
+        None => Lval::Err(format!("Index {} is out of range", i)),
+    }
+}
+
+/// (char string index) is the character at index
+pub fn builtin_char(_e: Rc<RefCell<Lenv>>, args: Vec<Lval>) -> Lval {
+    match args.as_slice() {
+        [Lval::Str(s), index] => char_at(s, std::slice::from_ref(index), "char"),
+        [_, _] => Lval::Err("Argument to char must be a string".to_string()),
+        _ => Lval::Err("Function 'char' passed incorrect number of arguments.".to_string()),
+    }
+}
+
+/// (char-code c) is the character's Unicode code point
+pub fn builtin_char_code(_e: Rc<RefCell<Lenv>>, args: Vec<Lval>) -> Lval {
+    match one_char(&args, "char-code") {
+        Ok(c) => Lval::Num(c as i64),
+        Err(err) => err,
+    }
+}
+
+/// (code-char n) is the character with code point n, or NIL if there is none
+pub fn builtin_code_char(_e: Rc<RefCell<Lenv>>, args: Vec<Lval>) -> Lval {
+    match args.as_slice() {
+        [Lval::Num(n)] => u32::try_from(*n).ok().and_then(char::from_u32).map_or(Lval::NIL, Lval::Char),
+        [_] => Lval::Err("Argument to code-char must be a number".to_string()),
+        _ => Lval::Err("Function 'code-char' passed incorrect number of arguments.".to_string()),
+    }
+}
+
+pub fn builtin_char_upcase(e: Rc<RefCell<Lenv>>, args: Vec<Lval>) -> Lval { change_case(e, args, "char-upcase") }
+pub fn builtin_char_downcase(e: Rc<RefCell<Lenv>>, args: Vec<Lval>) -> Lval { change_case(e, args, "char-downcase") }
+
+/// (char-upcase c) and (char-downcase c) are the character in the other case,
+/// or c itself when that is not a single character
+fn change_case(_e: Rc<RefCell<Lenv>>, args: Vec<Lval>, name: &str) -> Lval {
+    let c = match one_char(&args, name) {
+        Ok(c) => c,
+        Err(err) => return err,
+    };
+    let mut changed: Box<dyn Iterator<Item = char>> = match name {
+        "char-upcase" => Box::new(c.to_uppercase()),
+        _ => Box::new(c.to_lowercase()),
+    };
+    match (changed.next(), changed.next()) {
+        (Some(single), None) => Lval::Char(single),
+        _ => Lval::Char(c),
+    }
+}
+
+/// (alpha-char-p c) is T for a letter
+pub fn builtin_alpha_char_p(_e: Rc<RefCell<Lenv>>, args: Vec<Lval>) -> Lval {
+    match one_char(&args, "alpha-char-p") {
+        Ok(c) => boolean(c.is_alphabetic()),
+        Err(err) => err,
+    }
+}
+
+/// (digit-char-p c [radix]) is the digit's value in radix, 10 by default, or
+/// NIL if c is not a digit
+pub fn builtin_digit_char_p(_e: Rc<RefCell<Lenv>>, args: Vec<Lval>) -> Lval {
+    if args.is_empty() || args.len() > 2 {
+        return Lval::Err("Function 'digit-char-p' passed incorrect number of arguments.".to_string());
+    }
+    let radix = match args.get(1) {
+        None => 10,
+        Some(Lval::Num(r)) if (2..=36).contains(r) => *r as u32,
+        Some(_) => return Lval::Err("Radix given to digit-char-p must be a number from 2 to 36".to_string()),
+    };
+    match char_arg(&args[0], "digit-char-p") {
+        Ok(c) => c.to_digit(radix).map_or(Lval::NIL, |d| Lval::Num(d as i64)),
+        Err(err) => err,
+    }
+}
+
+pub fn builtin_char_eq(e: Rc<RefCell<Lenv>>, args: Vec<Lval>) -> Lval { compare(e, args, "char=") }
+pub fn builtin_char_ne(e: Rc<RefCell<Lenv>>, args: Vec<Lval>) -> Lval { compare(e, args, "char/=") }
+pub fn builtin_char_lt(e: Rc<RefCell<Lenv>>, args: Vec<Lval>) -> Lval { compare(e, args, "char<") }
+pub fn builtin_char_gt(e: Rc<RefCell<Lenv>>, args: Vec<Lval>) -> Lval { compare(e, args, "char>") }
+pub fn builtin_char_le(e: Rc<RefCell<Lenv>>, args: Vec<Lval>) -> Lval { compare(e, args, "char<=") }
+pub fn builtin_char_ge(e: Rc<RefCell<Lenv>>, args: Vec<Lval>) -> Lval { compare(e, args, "char>=") }
+
+/// (char< a b ...) and the other comparisons of code points are T when each
+/// character compares so with the next; char/= is T when no two are equal
+fn compare(_e: Rc<RefCell<Lenv>>, args: Vec<Lval>, name: &str) -> Lval {
+    if args.is_empty() { return Lval::Err(format!("Function '{}' passed incorrect number of arguments.", name)); }
+    let chars = match args.iter().map(|v| char_arg(v, name)).collect::<Result<Vec<char>, _>>() {
+        Ok(chars) => chars,
+        Err(err) => return err,
+    };
+    if name == "char/=" {
+        return boolean(chars.iter().enumerate().all(|(i, c)| !chars[i + 1..].contains(c)));
+    }
+    let holds = |a: char, b: char| match name {
+        "char=" => a == b,
+        "char<" => a < b,
+        "char>" => a > b,
+        "char<=" => a <= b,
+        _ => a >= b,
+    };
+    boolean(chars.windows(2).all(|pair| holds(pair[0], pair[1])))
+}
@@ -0,0 +1,258 @@
+// REPL meta-commands. Lines starting with ':' are handled here instead of
+// being parsed as Lisp, so the session can be inspected without writing code.
+
+use rustlisp::limits::{eval_steps, reset_eval_steps};
+use rustlisp::debug::step_next;
+use rustlisp::trace::untrace_all;
+use rustlisp::symbol::Symbol;
+use rustlisp::types::{Lenv, Lval};
+use rustlisp::capability::{denied, Profile};
+use rustlisp::{add_builtins_with, builtin_profile, builtin_signature};
+use crate::Evaluator;
+use std::cell::RefCell;
+use std::fs;
+use std::path::Path;
+use std::rc::Rc;
+use std::time::Instant;
+
+/// what the REPL should do after a command
+pub enum Command {
+    Continue,
+    Quit,
+}
+
+const HELP: &str = "\
+:help            show this message
+:env             list bindings in the global environment
+:describe sym    show what sym is bound to; functions show formals and body
+:time expr       evaluate expr and report wall time and evaluation steps
+:step expr       evaluate expr in the debugger, pausing before each form
+:load file       evaluate every form in file, as (load file) does
+:reset           discard all definitions and reinstall the builtins
+:quit            exit the REPL";
+
+/// runs one colon command typed at the prompt. Commands that evaluate code use
+/// the session's evaluator and are held to its profile, like ordinary input
+pub fn run_command(env: &Rc<RefCell<Lenv>>, evaluator: &mut Evaluator, profile: Profile, line: &str) -> Command {
+    let line = line.trim();
+    let (name, arg) = match line.split_once(char::is_whitespace) {
+        Some((name, arg)) => (name, arg.trim()),
+        None => (line, ""),
+    };
+
+    match name {
+        ":help" | ":h" | ":?" => println!("{}", HELP),
+        ":env" => print_env(env),
+        ":describe" | ":d" => describe(env, arg),
+        ":time" | ":t" => time(evaluator, arg),
+        ":step" | ":s" => step(evaluator, arg),
+        ":load" | ":l" => load(evaluator, profile, arg),
+        ":reset" => {
+            // clear in place so everything holding the global environment sees the reset
+            *env.borrow_mut() = Lenv::new();
+            add_builtins_with(env.clone(), profile);
+            untrace_all();
+            println!("Environment reset.");
+        },
+        ":quit" | ":q" => return Command::Quit,
+        _ => println!("Error: Unknown command '{}'. Type :help for a list of commands.", name),
+    }
+    Command::Continue
+}
+
+fn print_env(env: &Rc<RefCell<Lenv>>) {
+    let env = env.borrow();
+    let mut names: Vec<&Symbol> = env.data.keys().collect();
+    names.sort_by_key(|k| k.name());
+
+    let (builtins, defined): (Vec<&Symbol>, Vec<&Symbol>) = names
+        .into_iter()
+        .partition(|name| matches!(env.data[*name], Lval::Fun(_)));
+
+    for name in defined {
+        println!("{} = {}", name, env.data[name]);
+    }
+    println!(";; {} builtins: {}", builtins.len(), builtins.iter().map(|s| s.name()).collect::<Vec<_>>().join(" "));
+}
+
+fn describe(env: &Rc<RefCell<Lenv>>, name: &str) {
+    if name.is_empty() {
+        println!("Error: :describe needs a symbol");
+        return;
+    }
+    match Symbol::lookup(name).and_then(|sym| env.borrow().get(sym)) {
+        Some(Lval::Lambda(_, formals, body)) => {
+            let list = |v: &Lval| match v {
+                Lval::Qexpr(cells) => Lval::Sexpr(cells.clone()),
+                other => other.clone(),
+            };
+            println!("{} is a function", name);
+            println!("  formals: {}", list(&formals));
+            println!("  body:    {}", list(&body));
+        },
+        Some(Lval::Fun(_)) => match builtin_signature(name) {
+            Some(args) => println!("{} is a builtin function: ({} {})", name, name, args),
+            None => println!("{} is a builtin function", name),
+        },
+        Some(value) => println!("{} is bound to {}", name, value),
+        None => println!("{} is unbound", name),
+    }
+}
+
+fn time(evaluator: &mut Evaluator, src: &str) {
+    reset_eval_steps();
+    let start = Instant::now();
+    let res = evaluator.eval_source(src, |result| println!("{}", result));
+    let elapsed = start.elapsed();
+    if let Err(e) = res {
+        println!("Error: {}", e);
+        return;
+    }
+    println!(";; {:.6}s, {} evaluation steps", elapsed.as_secs_f64(), eval_steps());
+}
+
+fn step(evaluator: &mut Evaluator, src: &str) {
+    if src.is_empty() {
+        println!("Error: :step needs an expression");
+        return;
+    }
+    if !matches!(evaluator, Evaluator::Tree(_)) {
+        println!("Error: :step needs --backend tree");
+        return;
+    }
+    step_next();
+    if let Err(e) = evaluator.eval_source(src, |result| println!("{}", result)) {
+        println!("Error: {}", e);
+    }
+}
+
+/// evaluates every form in a file, printing any errors they return, unless
+/// `profile` withholds load. returns how many forms were evaluated
+pub fn load_file(evaluator: &mut Evaluator, profile: Profile, path: &Path) -> Result<usize, String> {
+    if let Some(required) = builtin_profile("load").filter(|required| *required > profile) {
+        if let Lval::Err(msg) = denied("load", required) { return Err(msg); }
+    }
+    let src = fs::read_to_string(path)
+        .map_err(|e| format!("cannot read '{}': {}", path.display(), e))?;
+
+    let mut forms = 0;
+    evaluator.eval_source(&src, |result| {
+        forms += 1;
+        if let Lval::Err(_) = result {
+            println!("{}", result);
+        }
+    })?;
+    Ok(forms)
+}
+
+fn load(evaluator: &mut Evaluator, profile: Profile, path: &str) {
+    match load_file(evaluator, profile, Path::new(path)) {
+        Ok(forms) => println!("Loaded {} forms from {}", forms, path),
+        Err(e) => println!("Error: {}", e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Backend;
+    use rustlisp::eval_source;
+    use rustlisp::trace::is_traced;
+
+    fn session(profile: Profile) -> Rc<RefCell<Lenv>> {
+        let env = Rc::new(RefCell::new(Lenv::new()));
+        add_builtins_with(env.clone(), profile);
+        env
+    }
+
+    fn bound(env: &Rc<RefCell<Lenv>>, name: &str) -> bool {
+        env.borrow().get(Symbol::intern(name)).is_some()
+    }
+
+    #[test]
+    fn reset_clears_definitions_but_keeps_the_profile() {
+        let env = session(Profile::IoRead);
+        eval_source(&env, "(defun square (x) (* x x)) (setq car 1) (trace square)", |_| {}).unwrap();
+        assert!(bound(&env, "square"));
+        assert!(is_traced(Symbol::intern("square")));
+
+        assert!(matches!(run_command(&env, &mut Evaluator::new(Backend::Tree, &env), Profile::IoRead, ":reset"), Command::Continue));
+        assert!(!bound(&env, "square"));
+        assert!(!is_traced(Symbol::intern("square")));
+        assert!(matches!(env.borrow().get(Symbol::intern("car")), Some(Lval::Fun(_))));
+        assert!(bound(&env, "read-file"));
+        assert!(!bound(&env, "write-file"));
+        let mut last = Lval::NIL;
+        eval_source(&env, "(car '(1 2))", |v| last = v).unwrap();
+        assert_eq!(last.to_string(), "1");
+    }
+
+    #[test]
+    fn commands_continue_until_quit() {
+        let env = session(Profile::Pure);
+        let mut evaluator = Evaluator::new(Backend::Tree, &env);
+        let mut run = |line| run_command(&env, &mut evaluator, Profile::Pure, line);
+        assert!(matches!(run(":quit"), Command::Quit));
+        assert!(matches!(run(":q"), Command::Quit));
+        assert!(matches!(run(":nonsense"), Command::Continue));
+        assert!(matches!(run(":describe car"), Command::Continue));
+        assert!(matches!(run(":describe never-described"), Command::Continue));
+        assert_eq!(Symbol::lookup("never-described"), None);
+    }
+
+    #[test]
+    fn load_file_counts_forms() {
+        let path = std::env::temp_dir().join(format!("rustlisp-commands-{}.lisp", std::process::id()));
+        fs::write(&path, "(setq a 1)\n(setq b (+ a 1))\n(undefined)\n").unwrap();
+        let env = session(Profile::IoRead);
+        let mut evaluator = Evaluator::new(Backend::Tree, &env);
+        assert_eq!(load_file(&mut evaluator, Profile::IoRead, &path), Ok(3));
+        assert!(bound(&env, "b"));
+        fs::remove_file(&path).unwrap();
+        assert!(load_file(&mut evaluator, Profile::IoRead, &path).unwrap_err().starts_with("cannot read"));
+    }
+
+    #[test]
+    fn load_needs_the_profile_that_allows_load() {
+        let path = std::env::temp_dir().join(format!("rustlisp-commands-pure-{}.lisp", std::process::id()));
+        fs::write(&path, "(setq loaded 1)\n").unwrap();
+        let env = session(Profile::Pure);
+        let mut evaluator = Evaluator::new(Backend::Tree, &env);
+        let denied = load_file(&mut evaluator, Profile::Pure, &path);
+        assert_eq!(denied, Err("Capability denied: 'load' requires the 'io-read' profile".to_string()));
+        run_command(&env, &mut evaluator, Profile::Pure, &format!(":load {}", path.display()));
+        assert!(!bound(&env, "loaded"));
+        fs::remove_file(&path).unwrap();
+    }
+
+    /// evaluation steps `line` takes when run as a command with `backend`
+    fn command_steps(backend: Backend, line: &str) -> u64 {
+        let env = session(Profile::Full);
+        run_command(&env, &mut Evaluator::new(backend, &env), Profile::Full, line);
+        eval_steps()
+    }
+
+    /// evaluation steps `src` takes when typed at the prompt with `backend`
+    fn input_steps(backend: Backend, src: &str) -> u64 {
+        let env = session(Profile::Full);
+        reset_eval_steps();
+        Evaluator::new(backend, &env).eval_source(src, |_| {}).unwrap();
+        eval_steps()
+    }
+
+    #[test]
+    fn commands_evaluate_with_the_selected_backend() {
+        let src = "(defun sum (n) (cond ((eq n 0) 0) (t (+ n (sum (- n 1)))))) (sum 20)";
+        let path = std::env::temp_dir().join(format!("rustlisp-commands-backend-{}.lisp", std::process::id()));
+        fs::write(&path, src).unwrap();
+        for backend in [Backend::Tree, Backend::Ast, Backend::Vm] {
+            let steps = input_steps(backend, src);
+            assert_eq!(command_steps(backend, &format!(":time {}", src)), steps, "{:?}", backend);
+            reset_eval_steps();
+            assert_eq!(command_steps(backend, &format!(":load {}", path.display())), steps, "{:?}", backend);
+        }
+        // the backends count steps differently, so the counts above tell them apart
+        assert_ne!(input_steps(Backend::Tree, src), input_steps(Backend::Vm, src));
+        fs::remove_file(&path).unwrap();
+    }
+}
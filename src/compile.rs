@@ -0,0 +1,333 @@
+// Compiles Lval forms to bytecode for the VM in vm.rs. Each function body
+// becomes a Proto: a flat list of Ops plus the constants, names and call
+// sites they refer to. Parameters are resolved to frame slots at compile time;
+// every other symbol stays a name looked up at run time, because scoping is
+// dynamic and a callee sees its callers' variables.
+//
+// The compiler follows lval_eval form by form so both backends agree: every
+// element of a call is evaluated before the first error among them is
+// returned, a one element list is its element's value unless it is a symbol
+// naming a function that takes no arguments, and special forms are
+// recognized by the head's name.
+
+use crate::eval::{duplicate_formal, ENV_BUILTINS};
+use crate::symbol::{self, Symbol};
+use crate::types::Lval;
+use std::rc::Rc;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Op {
+    /// pushes constants[i]
+    Const(usize),
+    /// pushes the value of the quoted form constants[i], with new cells for its lists
+    Quote(usize),
+    /// pushes the parameter in slot i of the current frame
+    Local(usize),
+    /// pushes the value of names[i], searching the calling frames innermost
+    /// first and then the global environment
+    Var(usize),
+    /// (setq param v): stores the value on top of the stack in slot i
+    SetLocal(usize),
+    /// (setq name v) for any other name: binds it in the innermost scope
+    SetVar(usize),
+    /// (defun ...): defines names[i] as constants[j] in the global environment
+    Define(usize, usize),
+    /// looks up the function of call site i by its name
+    CalleeVar(usize),
+    /// takes the function of a call whose head is not a symbol off the stack
+    Callee,
+    /// calls the pending function with the arguments of call site i
+    Call(usize),
+    /// a call in tail position, which reuses the current frame for a lambda
+    TailCall(usize),
+    /// pops a cond test: an error is the cond's value and jumps to `end`,
+    /// false jumps to the next clause at `next`
+    Test { next: usize, end: usize },
+    Jump(usize),
+    /// evaluates constants[i] with the tree-walking evaluator, for the
+    /// special forms other than quote, setq, defun and cond
+    Walk(usize),
+    Return,
+}
+
+#[derive(Clone, Debug)]
+pub struct CallSite {
+    /// number of arguments, not counting the function
+    pub argc: usize,
+    /// the symbol the function is called by, for trace output and the compile cache
+    pub name: Option<Symbol>,
+    /// the function is one of ENV_BUILTINS when called by this name
+    pub needs_env: bool,
+}
+
+/// compiled code for a function body or a top level form
+pub struct Proto {
+    /// parameter names; parameter i lives in slot i of the frame
+    pub params: Vec<Symbol>,
+    pub code: Vec<Op>,
+    pub constants: Vec<Lval>,
+    pub names: Vec<Symbol>,
+    pub sites: Vec<CallSite>,
+    /// the formals the function was compiled from, a Qexpr
+    pub formals: Rc<Lval>,
+    /// the body the function was compiled from, a Qexpr, shared with the
+    /// lambda so the cache can tell it by identity
+    pub body: Rc<Lval>,
+}
+
+/// compiles a lambda's formals and body, both Qexprs as builtin_lambda builds them
+pub fn compile_function(formals: &Rc<Lval>, body: &Rc<Lval>) -> Proto {
+    // a formal that is not a symbol makes the call fail before the body runs,
+    // so its slot is never read
+    let params = match &**formals {
+        Lval::Qexpr(cells) => cells.iter().map(|c| match c {
+            Lval::Sym(s) => *s,
+            _ => Symbol::intern(""),
+        }).collect(),
+        _ => Vec::new(),
+    };
+    let form = Lval::Sexpr(match &**body {
+        Lval::Qexpr(cells) => cells.clone(),
+        _ => Vec::new(),
+    });
+    let mut c = Compiler::new(params);
+    c.form(&form, true);
+    c.emit(Op::Return);
+    c.finish(formals.clone(), body.clone())
+}
+
+/// compiles a top level form, evaluated outside any function
+pub fn compile_toplevel(form: &Lval) -> Proto {
+    let mut c = Compiler::new(Vec::new());
+    c.form(form, false);
+    c.emit(Op::Return);
+    c.finish(Rc::new(Lval::Qexpr(Vec::new())), Rc::new(Lval::Qexpr(Vec::new())))
+}
+
+struct Compiler {
+    params: Vec<Symbol>,
+    code: Vec<Op>,
+    constants: Vec<Lval>,
+    names: Vec<Symbol>,
+    sites: Vec<CallSite>,
+}
+
+impl Compiler {
+    fn new(params: Vec<Symbol>) -> Self {
+        Compiler { params, code: Vec::new(), constants: Vec::new(), names: Vec::new(), sites: Vec::new() }
+    }
+
+    fn finish(self, formals: Rc<Lval>, body: Rc<Lval>) -> Proto {
+        Proto {
+            params: self.params,
+            code: self.code,
+            constants: self.constants,
+            names: self.names,
+            sites: self.sites,
+            formals,
+            body,
+        }
+    }
+
+    fn emit(&mut self, op: Op) -> usize {
+        self.code.push(op);
+        self.code.len() - 1
+    }
+
+    fn constant(&mut self, v: Lval) -> usize {
+        self.constants.push(v);
+        self.constants.len() - 1
+    }
+
+    fn name(&mut self, s: Symbol) -> usize {
+        match self.names.iter().position(|&n| n == s) {
+            Some(i) => i,
+            None => {
+                self.names.push(s);
+                self.names.len() - 1
+            },
+        }
+    }
+
+    fn slot(&self, s: Symbol) -> Option<usize> {
+        self.params.iter().position(|&p| p == s)
+    }
+
+    fn error(&mut self, message: &str) {
+        let i = self.constant(Lval::Err(message.to_string()));
+        self.emit(Op::Const(i));
+    }
+
+    /// compiles code leaving the form's value on the stack. In tail position
+    /// a call may instead replace the frame and return the value itself
+    fn form(&mut self, v: &Lval, tail: bool) {
+        match v {
+            Lval::Sym(s) if s.is_t() => {
+                let i = self.constant(Lval::T);
+                self.emit(Op::Const(i));
+            },
+            Lval::Sym(s) if s.is_nil() => {
+                let i = self.constant(Lval::NIL);
+                self.emit(Op::Const(i));
+            },
+            Lval::Sym(s) if s.is_keyword() => {
+                let i = self.constant(v.clone());
+                self.emit(Op::Const(i));
+            },
+            Lval::Sym(s) => match self.slot(*s) {
+                Some(slot) => { self.emit(Op::Local(slot)); },
+                None => {
+                    let i = self.name(*s);
+                    self.emit(Op::Var(i));
+                },
+            },
+            Lval::Sexpr(cells) => self.list(v, cells, tail),
+            Lval::Qexpr(_) => {
+                let i = self.constant(v.clone());
+                self.emit(Op::Quote(i));
+            },
+            other => {
+                let i = self.constant(other.clone());
+                self.emit(Op::Const(i));
+            },
+        }
+    }
+
+    fn list(&mut self, v: &Lval, cells: &[Lval], tail: bool) {
+        let head = match cells.first() {
+            Some(Lval::Sym(s)) => Some(*s),
+            Some(_) => None,
+            None => {
+                let i = self.constant(Lval::Sexpr(Vec::new()));
+                self.emit(Op::Const(i));
+                return;
+            },
+        };
+        match head {
+            Some(symbol::QUOTE) => {
+                if cells.len() != 2 { return self.error("Function 'quote' passed incorrect number of arguments."); }
+                let i = self.constant(Lval::Qexpr(vec![cells[1].clone()]));
+                self.emit(Op::Quote(i));
+            },
+            Some(symbol::SETQ) => self.setq(cells),
+            Some(symbol::DEFUN) => self.defun(cells),
+            Some(symbol::COND) => self.cond(&cells[1..], tail),
+            // every other special form is left to the tree-walker
+            Some(s) if s.special_form().is_some() => {
+                let i = self.constant(v.clone());
+                self.emit(Op::Walk(i));
+            },
+            _ if cells.len() == 1 && head.is_none() => self.form(&cells[0], tail),
+            _ => self.call(cells, tail),
+        }
+    }
+
+    fn call(&mut self, cells: &[Lval], tail: bool) {
+        let name = match cells[0] {
+            Lval::Sym(s) => Some(s),
+            _ => None,
+        };
+        let site = self.sites.len();
+        self.sites.push(CallSite {
+            argc: cells.len() - 1,
+            name,
+            needs_env: name.is_some_and(|n| ENV_BUILTINS.contains(&n.name())),
+        });
+        match name {
+            // T, nil and keywords evaluate to themselves rather than naming a function
+            Some(s) if !s.is_t() && !s.is_nil() && !s.is_keyword() => { self.emit(Op::CalleeVar(site)); },
+            _ => {
+                self.form(&cells[0], false);
+                self.emit(Op::Callee);
+            },
+        }
+        for arg in &cells[1..] {
+            self.form(arg, false);
+        }
+        self.emit(if tail { Op::TailCall(site) } else { Op::Call(site) });
+    }
+
+    fn setq(&mut self, cells: &[Lval]) {
+        if cells.len() != 3 { return self.error("Function 'setq' passed incorrect number of arguments."); }
+        let Lval::Sym(s) = cells[1] else {
+            return self.error("First argument to setq must be a symbol");
+        };
+        self.form(&cells[2], false);
+        match self.slot(s) {
+            Some(slot) => self.emit(Op::SetLocal(slot)),
+            None => {
+                let i = self.name(s);
+                self.emit(Op::SetVar(i))
+            },
+        };
+    }
+
+    fn defun(&mut self, cells: &[Lval]) {
+        if cells.len() != 4 { return self.error("Function 'defun' passed incorrect number of arguments."); }
+        let Lval::Sym(s) = cells[1] else {
+            return self.error("First argument to defun must be a symbol");
+        };
+        let formals = match &cells[2] {
+            Lval::Sexpr(v) | Lval::Qexpr(v) => Lval::Qexpr(v.clone()),
+            _ => return self.error("Second argument to defun must be a list"),
+        };
+        let body = match &cells[3] {
+            Lval::Sexpr(v) | Lval::Qexpr(v) => Lval::Qexpr(v.clone()),
+            _ => return self.error("Third argument to defun must be a list"),
+        };
+        if let Some(Lval::Err(message)) = duplicate_formal(&formals) { return self.error(&message); }
+        let lambda = Lval::Lambda(Default::default(), Rc::new(formals), Rc::new(body));
+        let (name, value) = (self.name(s), self.constant(lambda));
+        self.emit(Op::Define(name, value));
+    }
+
+    /// each clause tests and then either evaluates its body and jumps to the
+    /// end or falls through to the next clause; no true clause gives ()
+    fn cond(&mut self, clauses: &[Lval], tail: bool) {
+        let mut exits = Vec::new();
+        let mut tests = Vec::new();
+        for clause in clauses {
+            let next = self.code.len();
+            for t in tests.drain(..) {
+                if let Op::Test { next: ref mut n, .. } = self.code[t] { *n = next; }
+            }
+            let mut cells = match clause {
+                Lval::Sexpr(c) | Lval::Qexpr(c) => c.as_slice(),
+                _ => {
+                    self.error("Cond branches must be Qexpr or Sexpr");
+                    return self.patch_exits(exits);
+                },
+            };
+            if let [Lval::Sexpr(inner)] = cells { cells = inner; }
+            if cells.len() < 2 {
+                self.error("Cond branch too short");
+                return self.patch_exits(exits);
+            }
+            self.form(&cells[0], false);
+            let test = self.emit(Op::Test { next: 0, end: 0 });
+            tests.push(test);
+            exits.push(test);
+            self.form(&cells[1], tail);
+            exits.push(self.emit(Op::Jump(0)));
+        }
+        let next = self.code.len();
+        for t in tests {
+            if let Op::Test { next: ref mut n, .. } = self.code[t] { *n = next; }
+        }
+        let i = self.constant(Lval::Sexpr(Vec::new()));
+        self.emit(Op::Const(i));
+        self.patch_exits(exits);
+    }
+
+    /// points the Test and Jump ops of a cond at the code after it
+    fn patch_exits(&mut self, exits: Vec<usize>) {
+        let after = self.code.len();
+        for i in exits {
+            match self.code[i] {
+                Op::Test { ref mut end, .. } => *end = after,
+                Op::Jump(ref mut target) => *target = after,
+                _ => {},
+            }
+        }
+    }
+}
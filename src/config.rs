@@ -0,0 +1,217 @@
+// Startup settings for the REPL, read from command line flags and
+// environment variables. Flags win over environment variables, which win
+// over the defaults.
+
+use rustlisp::capability::Profile;
+use rustlisp::limits::Limits;
+use std::ffi::OsString;
+use std::path::PathBuf;
+
+/// environment variable naming the history file
+pub const HISTORY_ENV: &str = "RUSTLISP_HISTORY";
+/// environment variable naming the init file
+pub const INIT_ENV: &str = "RUSTLISP_INIT";
+
+pub const USAGE: &str = "\
+Usage: rustlisp [options]
+       rustlisp dap     serve the Debug Adapter Protocol on stdin/stdout
+       rustlisp lsp     serve the Language Server Protocol on stdin/stdout
+       rustlisp fmt     format source files (see rustlisp fmt --help)
+       rustlisp lint    check source files for likely mistakes (see rustlisp lint --help)
+
+Options:
+  --history FILE   save REPL history to FILE (env: RUSTLISP_HISTORY)
+  --no-history     do not load or save history
+  --init FILE      evaluate FILE at startup instead of ~/.rustlisprc (env: RUSTLISP_INIT)
+  --no-init        skip the init file
+  --profile NAME   builtins to install: pure, io-read or full (default full)
+  --max-steps N    stop a form after N evaluation steps
+  --max-depth N    stop a form nested more than N calls deep (default 10000, 0 = unlimited)
+  --max-memory N   stop a form after building about N bytes of data (K, M and G suffixes allowed)
+  --backend NAME   evaluator for REPL input: tree, ast or vm (default tree; only tree debugs)
+  -h, --help       print this message";
+
+/// the evaluators REPL input can run on
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Backend {
+    /// lval_eval over the read forms
+    Tree,
+    /// forms analyzed into resolved trees first (ast.rs)
+    Ast,
+    /// forms compiled to bytecode (compile.rs, vm.rs)
+    Vm,
+}
+
+impl Backend {
+    fn from_name(name: &str) -> Option<Backend> {
+        match name {
+            "tree" => Some(Backend::Tree),
+            "ast" => Some(Backend::Ast),
+            "vm" => Some(Backend::Vm),
+            _ => None,
+        }
+    }
+}
+
+pub struct Config {
+    /// where REPL history is kept, None disables history
+    pub history: Option<PathBuf>,
+    /// file evaluated before the first prompt, None skips it
+    pub init_file: Option<PathBuf>,
+    /// which builtins are installed
+    pub profile: Profile,
+    /// resource limits applied to every top level form
+    pub limits: Limits,
+    /// which evaluator runs REPL input
+    pub backend: Backend,
+    /// print usage and exit
+    pub help: bool,
+}
+
+impl Config {
+    /// builds the configuration from the process arguments (without the
+    /// program name) and environment variables, looked up with `var`
+    pub fn from_args(args: impl IntoIterator<Item = String>, var: impl Fn(&str) -> Option<OsString>) -> Result<Config, String> {
+        let mut config = Config {
+            history: var(HISTORY_ENV).map(PathBuf::from).or_else(|| default_history(&var)),
+            init_file: var(INIT_ENV).map(PathBuf::from).or_else(default_init_file),
+            profile: Profile::default(),
+            limits: Limits::default(),
+            backend: Backend::Tree,
+            help: false,
+        };
+
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--history" => config.history = Some(PathBuf::from(flag_value(&mut args, &arg)?)),
+                "--no-history" => config.history = None,
+                "--init" => config.init_file = Some(PathBuf::from(flag_value(&mut args, &arg)?)),
+                "--no-init" => config.init_file = None,
+                "--profile" => {
+                    let name = flag_value(&mut args, &arg)?;
+                    config.profile = Profile::from_name(&name)
+                        .ok_or_else(|| format!("unknown profile '{}'", name))?;
+                },
+                "--max-steps" => config.limits.max_steps = Some(parse_count(&flag_value(&mut args, &arg)?)? as u64),
+                "--max-depth" => config.limits.max_depth = match parse_count(&flag_value(&mut args, &arg)?)? {
+                    0 => None,
+                    n => Some(n),
+                },
+                "--max-memory" => config.limits.max_memory = Some(parse_count(&flag_value(&mut args, &arg)?)?),
+                "--backend" => {
+                    let name = flag_value(&mut args, &arg)?;
+                    config.backend = Backend::from_name(&name)
+                        .ok_or_else(|| format!("unknown backend '{}'", name))?;
+                },
+                "-h" | "--help" => config.help = true,
+                _ => return Err(format!("unknown option '{}'", arg)),
+            }
+        }
+        Ok(config)
+    }
+}
+
+fn flag_value(args: &mut impl Iterator<Item = String>, flag: &str) -> Result<String, String> {
+    args.next().ok_or_else(|| format!("option '{}' needs a value", flag))
+}
+
+/// parses a non-negative count with an optional K, M or G suffix
+fn parse_count(value: &str) -> Result<usize, String> {
+    let (digits, scale) = match value.chars().last().map(|c| c.to_ascii_uppercase()) {
+        Some('K') => (&value[..value.len() - 1], 1 << 10),
+        Some('M') => (&value[..value.len() - 1], 1 << 20),
+        Some('G') => (&value[..value.len() - 1], 1 << 30),
+        _ => (value, 1),
+    };
+    digits.parse::<usize>()
+        .ok()
+        .and_then(|n| n.checked_mul(scale))
+        .ok_or_else(|| format!("invalid number '{}'", value))
+}
+
+/// $XDG_DATA_HOME/rustlisp/history.txt, or the platform equivalent. Only
+/// Linux reads XDG_DATA_HOME, and like dirs only when it is absolute
+fn default_history(var: impl Fn(&str) -> Option<OsString>) -> Option<PathBuf> {
+    let xdg = var("XDG_DATA_HOME").map(PathBuf::from).filter(|d| cfg!(target_os = "linux") && d.is_absolute());
+    xdg.or_else(dirs::data_dir).map(|d| d.join("rustlisp").join("history.txt"))
+}
+
+/// ~/.rustlisprc
+fn default_init_file() -> Option<PathBuf> {
+    dirs::home_dir().map(|d| d.join(".rustlisprc"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    /// parses flags with the environment variables in `vars` and no others
+    fn parse_with(args: &[&str], vars: &[(&str, &str)]) -> Result<Config, String> {
+        let vars: HashMap<String, OsString> = vars.iter().map(|(k, v)| (k.to_string(), OsString::from(v))).collect();
+        Config::from_args(args.iter().map(|a| a.to_string()), |name| vars.get(name).cloned())
+    }
+
+    fn parse(args: &[&str]) -> Result<Config, String> {
+        parse_with(args, &[])
+    }
+
+    #[test]
+    fn flags_win_over_the_environment_which_wins_over_xdg() {
+        let config = parse_with(&[], &[("XDG_DATA_HOME", "/xdg/data")]).unwrap();
+        // other platforms keep data where dirs puts it whatever XDG_DATA_HOME says
+        #[cfg(target_os = "linux")]
+        assert_eq!(config.history, Some(PathBuf::from("/xdg/data/rustlisp/history.txt")));
+        #[cfg(not(target_os = "linux"))]
+        assert_eq!(config.history, dirs::data_dir().map(|d| d.join("rustlisp").join("history.txt")));
+        assert_eq!(config.init_file, dirs::home_dir().map(|d| d.join(".rustlisprc")));
+
+        let env = [("XDG_DATA_HOME", "/xdg/data"), (HISTORY_ENV, "/env/history"), (INIT_ENV, "/env/init.lisp")];
+        let config = parse_with(&[], &env).unwrap();
+        assert_eq!(config.history, Some(PathBuf::from("/env/history")));
+        assert_eq!(config.init_file, Some(PathBuf::from("/env/init.lisp")));
+
+        let config = parse_with(&["--history", "/flag/history", "--init", "/flag/init.lisp"], &env).unwrap();
+        assert_eq!(config.history, Some(PathBuf::from("/flag/history")));
+        assert_eq!(config.init_file, Some(PathBuf::from("/flag/init.lisp")));
+
+        let config = parse_with(&["--no-history", "--no-init"], &env).unwrap();
+        assert_eq!(config.history, None);
+        assert_eq!(config.init_file, None);
+    }
+
+    #[test]
+    fn the_last_flag_wins() {
+        let config = parse(&["--no-history", "--history", "h1", "--backend", "ast", "--backend", "vm"]).unwrap();
+        assert_eq!(config.history, Some(PathBuf::from("h1")));
+        assert_eq!(config.backend, Backend::Vm);
+        assert_eq!(parse(&["--history", "h1", "--no-history"]).unwrap().history, None);
+    }
+
+    #[test]
+    fn profiles_limits_and_backends() {
+        let config = parse(&[]).unwrap();
+        assert_eq!(config.profile, Profile::Full);
+        assert_eq!(config.backend, Backend::Tree);
+        assert!(!config.help);
+
+        let config = parse(&["--profile", "io-read", "--max-steps", "100", "--max-depth", "0", "--max-memory", "2K", "-h"]).unwrap();
+        assert_eq!(config.profile, Profile::IoRead);
+        assert_eq!(config.limits.max_steps, Some(100));
+        assert_eq!(config.limits.max_depth, None);
+        assert_eq!(config.limits.max_memory, Some(2048));
+        assert!(config.help);
+        assert_eq!(parse(&["--max-memory", "3m"]).unwrap().limits.max_memory, Some(3 << 20));
+    }
+
+    #[test]
+    fn bad_arguments_are_errors() {
+        assert_eq!(parse(&["--profile", "root"]).err(), Some("unknown profile 'root'".to_string()));
+        assert_eq!(parse(&["--backend", "jit"]).err(), Some("unknown backend 'jit'".to_string()));
+        assert_eq!(parse(&["--max-steps", "-1"]).err(), Some("invalid number '-1'".to_string()));
+        assert_eq!(parse(&["--max-memory", "K"]).err(), Some("invalid number 'K'".to_string()));
+        assert_eq!(parse(&["--history"]).err(), Some("option '--history' needs a value".to_string()));
+        assert_eq!(parse(&["--verbose"]).err(), Some("unknown option '--verbose'".to_string()));
+    }
+}
@@ -0,0 +1,184 @@
+// Cons cells. A list made while a program runs is a chain of cells on the gc
+// heap: (cons x list) makes one, list results such as mapcar's are built from
+// them, and evaluating a quoted list makes a fresh chain of its elements. Every
+// copy of the value shares the cells, so rplaca, rplacd and setf of car, cdr
+// or nth are seen through every variable holding the list, and rplacd can
+// make a circular list, which the collector reclaims once nothing holds it.
+//
+// Code stays in the vectors the reader builds (Sexpr, and Qexpr for a quoted
+// form), which the evaluators walk without following cells. The builtins that
+// take code as a value, such as \ and eval, turn chains back into vectors
+// with `code`. A quoted list keeps its Qexpr around the chain, so '(1 2 3)
+// still prints as it was written.
+
+use crate::gc::{self, Trace, Tracer};
+use crate::types::Lval;
+use std::cell::RefCell;
+use std::rc::Rc;
+
+pub struct Cons {
+    pub car: Lval,
+    /// the next cell, NIL at the end of a list, or any other value for a dotted pair
+    pub cdr: Lval,
+}
+
+impl Trace for Cons {
+    fn trace(&self, tracer: &mut Tracer) {
+        tracer.value(&self.car);
+        tracer.value(&self.cdr);
+    }
+
+    fn clear(&mut self) {
+        self.car = Lval::NIL;
+        self.cdr = Lval::NIL;
+    }
+}
+
+// a long list is freed one cell at a time rather than by dropping each cdr
+// inside the last, which would overflow the stack
+impl Drop for Cons {
+    fn drop(&mut self) {
+        let mut next = std::mem::replace(&mut self.cdr, Lval::NIL);
+        while let Lval::Cons(cell) = next {
+            let Ok(mut cell) = Rc::try_unwrap(cell) else { break };
+            next = std::mem::replace(&mut cell.get_mut().cdr, Lval::NIL);
+        }
+    }
+}
+
+/// the end of a list as a cdr holds it: an empty list is NIL, and a list the
+/// reader built becomes cells, so a chain only ends in NIL or a dotted value
+pub fn tail(v: Lval) -> Lval {
+    match v {
+        Lval::Sexpr(items) if items.is_empty() => Lval::NIL,
+        Lval::Sexpr(items) => list(items),
+        other => other,
+    }
+}
+
+/// a new cell
+pub fn cons(car: Lval, cdr: Lval) -> Lval {
+    Lval::Cons(gc::alloc("conses", Cons { car, cdr: tail(cdr) }))
+}
+
+/// a chain of new cells holding `items`, or () when there are none
+pub fn list(items: Vec<Lval>) -> Lval {
+    if items.is_empty() { return Lval::Sexpr(Vec::new()); }
+    items.into_iter().rev().fold(Lval::NIL, |cdr, car| Lval::Cons(gc::alloc("conses", Cons { car, cdr })))
+}
+
+/// the first cell of a list made of cells, quoted or not
+pub fn cell(v: &Lval) -> Option<Rc<RefCell<Cons>>> {
+    match v {
+        Lval::Cons(cell) => Some(cell.clone()),
+        Lval::Qexpr(cells) => match cells.as_slice() {
+            [Lval::Cons(cell)] => Some(cell.clone()),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// a cdr as cdr returns it: the end of a list is ()
+pub fn rest(cdr: &Lval) -> Lval {
+    match cdr {
+        Lval::NIL => Lval::Sexpr(Vec::new()),
+        other => other.clone(),
+    }
+}
+
+/// the cell `n` cells along the chain from `cell`, if the list is that long
+pub fn nth_cell(cell: &Rc<RefCell<Cons>>, n: usize) -> Option<Rc<RefCell<Cons>>> {
+    let mut current = cell.clone();
+    for _ in 0..n {
+        let next = match &current.borrow().cdr {
+            Lval::Cons(next) => next.clone(),
+            _ => return None,
+        };
+        current = next;
+    }
+    Some(current)
+}
+
+/// the elements of the chain starting at `cell` and the value ending it, NIL
+/// for a proper list. None when the chain is circular
+pub fn walk(cell: &Rc<RefCell<Cons>>) -> Option<(Vec<Lval>, Lval)> {
+    let mut items = Vec::new();
+    let mut current = cell.clone();
+    // moves one cell for every two `current` moves, so `current` only meets
+    // it again by going round a cycle
+    let mut slow = cell.clone();
+    loop {
+        let cdr = {
+            let c = current.borrow();
+            items.push(c.car.clone());
+            c.cdr.clone()
+        };
+        let Lval::Cons(next) = cdr else { return Some((items, cdr)) };
+        if items.len() % 2 == 0 {
+            let behind = match &slow.borrow().cdr {
+                Lval::Cons(behind) => behind.clone(),
+                _ => unreachable!("the slow cell trails the current one"),
+            };
+            slow = behind;
+        }
+        if Rc::ptr_eq(&next, &slow) { return None; }
+        current = next;
+    }
+}
+
+/// whether two chains hold equal elements and end in equal values
+pub fn equal(a: &Rc<RefCell<Cons>>, b: &Rc<RefCell<Cons>>) -> bool {
+    match (walk(a), walk(b)) {
+        (Some((a, a_end)), Some((b, b_end))) => a == b && a_end == b_end,
+        _ => false,
+    }
+}
+
+/// the value of a quoted form: a fresh chain for a list, so changing it
+/// leaves the code it was written in alone. Anything else is its own value
+pub fn quoted(v: &Lval) -> Lval {
+    match v {
+        Lval::Qexpr(cells) if cells.iter().any(|c| matches!(c, Lval::Sexpr(items) if !items.is_empty())) => {
+            Lval::Qexpr(cells.iter().map(datum).collect())
+        },
+        _ => v.clone(),
+    }
+}
+
+fn datum(v: &Lval) -> Lval {
+    match v {
+        Lval::Sexpr(items) => list(items.iter().map(datum).collect()),
+        Lval::Qexpr(cells) => Lval::Qexpr(cells.iter().map(datum).collect()),
+        other => other.clone(),
+    }
+}
+
+/// a value as code: chains become the vectors the reader builds, at any
+/// depth. A list that is circular or dotted cannot be code
+pub fn code(v: &Lval) -> Result<Lval, Lval> {
+    code_in(v, &mut Vec::new())
+}
+
+/// `within` holds the cells of the lists `v` is inside, so a list holding
+/// itself as an element is found as well
+fn code_in(v: &Lval, within: &mut Vec<*const RefCell<Cons>>) -> Result<Lval, Lval> {
+    let circular = || Lval::Err("A circular list cannot be evaluated".to_string());
+    match v {
+        Lval::Cons(cell) => {
+            if within.contains(&Rc::as_ptr(cell)) { return Err(circular()); }
+            let items = match walk(cell) {
+                Some((items, Lval::NIL)) => items,
+                Some(_) => return Err(Lval::Err(format!("{} is not a proper list", v))),
+                None => return Err(circular()),
+            };
+            within.push(Rc::as_ptr(cell));
+            let items = items.iter().map(|item| code_in(item, within)).collect::<Result<_, _>>();
+            within.pop();
+            items.map(Lval::Sexpr)
+        },
+        Lval::Sexpr(cells) => cells.iter().map(|c| code_in(c, within)).collect::<Result<_, _>>().map(Lval::Sexpr),
+        Lval::Qexpr(cells) => cells.iter().map(|c| code_in(c, within)).collect::<Result<_, _>>().map(Lval::Qexpr),
+        other => Ok(other.clone()),
+    }
+}
@@ -0,0 +1,395 @@
+// Debug Adapter Protocol server, so editors such as VS Code can debug Lisp
+// programs. Messages are JSON with a Content-Length header, read from `input`
+// and written to `output` (stdio for `rustlisp dap`).
+//
+// The program runs on the calling thread. While it runs the server only
+// reads messages when the evaluation pauses, which is when the debugger hooks
+// in lval_eval hand control to DapDebugger. Line breakpoints are mapped to
+// the first s-expression read on that line; because forms carry no
+// positions, every form equal to it pauses, and a paused form is reported at
+// the first line where an equal form appears.
+
+use crate::debug::{self, Debugger, Pause, PauseReason, Resume};
+use crate::eval::lval_eval;
+use crate::framing::{read_message, write_message};
+use crate::io::set_output;
+use crate::symbol::Symbol;
+use crate::types::{Lval, Lenv};
+use crate::{add_builtins, eval_source, read_source, read_spans, Spanned};
+use serde_json::{json, Value};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, BufRead, Write};
+use std::rc::Rc;
+
+/// the only thread the program runs on
+const THREAD_ID: i64 = 1;
+/// variables reference of the global scope; frame n's locals use n + 2
+const GLOBALS_REF: i64 = 1;
+
+/// framed messages in both directions
+struct Connection {
+    input: Box<dyn BufRead>,
+    output: Box<dyn Write>,
+    seq: i64,
+}
+
+impl Connection {
+    /// the next message, or None when the client has gone away
+    fn read(&mut self) -> io::Result<Option<Value>> {
+        read_message(&mut self.input)
+    }
+
+    fn send(&mut self, mut message: Value) -> io::Result<()> {
+        self.seq += 1;
+        message["seq"] = json!(self.seq);
+        write_message(&mut self.output, &message)
+    }
+
+    fn respond(&mut self, request: &Value, body: Value) -> io::Result<()> {
+        self.send(json!({
+            "type": "response",
+            "request_seq": request["seq"],
+            "command": request["command"],
+            "success": true,
+            "body": body,
+        }))
+    }
+
+    fn fail(&mut self, request: &Value, message: &str) -> io::Result<()> {
+        self.send(json!({
+            "type": "response",
+            "request_seq": request["seq"],
+            "command": request["command"],
+            "success": false,
+            "message": message,
+        }))
+    }
+
+    fn event(&mut self, event: &str, body: Value) -> io::Result<()> {
+        self.send(json!({ "type": "event", "event": event, "body": body }))
+    }
+}
+
+/// what the server knows about the debug session
+struct Session {
+    global: Rc<RefCell<Lenv>>,
+    program: Option<String>,
+    /// forms of the program, used to find the line of a paused form
+    spans: Vec<Spanned>,
+    /// breakpoint forms for each source path
+    breakpoints: HashMap<String, Vec<Lval>>,
+    stop_on_entry: bool,
+    launched: bool,
+    configured: bool,
+    /// index of the top level form being evaluated
+    top_form: usize,
+    disconnected: bool,
+}
+
+impl Session {
+    /// line of the first form equal to `form`
+    fn line_of(&self, form: &Lval) -> Option<usize> {
+        self.spans.iter().find(|s| s.form == *form).map(|s| s.line)
+    }
+
+    /// line of the top level form being evaluated
+    fn top_line(&self) -> usize {
+        self.spans.iter().filter(|s| s.top).nth(self.top_form).map_or(1, |s| s.line)
+    }
+}
+
+/// a stack frame as shown to the client, innermost first
+struct StackFrame {
+    name: String,
+    line: usize,
+    env: Rc<RefCell<Lenv>>,
+}
+
+/// serves one debug session, returning when the client disconnects
+pub fn serve(input: Box<dyn BufRead>, output: Box<dyn Write>) -> io::Result<()> {
+    let conn = Rc::new(RefCell::new(Connection { input, output, seq: 0 }));
+    let global = Rc::new(RefCell::new(Lenv::new()));
+    add_builtins(global.clone());
+    let session = Rc::new(RefCell::new(Session {
+        global,
+        program: None,
+        spans: Vec::new(),
+        breakpoints: HashMap::new(),
+        stop_on_entry: false,
+        launched: false,
+        configured: false,
+        top_form: 0,
+        disconnected: false,
+    }));
+
+    while !session.borrow().disconnected {
+        let request = match conn.borrow_mut().read()? {
+            Some(request) => request,
+            None => break,
+        };
+        let command = request["command"].as_str().unwrap_or("");
+        match command {
+            "initialize" => {
+                let mut conn = conn.borrow_mut();
+                conn.respond(&request, json!({
+                    "supportsConfigurationDoneRequest": true,
+                    "supportsEvaluateForHovers": true,
+                }))?;
+                conn.event("initialized", json!({}))?;
+            },
+            "launch" => {
+                let program = request["arguments"]["program"].as_str().unwrap_or("").to_string();
+                let spans = match fs::read_to_string(&program) {
+                    Ok(src) => read_spans(&src),
+                    Err(e) => Err(format!("cannot read '{}': {}", program, e)),
+                };
+                match spans {
+                    Ok(spans) => {
+                        let mut s = session.borrow_mut();
+                        s.spans = spans;
+                        s.program = Some(program);
+                        s.stop_on_entry = request["arguments"]["stopOnEntry"].as_bool().unwrap_or(false);
+                        s.launched = true;
+                        conn.borrow_mut().respond(&request, json!({}))?;
+                    },
+                    Err(e) => conn.borrow_mut().fail(&request, &e)?,
+                }
+                if session.borrow().configured { run(&conn, &session)?; }
+            },
+            "configurationDone" => {
+                conn.borrow_mut().respond(&request, json!({}))?;
+                session.borrow_mut().configured = true;
+                if session.borrow().launched { run(&conn, &session)?; }
+            },
+            "evaluate" => {
+                let env = session.borrow().global.clone();
+                evaluate(&conn, &request, &env)?;
+            },
+            "disconnect" => {
+                conn.borrow_mut().respond(&request, json!({}))?;
+                session.borrow_mut().disconnected = true;
+            },
+            _ => handle_common(&conn, &session, &request, &[])?,
+        }
+    }
+    Ok(())
+}
+
+/// requests answered the same way whether or not the program is paused
+fn handle_common(conn: &Rc<RefCell<Connection>>, session: &Rc<RefCell<Session>>, request: &Value, frames: &[StackFrame]) -> io::Result<()> {
+    let args = &request["arguments"];
+    let mut conn = conn.borrow_mut();
+    match request["command"].as_str().unwrap_or("") {
+        "setBreakpoints" => {
+            let path = args["source"]["path"].as_str().unwrap_or("").to_string();
+            let spans = fs::read_to_string(&path).ok().and_then(|src| read_spans(&src).ok()).unwrap_or_default();
+            let mut forms = Vec::new();
+            let mut verified = Vec::new();
+            for bp in args["breakpoints"].as_array().into_iter().flatten() {
+                let line = bp["line"].as_u64().unwrap_or(0) as usize;
+                match spans.iter().find(|s| s.line == line) {
+                    Some(span) => {
+                        forms.push(span.form.clone());
+                        verified.push(json!({ "verified": true, "line": line }));
+                    },
+                    None => verified.push(json!({ "verified": false, "line": line, "message": "no form starts on this line" })),
+                }
+            }
+            let mut s = session.borrow_mut();
+            s.breakpoints.insert(path, forms);
+            debug::set_breakpoints(s.breakpoints.values().flatten().cloned().collect());
+            conn.respond(request, json!({ "breakpoints": verified }))
+        },
+        "setExceptionBreakpoints" => conn.respond(request, json!({ "breakpoints": [] })),
+        "threads" => conn.respond(request, json!({ "threads": [{ "id": THREAD_ID, "name": "main" }] })),
+        "stackTrace" => {
+            let s = session.borrow();
+            let source = json!({ "path": s.program });
+            let frames: Vec<Value> = frames.iter().enumerate().map(|(id, f)| json!({
+                "id": id,
+                "name": f.name,
+                "source": source,
+                "line": f.line,
+                "column": 1,
+            })).collect();
+            conn.respond(request, json!({ "stackFrames": frames, "totalFrames": frames.len() }))
+        },
+        "scopes" => {
+            let id = args["frameId"].as_u64().unwrap_or(0) as usize;
+            let mut scopes = Vec::new();
+            if frames.get(id).is_some_and(|f| f.env.borrow().par.is_some()) {
+                scopes.push(json!({ "name": "Locals", "variablesReference": id as i64 + 2, "expensive": false }));
+            }
+            scopes.push(json!({ "name": "Globals", "variablesReference": GLOBALS_REF, "expensive": false }));
+            conn.respond(request, json!({ "scopes": scopes }))
+        },
+        "variables" => {
+            let reference = args["variablesReference"].as_i64().unwrap_or(0);
+            let env = if reference == GLOBALS_REF {
+                Some(session.borrow().global.clone())
+            } else {
+                usize::try_from(reference - 2).ok().and_then(|i| frames.get(i)).map(|f| f.env.clone())
+            };
+            let variables = env.map(|env| variables(&env.borrow())).unwrap_or_default();
+            conn.respond(request, json!({ "variables": variables }))
+        },
+        "" => Ok(()),
+        other => conn.fail(request, &format!("unsupported request '{}'", other)),
+    }
+}
+
+/// the bindings of one environment, leaving out builtins
+fn variables(env: &Lenv) -> Vec<Value> {
+    let mut names: Vec<&Symbol> = env.data.keys()
+        .filter(|k| !matches!(env.data[*k], Lval::Fun(_)))
+        .collect();
+    names.sort_by_key(|k| k.name());
+    names.into_iter()
+        .map(|name| json!({ "name": name.name(), "value": env.data[name].to_string(), "variablesReference": 0 }))
+        .collect()
+}
+
+/// evaluates the request's expression in `env` and responds with the result
+fn evaluate(conn: &Rc<RefCell<Connection>>, request: &Value, env: &Rc<RefCell<Lenv>>) -> io::Result<()> {
+    let expression = request["arguments"]["expression"].as_str().unwrap_or("");
+    // no connection borrow is held while evaluating: print sends output events
+    let result = match read_source(expression) {
+        Ok(forms) => forms.into_iter().map(|f| lval_eval(env.clone(), f)).last().unwrap_or(Lval::NIL),
+        Err(e) => return conn.borrow_mut().fail(request, &e),
+    };
+    match result {
+        Lval::Err(e) => conn.borrow_mut().fail(request, &e),
+        value => conn.borrow_mut().respond(request, json!({ "result": value.to_string(), "variablesReference": 0 })),
+    }
+}
+
+/// runs the launched program with the debugger installed
+fn run(conn: &Rc<RefCell<Connection>>, session: &Rc<RefCell<Session>>) -> io::Result<()> {
+    let (env, program) = {
+        let s = session.borrow();
+        (s.global.clone(), s.program.clone().unwrap_or_default())
+    };
+    let src = fs::read_to_string(&program)?;
+
+    let sink = conn.clone();
+    set_output(Some(Box::new(move |text| {
+        let _ = sink.borrow_mut().event("output", json!({ "category": "stdout", "output": text }));
+    })));
+    debug::set_debugger(Some(Box::new(DapDebugger {
+        conn: conn.clone(),
+        session: session.clone(),
+        entry: session.borrow().stop_on_entry,
+    })));
+    if session.borrow().stop_on_entry { debug::step_next(); }
+
+    let mut errors = Vec::new();
+    let parsed = eval_source(&env, &src, |result| {
+        session.borrow_mut().top_form += 1;
+        if let Lval::Err(e) = result { errors.push(e); }
+    });
+
+    debug::set_debugger(None);
+    set_output(None);
+    if session.borrow().disconnected { return Ok(()); }
+
+    let mut conn = conn.borrow_mut();
+    if let Err(e) = parsed { errors.push(e); }
+    for e in &errors {
+        conn.event("output", json!({ "category": "stderr", "output": format!("Error: {}\n", e) }))?;
+    }
+    conn.event("terminated", json!({}))?;
+    conn.event("exited", json!({ "exitCode": if errors.is_empty() { 0 } else { 1 } }))
+}
+
+struct DapDebugger {
+    conn: Rc<RefCell<Connection>>,
+    session: Rc<RefCell<Session>>,
+    /// the first pause is reported as stopping on entry
+    entry: bool,
+}
+
+impl DapDebugger {
+    fn frames(&self, pause: &Pause) -> Vec<StackFrame> {
+        let s = self.session.borrow();
+        let mut frames = vec![StackFrame {
+            name: pause.frames.last().map_or("<top level>".to_string(), |f| f.name.clone()),
+            line: s.line_of(pause.expr).unwrap_or_else(|| s.top_line()),
+            env: pause.env.clone(),
+        }];
+        if let Some((_, callers)) = pause.frames.split_last() {
+            for f in callers.iter().rev() {
+                frames.push(StackFrame {
+                    name: f.name.clone(),
+                    line: s.line_of(&f.form()).unwrap_or_else(|| s.top_line()),
+                    env: f.env.clone(),
+                });
+            }
+            frames.push(StackFrame { name: "<top level>".to_string(), line: s.top_line(), env: s.global.clone() });
+        }
+        frames
+    }
+
+    /// answers requests until the client resumes the program
+    fn serve_pause(&mut self, pause: &Pause) -> io::Result<Resume> {
+        let reason = match pause.reason {
+            _ if self.entry => "entry",
+            PauseReason::Step => "step",
+            PauseReason::Break | PauseReason::Breakpoint => "breakpoint",
+        };
+        self.entry = false;
+        self.conn.borrow_mut().event("stopped", json!({
+            "reason": reason,
+            "threadId": THREAD_ID,
+            "allThreadsStopped": true,
+        }))?;
+
+        let frames = self.frames(pause);
+        loop {
+            let request = match self.conn.borrow_mut().read()? {
+                Some(request) => request,
+                None => {
+                    self.session.borrow_mut().disconnected = true;
+                    return Ok(Resume::Abort);
+                },
+            };
+            let resume = match request["command"].as_str().unwrap_or("") {
+                "continue" => Resume::Continue,
+                "next" => Resume::StepOver,
+                "stepIn" => Resume::StepInto,
+                "stepOut" => Resume::StepOut,
+                "disconnect" => {
+                    self.conn.borrow_mut().respond(&request, json!({}))?;
+                    self.session.borrow_mut().disconnected = true;
+                    return Ok(Resume::Abort);
+                },
+                "evaluate" => {
+                    let id = request["arguments"]["frameId"].as_u64().unwrap_or(0) as usize;
+                    let env = frames.get(id).map_or(pause.env.clone(), |f| f.env.clone());
+                    evaluate(&self.conn, &request, &env)?;
+                    continue;
+                },
+                _ => {
+                    handle_common(&self.conn, &self.session, &request, &frames)?;
+                    continue;
+                },
+            };
+            let body = if resume == Resume::Continue { json!({ "allThreadsContinued": true }) } else { json!({}) };
+            self.conn.borrow_mut().respond(&request, body)?;
+            return Ok(resume);
+        }
+    }
+}
+
+impl Debugger for DapDebugger {
+    fn pause(&mut self, pause: &Pause) -> Resume {
+        match self.serve_pause(pause) {
+            Ok(resume) => resume,
+            Err(_) => {
+                self.session.borrow_mut().disconnected = true;
+                Resume::Abort
+            },
+        }
+    }
+}
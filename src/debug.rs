@@ -0,0 +1,261 @@
+// Hooks for pausing an evaluation. A front end (the REPL's step debugger or
+// the DAP server) installs a Debugger; lval_eval then asks it what to do
+// before evaluating each form while stepping or when a form matches a
+// breakpoint, and (break) pauses wherever it is called. Only while stepping
+// or with breakpoints set does lval_eval go through the hook, so an installed
+// debugger that is waiting for a (break) costs a flag test per form. While
+// a debugger is installed, lambda calls also push frames so the paused
+// evaluation can show a backtrace and the bindings of every frame.
+
+use crate::eval::{interrupt, lval_eval};
+use crate::symbol::Symbol;
+use crate::types::{Lval, Lenv};
+use std::cell::{Cell, RefCell};
+use std::rc::Rc;
+
+/// why the evaluation stopped
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PauseReason {
+    /// stepping reached the next form
+    Step,
+    /// a (break) call
+    Break,
+    /// a form set as a breakpoint
+    Breakpoint,
+}
+
+/// what the debugger wants after a pause
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Resume {
+    /// run until the next (break)
+    Continue,
+    /// pause at the next form, including inside called functions
+    StepInto,
+    /// pause at the next form that is not part of the paused one
+    StepOver,
+    /// pause once the current function has returned
+    StepOut,
+    /// abandon the whole evaluation
+    Abort,
+}
+
+/// one lambda call in progress
+#[derive(Clone)]
+pub struct Frame {
+    /// the name the function was called by, "lambda" for anonymous calls
+    pub name: String,
+    /// the call's environment: its arguments, with the caller as parent
+    pub env: Rc<RefCell<Lenv>>,
+    /// the function body being evaluated, the Qexpr the lambda holds
+    pub body: Rc<Lval>,
+}
+
+impl Frame {
+    /// the body as the form evaluated for the call
+    pub fn form(&self) -> Lval {
+        body_form(&self.body)
+    }
+}
+
+/// a Qexpr body is evaluated as the list it holds; any other, such as the
+/// lone atom a defmethod body can be, as itself
+fn body_form(body: &Lval) -> Lval {
+    match body {
+        Lval::Qexpr(cells) => Lval::Sexpr(cells.clone()),
+        atom => atom.clone(),
+    }
+}
+
+/// the evaluation state handed to the debugger at a pause
+pub struct Pause<'a> {
+    pub reason: PauseReason,
+    /// the form about to be evaluated; the (break) call itself for breaks
+    pub expr: &'a Lval,
+    /// the environment the form is evaluated in
+    pub env: &'a Rc<RefCell<Lenv>>,
+    /// active lambda calls, outermost first
+    pub frames: &'a [Frame],
+}
+
+pub trait Debugger {
+    /// called when the evaluation pauses; blocks until the user decides how to go on
+    fn pause(&mut self, pause: &Pause) -> Resume;
+
+    /// called with the value of a form the evaluation paused on
+    fn returned(&mut self, _value: &Lval) {}
+}
+
+/// when the next pause happens
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum StepMode {
+    Run,
+    Into,
+    /// pause at a form nested no deeper than this
+    Over(usize),
+    /// pause once fewer than this many frames are active
+    Out(usize),
+}
+
+thread_local! {
+    static DEBUGGER: RefCell<Option<Box<dyn Debugger>>> = RefCell::new(None);
+    static MODE: Cell<StepMode> = const { Cell::new(StepMode::Run) };
+    // lval_eval nesting, used to step over a form
+    static EVAL_DEPTH: Cell<usize> = const { Cell::new(0) };
+    static FRAMES: RefCell<Vec<Frame>> = const { RefCell::new(Vec::new()) };
+    // forms that pause whenever they are evaluated
+    static BREAKPOINTS: RefCell<Vec<Lval>> = const { RefCell::new(Vec::new()) };
+    // whether lval_eval must go through eval_hooked: a debugger is installed
+    // and is stepping or has breakpoints
+    static HOOKED: Cell<bool> = const { Cell::new(false) };
+}
+
+/// installs (or with None removes) the debugger for this thread
+pub fn set_debugger(debugger: Option<Box<dyn Debugger>>) {
+    DEBUGGER.with(|d| *d.borrow_mut() = debugger);
+    rehook();
+}
+
+/// true when forms must be evaluated with eval_hooked
+pub fn is_hooked() -> bool {
+    HOOKED.with(|h| h.get())
+}
+
+/// sets the step mode and whether forms need the hook after it changed
+fn set_mode(mode: StepMode) {
+    MODE.with(|m| m.set(mode));
+    rehook();
+}
+
+fn rehook() {
+    let stepping = MODE.with(|m| m.get()) != StepMode::Run;
+    let breakpoints = BREAKPOINTS.with(|b| !b.borrow().is_empty());
+    HOOKED.with(|h| h.set(is_debugging() && (stepping || breakpoints)));
+}
+
+/// true when a debugger is installed and not busy handling a pause
+pub fn is_debugging() -> bool {
+    DEBUGGER.with(|d| d.borrow().is_some())
+}
+
+/// replaces the breakpoints. A breakpoint is a form: evaluating any form equal
+/// to it pauses, so a front end can map source lines to the forms read there
+pub fn set_breakpoints(forms: Vec<Lval>) {
+    BREAKPOINTS.with(|b| *b.borrow_mut() = forms);
+    rehook();
+}
+
+/// makes the next evaluated form pause, as :step does
+pub fn step_next() {
+    set_mode(StepMode::Into);
+}
+
+/// stops stepping and forgets frames left over from an abandoned evaluation
+pub fn reset() {
+    EVAL_DEPTH.with(|d| d.set(0));
+    FRAMES.with(|f| f.borrow_mut().clear());
+    set_mode(StepMode::Run);
+}
+
+/// the active lambda calls, outermost first
+pub fn backtrace() -> Vec<Frame> {
+    FRAMES.with(|f| f.borrow().clone())
+}
+
+/// evaluates a form with `eval`, pausing first if the step mode asks for it
+pub fn eval_hooked(e: Rc<RefCell<Lenv>>, v: Lval, eval: fn(Rc<RefCell<Lenv>>, Lval) -> Lval) -> Lval {
+    let depth = EVAL_DEPTH.with(|d| { d.set(d.get() + 1); d.get() });
+    let stop = match v {
+        Lval::Sexpr(ref cells) if !cells.is_empty() => {
+            let step = match MODE.with(|m| m.get()) {
+                StepMode::Run => false,
+                StepMode::Into => true,
+                StepMode::Over(d) => depth <= d,
+                StepMode::Out(n) => frame_count() < n,
+            };
+            if step {
+                Some(PauseReason::Step)
+            } else if BREAKPOINTS.with(|b| b.borrow().contains(&v)) {
+                Some(PauseReason::Breakpoint)
+            } else {
+                None
+            }
+        },
+        _ => None,
+    };
+
+    let result = if let Some(reason) = stop {
+        match pause(reason, &v, &e, depth) {
+            Some(err) => err,
+            None => {
+                let result = eval(e, v);
+                with_debugger(|d| d.returned(&result));
+                result
+            },
+        }
+    } else {
+        eval(e, v)
+    };
+    EVAL_DEPTH.with(|d| d.set(depth - 1));
+    result
+}
+
+/// evaluates a lambda body, a Qexpr, as a new frame
+pub fn call_frame(name: Option<&str>, env: Rc<RefCell<Lenv>>, body: Rc<Lval>) -> Lval {
+    let form = body_form(&body);
+    if !is_debugging() { return lval_eval(env, form); }
+    let name = name.unwrap_or("lambda").to_string();
+    FRAMES.with(|f| f.borrow_mut().push(Frame { name, env: env.clone(), body }));
+    let result = lval_eval(env, form);
+    FRAMES.with(|f| f.borrow_mut().pop());
+    result
+}
+
+/// (break) pauses the evaluation where it is called. Without a debugger it does nothing
+pub fn builtin_break(e: Rc<RefCell<Lenv>>, args: Vec<Lval>) -> Lval {
+    if !args.is_empty() { return Lval::Err("Function 'break' passed incorrect number of arguments.".to_string()); }
+    if !is_debugging() { return Lval::NIL; }
+    let expr = Lval::Sexpr(vec![Lval::Sym(Symbol::intern("break"))]);
+    // without the hook this call was not counted, but the forms after it will be
+    let depth = EVAL_DEPTH.with(|d| d.get()) + usize::from(!is_hooked());
+    match pause(PauseReason::Break, &expr, &e, depth) {
+        Some(err) => err,
+        None => Lval::NIL,
+    }
+}
+
+fn frame_count() -> usize {
+    FRAMES.with(|f| f.borrow().len())
+}
+
+fn with_debugger(f: impl FnOnce(&mut dyn Debugger)) {
+    DEBUGGER.with(|d| {
+        if let Some(ref mut debugger) = *d.borrow_mut() {
+            f(debugger.as_mut());
+        }
+    });
+}
+
+/// hands a pause to the debugger and sets the step mode from its answer.
+/// Returns the error to evaluate to when the user aborts
+fn pause(reason: PauseReason, expr: &Lval, env: &Rc<RefCell<Lenv>>, depth: usize) -> Option<Lval> {
+    // take the debugger out so forms it evaluates in the paused frame run without pausing
+    let mut debugger = DEBUGGER.with(|d| d.borrow_mut().take())?;
+    let frames = backtrace();
+    HOOKED.with(|h| h.set(false));
+    let resume = debugger.pause(&Pause { reason, expr, env, frames: &frames });
+    DEBUGGER.with(|d| *d.borrow_mut() = Some(debugger));
+
+    let mode = match resume {
+        Resume::Continue => StepMode::Run,
+        Resume::StepInto => StepMode::Into,
+        Resume::StepOver => StepMode::Over(depth),
+        Resume::StepOut => StepMode::Out(frames.len()),
+        Resume::Abort => {
+            set_mode(StepMode::Run);
+            interrupt();
+            return Some(Lval::Err("Evaluation aborted from the debugger".to_string()));
+        },
+    };
+    set_mode(mode);
+    None
+}
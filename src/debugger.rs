@@ -0,0 +1,150 @@
+// The REPL's step debugger. It is installed at startup, so (break) pauses
+// anywhere and :step expr pauses before every form of expr. At the debug>
+// prompt the user inspects the paused evaluation and decides how to go on.
+
+use crate::repl::LispHelper;
+use crate::{read_line, Input};
+use rustlisp::debug::{Debugger, Frame, Pause, PauseReason, Resume};
+use rustlisp::eval::lval_eval;
+use rustlisp::read_source;
+use rustlisp::symbol::Symbol;
+use rustlisp::types::{Lenv, Lval};
+use rustyline::history::DefaultHistory;
+use rustyline::Editor;
+use std::cell::RefCell;
+use std::rc::Rc;
+
+const HELP: &str = "\
+s, step          evaluate the next form, stepping into function calls
+n, next          evaluate this form without pausing inside it
+o, out           finish the current function call
+c, continue      run until the next (break)
+bt, backtrace    list the active function calls
+env              show the bindings visible in the paused frame
+p, eval expr     evaluate expr in the paused frame
+q, abort         abandon the evaluation
+an empty line repeats the last step command";
+
+pub struct ReplDebugger {
+    rl: Editor<LispHelper, DefaultHistory>,
+    last: Resume,
+}
+
+impl ReplDebugger {
+    pub fn new(env: Rc<RefCell<Lenv>>) -> ReplDebugger {
+        let mut rl: Editor<LispHelper, DefaultHistory> = Editor::new().unwrap();
+        rl.set_helper(Some(LispHelper::new(env)));
+        ReplDebugger { rl, last: Resume::StepInto }
+    }
+}
+
+impl Debugger for ReplDebugger {
+    fn pause(&mut self, pause: &Pause) -> Resume {
+        let place = match pause.frames.last() {
+            Some(frame) => format!(" in {}", frame.name),
+            None => String::new(),
+        };
+        match pause.reason {
+            PauseReason::Break => println!(";; break{}", place),
+            PauseReason::Step => println!(";; {}{}", pause.expr, place),
+            PauseReason::Breakpoint => println!(";; breakpoint at {}{}", pause.expr, place),
+        }
+
+        loop {
+            let line = match read_line(&mut self.rl, "debug> ") {
+                Input::Line(l) => l,
+                Input::Interrupted | Input::Exit => return Resume::Abort,
+            };
+            let line = line.trim();
+            let _ = self.rl.add_history_entry(line);
+            let (name, arg) = match line.split_once(char::is_whitespace) {
+                Some((name, arg)) => (name, arg.trim()),
+                None => (line, ""),
+            };
+
+            let resume = match name {
+                "" => self.last,
+                "s" | "step" => Resume::StepInto,
+                "n" | "next" => Resume::StepOver,
+                "o" | "out" => Resume::StepOut,
+                "c" | "continue" => Resume::Continue,
+                "q" | "abort" => return Resume::Abort,
+                "bt" | "backtrace" => {
+                    print_backtrace(pause.frames);
+                    continue;
+                },
+                "env" => {
+                    print_env_chain(pause.env);
+                    continue;
+                },
+                "p" | "eval" => {
+                    eval_in_frame(pause.env, arg);
+                    continue;
+                },
+                "h" | "help" => {
+                    println!("{}", HELP);
+                    continue;
+                },
+                _ => {
+                    println!("Error: Unknown debugger command '{}'. Type help for a list of commands.", name);
+                    continue;
+                },
+            };
+            self.last = resume;
+            return resume;
+        }
+    }
+
+    fn returned(&mut self, value: &Lval) {
+        println!(";; => {}", value);
+    }
+}
+
+/// innermost call first, each with its arguments
+fn print_backtrace(frames: &[Frame]) {
+    if frames.is_empty() {
+        println!("  (top level)");
+        return;
+    }
+    for (i, frame) in frames.iter().rev().enumerate() {
+        let env = frame.env.borrow();
+        let mut args: Vec<String> = env.data.iter().map(|(k, v)| format!("{}={}", k, v)).collect();
+        args.sort();
+        println!("  #{} {} {}", i, frame.name, args.join(" "));
+    }
+}
+
+/// every environment from the paused one out to the global, skipping builtins
+fn print_env_chain(env: &Rc<RefCell<Lenv>>) {
+    let mut level = Some(env.clone());
+    let mut depth = 0;
+    while let Some(env) = level {
+        let env = env.borrow();
+        let label = if env.par.is_none() { "global".to_string() } else { format!("#{}", depth) };
+        let mut names: Vec<&Symbol> = env.data.keys()
+            .filter(|k| !matches!(env.data[*k], Lval::Fun(_)))
+            .collect();
+        names.sort_by_key(|k| k.name());
+        println!("  {}:", label);
+        for name in names {
+            println!("    {} = {}", name, env.data[name]);
+        }
+        level = env.par.clone();
+        depth += 1;
+    }
+}
+
+fn eval_in_frame(env: &Rc<RefCell<Lenv>>, src: &str) {
+    if src.is_empty() {
+        println!("Error: eval needs an expression");
+        return;
+    }
+    match read_source(src) {
+        Ok(forms) => {
+            for form in forms {
+                println!("{}", lval_eval(env.clone(), form));
+            }
+        },
+        Err(e) => println!("Error: {}", e),
+    }
+}
@@ -1,39 +1,118 @@
 // Defines the evaluation logic of the program
 
-use crate::types::{Lval, Lenv, Builtin};
+use crate::builtin_profile;
+use crate::capability::denied;
+use crate::debug::{call_frame, eval_hooked, is_hooked};
+use crate::io::write_output;
+use crate::limits::{count_step, enter_call, exit_call};
+use crate::trace::{builtin_trace, builtin_untrace, is_traced, traced_call};
+use crate::cons;
+use crate::gc;
+use crate::record::{self, builtin_defstruct};
+use crate::place::{builtin_decf, builtin_incf, builtin_pop, builtin_push, builtin_rplaca, builtin_rplacd, builtin_setf};
+use crate::object::{self, builtin_defclass, builtin_defgeneric, builtin_defmethod};
+use crate::symbol::Symbol;
+use crate::types::{Builtin, Lval, Lenv, Princ};
 use std::rc::Rc;
 use std::cell::RefCell;
+use std::sync::atomic::{AtomicBool, Ordering};
 
+// set from the SIGINT handler; every lval_eval checks it so a runaway
+// evaluation unwinds back to the prompt
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+pub fn interrupt() {
+    INTERRUPTED.store(true, Ordering::SeqCst);
+}
+
+pub fn clear_interrupt() {
+    INTERRUPTED.store(false, Ordering::SeqCst);
+}
+
+pub fn is_interrupted() -> bool {
+    INTERRUPTED.load(Ordering::Relaxed)
+}
+
+/// builtins that read or bind variables in the environment they are called
+/// from. The compiled evaluators (ast.rs, vm.rs) hand these their scopes as
+/// an Lenv chain; every other builtin gets the global environment. maphash,
+/// mapcar and reduce are here because the functions they call see their
+/// caller's variables, as does call-next-method, and break because the
+/// debugger shows them
+pub const ENV_BUILTINS: &[&str] = &["eval", "=", "set", "load", "maphash", "mapcar", "reduce", "call-next-method", "break"];
+
+/// forms lval_eval evaluates by name, before their arguments, rather than
+/// looking the head up in the environment. Each gets the whole form, head
+/// included. The symbol table interns these names first, so a head is told to
+/// be one by its ID (Symbol::special_form), and the ast and vm backends leave
+/// all but quote, setq, defun and cond to the tree-walker. The middle entry is
+/// the argument list shown as a hint
+pub const SPECIAL_FORMS: &[(&str, &str, Builtin)] = &[
+    ("quote", "x", builtin_quote),
+    ("setq", "sym value", builtin_putq),
+    ("defun", "name formals body", builtin_defun),
+    ("cond", "&rest (test body)", special_cond),
+    ("trace", "&rest fns", builtin_trace),
+    ("untrace", "&rest fns", builtin_untrace),
+    ("setf", "place value &rest more", builtin_setf),
+    ("push", "item place", builtin_push),
+    ("pop", "place", builtin_pop),
+    ("incf", "place &optional delta", builtin_incf),
+    ("decf", "place &optional delta", builtin_decf),
+    ("rplaca", "list value", builtin_rplaca),
+    ("rplacd", "list value", builtin_rplacd),
+    ("defstruct", "name &rest slots", builtin_defstruct),
+    ("defclass", "name superclasses slots", builtin_defclass),
+    ("defgeneric", "name formals", builtin_defgeneric),
+    ("defmethod", "name [qualifier] formals body", builtin_defmethod),
+];
+
+/// true when `name` is one of the SPECIAL_FORMS
+pub fn is_special_form(name: &str) -> bool {
+    SPECIAL_FORMS.iter().any(|(n, _, _)| *n == name)
+}
+
+fn special_cond(e: Rc<RefCell<Lenv>>, cells: Vec<Lval>) -> Lval {
+    builtin_cond(e, cells[1..].to_vec())
+}
 
 pub fn lval_eval(e: Rc<RefCell<Lenv>>, v: Lval) -> Lval {
+    if let Err(err) = count_step() { return err; }
+    if is_interrupted() { return Lval::Err("Evaluation interrupted".to_string()); }
+    if is_hooked() { return eval_hooked(e, v, eval_form); }
+    eval_form(e, v)
+}
+
+/// evaluates one form without consulting the debugger
+fn eval_form(e: Rc<RefCell<Lenv>>, v: Lval) -> Lval {
     match v {
         Lval::Sym(s) => {
-            if s == "T" || s == "t" { return Lval::T; }
-            if s == "nil" { return Lval::NIL; }
-            if let Some(val) = e.borrow().get(&s) {
+            if s.is_t() { return Lval::T; }
+            if s.is_nil() { return Lval::NIL; }
+            if s.is_keyword() { return Lval::Sym(s); }
+            if let Some(val) = e.borrow().get(s) {
                 val
+            } else if let Some(required) = builtin_profile(s.name()) {
+                // a builtin that exists but was left out by the capability profile
+                denied(s.name(), required)
             } else {
                 Lval::Err(format!("Unbound symbol '{}'", s))
             }
         },
-        Lval::Sexpr(mut cells) => {
+        Lval::Sexpr(cells) => {
             if cells.is_empty() { return Lval::Sexpr(cells); }
 
-            if let Lval::Sym(ref s) = cells[0] {
-                if s == "quote" {
-                    return builtin_quote(e, cells);
-                }
-                if s == "setq" {
-                    return builtin_putq(e, cells);
-                }
-                if s == "defun" {
-                    return builtin_defun(e, cells);
-                }
-                if s == "cond" {
-                    return builtin_cond(e, cells[1..].to_vec());
-                }
+            if let Some(form) = match cells[0] { Lval::Sym(s) => s.special_form(), _ => None } {
+                return form(e, cells);
             }
 
+            // remember the function's name before the head is evaluated, for
+            // trace output and debugger frames
+            let name = match cells[0] {
+                Lval::Sym(s) => Some(s),
+                _ => None,
+            };
+
             let mut evaluated = Vec::new();
             for cell in cells {
                 evaluated.push(lval_eval(e.clone(), cell));
@@ -48,30 +127,50 @@ pub fn lval_eval(e: Rc<RefCell<Lenv>>, v: Lval) -> Lval {
 
             if evaluated.is_empty() { return Lval::Sexpr(evaluated); }
 
-            if evaluated.len() == 1 {
+            // (f) calls the function a symbol names when it takes no arguments;
+            // any other one element list is its element's value, as the body
+            // '(+ x 1) is evaluated as ((+ x 1)) and the body '(f) of a lambda
+            // whose parameter f is car returns car
+            if evaluated.len() == 1 && !(name.is_some() && evaluated[0].takes_no_arguments()) {
                 return evaluated.remove(0);
             }
 
             // once the sexpr is evaluate make function call to evaluate the statement
             let f = evaluated.remove(0);
-            lval_call(e, f, evaluated)
+            match name {
+                Some(name) if is_traced(name) && matches!(f, Lval::Lambda(..)) => traced_call(e, name.name(), f, evaluated),
+                Some(name) => lval_call_named(e, name.name(), f, evaluated),
+                None => lval_call(e, f, evaluated),
+            }
         },
+        Lval::Qexpr(_) => cons::quoted(&v),
         _ => v,
     }
 }
 
 pub fn lval_call(e: Rc<RefCell<Lenv>>, f: Lval, args: Vec<Lval>) -> Lval {
+    apply(e, None, f, args)
+}
+
+/// calls a function by the name it was looked up under, so the debugger can show it
+pub fn lval_call_named(e: Rc<RefCell<Lenv>>, name: &str, f: Lval, args: Vec<Lval>) -> Lval {
+    apply(e, Some(name), f, args)
+}
+
+fn apply(e: Rc<RefCell<Lenv>>, name: Option<&str>, f: Lval, args: Vec<Lval>) -> Lval {
     match f {
         Lval::Fun(func) => func(e, args),   // evaluate builtin functions
+        Lval::StructFn(kind, op) => record::call(e, &kind, op, args),
+        Lval::Generic(generic) => object::call(e, &generic, args),
         Lval::Lambda(env, formals, body) => {       // evaluate custom user defined functions
             let mut f_env = env;
             let given = args.len();
             let total = if let Lval::Qexpr(ref cells) = *formals { cells.len() } else { 0 };
             
-            let mut args_iter = args.into_iter();
-            let mut formals_vec = if let Lval::Qexpr(cells) = *formals { cells } else { vec![] };
+            let args_iter = args.into_iter();
+            let mut formals_vec = if let Lval::Qexpr(ref cells) = *formals { cells.clone() } else { vec![] };
             
-            while let Some(arg) = args_iter.next() {
+            for arg in args_iter {
                 if formals_vec.is_empty() {
                     return Lval::Err(format!("Function passed too many arguments. Got {}, Expected {}.", given, total));
                 }
@@ -86,11 +185,13 @@ pub fn lval_call(e: Rc<RefCell<Lenv>>, f: Lval, args: Vec<Lval>) -> Lval {
             
             if formals_vec.is_empty() {
                 f_env.par = Some(e.clone());
-                let env_rc = Rc::new(RefCell::new(f_env));
-                let body_sexpr = Lval::Sexpr(if let Lval::Qexpr(cells) = *body { cells } else { vec![] });
-                lval_eval(env_rc, body_sexpr)
+                let env_rc = gc::env(f_env);
+                if let Err(err) = enter_call() { return err; }
+                let result = call_frame(name, env_rc, body);
+                exit_call();
+                result
             } else {
-                Lval::Lambda(f_env, Box::new(Lval::Qexpr(formals_vec)), body)
+                Lval::Lambda(f_env, Rc::new(Lval::Qexpr(formals_vec)), body)
             }
         },
         _ => Lval::Err("S-expression starts with incorrect type".to_string()),
@@ -117,7 +218,7 @@ fn builtin_op(_e: Rc<RefCell<Lenv>>, args: Vec<Lval>, op: &str) -> Lval {
         return Lval::Num(-x);
     }
 
-    while let Some(arg) = args_iter.next() {
+    for arg in args_iter {
         let y = match arg { Lval::Num(n) => n, _ => 0 };
         match op {
             "+" => x += y,
@@ -177,8 +278,9 @@ pub fn builtin_defun(e: Rc<RefCell<Lenv>>, args: Vec<Lval>) -> Lval {
         Lval::Qexpr(v) => Lval::Qexpr(v),
         _ => return Lval::Err("Third argument to defun must be a list".to_string()),
     };
+    if let Some(err) = duplicate_formal(&formals_q) { return err; }
     
-    let lambda = Lval::Lambda(Lenv::new(), Box::new(formals_q), Box::new(body_q));
+    let lambda = Lval::Lambda(Lenv::new(), Rc::new(formals_q), Rc::new(body_q));
     
     builtin_var(e, vec![Lval::Qexpr(vec![sym]), lambda], "def")
 }
@@ -187,7 +289,10 @@ fn builtin_var(e: Rc<RefCell<Lenv>>, args: Vec<Lval>, func: &str) -> Lval {
     if args.is_empty() { return Lval::Err("Too few args".to_string()); }
     
     let mut args_iter = args.into_iter();
-    let syms = args_iter.next().unwrap();
+    let syms = match cons::code(&args_iter.next().unwrap()) {
+        Ok(syms) => syms,
+        Err(err) => return err,
+    };
     
     let syms_vec = match syms {
         Lval::Qexpr(mut v) => {
@@ -206,7 +311,7 @@ fn builtin_var(e: Rc<RefCell<Lenv>>, args: Vec<Lval>, func: &str) -> Lval {
         _ => return Lval::Err("First arg must be Qexpr".to_string()),
     };
     
-    for (i, sym) in syms_vec.iter().enumerate() {
+    for sym in syms_vec.iter() {
         let val = match args_iter.next() {
             Some(v) => v,
             None => return Lval::Err("Too few values".to_string()),
@@ -214,9 +319,9 @@ fn builtin_var(e: Rc<RefCell<Lenv>>, args: Vec<Lval>, func: &str) -> Lval {
         
         if let Lval::Sym(s) = sym {
             if func == "def" {
-                e.borrow_mut().def(s.clone(), val);
+                e.borrow_mut().def(*s, val);
             } else {
-                e.borrow_mut().put(s.clone(), val);
+                e.borrow_mut().put(*s, val);
             }
         } else {
             return Lval::Err("Cannot define non-symbol".to_string());
@@ -228,9 +333,11 @@ fn builtin_var(e: Rc<RefCell<Lenv>>, args: Vec<Lval>, func: &str) -> Lval {
 
 pub fn builtin_lambda(_e: Rc<RefCell<Lenv>>, args: Vec<Lval>) -> Lval {
     if args.len() != 2 { return Lval::Err("Expected 2 args".to_string()); }
-    let mut args_iter = args.into_iter();
-    let formals = args_iter.next().unwrap();
-    let body = args_iter.next().unwrap();
+    // lists of cells are data; the lambda keeps its formals and body as code
+    let (formals, body) = match (cons::code(&args[0]), cons::code(&args[1])) {
+        (Ok(formals), Ok(body)) => (formals, body),
+        (Err(err), _) | (_, Err(err)) => return err,
+    };
     
     if let Lval::Qexpr(_) = body { } else { return Lval::Err("Body must be Qexpr".to_string()); }
     
@@ -250,14 +357,27 @@ pub fn builtin_lambda(_e: Rc<RefCell<Lenv>>, args: Vec<Lval>) -> Lval {
         },
         _ => return Lval::Err("Formals must be Qexpr".to_string()),
     };
+    if let Some(err) = duplicate_formal(&new_formals) { return err; }
     
-    Lval::Lambda(Lenv::new(), Box::new(new_formals), Box::new(body))
+    Lval::Lambda(Lenv::new(), Rc::new(new_formals), Rc::new(body))
+}
+
+/// the error for formals that name a variable twice. Binding both would keep
+/// the last argument here but the first in the ast and vm backends, so every
+/// backend refuses them when the function is made
+pub fn duplicate_formal(formals: &Lval) -> Option<Lval> {
+    let Lval::Qexpr(cells) = formals else { return None };
+    cells.iter().enumerate().find_map(|(i, formal)| match formal {
+        Lval::Sym(s) if cells[..i].contains(formal) => Some(Lval::Err(format!("Formal '{}' appears more than once", s))),
+        _ => None,
+    })
 }
 
 /// used for car operation
 pub fn builtin_head(_e: Rc<RefCell<Lenv>>, args: Vec<Lval>) -> Lval {
     if args.len() != 1 { return Lval::Err("Expected 1 arg".to_string()); }
     let a = args.into_iter().next().unwrap();
+    if let Some(cell) = cons::cell(&a) { return cell.borrow().car.clone(); }
     match a {
         Lval::Qexpr(mut cells) => {
             if cells.is_empty() { return Lval::Err("Argument is empty".to_string()); }
@@ -267,8 +387,7 @@ pub fn builtin_head(_e: Rc<RefCell<Lenv>>, args: Vec<Lval>) -> Lval {
             match &mut cells[0] {
                 Lval::Sexpr(children) => {
                     if children.is_empty() { return Lval::Err("List is empty".to_string()); }
-                    let first = children.remove(0);
-                    first
+                    children.remove(0)
                 },
                 _ => Lval::Err("Argument must be a list (Sexpr)".to_string()),
             }
@@ -285,6 +404,7 @@ pub fn builtin_head(_e: Rc<RefCell<Lenv>>, args: Vec<Lval>) -> Lval {
 pub fn builtin_tail(_e: Rc<RefCell<Lenv>>, args: Vec<Lval>) -> Lval {
     if args.len() != 1 { return Lval::Err("Expected 1 arg".to_string()); }
     let a = args.into_iter().next().unwrap();
+    if let Some(cell) = cons::cell(&a) { return cons::rest(&cell.borrow().cdr); }
     match a {
         Lval::Qexpr(mut cells) => {
             if cells.is_empty() { return Lval::Err("Argument is empty".to_string()); }
@@ -313,9 +433,23 @@ pub fn builtin_tail(_e: Rc<RefCell<Lenv>>, args: Vec<Lval>) -> Lval {
     }
 }
 
+/// (nth i list) is the element at index i, or NIL past the end of the list
+pub fn builtin_nth(_e: Rc<RefCell<Lenv>>, args: Vec<Lval>) -> Lval {
+    if args.len() != 2 { return Lval::Err("Expected 2 args".to_string()); }
+    let Some(cells) = args[1].list_items() else { return Lval::Err("Argument must be a list".to_string()) };
+    match args[0] {
+        Lval::Num(i) if i >= 0 => usize::try_from(i).ok().and_then(|i| cells.get(i)).cloned().unwrap_or(Lval::NIL),
+        Lval::Num(_) => Lval::Err("Index given to nth must not be negative".to_string()),
+        _ => Lval::Err("Index given to nth must be a number".to_string()),
+    }
+}
+
 pub fn builtin_eval(e: Rc<RefCell<Lenv>>, args: Vec<Lval>) -> Lval {
     if args.len() != 1 { return Lval::Err("Expected 1 arg".to_string()); }
-    let mut a = args.into_iter().next().unwrap();
+    let mut a = match cons::code(&args[0]) {
+        Ok(code) => code,
+        Err(err) => return err,
+    };
 
     // println!("Eval input: {:?}", a);
     // Recursively unwrap single-element Qexprs to handle nested quoting from car/head
@@ -347,50 +481,79 @@ pub fn builtin_eval(e: Rc<RefCell<Lenv>>, args: Vec<Lval>) -> Lval {
 }
 
 
+/// (cons x list) is a new cell holding x in front of list, which it shares.
+/// Anything other than a list makes a dotted pair
 pub fn builtin_cons(_e: Rc<RefCell<Lenv>>, args: Vec<Lval>) -> Lval {
     if args.len() != 2 { return Lval::Err("Expected 2 args".to_string()); }
-    
-    let mut joined = Vec::new();
-    
-    for arg in args {
-        match arg {
-            Lval::Qexpr(cells) => {
-                for cell in cells {
-                    match cell {
-                        Lval::Sexpr(children) => joined.extend(children),
-                        _ => joined.push(cell),
-                    }
-                }
-            },
-            Lval::Sexpr(cells) => {
-                joined.extend(cells);
-            },
-            Lval::NIL => {},
-            _ => joined.push(arg),
-        }
-    }
-    
-    Lval::Sexpr(joined)
+    let mut args = args.into_iter().map(Lval::unquote);
+    let (car, cdr) = (args.next().unwrap(), args.next().unwrap());
+    cons::cons(car, cdr)
 }
 
-pub fn builtin_eq(e: Rc<RefCell<Lenv>>, args: Vec<Lval>) -> Lval {
+pub fn builtin_eq(_e: Rc<RefCell<Lenv>>, args: Vec<Lval>) -> Lval {
     if args.len() != 2 { return Lval::Err("Expected 2 args".to_string()); }
-    let mut iter = args.into_iter();
-    let a = iter.next().unwrap();
-    let b = iter.next().unwrap();
-    
+    if is_eq(&args[0], &args[1]) { Lval::T } else { Lval::NIL }
+}
+
+/// whether eq finds two values the same, as the eq test of a hash table does
+pub fn is_eq(a: &Lval, b: &Lval) -> bool {
     match (a, b) {
-        (Lval::Num(v1), Lval::Num(v2)) => if v1 == v2 { Lval::T } else { Lval::NIL },
-        (Lval::Sym(v1), Lval::Sym(v2)) => if v1 == v2 { Lval::T } else { Lval::NIL },
-        (Lval::T, Lval::T) => Lval::T,
-        (Lval::NIL, Lval::NIL) => Lval::T,
+        (Lval::Num(v1), Lval::Num(v2)) => v1 == v2,
+        (Lval::Sym(v1), Lval::Sym(v2)) => v1 == v2,
+        (Lval::Char(c1), Lval::Char(c2)) => c1 == c2,
+        (Lval::T, Lval::T) => true,
+        (Lval::NIL, Lval::NIL) => true,
+        // tables, arrays, structures and instances are shared, so eq checks identity
+        (Lval::Hash(t1), Lval::Hash(t2)) => Rc::ptr_eq(t1, t2),
+        (Lval::Array(a1), Lval::Array(a2)) => Rc::ptr_eq(a1, a2),
+        (Lval::Struct(s1), Lval::Struct(s2)) => Rc::ptr_eq(s1, s2),
+        (Lval::Object(o1), Lval::Object(o2)) => Rc::ptr_eq(o1, o2),
+        (Lval::Cons(c1), Lval::Cons(c2)) => Rc::ptr_eq(c1, c2),
+        // a quoted list is the same list as its cells
+        (Lval::Qexpr(q1), Lval::Qexpr(q2)) => matches!((q1.as_slice(), q2.as_slice()), ([c1 @ Lval::Cons(_)], [c2 @ Lval::Cons(_)]) if is_eq(c1, c2)),
+        (Lval::Qexpr(q), c @ Lval::Cons(_)) | (c @ Lval::Cons(_), Lval::Qexpr(q)) => matches!(q.as_slice(), [q] if is_eq(q, c)),
         // For composite objects (Sexpr, Qexpr) and others, eq checks identity.
         // Since values are cloned from environment, they are distinct objects.
-        _ => Lval::NIL,
+        _ => false,
     }
 }
 
-pub fn builtin_equal(e: Rc<RefCell<Lenv>>, args: Vec<Lval>) -> Lval {
+/// (type-of x) names the type of a value: integer, string, character, symbol, keyword,
+/// cons, null, boolean, function, hash-table, vector, array, error, or the
+/// name of a structure's type or an instance's class
+pub fn builtin_type_of(_e: Rc<RefCell<Lenv>>, args: Vec<Lval>) -> Lval {
+    if args.len() != 1 { return Lval::Err("Expected 1 arg".to_string()); }
+    Lval::Sym(type_of(&args[0]))
+}
+
+/// the name type-of gives a value's type
+pub fn type_of(v: &Lval) -> Symbol {
+    let name = match v {
+        Lval::Num(_) => "integer",
+        Lval::Str(_) => "string",
+        Lval::Char(_) => "character",
+        Lval::Sym(s) if s.is_keyword() => "keyword",
+        Lval::Sym(_) => "symbol",
+        v if v.list_items().is_some_and(|items| items.is_empty()) => "null",
+        Lval::Sexpr(_) | Lval::Cons(_) => "cons",
+        // a quoted symbol or list is its datum
+        Lval::Qexpr(cells) if cells.len() == 1 => return type_of(&cells[0]),
+        Lval::Qexpr(_) => "cons",
+        Lval::T => "boolean",
+        Lval::NIL => "null",
+        Lval::Fun(_) | Lval::Lambda(..) | Lval::StructFn(..) | Lval::Generic(_) => "function",
+        Lval::Hash(_) => "hash-table",
+        Lval::Array(array) if array.borrow().dims().len() == 1 => "vector",
+        Lval::Array(_) => "array",
+        Lval::Struct(record) => return record.borrow().kind.name,
+        Lval::Object(instance) => return instance.borrow().class.name,
+        Lval::Err(_) => "error",
+        Lval::Void => "null",
+    };
+    Symbol::intern(name)
+}
+
+pub fn builtin_equal(_e: Rc<RefCell<Lenv>>, args: Vec<Lval>) -> Lval {
     if args.len() != 2 { return Lval::Err("Expected 2 args".to_string()); }
     let mut iter = args.into_iter();
     let a = iter.next().unwrap();
@@ -419,6 +582,10 @@ pub fn builtin_null(_e: Rc<RefCell<Lenv>>, args: Vec<Lval>) -> Lval {
 
 pub fn builtin_cond(e: Rc<RefCell<Lenv>>, args: Vec<Lval>) -> Lval {
     for arg in args {
+        let arg = match cons::code(&arg) {
+            Ok(code) => code,
+            Err(err) => return err,
+        };
         let mut cells = match arg {
             Lval::Qexpr(c) => c,
             Lval::Sexpr(c) => c,
@@ -440,11 +607,7 @@ pub fn builtin_cond(e: Rc<RefCell<Lenv>>, args: Vec<Lval>) -> Lval {
         if let Lval::Err(_) = res { return res; }
         
         // checks truth of evaluated expression
-        let is_true = match res {
-            Lval::NIL => false,
-            Lval::Num(0) => false,
-            _ => true,
-        };
+        let is_true = !matches!(res, Lval::NIL | Lval::Num(0));
         
         // if the condition is true then execute body
         if is_true {
@@ -457,13 +620,19 @@ pub fn builtin_cond(e: Rc<RefCell<Lenv>>, args: Vec<Lval>) -> Lval {
 
 pub fn builtin_quote(_e: Rc<RefCell<Lenv>>, args: Vec<Lval>) -> Lval {
     if args.len() != 2 { return Lval::Err("Function 'quote' passed incorrect number of arguments.".to_string()); }
-    return Lval::Qexpr(vec![args[1].clone()])
+    cons::quoted(&Lval::Qexpr(vec![args[1].clone()]))
 }
 
+/// (print v ...) and (prin1 v ...) write values so the reader can read them back
 pub fn builtin_print(_e: Rc<RefCell<Lenv>>, args: Vec<Lval>) -> Lval {
-    for (i, arg) in args.iter().enumerate() {
-        if i > 0 { print!(" "); }
-        print!("{}", arg);
-    }
+    let shown: Vec<String> = args.iter().map(|a| a.to_string()).collect();
+    write_output(&shown.join(" "));
     Lval::Void
-}
\ No newline at end of file
+}
+
+/// (princ v ...) writes values for people: strings without quotes
+pub fn builtin_princ(_e: Rc<RefCell<Lenv>>, args: Vec<Lval>) -> Lval {
+    let shown: Vec<String> = args.iter().map(|a| Princ(a).to_string()).collect();
+    write_output(&shown.join(" "));
+    Lval::Void
+}
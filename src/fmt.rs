@@ -0,0 +1,144 @@
+// `rustlisp fmt`: reformats source files in place, or standard input to
+// standard output when no files are given. With --check nothing is written
+// and the exit status says whether every file was already formatted.
+
+use rustlisp::format::{format_source, DEFAULT_WIDTH};
+use std::fs;
+use std::io::{self, Read};
+
+pub const USAGE: &str = "\
+Usage: rustlisp fmt [--check] [--width N] [FILE...]
+
+Options:
+  --check          report files that would change instead of rewriting them
+  --width N        fit lines in N columns (default 80)";
+
+/// runs the command on the arguments after `fmt`, returning the exit status
+pub fn run(args: impl IntoIterator<Item = String>) -> i32 {
+    let mut check = false;
+    let mut width = DEFAULT_WIDTH;
+    let mut files = Vec::new();
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--check" => check = true,
+            "--width" => match args.next().and_then(|w| w.parse::<usize>().ok()) {
+                Some(w) if w > 0 => width = w,
+                _ => {
+                    eprintln!("Error: option '--width' needs a positive number\n\n{}", USAGE);
+                    return 2;
+                },
+            },
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                return 0;
+            },
+            _ if arg.starts_with('-') => {
+                eprintln!("Error: unknown option '{}'\n\n{}", arg, USAGE);
+                return 2;
+            },
+            _ => files.push(arg),
+        }
+    }
+
+    if files.is_empty() {
+        let mut src = String::new();
+        if let Err(e) = io::stdin().read_to_string(&mut src) {
+            eprintln!("Error: {}", e);
+            return 1;
+        }
+        return match format_source(&src, width) {
+            Ok(formatted) if check => i32::from(formatted != src),
+            Ok(formatted) => {
+                print!("{}", formatted);
+                0
+            },
+            Err(e) => {
+                eprintln!("Error: <stdin>: {}", e);
+                1
+            },
+        };
+    }
+
+    let mut status = 0;
+    for file in &files {
+        let formatted = fs::read_to_string(file)
+            .map_err(|e| e.to_string())
+            .and_then(|src| format_source(&src, width).map(|f| (f != src, f)));
+        match formatted {
+            Ok((false, _)) => {},
+            Ok((true, _)) if check => {
+                println!("{} is not formatted", file);
+                status = 1;
+            },
+            Ok((true, text)) => {
+                if let Err(e) = fs::write(file, text) {
+                    eprintln!("Error: {}: {}", file, e);
+                    status = 1;
+                }
+            },
+            Err(e) => {
+                eprintln!("Error: {}: {}", file, e);
+                status = 1;
+            },
+        }
+    }
+    status
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// a file in the temp directory holding `text`, named for the test using it
+    fn temp_file(name: &str, text: &str) -> String {
+        let path = std::env::temp_dir().join(format!("rustlisp-fmt-{}-{}.lisp", name, std::process::id()));
+        fs::write(&path, text).unwrap();
+        path.to_str().unwrap().to_string()
+    }
+
+    fn fmt(args: &[&str]) -> i32 {
+        run(args.iter().map(|a| a.to_string()))
+    }
+
+    #[test]
+    fn check_reports_unformatted_files_without_writing() {
+        let formatted = temp_file("formatted", "(a)\n\n(b)\n");
+        let messy = temp_file("messy", "(a)\n\n\n(b)");
+        assert_eq!(fmt(&["--check", &formatted]), 0);
+        assert_eq!(fmt(&["--check", &messy]), 1);
+        assert_eq!(fmt(&["--check", &formatted, &messy]), 1);
+        assert_eq!(fs::read_to_string(&messy).unwrap(), "(a)\n\n\n(b)");
+
+        assert_eq!(fmt(&[&messy]), 0);
+        assert_eq!(fs::read_to_string(&messy).unwrap(), "(a)\n\n(b)\n");
+        assert_eq!(fmt(&["--check", &messy]), 0);
+        fs::remove_file(formatted).unwrap();
+        fs::remove_file(messy).unwrap();
+    }
+
+    #[test]
+    fn width_changes_what_is_formatted() {
+        let file = temp_file("width", "(+ first-argument second-argument)\n");
+        assert_eq!(fmt(&["--check", &file]), 0);
+        assert_eq!(fmt(&["--check", "--width", "20", &file]), 1);
+        assert_eq!(fmt(&["--width", "20", &file]), 0);
+        assert_eq!(fs::read_to_string(&file).unwrap(), "(+ first-argument\n   second-argument)\n");
+        fs::remove_file(file).unwrap();
+    }
+
+    #[test]
+    fn errors_set_the_exit_status() {
+        let broken = temp_file("broken", "(a");
+        assert_eq!(fmt(&["--check", &broken]), 1);
+        assert_eq!(fmt(&[&broken]), 1);
+        assert_eq!(fs::read_to_string(&broken).unwrap(), "(a");
+        assert_eq!(fmt(&["--check", "/nonexistent/file.lisp"]), 1);
+        fs::remove_file(broken).unwrap();
+
+        assert_eq!(fmt(&["--width", "0"]), 2);
+        assert_eq!(fmt(&["--width"]), 2);
+        assert_eq!(fmt(&["--verbose"]), 2);
+        assert_eq!(fmt(&["--help"]), 0);
+    }
+}
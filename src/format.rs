@@ -0,0 +1,285 @@
+// Width-aware pretty printing. Source files are parsed into a concrete syntax
+// tree (cst.pest) that keeps ;; comments and blank lines, so `rustlisp fmt`
+// can reformat them losslessly; (pprint value) renders data through the same
+// layout by converting the Lval into that tree.
+//
+// A list is printed on one line when it fits. Otherwise the first few
+// elements stay on the opening line and the rest go one per line: bodies of
+// defun-like forms are indented by two, other calls line up under their
+// first argument, and data lists line up under their first element.
+
+use crate::cons;
+use crate::io::write_output;
+use crate::types::{Lval, Lenv};
+use pest::iterators::Pair;
+use pest::Parser;
+use std::cell::RefCell;
+use std::rc::Rc;
+
+#[derive(Parser)]
+#[grammar = "grammar.pest"]
+#[grammar = "cst.pest"]
+struct CstParser;
+
+/// columns a formatted line should fit in
+pub const DEFAULT_WIDTH: usize = 80;
+
+/// forms whose first `n` elements stay on the opening line and whose body is indented by two
+const BODY_FORMS: &[(&str, usize)] = &[
+    ("defun", 3),
+    ("defmacro", 3),
+    ("defclass", 3),
+    ("defgeneric", 3),
+    ("defmethod", 3),
+    ("\\", 2),
+    ("lambda", 2),
+    ("let", 2),
+    ("let*", 2),
+    ("when", 2),
+    ("unless", 2),
+    ("dolist", 2),
+];
+
+/// concrete syntax tree node
+#[derive(Clone, Debug, PartialEq)]
+pub enum Cst {
+    /// a number, symbol, string or #S(...) literal exactly as written
+    Atom(String),
+    List(Vec<Cst>),
+    /// a quoted datum, with any ;; comments between the quote and the datum
+    Quote(Vec<String>, Box<Cst>),
+    /// a ;; comment, without the line break that ends it
+    Comment(String),
+    Newline,
+}
+
+impl Cst {
+    fn from_pair(pair: Pair<Rule>) -> Cst {
+        match pair.as_rule() {
+            Rule::list => Cst::List(pair.into_inner().map(Cst::from_pair).collect()),
+            Rule::quoted => {
+                let mut comments = Vec::new();
+                let mut datum = None;
+                for child in pair.into_inner().map(Cst::from_pair) {
+                    match child {
+                        Cst::Comment(text) => comments.push(text),
+                        Cst::Newline => {},
+                        other => datum = Some(other),
+                    }
+                }
+                Cst::Quote(comments, Box::new(datum.unwrap()))
+            },
+            Rule::comment => Cst::Comment(pair.as_str().trim_end().to_string()),
+            Rule::newline => Cst::Newline,
+            _ => Cst::Atom(pair.as_str().to_string()),
+        }
+    }
+
+    /// the tree pprint lays out for a value
+    pub fn from_lval(v: &Lval) -> Cst {
+        match v {
+            Lval::Sexpr(cells) => Cst::List(cells.iter().map(Cst::from_lval).collect()),
+            Lval::Qexpr(cells) if cells.len() == 1 => Cst::Quote(Vec::new(), Box::new(Cst::from_lval(&cells[0]))),
+            Lval::Qexpr(cells) => Cst::Quote(Vec::new(), Box::new(Cst::List(cells.iter().map(Cst::from_lval).collect()))),
+            // a dotted or circular list is laid out on one line as it prints
+            Lval::Cons(_) => match cons::code(v) {
+                Ok(list) => Cst::from_lval(&list),
+                Err(_) => Cst::Atom(v.to_string()),
+            },
+            other => Cst::Atom(other.to_string()),
+        }
+    }
+}
+
+/// parses source text, keeping comments and line breaks
+pub fn parse_cst(src: &str) -> Result<Vec<Cst>, String> {
+    let mut pairs = CstParser::parse(Rule::cst, src).map_err(|e| e.to_string())?;
+    Ok(pairs.next().unwrap().into_inner()
+        .filter(|pair| pair.as_rule() != Rule::EOI)
+        .map(Cst::from_pair)
+        .collect())
+}
+
+/// reformats source text; every comment is kept and runs of blank lines become one
+pub fn format_source(src: &str, width: usize) -> Result<String, String> {
+    let mut out = String::new();
+    for (i, item) in items(&parse_cst(src)?).iter().enumerate() {
+        if i > 0 {
+            out.push('\n');
+            if item.blank_before { out.push('\n'); }
+        }
+        out.push_str(&render(item.node, 0, width));
+        if let Some(comment) = item.comment {
+            out.push(' ');
+            out.push_str(comment);
+        }
+    }
+    if !out.is_empty() { out.push('\n'); }
+    Ok(out)
+}
+
+/// lays out a value over several lines when it does not fit in `width`
+pub fn pretty(v: &Lval, width: usize) -> String {
+    render(&Cst::from_lval(v), 0, width)
+}
+
+/// (pprint value [width]) prints a value laid out to fit the width, 80 by default
+pub fn builtin_pprint(_e: Rc<RefCell<Lenv>>, args: Vec<Lval>) -> Lval {
+    let width = match args.as_slice() {
+        [_] => DEFAULT_WIDTH,
+        [_, Lval::Num(n)] if *n > 0 => *n as usize,
+        [_, _] => return Lval::Err("Width given to pprint must be a positive number".to_string()),
+        _ => return Lval::Err("Function 'pprint' passed incorrect number of arguments.".to_string()),
+    };
+    write_output(&format!("{}\n", pretty(&args[0], width)));
+    Lval::Void
+}
+
+/// an element of a list or file with the comments around it
+struct Item<'a> {
+    /// a datum, or a comment on its own line
+    node: &'a Cst,
+    /// a comment later on the same line as the datum
+    comment: Option<&'a str>,
+    /// the source had a blank line before this item
+    blank_before: bool,
+}
+
+impl Item<'_> {
+    fn ends_line(&self) -> bool {
+        self.comment.is_some() || matches!(self.node, Cst::Comment(_))
+    }
+}
+
+/// groups list children (or top level nodes) into items
+fn items(children: &[Cst]) -> Vec<Item<'_>> {
+    let mut items: Vec<Item> = Vec::new();
+    let mut newlines = 0;
+    for child in children {
+        match child {
+            Cst::Newline => newlines += 1,
+            Cst::Comment(text) => {
+                match items.last_mut() {
+                    Some(last) if newlines == 0 && !last.ends_line() => last.comment = Some(text),
+                    _ => items.push(Item { node: child, comment: None, blank_before: newlines > 1 && !items.is_empty() }),
+                }
+                newlines = 0;
+            },
+            _ => {
+                items.push(Item { node: child, comment: None, blank_before: newlines > 1 && !items.is_empty() });
+                newlines = 0;
+            },
+        }
+    }
+    items
+}
+
+/// the node on one line, or None when a comment forces line breaks
+fn flat(node: &Cst) -> Option<String> {
+    match node {
+        Cst::Atom(s) => Some(s.clone()),
+        Cst::Quote(comments, _) if !comments.is_empty() => None,
+        Cst::Quote(_, inner) => flat(inner).map(|s| format!("'{}", s)),
+        Cst::List(children) => {
+            let mut parts = Vec::new();
+            for child in children {
+                match child {
+                    Cst::Newline => {},
+                    Cst::Comment(_) => return None,
+                    _ => parts.push(flat(child)?),
+                }
+            }
+            Some(format!("({})", parts.join(" ")))
+        },
+        Cst::Comment(text) => Some(text.clone()),
+        Cst::Newline => Some(String::new()),
+    }
+}
+
+fn width_of(s: &str) -> usize {
+    s.chars().count()
+}
+
+/// the column after the last character of `text` when it starts at `col`
+fn end_column(text: &str, col: usize) -> usize {
+    match text.rfind('\n') {
+        Some(i) => width_of(&text[i + 1..]),
+        None => col + width_of(text),
+    }
+}
+
+/// renders a node starting at column `col`; lines after the first carry their own indentation
+fn render(node: &Cst, col: usize, width: usize) -> String {
+    if let Some(line) = flat(node) {
+        if col + width_of(&line) <= width { return line; }
+    }
+    match node {
+        // each comment ends its line, and the datum goes under the first one
+        Cst::Quote(comments, inner) => {
+            let mut out = String::from("'");
+            for comment in comments {
+                out.push(' ');
+                out.push_str(comment);
+                out.push('\n');
+                out.push_str(&" ".repeat(col + 1));
+            }
+            out.push_str(&render(inner, col + 1, width));
+            out
+        },
+        Cst::List(children) => render_list(children, col, width),
+        _ => flat(node).unwrap_or_default(),
+    }
+}
+
+fn render_list(children: &[Cst], col: usize, width: usize) -> String {
+    let items = items(children);
+    let head = match items.first().map(|i| i.node) {
+        Some(Cst::Atom(s)) => Some(s.as_str()),
+        _ => None,
+    };
+    // how many items share the opening line, and where the rest are indented
+    let (inline, body_col) = match head {
+        Some(h) => match BODY_FORMS.iter().find(|(name, _)| *name == h) {
+            Some((_, n)) => (*n, col + 2),
+            // a call lines its arguments up under the first one unless that is far to the right
+            None if col + 2 + width_of(h) <= width / 2 => (2, col + 2 + width_of(h)),
+            None => (1, col + 2),
+        },
+        None => (1, col + 1),
+    };
+
+    let mut out = String::from("(");
+    let mut cur = col + 1;
+    let mut same_line = true;
+    for (i, item) in items.iter().enumerate() {
+        if i < inline && same_line && !matches!(item.node, Cst::Comment(_)) {
+            if i > 0 {
+                out.push(' ');
+                cur += 1;
+            }
+        } else {
+            out.push('\n');
+            if item.blank_before { out.push('\n'); }
+            out.push_str(&" ".repeat(body_col));
+            cur = body_col;
+            same_line = false;
+        }
+        let text = render(item.node, cur, width);
+        // a multi-line element ends the opening line
+        if text.contains('\n') { same_line = false; }
+        cur = end_column(&text, cur);
+        out.push_str(&text);
+        if let Some(comment) = item.comment {
+            out.push(' ');
+            out.push_str(comment);
+            same_line = false;
+        }
+    }
+    // a closing paren after a comment would be commented out
+    if items.last().is_some_and(|i| i.ends_line()) {
+        out.push('\n');
+        out.push_str(&" ".repeat(body_col));
+    }
+    out.push(')');
+    out
+}
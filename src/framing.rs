@@ -0,0 +1,31 @@
+// Content-Length framing shared by the DAP and LSP servers: each message is
+// a JSON body preceded by a `Content-Length: N` header and a blank line.
+
+use serde_json::Value;
+use std::io::{self, BufRead, Write};
+
+/// the next message, or None at end of input
+pub fn read_message(input: &mut dyn BufRead) -> io::Result<Option<Value>> {
+    let mut length = None;
+    loop {
+        let mut header = String::new();
+        if input.read_line(&mut header)? == 0 { return Ok(None); }
+        let header = header.trim();
+        if header.is_empty() { break; }
+        if let Some((name, value)) = header.split_once(':') {
+            if name.eq_ignore_ascii_case("Content-Length") {
+                length = value.trim().parse::<usize>().ok();
+            }
+        }
+    }
+    let length = length.ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "missing Content-Length"))?;
+    let mut body = vec![0; length];
+    input.read_exact(&mut body)?;
+    serde_json::from_slice(&body).map(Some).map_err(io::Error::from)
+}
+
+pub fn write_message(output: &mut dyn Write, message: &Value) -> io::Result<()> {
+    let body = message.to_string();
+    write!(output, "Content-Length: {}\r\n\r\n{}", body.len(), body)?;
+    output.flush()
+}
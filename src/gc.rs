@@ -0,0 +1,243 @@
+// The heap of shared, mutable objects: environments made for function calls
+// and data that is changed in place, such as cons cells, hash tables, arrays,
+// structures, instances and generic functions. Each lives in an
+// Rc<RefCell<_>> that is registered here when it is allocated.
+// Reference counting frees most of them, but not a cycle, such as a closure
+// stored in the environment it captured or a list whose last cdr is its
+// first cell.
+//
+// collect finds those cycles by mark and sweep. Its roots come from the
+// reference counts: an object with more strong references than the other
+// registered objects hold to it is referenced from outside the heap (a
+// Rust local, an evaluation in progress, the REPL's global environment), so
+// it is live. Everything reachable from a root is marked, and each unmarked
+// object is cleared, which breaks its cycle and lets the counts free it.
+// Because roots never need to be listed, a collection is safe at any point
+// of an evaluation, and alloc runs one after enough allocations.
+//
+// A closure is not a heap object itself: it holds its captured environment
+// inline, and tracing it traces that environment's variables and parent, so
+// the objects it keeps alive are marked through whatever holds the closure.
+// Numbers, strings, symbols and characters, and the lists the reader builds
+// for code, are values rather than heap objects. They are copied rather than
+// shared and never changed in place, so they cannot form a cycle, and
+// dropping the last copy frees them.
+
+use crate::limits;
+use crate::symbol::Symbol;
+use crate::types::{Lenv, Lval};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::{Rc, Weak};
+
+/// allocations between automatic collections, at least
+const MIN_THRESHOLD: usize = 10_000;
+
+/// every kind of object on the heap, in the order room reports them
+pub const KINDS: &[&str] = &["environments", "conses", "hash-tables", "arrays", "structures", "instances", "generic-functions"];
+
+/// an object the heap can trace
+pub trait Trace {
+    /// reports every heap object this one holds a strong reference to
+    fn trace(&self, tracer: &mut Tracer);
+    /// drops everything the object holds, to break a cycle it is part of
+    fn clear(&mut self);
+    /// the bytes the object was made with, charged to the memory limit
+    fn bytes(&self) -> usize {
+        std::mem::size_of_val(self)
+    }
+}
+
+/// collects the addresses of the heap objects an object refers to
+#[derive(Default)]
+pub struct Tracer {
+    found: Vec<usize>,
+}
+
+impl Tracer {
+    pub fn value(&mut self, v: &Lval) {
+        match v {
+            Lval::Lambda(env, formals, body) => {
+                self.lenv(env);
+                self.value(formals);
+                self.value(body);
+            },
+            Lval::Sexpr(cells) | Lval::Qexpr(cells) => {
+                for c in cells { self.value(c); }
+            },
+            Lval::Cons(cell) => self.object(cell),
+            Lval::Hash(table) => self.object(table),
+            Lval::Array(array) => self.object(array),
+            Lval::Struct(record) => self.object(record),
+            Lval::Object(instance) => self.object(instance),
+            Lval::Generic(generic) => self.object(generic),
+            _ => {},
+        }
+    }
+
+    /// reports a reference to a heap object
+    pub fn object<T: ?Sized>(&mut self, object: &Rc<RefCell<T>>) {
+        self.found.push(address(object));
+    }
+
+    fn lenv(&mut self, env: &Lenv) {
+        for v in env.data.values() { self.value(v); }
+        if let Some(ref par) = env.par { self.object(par); }
+    }
+}
+
+impl Trace for Lenv {
+    fn trace(&self, tracer: &mut Tracer) {
+        tracer.lenv(self);
+    }
+
+    fn clear(&mut self) {
+        self.data.clear();
+        self.par = None;
+    }
+}
+
+struct Entry {
+    kind: &'static str,
+    object: Weak<RefCell<dyn Trace>>,
+}
+
+struct Heap {
+    entries: Vec<Entry>,
+    /// allocations since the last collection, and how many trigger the next
+    allocated: usize,
+    threshold: usize,
+    collections: u64,
+    freed: u64,
+}
+
+thread_local! {
+    static HEAP: RefCell<Heap> = const { RefCell::new(Heap {
+        entries: Vec::new(),
+        allocated: 0,
+        threshold: MIN_THRESHOLD,
+        collections: 0,
+        freed: 0,
+    }) };
+}
+
+/// what room reports
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct HeapStats {
+    /// live objects of each of the KINDS
+    pub live: Vec<(&'static str, usize)>,
+    pub collections: u64,
+    /// objects freed by all collections so far
+    pub freed: u64,
+}
+
+fn address<T: ?Sized>(object: &Rc<RefCell<T>>) -> usize {
+    Rc::as_ptr(object) as *const () as usize
+}
+
+/// moves `value` into the heap as an object of `kind`, collecting first when due
+pub fn alloc<T: Trace + 'static>(kind: &'static str, value: T) -> Rc<RefCell<T>> {
+    debug_assert!(KINDS.contains(&kind), "unknown heap kind {}", kind);
+    // call environments are left out: the depth limit bounds them, and each
+    // is freed when its call returns
+    if kind != "environments" { limits::charge(value.bytes()); }
+    let object = Rc::new(RefCell::new(value));
+    let weak = Rc::downgrade(&object);
+    let due = HEAP.with(|h| {
+        let mut h = h.borrow_mut();
+        h.entries.push(Entry { kind, object: weak });
+        h.allocated += 1;
+        h.allocated >= h.threshold
+    });
+    if due { collect(); }
+    object
+}
+
+/// an environment for a function call, the heap object allocated most often
+pub fn env(env: Lenv) -> Rc<RefCell<Lenv>> {
+    alloc("environments", env)
+}
+
+/// frees every object only reachable through a cycle, returning how many
+pub fn collect() -> usize {
+    let objects: Vec<Rc<RefCell<dyn Trace>>> = HEAP.with(|h| {
+        let mut h = h.borrow_mut();
+        h.entries.retain(|e| e.object.strong_count() > 0);
+        h.entries.iter().filter_map(|e| e.object.upgrade()).collect()
+    });
+    let index: HashMap<usize, usize> = objects.iter().enumerate().map(|(i, o)| (address(o), i)).collect();
+
+    // references from outside the heap: the strong count, less the one in
+    // `objects` and those held by other objects
+    let mut outside: Vec<isize> = objects.iter().map(|o| Rc::strong_count(o) as isize - 1).collect();
+    let mut children = Vec::with_capacity(objects.len());
+    for (i, o) in objects.iter().enumerate() {
+        let mut tracer = Tracer::default();
+        match o.try_borrow() {
+            Ok(o) => o.trace(&mut tracer),
+            // being changed right now, so in use
+            Err(_) => outside[i] += 1,
+        }
+        let found: Vec<usize> = tracer.found.iter().filter_map(|a| index.get(a).copied()).collect();
+        for &c in &found { outside[c] -= 1; }
+        children.push(found);
+    }
+
+    let mut marked = vec![false; objects.len()];
+    let mut pending: Vec<usize> = (0..objects.len()).filter(|&i| outside[i] > 0).collect();
+    while let Some(i) = pending.pop() {
+        if marked[i] { continue; }
+        marked[i] = true;
+        pending.extend(&children[i]);
+    }
+
+    // `objects` keeps every object alive while the garbage is cleared, and
+    // dropping it afterwards frees them
+    let mut freed = 0;
+    for (o, _) in objects.iter().zip(&marked).filter(|(_, &m)| !m) {
+        o.borrow_mut().clear();
+        freed += 1;
+    }
+    let survivors = objects.len() - freed;
+    drop(objects);
+
+    HEAP.with(|h| {
+        let mut h = h.borrow_mut();
+        h.entries.retain(|e| e.object.strong_count() > 0);
+        h.allocated = 0;
+        h.threshold = MIN_THRESHOLD.max(survivors);
+        h.collections += 1;
+        h.freed += freed as u64;
+    });
+    freed
+}
+
+pub fn stats() -> HeapStats {
+    HEAP.with(|h| {
+        let h = h.borrow();
+        let mut live: Vec<(&'static str, usize)> = KINDS.iter().map(|&k| (k, 0)).collect();
+        for e in h.entries.iter().filter(|e| e.object.strong_count() > 0) {
+            if let Some(count) = live.iter_mut().find(|(k, _)| *k == e.kind) { count.1 += 1; }
+        }
+        HeapStats { live, collections: h.collections, freed: h.freed }
+    })
+}
+
+/// (gc) collects now and returns the number of objects freed
+pub fn builtin_gc(_e: Rc<RefCell<Lenv>>, args: Vec<Lval>) -> Lval {
+    if !args.is_empty() { return Lval::Err("Function 'gc' passed incorrect number of arguments.".to_string()); }
+    Lval::Num(collect() as i64)
+}
+
+/// (room) returns the heap statistics as an alist: the live objects of each
+/// kind on the heap, then collections and freed. Numbers, strings and other
+/// values are not on the heap, so they are not counted
+pub fn builtin_room(_e: Rc<RefCell<Lenv>>, args: Vec<Lval>) -> Lval {
+    if !args.is_empty() { return Lval::Err("Function 'room' passed incorrect number of arguments.".to_string()); }
+    let stats = stats();
+    let entry = |name: &str, n: u64| Lval::Sexpr(vec![Lval::Sym(Symbol::intern(name)), Lval::Num(n as i64)]);
+    let mut entries: Vec<Lval> = stats.live.iter().map(|&(kind, n)| entry(kind, n as u64)).collect();
+    entries.push(entry("collections", stats.collections));
+    entries.push(entry("freed", stats.freed));
+    Lval::Sexpr(entries)
+}
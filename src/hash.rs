@@ -0,0 +1,311 @@
+// Hash tables. Lval::Hash holds a table on the gc heap that every copy of the
+// value shares, so a change made through one copy is seen through all of
+// them. Keys are compared with eq or equal, chosen when the table is made.
+// Entries keep the order they were added in, except that removing one moves
+// the last entry into its place.
+//
+// Tables print as #S(hash-table :test eq :data (key value ...)), which the
+// reader reads back as a new table with the same entries.
+
+use crate::cons;
+use crate::eval::{builtin_eq, builtin_equal, is_eq, lval_call, lval_eval};
+use crate::gc::{self, Trace, Tracer};
+use crate::limits::charge;
+use crate::types::{Builtin, Lenv, Lval};
+use std::cell::RefCell;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use std::mem::size_of;
+use std::ptr::fn_addr_eq;
+use std::rc::Rc;
+
+/// how a table compares keys
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Test {
+    Eq,
+    Equal,
+}
+
+impl Test {
+    pub fn name(self) -> &'static str {
+        match self {
+            Test::Eq => "eq",
+            Test::Equal => "equal",
+        }
+    }
+
+    fn from_name(name: &str) -> Option<Test> {
+        match name {
+            "eq" => Some(Test::Eq),
+            "equal" => Some(Test::Equal),
+            _ => None,
+        }
+    }
+
+    fn same(self, a: &Lval, b: &Lval) -> bool {
+        match self {
+            Test::Eq => is_eq(a, b),
+            Test::Equal => a == b,
+        }
+    }
+
+    fn hash(self, v: &Lval) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        self.hash_into(v, &mut hasher);
+        hasher.finish()
+    }
+
+    /// hashes the parts of a value the test compares, so that keys the test
+    /// finds the same hash the same
+    fn hash_into(self, v: &Lval, hasher: &mut DefaultHasher) {
+        // equal finds a list of cells the same as a list the reader built,
+        // so the two hash alike. A dotted or circular one is only equal to
+        // another chain of cells
+        if let (Test::Equal, Lval::Cons(_)) = (self, v) {
+            if let Some(items) = v.list_items() { return self.hash_into(&Lval::Sexpr(items.into_owned()), hasher); }
+        }
+        std::mem::discriminant(v).hash(hasher);
+        match v {
+            Lval::Num(n) => n.hash(hasher),
+            Lval::Sym(s) => s.hash(hasher),
+            Lval::Char(c) => c.hash(hasher),
+            Lval::Hash(table) => Rc::as_ptr(table).hash(hasher),
+            Lval::Array(array) => Rc::as_ptr(array).hash(hasher),
+            Lval::Struct(record) => Rc::as_ptr(record).hash(hasher),
+            Lval::Object(instance) => Rc::as_ptr(instance).hash(hasher),
+            Lval::Cons(cell) if self != Test::Equal => Rc::as_ptr(cell).hash(hasher),
+            Lval::Str(s) if self == Test::Equal => s.hash(hasher),
+            Lval::Sexpr(cells) | Lval::Qexpr(cells) if self == Test::Equal => {
+                for c in cells { self.hash_into(c, hasher); }
+            },
+            _ => {},
+        }
+    }
+}
+
+pub struct HashTable {
+    pub test: Test,
+    entries: Vec<(Lval, Lval)>,
+    /// positions in `entries` by the hash of their key
+    index: HashMap<u64, Vec<usize>>,
+}
+
+impl HashTable {
+    pub fn new(test: Test) -> Self {
+        HashTable { test, entries: Vec::new(), index: HashMap::new() }
+    }
+
+    fn position(&self, key: &Lval) -> Option<usize> {
+        let positions = self.index.get(&self.test.hash(key))?;
+        positions.iter().copied().find(|&i| self.test.same(&self.entries[i].0, key))
+    }
+
+    pub fn get(&self, key: &Lval) -> Option<&Lval> {
+        self.position(key).map(|i| &self.entries[i].1)
+    }
+
+    pub fn insert(&mut self, key: Lval, value: Lval) {
+        match self.position(&key) {
+            Some(i) => self.entries[i].1 = value,
+            None => {
+                charge(size_of::<(Lval, Lval)>());
+                self.index.entry(self.test.hash(&key)).or_default().push(self.entries.len());
+                self.entries.push((key, value));
+            },
+        }
+    }
+
+    /// removes the key's entry, returning false if there was none
+    pub fn remove(&mut self, key: &Lval) -> bool {
+        let Some(i) = self.position(key) else { return false };
+        let hash = self.test.hash(key);
+        let positions = self.index.get_mut(&hash).unwrap();
+        positions.retain(|&p| p != i);
+        if positions.is_empty() { self.index.remove(&hash); }
+
+        let last = self.entries.len() - 1;
+        if i != last {
+            let moved = self.test.hash(&self.entries[last].0);
+            for p in self.index.get_mut(&moved).unwrap().iter_mut().filter(|p| **p == last) {
+                *p = i;
+            }
+        }
+        self.entries.swap_remove(i);
+        true
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn entries(&self) -> &[(Lval, Lval)] {
+        &self.entries
+    }
+}
+
+impl Trace for HashTable {
+    fn trace(&self, tracer: &mut Tracer) {
+        for (k, v) in &self.entries {
+            tracer.value(k);
+            tracer.value(v);
+        }
+    }
+
+    fn clear(&mut self) {
+        self.entries.clear();
+        self.index.clear();
+    }
+}
+
+/// a new empty table on the heap
+pub fn new_table(test: Test) -> Lval {
+    Lval::Hash(gc::alloc("hash-tables", HashTable::new(test)))
+}
+
+/// the table read from #S(hash-table :test eq :data (key value ...)),
+/// given the elements after hash-table
+pub fn read_table(options: &[Lval]) -> Lval {
+    let malformed = || Lval::Err("Malformed hash table literal".to_string());
+    let (mut test, mut data) = (Test::Eq, &[][..]);
+    for pair in options.chunks(2) {
+        match pair {
+            [Lval::Sym(k), Lval::Sym(t)] if k.name() == ":test" => match Test::from_name(t.name()) {
+                Some(t) => test = t,
+                None => return malformed(),
+            },
+            [Lval::Sym(k), Lval::Sexpr(cells)] if k.name() == ":data" && cells.len() % 2 == 0 => data = cells,
+            _ => return malformed(),
+        }
+    }
+    let mut table = HashTable::new(test);
+    for entry in data.chunks(2) {
+        table.insert(entry[0].clone(), entry[1].clone());
+    }
+    Lval::Hash(gc::alloc("hash-tables", table))
+}
+
+/// the table in a builtin's argument
+pub fn table_arg(v: &Lval, func: &str) -> Result<Rc<RefCell<HashTable>>, Lval> {
+    match v {
+        Lval::Hash(table) => Ok(table.clone()),
+        _ => Err(Lval::Err(format!("Argument to {} must be a hash table", func))),
+    }
+}
+
+/// (make-hash-table [:test eq|equal]) returns a new empty table comparing keys
+/// with eq unless told otherwise. The test is the function eq or equal, or its name
+pub fn builtin_make_hash_table(_e: Rc<RefCell<Lenv>>, args: Vec<Lval>) -> Lval {
+    let mut test = Test::Eq;
+    for pair in args.chunks(2) {
+        let name = match pair {
+            [Lval::Sym(k), Lval::Fun(f)] if k.name() == ":test" => {
+                if fn_addr_eq(*f, builtin_eq as Builtin) {
+                    "eq"
+                } else if fn_addr_eq(*f, builtin_equal as Builtin) {
+                    "equal"
+                } else {
+                    ""
+                }
+            },
+            [Lval::Sym(k), Lval::Sym(t)] if k.name() == ":test" => t.name(),
+            [Lval::Sym(k), Lval::Qexpr(q)] if k.name() == ":test" => match q.as_slice() {
+                [Lval::Sym(t)] => t.name(),
+                _ => "",
+            },
+            [Lval::Sym(k), _] if k.is_keyword() => {
+                return Lval::Err(format!("Unknown keyword argument {} to make-hash-table", k));
+            },
+            _ => return Lval::Err("Function 'make-hash-table' passed incorrect arguments.".to_string()),
+        };
+        test = match Test::from_name(name) {
+            Some(t) => t,
+            None => return Lval::Err("Hash table test must be eq or equal".to_string()),
+        };
+    }
+    new_table(test)
+}
+
+/// (gethash key table [default]) is the key's value, or default (NIL) if it has none
+pub fn builtin_gethash(_e: Rc<RefCell<Lenv>>, args: Vec<Lval>) -> Lval {
+    if args.len() != 2 && args.len() != 3 { return Lval::Err("Function 'gethash' passed incorrect number of arguments.".to_string()); }
+    let table = match table_arg(&args[1], "gethash") {
+        Ok(table) => table,
+        Err(err) => return err,
+    };
+    let key = args[0].clone().unquote();
+    let found = table.borrow().get(&key).cloned();
+    found.unwrap_or_else(|| args.get(2).cloned().unwrap_or(Lval::NIL))
+}
+
+/// (puthash key value table) sets the key's value and returns it
+pub fn builtin_puthash(_e: Rc<RefCell<Lenv>>, args: Vec<Lval>) -> Lval {
+    if args.len() != 3 { return Lval::Err("Function 'puthash' passed incorrect number of arguments.".to_string()); }
+    let table = match table_arg(&args[2], "puthash") {
+        Ok(table) => table,
+        Err(err) => return err,
+    };
+    let mut args = args.into_iter();
+    let (key, value) = (args.next().unwrap().unquote(), args.next().unwrap());
+    table.borrow_mut().insert(key, value.clone().unquote());
+    value
+}
+
+/// (remhash key table) removes the key's entry, returning T if there was one
+pub fn builtin_remhash(_e: Rc<RefCell<Lenv>>, args: Vec<Lval>) -> Lval {
+    if args.len() != 2 { return Lval::Err("Function 'remhash' passed incorrect number of arguments.".to_string()); }
+    let table = match table_arg(&args[1], "remhash") {
+        Ok(table) => table,
+        Err(err) => return err,
+    };
+    let removed = table.borrow_mut().remove(&args[0].clone().unquote());
+    if removed { Lval::T } else { Lval::NIL }
+}
+
+/// (maphash f table) calls f with each key and its value and returns NIL.
+/// Entries added or removed by f do not change which entries are visited
+pub fn builtin_maphash(e: Rc<RefCell<Lenv>>, args: Vec<Lval>) -> Lval {
+    if args.len() != 2 { return Lval::Err("Function 'maphash' passed incorrect number of arguments.".to_string()); }
+    let table = match table_arg(&args[1], "maphash") {
+        Ok(table) => table,
+        Err(err) => return err,
+    };
+    let entries = table.borrow().entries().to_vec();
+    for (k, v) in entries {
+        if let err @ Lval::Err(_) = lval_call(e.clone(), args[0].clone(), vec![k, v]) { return err; }
+    }
+    Lval::NIL
+}
+
+/// (hash-table-count table) is the number of entries
+pub fn builtin_hash_table_count(_e: Rc<RefCell<Lenv>>, args: Vec<Lval>) -> Lval {
+    if args.len() != 1 { return Lval::Err("Function 'hash-table-count' passed incorrect number of arguments.".to_string()); }
+    match table_arg(&args[0], "hash-table-count") {
+        Ok(table) => Lval::Num(table.borrow().len() as i64),
+        Err(err) => err,
+    }
+}
+
+/// (hash-table-keys table) lists the keys in the table's order
+pub fn builtin_hash_table_keys(_e: Rc<RefCell<Lenv>>, args: Vec<Lval>) -> Lval {
+    if args.len() != 1 { return Lval::Err("Function 'hash-table-keys' passed incorrect number of arguments.".to_string()); }
+    match table_arg(&args[0], "hash-table-keys") {
+        Ok(table) => cons::list(table.borrow().entries().iter().map(|(k, _)| k.clone()).collect()),
+        Err(err) => err,
+    }
+}
+
+/// evaluates the key and table of a (gethash key table [default]) place
+pub fn place_args(e: &Rc<RefCell<Lenv>>, key: &Lval, table: &Lval) -> Result<(Lval, Rc<RefCell<HashTable>>), Lval> {
+    let key = match lval_eval(e.clone(), key.clone()) {
+        err @ Lval::Err(_) => return Err(err),
+        k => k.unquote(),
+    };
+    let table = lval_eval(e.clone(), table.clone());
+    if let Lval::Err(_) = table { return Err(table); }
+    Ok((key, table_arg(&table, "gethash")?))
+}
@@ -0,0 +1,108 @@
+// Builtins that reach outside the interpreter: files and the shell.
+// They are only installed by the io-read and full capability profiles.
+// Also holds the sink for program output (print and trace), which goes to
+// stdout unless an embedder such as the DAP server redirects it.
+
+use crate::eval::lval_eval;
+use crate::read_forms;
+use crate::types::{Lval, Lenv};
+use std::cell::RefCell;
+use std::fs;
+use std::io::Write;
+use std::process::Command;
+use std::rc::Rc;
+
+/// receives program output in place of stdout
+pub type OutputSink = Box<dyn FnMut(&str)>;
+
+thread_local! {
+    static OUTPUT: RefCell<Option<OutputSink>> = RefCell::new(None);
+}
+
+/// sends program output to `sink` instead of stdout; None restores stdout
+pub fn set_output(sink: Option<OutputSink>) {
+    OUTPUT.with(|o| *o.borrow_mut() = sink);
+}
+
+/// writes program output to the current sink
+pub fn write_output(text: &str) {
+    OUTPUT.with(|o| match *o.borrow_mut() {
+        Some(ref mut sink) => sink(text),
+        None => {
+            print!("{}", text);
+            let _ = std::io::stdout().flush();
+        },
+    });
+}
+
+/// pulls a string argument out of args, naming the function in the error
+fn string_arg<'a>(args: &'a [Lval], i: usize, func: &str) -> Result<&'a str, Lval> {
+    match args.get(i) {
+        Some(Lval::Str(s)) => Ok(s),
+        _ => Err(Lval::Err(format!("Argument {} to {} must be a string", i + 1, func))),
+    }
+}
+
+/// (load "file") evaluates every form in a file, stopping at the first error.
+/// The forms run inside the form calling load and share its limits budget, so
+/// loading a file cannot escape them; the REPL's :load gives each form its own
+pub fn builtin_load(e: Rc<RefCell<Lenv>>, args: Vec<Lval>) -> Lval {
+    if args.len() != 1 { return Lval::Err("Function 'load' passed incorrect number of arguments.".to_string()); }
+    let path = match string_arg(&args, 0, "load") {
+        Ok(p) => p,
+        Err(err) => return err,
+    };
+    let src = match fs::read_to_string(path) {
+        Ok(src) => src,
+        Err(err) => return Lval::Err(format!("load: cannot read '{}': {}", path, err)),
+    };
+    let forms = match read_forms(&src) {
+        Ok(forms) => forms,
+        Err(err) => return Lval::Err(format!("load: {}", err)),
+    };
+    for form in forms {
+        let result = lval_eval(e.clone(), form);
+        if let Lval::Err(_) = result { return result; }
+    }
+    Lval::T
+}
+
+/// (read-file "file") returns the contents of a file as a string
+pub fn builtin_read_file(_e: Rc<RefCell<Lenv>>, args: Vec<Lval>) -> Lval {
+    if args.len() != 1 { return Lval::Err("Function 'read-file' passed incorrect number of arguments.".to_string()); }
+    let path = match string_arg(&args, 0, "read-file") {
+        Ok(p) => p,
+        Err(err) => return err,
+    };
+    match fs::read_to_string(path) {
+        Ok(text) => Lval::string(text),
+        Err(err) => Lval::Err(format!("read-file: cannot read '{}': {}", path, err)),
+    }
+}
+
+/// (write-file "file" "text") replaces the contents of a file
+pub fn builtin_write_file(_e: Rc<RefCell<Lenv>>, args: Vec<Lval>) -> Lval {
+    if args.len() != 2 { return Lval::Err("Function 'write-file' passed incorrect number of arguments.".to_string()); }
+    let (path, text) = match (string_arg(&args, 0, "write-file"), string_arg(&args, 1, "write-file")) {
+        (Ok(p), Ok(t)) => (p, t),
+        (Err(err), _) | (_, Err(err)) => return err,
+    };
+    match fs::write(path, text) {
+        Ok(()) => Lval::T,
+        Err(err) => Lval::Err(format!("write-file: cannot write '{}': {}", path, err)),
+    }
+}
+
+/// (shell "command") runs a command with sh and returns its standard output
+pub fn builtin_shell(_e: Rc<RefCell<Lenv>>, args: Vec<Lval>) -> Lval {
+    if args.len() != 1 { return Lval::Err("Function 'shell' passed incorrect number of arguments.".to_string()); }
+    let cmd = match string_arg(&args, 0, "shell") {
+        Ok(c) => c,
+        Err(err) => return err,
+    };
+    match Command::new("sh").arg("-c").arg(cmd).output() {
+        Ok(out) if out.status.success() => Lval::string(String::from_utf8_lossy(&out.stdout).into_owned()),
+        Ok(out) => Lval::Err(format!("shell: command failed with {}: {}", out.status, String::from_utf8_lossy(&out.stderr).trim())),
+        Err(err) => Lval::Err(format!("shell: {}", err)),
+    }
+}
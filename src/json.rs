@@ -0,0 +1,241 @@
+// Bridges Lval and serde so Lisp programs can read and write JSON.
+//
+// Mapping between the two data models:
+//   JSON object  <->  alist of 2-element lists with symbol keys, e.g. ((name "x") (age 3))
+//   JSON array   <->  list
+//   JSON string  <->  Str
+//   JSON number  <->  Num (integers only; 2.0 reads as 2 and 1.5 is an error)
+//   true         <->  T
+//   false/null    ->  NIL, and NIL serializes as null
+//
+// Object keys become symbols so that `eq` works on them (as in my-assoc). A key
+// that would not read back as that symbol, such as "a b" or "12", stays a string.
+// A list of pairs is written as an object when at least one key is a symbol, so
+// a list of string/value pairs still round-trips as an array.
+//
+// Some JSON does not survive the trip back: {} reads as the empty list and is
+// written as [], false is written as null, and an object whose keys all stay
+// strings is written as an array of pairs.
+
+use crate::cons;
+use crate::limits::track_bytes;
+use crate::symbol::Symbol;
+use crate::{LispyParser, Rule};
+use pest::Parser;
+use crate::types::{Lval, Lenv};
+use serde::de::{self, Deserialize, Deserializer, MapAccess, SeqAccess, Visitor};
+use serde::ser::{self, Serialize, SerializeMap, SerializeSeq, Serializer};
+use std::cell::RefCell;
+use std::fmt;
+use std::rc::Rc;
+
+/// an alist is a non-empty list whose entries are all (key value) pairs, keyed
+/// by symbols or strings with at least one symbol among them
+fn is_alist(cells: &[Lval]) -> bool {
+    !cells.is_empty()
+        && cells.iter().all(|c| matches!(c.list_items().as_deref(), Some([Lval::Sym(_) | Lval::Str(_), _])))
+        && cells.iter().any(|c| matches!(c.list_items().as_deref(), Some([Lval::Sym(_), _])))
+}
+
+/// true when `k` is read as a symbol of that name, checked without interning it
+fn reads_as_symbol(k: &str) -> bool {
+    let Ok(mut pairs) = LispyParser::parse(Rule::expr, k) else { return false };
+    let Some(atom) = pairs.next().and_then(|expr| expr.into_inner().next()) else { return false };
+    atom.as_rule() == Rule::symbol && atom.as_str() == k && !matches!(k, "T" | "t" | "NIL" | "nil")
+}
+
+/// the alist key for an object key: a symbol when it reads back as one. A new
+/// symbol's name is charged to the memory limit, since it is never freed
+fn object_key<E: de::Error>(k: String) -> Result<Lval, E> {
+    if !reads_as_symbol(&k) { return Ok(Lval::Str(k)); }
+    match Symbol::intern_charged(&k) {
+        Ok(sym) => Ok(Lval::Sym(sym)),
+        Err(Lval::Err(message)) => Err(E::custom(message)),
+        Err(other) => Err(E::custom(other)),
+    }
+}
+
+impl Serialize for Lval {
+    fn serialize<S: Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+        // NIL is a list too, but serializes as null below
+        if let Some(cells) = self.list_items().filter(|_| !matches!(self, Lval::NIL)) {
+            if is_alist(&cells) {
+                let mut map = s.serialize_map(Some(cells.len()))?;
+                for entry in cells.iter() {
+                    match entry.list_items().as_deref() {
+                        Some([Lval::Sym(k), v]) => map.serialize_entry(k.name(), v)?,
+                        Some([Lval::Str(k), v]) => map.serialize_entry(k, v)?,
+                        _ => {},
+                    }
+                }
+                return map.end();
+            }
+            let mut seq = s.serialize_seq(Some(cells.len()))?;
+            for c in cells.iter() {
+                seq.serialize_element(c)?;
+            }
+            return seq.end();
+        }
+
+        match self {
+            Lval::Num(n) => s.serialize_i64(*n),
+            Lval::Sym(sym) => s.serialize_str(sym.name()),
+            Lval::Str(st) => s.serialize_str(st),
+            Lval::Char(c) => s.serialize_char(*c),
+            Lval::T => s.serialize_bool(true),
+            Lval::NIL => s.serialize_unit(),
+            // quoted atoms such as 'a serialize as the atom itself
+            Lval::Qexpr(cells) if cells.len() == 1 => cells[0].serialize(s),
+            Lval::Qexpr(cells) => {
+                let mut seq = s.serialize_seq(Some(cells.len()))?;
+                for c in cells {
+                    seq.serialize_element(c)?;
+                }
+                seq.end()
+            },
+            // a table is an object; keys that are not strings or symbols use their printed form
+            Lval::Hash(table) => {
+                let table = table.borrow();
+                let mut map = s.serialize_map(Some(table.len()))?;
+                for (k, v) in table.entries() {
+                    match k {
+                        Lval::Str(key) => map.serialize_entry(key, v)?,
+                        Lval::Sym(key) => map.serialize_entry(key.name(), v)?,
+                        other => map.serialize_entry(&other.to_string(), v)?,
+                    }
+                }
+                map.end()
+            },
+            // a vector is an array; other arrays nest one JSON array per dimension
+            Lval::Array(array) => {
+                let rows = array.borrow().rows();
+                let mut seq = s.serialize_seq(Some(rows.len()))?;
+                for row in &rows {
+                    seq.serialize_element(row)?;
+                }
+                seq.end()
+            },
+            Lval::Err(e) => Err(ser::Error::custom(format!("cannot serialize error '{}'", e))),
+            // structures and instances are objects of their slots
+            Lval::Struct(record) => {
+                let record = record.borrow();
+                let mut map = s.serialize_map(Some(record.kind.slots.len()))?;
+                for (slot, v) in record.slots() {
+                    map.serialize_entry(slot.name(), v)?;
+                }
+                map.end()
+            },
+            Lval::Object(instance) => {
+                let instance = instance.borrow();
+                let mut map = s.serialize_map(None)?;
+                for (slot, v) in instance.slots() {
+                    map.serialize_entry(slot.name(), v)?;
+                }
+                map.end()
+            },
+            Lval::Fun(_) | Lval::Lambda(..) | Lval::StructFn(..) | Lval::Generic(_) => Err(ser::Error::custom("cannot serialize a function")),
+            Lval::Void => s.serialize_unit(),
+            Lval::Sexpr(_) => unreachable!("lists are handled above"),
+            // proper lists of cells are handled above too
+            Lval::Cons(_) => Err(ser::Error::custom(format!("cannot serialize {}, which is dotted or circular", self))),
+        }
+    }
+}
+
+struct LvalVisitor;
+
+impl<'de> Visitor<'de> for LvalVisitor {
+    type Value = Lval;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "a JSON value representable as an Lval")
+    }
+
+    fn visit_bool<E: de::Error>(self, b: bool) -> Result<Lval, E> {
+        Ok(if b { Lval::T } else { Lval::NIL })
+    }
+
+    fn visit_i64<E: de::Error>(self, n: i64) -> Result<Lval, E> {
+        Ok(Lval::Num(n))
+    }
+
+    fn visit_u64<E: de::Error>(self, n: u64) -> Result<Lval, E> {
+        i64::try_from(n)
+            .map(Lval::Num)
+            .map_err(|_| E::custom(format!("number {} is out of range", n)))
+    }
+
+    fn visit_f64<E: de::Error>(self, n: f64) -> Result<Lval, E> {
+        if n.fract() == 0.0 && n >= i64::MIN as f64 && n <= i64::MAX as f64 {
+            Ok(Lval::Num(n as i64))
+        } else {
+            Err(E::custom(format!("floats are unsupported, got {}", n)))
+        }
+    }
+
+    fn visit_str<E: de::Error>(self, s: &str) -> Result<Lval, E> {
+        Ok(Lval::Str(s.to_string()))
+    }
+
+    fn visit_string<E: de::Error>(self, s: String) -> Result<Lval, E> {
+        Ok(Lval::Str(s))
+    }
+
+    fn visit_unit<E: de::Error>(self) -> Result<Lval, E> {
+        Ok(Lval::NIL)
+    }
+
+    fn visit_none<E: de::Error>(self) -> Result<Lval, E> {
+        Ok(Lval::NIL)
+    }
+
+    fn visit_some<D: Deserializer<'de>>(self, d: D) -> Result<Lval, D::Error> {
+        Lval::deserialize(d)
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Lval, A::Error> {
+        let mut cells = Vec::new();
+        while let Some(v) = seq.next_element::<Lval>()? {
+            cells.push(v);
+        }
+        Ok(cons::list(cells))
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Lval, A::Error> {
+        let mut entries = Vec::new();
+        while let Some((k, v)) = map.next_entry::<String, Lval>()? {
+            entries.push(cons::list(vec![object_key(k)?, v]));
+        }
+        Ok(cons::list(entries))
+    }
+}
+
+impl<'de> Deserialize<'de> for Lval {
+    fn deserialize<D: Deserializer<'de>>(d: D) -> Result<Lval, D::Error> {
+        d.deserialize_any(LvalVisitor)
+    }
+}
+
+/// (json-parse "text") reads a JSON document into Lisp data
+pub fn builtin_json_parse(_e: Rc<RefCell<Lenv>>, args: Vec<Lval>) -> Lval {
+    if args.len() != 1 { return Lval::Err("Function 'json-parse' passed incorrect number of arguments.".to_string()); }
+    let text = match &args[0] {
+        Lval::Str(s) => s,
+        _ => return Lval::Err("Argument to json-parse must be a string".to_string()),
+    };
+    // the parsed tree is roughly as large as its source text
+    if let Err(err) = track_bytes(text.len()) { return err; }
+    match serde_json::from_str::<Lval>(text) {
+        Ok(v) => v,
+        Err(err) => Lval::Err(format!("json-parse: {}", err)),
+    }
+}
+
+/// (json-stringify value) writes Lisp data as a JSON string
+pub fn builtin_json_stringify(_e: Rc<RefCell<Lenv>>, args: Vec<Lval>) -> Lval {
+    if args.len() != 1 { return Lval::Err("Function 'json-stringify' passed incorrect number of arguments.".to_string()); }
+    match serde_json::to_string(&args[0]) {
+        Ok(s) => Lval::string(s),
+        Err(err) => Lval::Err(format!("json-stringify: {}", err)),
+    }
+}
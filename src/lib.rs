@@ -0,0 +1,328 @@
+// Library entry point. Holds the reader, evaluator and builtins so the
+// interpreter can be embedded in other programs; main.rs runs the REPL on top of it.
+
+extern crate pest;
+#[macro_use]
+extern crate pest_derive;
+
+// Pest is a popular Parser Generator library. Turns raww text into a structured Abstract Syntax Tree (AST)
+use pest::Parser;
+use std::rc::Rc;
+use std::cell::RefCell;
+
+pub mod types;
+pub mod symbol;
+pub mod character;
+pub mod gc;
+pub mod cons;
+pub mod place;
+pub mod hash;
+pub mod array;
+pub mod sequence;
+pub mod record;
+pub mod object;
+pub mod eval;
+pub mod json;
+pub mod limits;
+pub mod capability;
+pub mod io;
+pub mod trace;
+pub mod debug;
+pub mod dap;
+pub mod lsp;
+pub mod format;
+pub mod ast;
+pub mod compile;
+pub mod vm;
+pub mod linter;
+mod framing;
+
+use types::{Builtin, Lval, Lenv};
+use capability::Profile;
+use eval::*;
+use json::*;
+use io::*;
+use format::builtin_pprint;
+use debug::builtin_break;
+use array::{builtin_aref, builtin_array_dimensions, builtin_make_array, builtin_vector, builtin_vector_push, builtin_vector_push_extend};
+use sequence::{builtin_length, builtin_mapcar, builtin_reduce};
+use character::{
+    builtin_alpha_char_p, builtin_char, builtin_char_code, builtin_char_downcase, builtin_char_eq, builtin_char_ge,
+    builtin_char_gt, builtin_char_le, builtin_char_lt, builtin_char_ne, builtin_char_upcase, builtin_code_char,
+    builtin_digit_char_p,
+};
+use object::{builtin_call_next_method, builtin_make_instance, builtin_slot_value};
+use hash::{builtin_gethash, builtin_make_hash_table, builtin_hash_table_count, builtin_hash_table_keys, builtin_maphash, builtin_puthash, builtin_remhash};
+use gc::{builtin_gc, builtin_room};
+use symbol::{builtin_gensym, builtin_intern, builtin_symbol_name, Symbol};
+use limits::{begin_evaluation, end_evaluation};
+
+// magic macro for pest
+// compiles code for parser at compile-time
+#[derive(Parser)]
+#[grammar = "grammar.pest"]
+pub struct LispyParser;
+
+/// converts pest parser output into lval for evaluation looks at the 
+/// rules generated by pest and creates the correct corresponding Lval type
+pub fn read(pair: pest::iterators::Pair<Rule>) -> Lval {
+    match pair.as_rule() {
+        // the grammar only matches digits, so the parse fails only when the number does not fit
+        Rule::number => match pair.as_str().parse::<i64>() {
+            Ok(n) => Lval::Num(n),
+            Err(_) => Lval::Err(format!("Integer {} is out of range", pair.as_str())),
+        },
+        Rule::symbol => match pair.as_str() {
+            // the constants read as themselves so printed values read back equal
+            "T" | "t" => Lval::T,
+            "NIL" | "nil" => Lval::NIL,
+            s => Lval::Sym(Symbol::intern(s)),
+        },
+        Rule::character => character::read_char(&pair.as_str()[2..]),
+        Rule::string => {
+            let s = pair.as_str();
+            Lval::Str(unescape(&s[1..s.len()-1]))
+        },
+        Rule::sexpr => {
+            let mut cells = Vec::new();
+            for inner_pair in pair.into_inner() {
+                cells.push(read(inner_pair));
+            }
+            Lval::Sexpr(cells)
+        },
+        Rule::qexpr => {
+            let inner_pair = pair.into_inner().next().unwrap();
+            let val = read(inner_pair);
+            Lval::Qexpr(vec![val])
+        },
+        // #S(name ...) literals, as tables print
+        Rule::structure => {
+            let cells: Vec<Lval> = pair.into_inner().map(read).collect();
+            match cells.split_first() {
+                Some((Lval::Sym(name), options)) if name.name() == "hash-table" => hash::read_table(options),
+                Some((Lval::Sym(name), options)) => record::read_record(*name, options),
+                _ => Lval::Err("Malformed structure literal".to_string()),
+            }
+        },
+        Rule::vector => array::read_vector(pair.into_inner().map(read).collect()),
+        Rule::array => {
+            let mut inner = pair.into_inner();
+            let prefix = inner.next().unwrap().as_str();
+            let rank = prefix[1..prefix.len() - 1].parse().unwrap_or(usize::MAX);
+            array::read_array(rank, &read(inner.next().unwrap()))
+        },
+        Rule::expr => {
+            read(pair.into_inner().next().unwrap())
+        },
+        Rule::lispy => {
+            Lval::Err("Should not call read on lispy rule directly".to_string())
+        },
+        _ => Lval::Err("Unknown rule".to_string()),
+    }
+}
+
+/// parses source text into its top level forms
+pub fn read_source(src: &str) -> Result<Vec<Lval>, String> {
+    read_forms(src).map(Iterator::collect)
+}
+
+/// parses source text, then reads its top level forms one at a time, so a
+/// form can use what the forms before it defined, as #S literals use defstruct
+pub fn read_forms(src: &str) -> Result<impl Iterator<Item = Lval> + '_, String> {
+    let mut pairs = LispyParser::parse(Rule::lispy, src).map_err(|e| e.to_string())?;
+    let lispy_pair = pairs.next().unwrap(); // lispy rule
+    Ok(lispy_pair.into_inner()
+        .filter(|pair| pair.as_rule() != Rule::EOI)
+        .map(read)) // convert parse tree to Lval using read
+}
+
+/// an s-expression in source text and where it starts
+pub struct Spanned {
+    pub form: Lval,
+    /// 1-based line and column of the opening paren
+    pub line: usize,
+    pub column: usize,
+    /// true for top level forms
+    pub top: bool,
+}
+
+/// parses source text into every s-expression it contains, outer forms
+/// before the forms nested in them, in source order
+pub fn read_spans(src: &str) -> Result<Vec<Spanned>, String> {
+    fn walk(pair: pest::iterators::Pair<Rule>, top: bool, out: &mut Vec<Spanned>) {
+        let top = match pair.as_rule() {
+            Rule::sexpr => {
+                let (line, column) = pair.as_span().start_pos().line_col();
+                out.push(Spanned { form: read(pair.clone()), line, column, top });
+                false
+            },
+            Rule::expr => top,
+            // a literal's contents are data, not forms
+            Rule::structure | Rule::vector | Rule::array => return,
+            _ => false,
+        };
+        for inner in pair.into_inner() {
+            walk(inner, top, out);
+        }
+    }
+
+    let mut pairs = LispyParser::parse(Rule::lispy, src).map_err(|e| e.to_string())?;
+    let mut spans = Vec::new();
+    for pair in pairs.next().unwrap().into_inner() {
+        walk(pair, true, &mut spans);
+    }
+    Ok(spans)
+}
+
+/// parses source text and evaluates each top level form in order,
+/// handing every result to `on_result`. Returns the parse error if the text is malformed
+pub fn eval_source(env: &Rc<RefCell<Lenv>>, src: &str, mut on_result: impl FnMut(Lval)) -> Result<(), String> {
+    let forms = read_forms(src)?;
+    clear_interrupt();
+    for lval in forms {
+        // each form gets its own step, depth and memory budget
+        begin_evaluation();
+
+        // evaluate Lval
+        on_result(end_evaluation(lval_eval(env.clone(), lval)));
+
+        // Ctrl-C abandons the rest of the input, not just the current form
+        if is_interrupted() { break; }
+    }
+    clear_interrupt();
+    // stepping never carries over into the next input
+    debug::reset();
+    Ok(())
+}
+
+/// resolves backslash escapes inside a string literal
+fn unescape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => out.push('\n'),
+            Some('t') => out.push('\t'),
+            Some(other) => out.push(other),
+            None => out.push('\\'),
+        }
+    }
+    out
+}
+
+
+/// every builtin with its argument list, shown as hints while typing a call,
+/// and the least capability profile that installs it
+pub const BUILTINS: &[(&str, &str, Builtin, Profile)] = &[
+    ("eval", "expr", builtin_eval, Profile::Pure),
+
+    ("+", "n &rest more", builtin_add, Profile::Pure),
+    ("-", "n &rest more", builtin_sub, Profile::Pure),
+    ("*", "n &rest more", builtin_mul, Profile::Pure),
+    ("/", "n &rest more", builtin_div, Profile::Pure),
+
+    ("def", "'sym value", builtin_def, Profile::Pure),
+    ("=", "'sym value", builtin_put, Profile::Pure),
+    ("set", "'sym value", builtin_put, Profile::Pure),
+    ("setq", "sym value", builtin_putq, Profile::Pure),
+    ("\\", "'formals 'body", builtin_lambda, Profile::Pure),
+
+    ("car", "list", builtin_head, Profile::Pure),
+    ("cdr", "list", builtin_tail, Profile::Pure),
+    ("cons", "x list", builtin_cons, Profile::Pure),
+    ("nth", "index list", builtin_nth, Profile::Pure),
+    ("defun", "name formals body", builtin_lambda, Profile::Pure),
+    ("eq", "a b", builtin_eq, Profile::Pure),
+    ("equal", "a b", builtin_equal, Profile::Pure),
+    ("type-of", "value", builtin_type_of, Profile::Pure),
+    ("neq", "a b", builtin_ne, Profile::Pure),
+    ("null", "x", builtin_null, Profile::Pure),
+    ("cond", "&rest (test body)", builtin_cond, Profile::Pure),
+    ("quote", "x", builtin_quote, Profile::Pure),
+    ("print", "&rest values", builtin_print, Profile::Pure),
+    ("prin1", "&rest values", builtin_print, Profile::Pure),
+    ("princ", "&rest values", builtin_princ, Profile::Pure),
+    ("pprint", "value &optional width", builtin_pprint, Profile::Pure),
+
+    ("intern", "name", builtin_intern, Profile::Pure),
+    ("symbol-name", "symbol", builtin_symbol_name, Profile::Pure),
+    ("gensym", "&optional prefix", builtin_gensym, Profile::Pure),
+
+    ("break", "", builtin_break, Profile::Pure),
+    ("gc", "", builtin_gc, Profile::Pure),
+    ("room", "", builtin_room, Profile::Pure),
+
+    ("make-hash-table", "&key test", builtin_make_hash_table, Profile::Pure),
+    ("gethash", "key table &optional default", builtin_gethash, Profile::Pure),
+    ("puthash", "key value table", builtin_puthash, Profile::Pure),
+    ("remhash", "key table", builtin_remhash, Profile::Pure),
+    ("maphash", "fn table", builtin_maphash, Profile::Pure),
+    ("hash-table-count", "table", builtin_hash_table_count, Profile::Pure),
+    ("hash-table-keys", "table", builtin_hash_table_keys, Profile::Pure),
+
+    ("make-array", "dims &key initial-element initial-contents fill-pointer", builtin_make_array, Profile::Pure),
+    ("vector", "&rest items", builtin_vector, Profile::Pure),
+    ("aref", "array &rest indices", builtin_aref, Profile::Pure),
+    ("array-dimensions", "array", builtin_array_dimensions, Profile::Pure),
+    ("vector-push", "item vector", builtin_vector_push, Profile::Pure),
+    ("vector-push-extend", "item vector", builtin_vector_push_extend, Profile::Pure),
+    ("length", "sequence", builtin_length, Profile::Pure),
+    ("mapcar", "fn sequence &rest more", builtin_mapcar, Profile::Pure),
+    ("reduce", "fn sequence &key initial-value", builtin_reduce, Profile::Pure),
+
+    ("char", "string index", builtin_char, Profile::Pure),
+    ("char-code", "char", builtin_char_code, Profile::Pure),
+    ("code-char", "code", builtin_code_char, Profile::Pure),
+    ("char-upcase", "char", builtin_char_upcase, Profile::Pure),
+    ("char-downcase", "char", builtin_char_downcase, Profile::Pure),
+    ("alpha-char-p", "char", builtin_alpha_char_p, Profile::Pure),
+    ("digit-char-p", "char &optional radix", builtin_digit_char_p, Profile::Pure),
+    ("char=", "&rest chars", builtin_char_eq, Profile::Pure),
+    ("char/=", "&rest chars", builtin_char_ne, Profile::Pure),
+    ("char<", "&rest chars", builtin_char_lt, Profile::Pure),
+    ("char>", "&rest chars", builtin_char_gt, Profile::Pure),
+    ("char<=", "&rest chars", builtin_char_le, Profile::Pure),
+    ("char>=", "&rest chars", builtin_char_ge, Profile::Pure),
+
+    ("make-instance", "class &rest initargs", builtin_make_instance, Profile::Pure),
+    ("slot-value", "instance slot", builtin_slot_value, Profile::Pure),
+    ("call-next-method", "&rest args", builtin_call_next_method, Profile::Pure),
+
+    ("json-parse", "string", builtin_json_parse, Profile::Pure),
+    ("json-stringify", "value", builtin_json_stringify, Profile::Pure),
+
+    ("load", "file", builtin_load, Profile::IoRead),
+    ("read-file", "file", builtin_read_file, Profile::IoRead),
+    ("write-file", "file text", builtin_write_file, Profile::Full),
+    ("shell", "command", builtin_shell, Profile::Full),
+];
+
+/// the profile a builtin needs, or None if no builtin has that name
+pub fn builtin_profile(name: &str) -> Option<Profile> {
+    BUILTINS.iter().find(|(n, _, _, _)| *n == name).map(|(_, _, _, p)| *p)
+}
+
+/// argument list of a special form or builtin
+pub fn builtin_signature(name: &str) -> Option<&'static str> {
+    let special = SPECIAL_FORMS.iter().find(|(n, _, _)| *n == name).map(|(_, args, _)| *args);
+    special.or_else(|| BUILTINS.iter().find(|(n, _, _, _)| *n == name).map(|(_, args, _, _)| *args))
+}
+
+// Registers bult-in functions 
+// by getting exclusive wwrite acces to the environment (lenv)
+pub fn add_builtins(e: Rc<RefCell<Lenv>>) {
+    add_builtins_with(e, Profile::Full);
+}
+
+/// registers only the builtins allowed by `profile`
+pub fn add_builtins_with(e: Rc<RefCell<Lenv>>, profile: Profile) {
+    for (name, _, func, required) in BUILTINS {
+        if *required <= profile {
+            e.borrow_mut().put(Symbol::intern(name), Lval::Fun(*func));
+        }
+    }
+}
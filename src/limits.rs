@@ -0,0 +1,163 @@
+// Resource limits for running untrusted code. Every top level form gets a
+// budget of evaluation steps, nested calls and (approximate) memory. Running
+// out produces an ordinary Lval::Err, so the REPL or an embedding program can
+// report it and carry on with the next form. Forms evaluated from inside
+// another, such as those of a file read by (load ...), count against the
+// budget of the form that started them.
+//
+// Memory is charged where data is made rather than by each builtin: gc::alloc
+// charges every object it puts on the heap (cons cells, tables, arrays,
+// structures, instances), a table charges each entry it adds, and
+// Lval::string charges each string built at run time. Those cannot return an
+// error, so the next evaluation step fails once the budget is spent, as does
+// a form whose last call spent it.
+
+use crate::types::Lval;
+use std::cell::Cell;
+use std::mem::size_of;
+
+/// default cap on nested lambda calls. Each level uses a few KB of Rust stack,
+/// so embedders raising this should run the interpreter on a thread with a large stack
+pub const DEFAULT_MAX_DEPTH: usize = 10_000;
+
+/// caps applied to each top level evaluation. None means unlimited
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Limits {
+    /// maximum number of lval_eval steps
+    pub max_steps: Option<u64>,
+    /// maximum number of nested lambda calls
+    pub max_depth: Option<usize>,
+    /// approximate number of bytes of heap objects and strings that may be built
+    pub max_memory: Option<usize>,
+}
+
+impl Limits {
+    pub const fn unlimited() -> Self {
+        Limits { max_steps: None, max_depth: None, max_memory: None }
+    }
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Limits { max_depth: Some(DEFAULT_MAX_DEPTH), ..Limits::unlimited() }
+    }
+}
+
+thread_local! {
+    static LIMITS: Cell<Limits> = const { Cell::new(Limits {
+        max_steps: None,
+        max_depth: Some(DEFAULT_MAX_DEPTH),
+        max_memory: None,
+    }) };
+    // number of lval_eval calls since the last reset, reported by the REPL's :time command
+    static EVAL_STEPS: Cell<u64> = const { Cell::new(0) };
+    // value of EVAL_STEPS when the current top level form started
+    static STEP_BASE: Cell<u64> = const { Cell::new(0) };
+    static CALL_DEPTH: Cell<usize> = const { Cell::new(0) };
+    static ALLOCATED: Cell<usize> = const { Cell::new(0) };
+}
+
+/// sets the limits used by evaluations on this thread
+pub fn set_limits(limits: Limits) {
+    LIMITS.with(|l| l.set(limits));
+}
+
+pub fn limits() -> Limits {
+    LIMITS.with(|l| l.get())
+}
+
+/// starts a fresh budget for the next top level form
+pub fn begin_evaluation() {
+    STEP_BASE.with(|b| b.set(eval_steps()));
+    CALL_DEPTH.with(|d| d.set(0));
+    ALLOCATED.with(|a| a.set(0));
+}
+
+pub fn eval_steps() -> u64 {
+    EVAL_STEPS.with(|s| s.get())
+}
+
+pub fn reset_eval_steps() {
+    EVAL_STEPS.with(|s| s.set(0));
+    STEP_BASE.with(|b| b.set(0));
+}
+
+/// records one evaluation step, failing once the step or memory budget is spent
+pub fn count_step() -> Result<(), Lval> {
+    check_memory()?;
+    let steps = EVAL_STEPS.with(|s| {
+        s.set(s.get() + 1);
+        s.get()
+    });
+    match limits().max_steps {
+        Some(max) if steps - STEP_BASE.with(|b| b.get()) > max => {
+            Err(Lval::Err(format!("Step limit exceeded: more than {} evaluation steps", max)))
+        },
+        _ => Ok(()),
+    }
+}
+
+/// records entry into a lambda call. Every successful enter_call must be paired with exit_call
+pub fn enter_call() -> Result<(), Lval> {
+    let depth = CALL_DEPTH.with(|d| d.get()) + 1;
+    if let Some(max) = limits().max_depth {
+        if depth > max {
+            return Err(Lval::Err(format!("Recursion depth limit exceeded: more than {} nested calls", max)));
+        }
+    }
+    CALL_DEPTH.with(|d| d.set(depth));
+    Ok(())
+}
+
+pub fn exit_call() {
+    CALL_DEPTH.with(|d| d.set(d.get().saturating_sub(1)));
+}
+
+/// current number of nested lambda calls
+pub fn call_depth() -> usize {
+    CALL_DEPTH.with(|d| d.get())
+}
+
+/// charges newly built bytes against the memory budget, where an error
+/// cannot be returned; the evaluation fails at its next step
+pub fn charge(bytes: usize) {
+    ALLOCATED.with(|a| a.set(a.get().saturating_add(bytes)));
+}
+
+/// fails once more was built than the memory budget allows
+pub fn check_memory() -> Result<(), Lval> {
+    fits(0)
+}
+
+/// fails if building `bytes` more would exceed the memory budget, without
+/// charging them
+pub fn fits(bytes: usize) -> Result<(), Lval> {
+    let total = ALLOCATED.with(|a| a.get()).saturating_add(bytes);
+    match limits().max_memory {
+        Some(max) if total > max => {
+            Err(Lval::Err(format!("Memory limit exceeded: more than {} bytes allocated", max)))
+        },
+        _ => Ok(()),
+    }
+}
+
+/// charges newly built bytes against the memory budget, failing at once if it is spent
+pub fn track_bytes(bytes: usize) -> Result<(), Lval> {
+    charge(bytes);
+    check_memory()
+}
+
+/// charges newly built list cells against the memory budget
+pub fn track_cells(cells: usize) -> Result<(), Lval> {
+    track_bytes(cells.saturating_mul(size_of::<Lval>()))
+}
+
+/// the result of a top level form, or the memory error if building it
+/// spent the budget after the form's last step
+pub fn end_evaluation(result: Lval) -> Lval {
+    match (result, check_memory()) {
+        (err @ Lval::Err(_), _) => err,
+        (_, Err(err)) => err,
+        (result, Ok(())) => result,
+    }
+}
@@ -0,0 +1,152 @@
+// `rustlisp lint`: reports likely mistakes in source files without running
+// them. Settings come from --config, or else the first .rustlisplint found in
+// the current directory or a parent. The exit status is 1 when any finding is
+// an error, or a file could not be read.
+
+use rustlisp::linter::{lint, LintConfig, Severity, RULES};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+pub const USAGE: &str = "\
+Usage: rustlisp lint [--config FILE] [--rules] FILE...
+
+Options:
+  --config FILE    read rule settings from FILE instead of .rustlisplint
+  --rules          list the rules and exit
+
+A config file has one setting per line:
+  unused-parameter = off       # off, warning or error
+  globals = helper log         # functions defined outside the checked files";
+
+/// name of the config file looked up from the current directory upwards
+const CONFIG_FILE: &str = ".rustlisplint";
+
+fn find_config() -> Option<PathBuf> {
+    let cwd = std::env::current_dir().ok()?;
+    cwd.ancestors().map(|dir| dir.join(CONFIG_FILE)).find(|path| path.is_file())
+}
+
+fn load_config(path: &Path) -> Result<LintConfig, String> {
+    let text = fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    LintConfig::parse(&text).map_err(|e| format!("{}: {}", path.display(), e))
+}
+
+/// runs the command on the arguments after `lint`, returning the exit status
+pub fn run(args: impl IntoIterator<Item = String>) -> i32 {
+    let mut config_path = None;
+    let mut files = Vec::new();
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--config" => match args.next() {
+                Some(path) => config_path = Some(PathBuf::from(path)),
+                None => {
+                    eprintln!("Error: option '--config' needs a file\n\n{}", USAGE);
+                    return 2;
+                },
+            },
+            "--rules" => {
+                for (rule, description) in RULES {
+                    println!("{:<20} {}", rule, description);
+                }
+                return 0;
+            },
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                return 0;
+            },
+            _ if arg.starts_with('-') => {
+                eprintln!("Error: unknown option '{}'\n\n{}", arg, USAGE);
+                return 2;
+            },
+            _ => files.push(arg),
+        }
+    }
+    if files.is_empty() {
+        eprintln!("Error: no files to lint\n\n{}", USAGE);
+        return 2;
+    }
+
+    let config = match config_path.or_else(find_config) {
+        Some(path) => match load_config(&path) {
+            Ok(config) => config,
+            Err(e) => {
+                eprintln!("Error: {}", e);
+                return 2;
+            },
+        },
+        None => LintConfig::default(),
+    };
+
+    let mut status = 0;
+    for file in &files {
+        let lints = fs::read_to_string(file)
+            .map_err(|e| e.to_string())
+            .and_then(|src| lint(&src, &config));
+        match lints {
+            Ok(lints) => for l in lints {
+                if l.severity == Severity::Error { status = 1; }
+                println!("{}:{}", file, l);
+            },
+            Err(e) => {
+                eprintln!("Error: {}: {}", file, e);
+                status = 1;
+            },
+        }
+    }
+    status
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// a file in the temp directory holding `text`, named for the test using it
+    fn temp_file(name: &str, text: &str) -> String {
+        let path = std::env::temp_dir().join(format!("rustlisp-lint-{}-{}", name, std::process::id()));
+        fs::write(&path, text).unwrap();
+        path.to_str().unwrap().to_string()
+    }
+
+    fn lint_status(args: &[&str]) -> i32 {
+        run(args.iter().map(|a| a.to_string()))
+    }
+
+    #[test]
+    fn only_errors_fail_the_run() {
+        let clean = temp_file("clean.lisp", "(defun f (x) x)\n(f 1)\n");
+        let unused = temp_file("unused.lisp", "(defun f (x y) x)\n");
+        let warnings = temp_file("warnings.rlint", "");
+        let errors = temp_file("errors.rlint", "unused-parameter = error\n");
+        let off = temp_file("off.rlint", "unused-parameter = off\n");
+        let overflow = temp_file("overflow.lisp", "(defun f (x) x)\n(f 99999999999999999999)\n");
+
+        assert_eq!(lint_status(&["--config", &warnings, &clean]), 0);
+        assert_eq!(lint_status(&["--config", &warnings, &unused]), 0);
+        assert_eq!(lint_status(&["--config", &errors, &unused]), 1);
+        assert_eq!(lint_status(&["--config", &errors, &clean, &unused]), 1);
+        assert_eq!(lint_status(&["--config", &off, &unused]), 0);
+        assert_eq!(lint_status(&["--config", &warnings, &overflow]), 1);
+        for file in [clean, unused, warnings, errors, off, overflow] {
+            fs::remove_file(file).unwrap();
+        }
+    }
+
+    #[test]
+    fn unreadable_files_and_bad_usage() {
+        let config = temp_file("empty.rlint", "");
+        let broken = temp_file("broken.lisp", "(defun f (x)");
+        let bad_config = temp_file("bad.rlint", "no-such-rule = off\n");
+        assert_eq!(lint_status(&["--config", &config, &broken]), 1);
+        assert_eq!(lint_status(&["--config", &config, "/nonexistent/file.lisp"]), 1);
+        assert_eq!(lint_status(&["--config", &bad_config, &broken]), 2);
+        assert_eq!(lint_status(&["--config", "/nonexistent/config", &broken]), 2);
+        assert_eq!(lint_status(&["--config"]), 2);
+        assert_eq!(lint_status(&[]), 2);
+        assert_eq!(lint_status(&["--verbose"]), 2);
+        assert_eq!(lint_status(&["--rules"]), 0);
+        for file in [config, broken, bad_config] {
+            fs::remove_file(file).unwrap();
+        }
+    }
+}
@@ -0,0 +1,416 @@
+// Static checks for common mistakes in Lisp source (`rustlisp lint`). The
+// source is read into Lval trees that remember where each form starts, and
+// each rule walks them looking for one kind of mistake. Rules can be turned
+// off or made errors per project, and functions defined outside the file
+// (in an init file, say) can be declared so calls to them are not reported.
+
+use crate::eval::is_special_form;
+use crate::record::function_names;
+use crate::{builtin_profile, read, LispyParser, Rule};
+use crate::types::Lval;
+use pest::iterators::Pair;
+use pest::Parser;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+/// every rule with what it looks for
+pub const RULES: &[(&str, &str)] = &[
+    ("cond-clause", "cond clauses that are not a (test body) pair"),
+    ("undefined-function", "calls to functions that are neither builtins nor defined in the file"),
+    ("arity", "calls passing a different number of arguments than a defun takes"),
+    ("shadow-builtin", "definitions and parameters that reuse a builtin's name"),
+    ("unused-parameter", "parameters the function body never mentions (names starting with _ are skipped)"),
+];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    Warning,
+    Error,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Severity::Warning => write!(f, "warning"),
+            Severity::Error => write!(f, "error"),
+        }
+    }
+}
+
+/// one problem found in the source
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Lint {
+    /// one of the RULES, or read for an atom the reader could not read
+    pub rule: &'static str,
+    pub severity: Severity,
+    pub message: String,
+    /// 1-based position of the offending form
+    pub line: usize,
+    pub column: usize,
+}
+
+impl fmt::Display for Lint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}: {}: {} [{}]", self.line, self.column, self.severity, self.message, self.rule)
+    }
+}
+
+/// which rules run and how seriously their findings are taken
+#[derive(Clone, Debug, Default)]
+pub struct LintConfig {
+    /// rules set to off, warning or error; unlisted rules are warnings
+    pub rules: HashMap<String, Option<Severity>>,
+    /// functions defined elsewhere that the file may call
+    pub globals: HashSet<String>,
+}
+
+impl LintConfig {
+    /// reads a config file of `rule = off|warning|error` and
+    /// `globals = name ...` lines; # starts a comment
+    pub fn parse(text: &str) -> Result<LintConfig, String> {
+        let mut config = LintConfig::default();
+        for (n, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or("").trim();
+            if line.is_empty() { continue; }
+            let (key, value) = line.split_once('=')
+                .ok_or_else(|| format!("line {}: expected 'name = value'", n + 1))?;
+            let (key, value) = (key.trim(), value.trim());
+            if key == "globals" {
+                config.globals.extend(value.split_whitespace().map(str::to_string));
+                continue;
+            }
+            if !RULES.iter().any(|(rule, _)| *rule == key) {
+                return Err(format!("line {}: unknown rule '{}'", n + 1, key));
+            }
+            let severity = match value {
+                "off" => None,
+                "warning" => Some(Severity::Warning),
+                "error" => Some(Severity::Error),
+                _ => return Err(format!("line {}: '{}' should be off, warning or error", n + 1, value)),
+            };
+            config.rules.insert(key.to_string(), severity);
+        }
+        Ok(config)
+    }
+
+    fn severity(&self, rule: &str) -> Option<Severity> {
+        self.rules.get(rule).copied().unwrap_or(Some(Severity::Warning))
+    }
+}
+
+/// a form read from the source with its position; lists keep their elements
+/// as forms too, and a quoted form keeps the form it quotes
+struct Form {
+    value: Lval,
+    line: usize,
+    column: usize,
+    items: Vec<Form>,
+    /// why the reader could not read an atom, such as an integer out of range
+    unreadable: Option<String>,
+}
+
+impl Form {
+    fn from_pair(pair: Pair<Rule>) -> Form {
+        if pair.as_rule() == Rule::expr {
+            return Form::from_pair(pair.into_inner().next().unwrap());
+        }
+        let (line, column) = pair.as_span().start_pos().line_col();
+        let value = read(pair.clone());
+        // literals such as #S(point ...) are left out, since what they read as
+        // depends on forms the linter does not run
+        let unreadable = match (pair.as_rule(), &value) {
+            (Rule::number | Rule::character, Lval::Err(e)) => Some(e.clone()),
+            _ => None,
+        };
+        let items = match pair.as_rule() {
+            Rule::sexpr | Rule::qexpr => pair.into_inner().map(Form::from_pair).collect(),
+            _ => Vec::new(),
+        };
+        Form { value, line, column, items, unreadable }
+    }
+
+    fn sym(&self) -> Option<&str> {
+        match self.value {
+            Lval::Sym(s) => Some(s.name()),
+            _ => None,
+        }
+    }
+
+    fn is_list(&self) -> bool {
+        matches!(self.value, Lval::Sexpr(_))
+    }
+
+    /// the symbols of a formals list or def target: (a b), 'a or '(a b)
+    fn names(&self) -> Vec<&Form> {
+        match self.value {
+            Lval::Sym(_) => vec![self],
+            Lval::Sexpr(_) => self.items.iter().filter(|f| f.sym().is_some()).collect(),
+            Lval::Qexpr(_) => self.items.first().map_or(Vec::new(), Form::names),
+            _ => Vec::new(),
+        }
+    }
+
+    /// every symbol mentioned anywhere in the form, quoted or not
+    fn mentions(&self, out: &mut HashSet<String>) {
+        if let Some(s) = self.sym() { out.insert(s.to_string()); }
+        for item in &self.items {
+            item.mentions(out);
+        }
+    }
+}
+
+struct Linter<'a> {
+    config: &'a LintConfig,
+    /// functions defined in the file, with their number of parameters when known
+    functions: HashMap<String, Option<usize>>,
+    /// variables defined in the file
+    variables: HashSet<String>,
+    lints: Vec<Lint>,
+}
+
+/// checks source text, returning the problems found in source order
+pub fn lint(src: &str, config: &LintConfig) -> Result<Vec<Lint>, String> {
+    let mut pairs = LispyParser::parse(Rule::lispy, src).map_err(|e| e.to_string())?;
+    let forms: Vec<Form> = pairs.next().unwrap().into_inner()
+        .filter(|p| p.as_rule() != Rule::EOI)
+        .map(Form::from_pair)
+        .collect();
+
+    let mut linter = Linter { config, functions: HashMap::new(), variables: HashSet::new(), lints: Vec::new() };
+    for form in &forms {
+        linter.collect(form);
+    }
+    let mut scopes = Vec::new();
+    for form in &forms {
+        linter.check(form, &mut scopes);
+        linter.check_literals(form);
+    }
+    linter.lints.sort_by_key(|l| (l.line, l.column));
+    Ok(linter.lints)
+}
+
+impl Linter<'_> {
+    fn report(&mut self, rule: &'static str, at: &Form, message: String) {
+        if let Some(severity) = self.config.severity(rule) {
+            self.lints.push(Lint { rule, severity, message, line: at.line, column: at.column });
+        }
+    }
+
+    /// reports atoms the reader could not read, quoted or not. These are
+    /// always errors, since evaluating one fails, so no rule setting applies
+    fn check_literals(&mut self, form: &Form) {
+        if let Some(e) = &form.unreadable {
+            let mut message = e.clone();
+            message[..1].make_ascii_lowercase();
+            self.lints.push(Lint { rule: "read", severity: Severity::Error, message, line: form.line, column: form.column });
+        }
+        for item in &form.items {
+            self.check_literals(item);
+        }
+    }
+
+    /// records what the file defines, so calls before a definition are not reported
+    fn collect(&mut self, form: &Form) {
+        if !form.is_list() { return; }
+        let items = &form.items;
+        match (items.first().and_then(Form::sym), items.get(1)) {
+            (Some("defun"), Some(name)) => if let Some(name) = name.sym() {
+                let arity = items.get(2).filter(|f| f.is_list()).map(|f| f.names().len());
+                self.functions.insert(name.to_string(), arity);
+            },
+            (Some("setq"), Some(name)) => if let Some(name) = name.sym() {
+                self.variables.insert(name.to_string());
+            },
+            (Some("defstruct"), Some(name)) => if let Some(name) = name.sym() {
+                // a slot is a name or (name default)
+                let slots: Vec<&str> = items[2..].iter()
+                    .filter_map(|slot| slot.sym().or_else(|| slot.items.first().and_then(Form::sym)))
+                    .collect();
+                self.functions.extend(function_names(name, &slots));
+            },
+            (Some("defclass"), Some(_)) => {
+                // a slot's :accessor and :reader options name generic functions
+                for slot in items.get(3).map_or(&[][..], |slots| &slots.items[..]) {
+                    for pair in slot.items.get(1..).unwrap_or(&[]).chunks(2) {
+                        if let [option, name] = pair {
+                            if let (Some(":accessor" | ":reader"), Some(name)) = (option.sym(), name.sym()) {
+                                self.functions.insert(name.to_string(), None);
+                            }
+                        }
+                    }
+                }
+            },
+            // a generic function's arity is not checked, since a short call is
+            // an error rather than a partial application
+            (Some("defgeneric" | "defmethod"), Some(name)) => if let Some(name) = name.sym() {
+                self.functions.insert(name.to_string(), None);
+            },
+            (Some("def" | "=" | "set"), Some(targets)) => {
+                // (def 'f (\ '(x) '(...))) defines a function of one parameter
+                let arity = match items.get(2) {
+                    Some(value) if value.items.first().and_then(Form::sym) == Some("\\") => {
+                        Some(value.items.get(1).map(|f| f.names().len()))
+                    },
+                    _ => None,
+                };
+                for target in targets.names() {
+                    let name = target.sym().unwrap().to_string();
+                    match arity {
+                        Some(arity) => { self.functions.insert(name, arity); },
+                        None => { self.variables.insert(name); },
+                    }
+                }
+            },
+            _ => {},
+        }
+        for item in items {
+            self.collect(item);
+        }
+    }
+
+    fn is_known_function(&self, name: &str, scopes: &[Vec<String>]) -> bool {
+        is_special_form(name)
+            || builtin_profile(name).is_some()
+            || self.functions.contains_key(name)
+            || self.variables.contains(name)
+            || self.config.globals.contains(name)
+            || scopes.iter().any(|params| params.iter().any(|p| p == name))
+    }
+
+    fn check_shadowing(&mut self, name: &Form, what: &str) {
+        if let Some(s) = name.sym() {
+            if builtin_profile(s).is_some() || is_special_form(s) {
+                self.report("shadow-builtin", name, format!("{} '{}' shadows the builtin of the same name", what, s));
+            }
+        }
+    }
+
+    /// reports parameters that `body` never mentions
+    fn check_unused(&mut self, params: &[&Form], body: &[Form], function: &str) {
+        let mut used = HashSet::new();
+        for form in body {
+            form.mentions(&mut used);
+        }
+        for param in params {
+            let name = param.sym().unwrap();
+            if !name.starts_with('_') && !used.contains(name) {
+                self.report("unused-parameter", param, format!("parameter '{}' of {} is never used", name, function));
+            }
+        }
+    }
+
+    /// checks a function body with its parameters in scope
+    fn check_function(&mut self, name: &str, params: Vec<&Form>, body: &[Form], scopes: &mut Vec<Vec<String>>) {
+        for param in &params {
+            self.check_shadowing(param, "parameter");
+        }
+        self.check_unused(&params, body, name);
+        scopes.push(params.iter().filter_map(|p| p.sym()).map(str::to_string).collect());
+        for form in body {
+            self.check(form, scopes);
+        }
+        scopes.pop();
+    }
+
+    /// checks a form in evaluated position. `scopes` holds the parameters
+    /// of the enclosing functions
+    fn check(&mut self, form: &Form, scopes: &mut Vec<Vec<String>>) {
+        if !form.is_list() || form.items.is_empty() { return; }
+        let items = &form.items;
+        let head = &items[0];
+
+        match head.sym() {
+            Some("quote" | "trace" | "untrace" | "defstruct" | "defclass" | "defgeneric") => return,
+            Some("defun") => {
+                let name = items.get(1).and_then(Form::sym).unwrap_or("defun").to_string();
+                if let Some(n) = items.get(1) { self.check_shadowing(n, "function"); }
+                let params = items.get(2).map(Form::names).unwrap_or_default();
+                self.check_function(&name, params, items.get(3..).unwrap_or(&[]), scopes);
+                return;
+            },
+            Some("defmethod") => {
+                let name = items.get(1).and_then(Form::sym).unwrap_or("defmethod").to_string();
+                // (defmethod name [qualifier] (arg ...) body), an arg being a name or (name class)
+                let rest = match items.get(2).and_then(Form::sym) {
+                    Some(q) if q.starts_with(':') => items.get(3..),
+                    _ => items.get(2..),
+                }.unwrap_or(&[]);
+                let params = rest.first().map_or(Vec::new(), |formals| formals.items.iter()
+                    .filter_map(|arg| if arg.sym().is_some() { Some(arg) } else { arg.items.first().filter(|p| p.sym().is_some()) })
+                    .collect());
+                self.check_function(&name, params, rest.get(1..).unwrap_or(&[]), scopes);
+                return;
+            },
+            Some("\\") => {
+                let params = items.get(1).map(Form::names).unwrap_or_default();
+                // the body is quoted: '(...) holds the form to check
+                let body = items.get(2).map_or(&[][..], |b| &b.items[..]);
+                self.check_function("lambda", params, body, scopes);
+                return;
+            },
+            Some("setq") => {
+                if let Some(n) = items.get(1) { self.check_shadowing(n, "variable"); }
+                for item in items.iter().skip(2) {
+                    self.check(item, scopes);
+                }
+                return;
+            },
+            Some("def" | "=" | "set") => {
+                if let Some(targets) = items.get(1) {
+                    for target in targets.names() {
+                        self.check_shadowing(target, "definition");
+                    }
+                }
+            },
+            Some("cond") => {
+                for clause in &items[1..] {
+                    self.check_clause(clause, scopes);
+                }
+                return;
+            },
+            Some(name) => self.check_call(form, name, scopes),
+            None => {},
+        }
+        for item in items {
+            self.check(item, scopes);
+        }
+    }
+
+    fn check_call(&mut self, form: &Form, name: &str, scopes: &[Vec<String>]) {
+        if !self.is_known_function(name, scopes) {
+            self.report("undefined-function", &form.items[0], format!("call to undefined function '{}'", name));
+            return;
+        }
+        // a parameter or variable may hide the function of the same name
+        let shadowed = scopes.iter().any(|params| params.iter().any(|p| p == name));
+        if let (false, Some(Some(arity))) = (shadowed, self.functions.get(name)) {
+            let given = form.items.len() - 1;
+            let takes = format!("'{}' takes {} argument{}", name, arity, if *arity == 1 { "" } else { "s" });
+            if given > *arity {
+                self.report("arity", form, format!("{} but is called with {}", takes, given));
+            } else if given < *arity {
+                self.report("arity", form, format!("{} but is called with {}, which returns a partial application", takes, given));
+            }
+        }
+    }
+
+    fn check_clause(&mut self, clause: &Form, scopes: &mut Vec<Vec<String>>) {
+        if !matches!(clause.value, Lval::Sexpr(_) | Lval::Qexpr(_)) {
+            self.report("cond-clause", clause, format!("cond clause {} should be a list (test body)", clause.value));
+            return;
+        }
+        // cond also takes '(test body) and ((test body)), unwrapping the inner list
+        let cells = match clause.items.as_slice() {
+            [inner] if inner.is_list() => &inner.items,
+            _ => &clause.items,
+        };
+        match cells.len() {
+            0 | 1 => self.report("cond-clause", clause, "cond clause needs a test and a body".to_string()),
+            2 => {},
+            n => self.report("cond-clause", clause, format!("cond clause has {} body forms; only the first is evaluated", n - 1)),
+        }
+        for item in cells {
+            self.check(item, scopes);
+        }
+    }
+}
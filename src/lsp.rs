@@ -0,0 +1,515 @@
+// Language Server Protocol server for Lisp source files (`rustlisp lsp`).
+// Documents are analysed from the pest parse on every change: syntax errors
+// and unbound symbols become diagnostics, and defun, def and setq forms
+// provide definitions, hovers and document symbols.
+//
+// Scoping is approximated statically: a symbol is bound if it names a
+// builtin, anything the document defines, or a parameter of an enclosing
+// defun or lambda. Callers' parameters seen through dynamic scope are reported.
+
+use crate::eval::{is_special_form, SPECIAL_FORMS};
+use crate::framing::{read_message, write_message};
+use crate::record::function_names;
+use crate::types::Lenv;
+use crate::{add_builtins, LispyParser, Rule};
+use pest::error::LineColLocation;
+use pest::iterators::Pair;
+use pest::Parser;
+use serde_json::{json, Value};
+use std::cell::RefCell;
+use std::collections::{BTreeSet, HashMap};
+use std::io::{self, BufRead, Write};
+use std::rc::Rc;
+
+/// symbols lval_eval resolves itself, besides keywords
+const CONSTANTS: &[&str] = &["t", "T", "nil", "NIL"];
+
+// LSP enumeration values
+const SEVERITY_ERROR: i64 = 1;
+const SEVERITY_WARNING: i64 = 2;
+const COMPLETION_FUNCTION: i64 = 3;
+const COMPLETION_VARIABLE: i64 = 6;
+const COMPLETION_KEYWORD: i64 = 14;
+const SYMBOL_FUNCTION: i64 = 12;
+const SYMBOL_VARIABLE: i64 = 13;
+
+/// a 0-based position; characters are counted as the parser counts them,
+/// which matches UTF-16 for ASCII source
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+struct Pos {
+    line: usize,
+    character: usize,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct Range {
+    start: Pos,
+    end: Pos,
+}
+
+impl Range {
+    fn of(pair: &Pair<Rule>) -> Range {
+        let span = pair.as_span();
+        let (l1, c1) = span.start_pos().line_col();
+        let (l2, c2) = span.end_pos().line_col();
+        Range {
+            start: Pos { line: l1 - 1, character: c1 - 1 },
+            end: Pos { line: l2 - 1, character: c2 - 1 },
+        }
+    }
+
+    /// true when `pos` is inside the range or just after its last character
+    fn contains(&self, pos: Pos) -> bool {
+        self.start <= pos && pos <= self.end
+    }
+
+    fn to_json(self) -> Value {
+        json!({
+            "start": { "line": self.start.line, "character": self.start.character },
+            "end": { "line": self.end.line, "character": self.end.character },
+        })
+    }
+}
+
+/// the parse tree with positions, which Lval does not keep
+enum Node {
+    Sym(String, Range),
+    /// numbers and strings
+    Atom,
+    List(Vec<Node>, Range),
+    Quote(Box<Node>),
+}
+
+impl Node {
+    fn from_pair(pair: Pair<Rule>) -> Node {
+        let range = Range::of(&pair);
+        match pair.as_rule() {
+            Rule::expr => Node::from_pair(pair.into_inner().next().unwrap()),
+            Rule::symbol => Node::Sym(pair.as_str().to_string(), range),
+            Rule::sexpr => Node::List(pair.into_inner().map(Node::from_pair).collect(), range),
+            Rule::qexpr => Node::Quote(Box::new(Node::from_pair(pair.into_inner().next().unwrap()))),
+            _ => Node::Atom,
+        }
+    }
+
+    fn sym(&self) -> Option<&str> {
+        match self {
+            Node::Sym(s, _) => Some(s),
+            _ => None,
+        }
+    }
+
+    /// symbols directly in a list, or a lone symbol, as in formals and def targets
+    fn sym_list(&self) -> Vec<(&str, Range)> {
+        match self {
+            Node::Sym(s, r) => vec![(s.as_str(), *r)],
+            Node::List(items, _) => items.iter().filter_map(|n| match n {
+                Node::Sym(s, r) => Some((s.as_str(), *r)),
+                _ => None,
+            }).collect(),
+            _ => Vec::new(),
+        }
+    }
+}
+
+/// the arguments of a defmethod form after its name: the formals, each a name
+/// or (name class), with the body after them
+fn method_parts(items: &[Node]) -> (Vec<(&str, Range)>, &[Node]) {
+    // a qualifier such as :before comes before the formals
+    let rest = match items.first().and_then(Node::sym) {
+        Some(q) if q.starts_with(':') => &items[1..],
+        _ => items,
+    };
+    let formals = match rest.first() {
+        Some(Node::List(formals, _)) => formals.iter().filter_map(|arg| match arg {
+            Node::Sym(s, r) => Some((s.as_str(), *r)),
+            Node::List(arg, _) => match arg.first() {
+                Some(Node::Sym(s, r)) => Some((s.as_str(), *r)),
+                _ => None,
+            },
+            _ => None,
+        }).collect(),
+        _ => Vec::new(),
+    };
+    (formals, rest.get(1..).unwrap_or(&[]))
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum DefKind {
+    Function,
+    Variable,
+    Parameter,
+}
+
+struct Definition {
+    name: String,
+    kind: DefKind,
+    /// where the name is written
+    name_range: Range,
+    /// the whole defining form
+    form_range: Range,
+    /// formals of a function
+    formals: Vec<String>,
+}
+
+/// what the server knows about one open document
+struct Analysis {
+    diagnostics: Vec<Value>,
+    /// every symbol occurrence, quoted or not
+    symbols: Vec<(String, Range)>,
+    definitions: Vec<Definition>,
+}
+
+impl Analysis {
+    fn new(src: &str) -> Analysis {
+        let mut analysis = Analysis { diagnostics: Vec::new(), symbols: Vec::new(), definitions: Vec::new() };
+        let nodes: Vec<Node> = match LispyParser::parse(Rule::lispy, src) {
+            Ok(mut pairs) => pairs.next().unwrap().into_inner()
+                .filter(|p| p.as_rule() != Rule::EOI)
+                .map(Node::from_pair)
+                .collect(),
+            Err(e) => {
+                let ((l1, c1), (l2, c2)) = match e.line_col {
+                    LineColLocation::Pos(p) => (p, p),
+                    LineColLocation::Span(start, end) => (start, end),
+                };
+                let range = Range {
+                    start: Pos { line: l1 - 1, character: c1 - 1 },
+                    end: Pos { line: l2 - 1, character: c2 - 1 },
+                };
+                analysis.diagnostics.push(diagnostic(range, SEVERITY_ERROR, format!("Syntax error: {}", e.variant.message())));
+                return analysis;
+            },
+        };
+
+        for node in &nodes {
+            analysis.collect(node);
+        }
+        let mut scopes = Vec::new();
+        for node in &nodes {
+            analysis.check(node, &mut scopes);
+        }
+        analysis
+    }
+
+    /// records symbol occurrences and the definitions made by defun, def, setq,
+    /// defstruct, defclass, defgeneric and defmethod
+    fn collect(&mut self, node: &Node) {
+        match node {
+            Node::Sym(s, r) => self.symbols.push((s.clone(), *r)),
+            Node::Atom => {},
+            Node::Quote(inner) => self.collect(inner),
+            Node::List(items, range) => {
+                let define = |name: &str, kind, name_range, formals| Definition {
+                    name: name.to_string(), kind, name_range, form_range: *range, formals,
+                };
+                match (items.first().and_then(Node::sym), items.get(1)) {
+                    (Some("defun"), Some(Node::Sym(name, r))) => {
+                        let formals = items.get(2).map(Node::sym_list).unwrap_or_default();
+                        let names = formals.iter().map(|(f, _)| f.to_string()).collect();
+                        self.definitions.push(define(name, DefKind::Function, *r, names));
+                        for (formal, fr) in formals {
+                            self.definitions.push(define(formal, DefKind::Parameter, fr, Vec::new()));
+                        }
+                    },
+                    (Some("setq"), Some(Node::Sym(name, r))) => {
+                        self.definitions.push(define(name, DefKind::Variable, *r, Vec::new()));
+                    },
+                    (Some("defstruct"), Some(Node::Sym(name, r))) => {
+                        // a slot is a name or (name default)
+                        let slots: Vec<&str> = items[2..].iter().filter_map(|slot| match slot {
+                            Node::List(slot, _) => slot.first().and_then(Node::sym),
+                            slot => slot.sym(),
+                        }).collect();
+                        for (function, arity) in function_names(name, &slots) {
+                            let formals = match arity {
+                                Some(_) => vec![name.clone()],
+                                None => slots.iter().map(|s| format!(":{}", s)).collect(),
+                            };
+                            self.definitions.push(define(&function, DefKind::Function, *r, formals));
+                        }
+                    },
+                    (Some("defclass"), Some(Node::Sym(class, _))) => {
+                        // a slot's :accessor and :reader options name generic functions
+                        let slots = match items.get(3) {
+                            Some(Node::List(slots, _)) => &slots[..],
+                            _ => &[],
+                        };
+                        for slot in slots {
+                            let Node::List(slot, _) = slot else { continue };
+                            for pair in slot.get(1..).unwrap_or(&[]).chunks(2) {
+                                if let [Node::Sym(option, _), Node::Sym(name, r)] = pair {
+                                    if option == ":accessor" || option == ":reader" {
+                                        self.definitions.push(define(name, DefKind::Function, *r, vec![class.clone()]));
+                                    }
+                                }
+                            }
+                        }
+                    },
+                    (Some("defgeneric"), Some(Node::Sym(name, r))) => {
+                        let formals = items.get(2).map(Node::sym_list).unwrap_or_default();
+                        let names = formals.iter().map(|(f, _)| f.to_string()).collect();
+                        self.definitions.push(define(name, DefKind::Function, *r, names));
+                    },
+                    (Some("defmethod"), Some(Node::Sym(name, r))) => {
+                        let (formals, _) = method_parts(&items[2..]);
+                        let names = formals.iter().map(|(f, _)| f.to_string()).collect();
+                        self.definitions.push(define(name, DefKind::Function, *r, names));
+                        for (formal, fr) in formals {
+                            self.definitions.push(define(formal, DefKind::Parameter, fr, Vec::new()));
+                        }
+                    },
+                    (Some("def" | "=" | "set"), Some(Node::Quote(targets))) => {
+                        // (def 'f (\ '(x) '(...))) defines a function
+                        let lambda = match items.get(2) {
+                            Some(Node::List(value, _)) if value.first().and_then(Node::sym) == Some("\\") => value.get(1),
+                            _ => None,
+                        };
+                        for (name, r) in targets.sym_list() {
+                            match lambda {
+                                Some(Node::Quote(formals)) => {
+                                    let names = formals.sym_list().iter().map(|(f, _)| f.to_string()).collect();
+                                    self.definitions.push(define(name, DefKind::Function, r, names));
+                                },
+                                _ => self.definitions.push(define(name, DefKind::Variable, r, Vec::new())),
+                            }
+                        }
+                    },
+                    (Some("\\"), Some(Node::Quote(formals))) => {
+                        for (formal, r) in formals.sym_list() {
+                            self.definitions.push(define(formal, DefKind::Parameter, r, Vec::new()));
+                        }
+                    },
+                    _ => {},
+                }
+                for item in items {
+                    self.collect(item);
+                }
+            },
+        }
+    }
+
+    /// reports unbound symbols in evaluated positions. `scopes` holds the
+    /// parameters of the enclosing defun and lambda forms
+    fn check(&mut self, node: &Node, scopes: &mut Vec<Vec<String>>) {
+        let items = match node {
+            Node::Sym(s, r) => {
+                if !self.is_bound(s, scopes) {
+                    self.diagnostics.push(diagnostic(*r, SEVERITY_WARNING, format!("Unbound symbol '{}'", s)));
+                }
+                return;
+            },
+            Node::Atom | Node::Quote(_) => return,
+            Node::List(items, _) => items,
+        };
+        let with_params = |formals: Option<&Node>| formals.map(Node::sym_list).unwrap_or_default()
+            .into_iter().map(|(f, _)| f.to_string()).collect::<Vec<_>>();
+
+        match items.first().and_then(Node::sym) {
+            Some("quote" | "trace" | "untrace" | "defstruct" | "defclass" | "defgeneric") => {},
+            Some("defmethod") => {
+                let (formals, body) = method_parts(items.get(2..).unwrap_or(&[]));
+                scopes.push(formals.into_iter().map(|(f, _)| f.to_string()).collect());
+                for item in body {
+                    self.check(item, scopes);
+                }
+                scopes.pop();
+            },
+            Some("defun") => {
+                scopes.push(with_params(items.get(2)));
+                for item in items.iter().skip(3) {
+                    self.check(item, scopes);
+                }
+                scopes.pop();
+            },
+            Some("setq") => {
+                for item in items.iter().skip(2) {
+                    self.check(item, scopes);
+                }
+            },
+            Some("\\") => {
+                let formals = match items.get(1) {
+                    Some(Node::Quote(formals)) => Some(formals.as_ref()),
+                    _ => None,
+                };
+                scopes.push(with_params(formals));
+                if let Some(Node::Quote(body)) = items.get(2) {
+                    self.check(body, scopes);
+                }
+                scopes.pop();
+            },
+            _ => {
+                for item in items {
+                    self.check(item, scopes);
+                }
+            },
+        }
+    }
+
+    fn is_bound(&self, name: &str, scopes: &[Vec<String>]) -> bool {
+        is_special_form(name)
+            || CONSTANTS.contains(&name)
+            || name.starts_with(':')
+            || crate::builtin_profile(name).is_some()
+            || scopes.iter().any(|params| params.iter().any(|p| p == name))
+            || self.definitions.iter().any(|d| d.kind != DefKind::Parameter && d.name == name)
+    }
+
+    fn symbol_at(&self, pos: Pos) -> Option<&(String, Range)> {
+        self.symbols.iter().find(|(_, r)| r.contains(pos))
+    }
+
+    /// the definition a symbol at `pos` refers to: a parameter of the
+    /// innermost enclosing form, otherwise the first global definition
+    fn definition(&self, name: &str, pos: Pos) -> Option<&Definition> {
+        let param = self.definitions.iter()
+            .rfind(|d| d.kind == DefKind::Parameter && d.name == name && d.form_range.contains(pos));
+        param.or_else(|| self.definitions.iter().find(|d| d.kind != DefKind::Parameter && d.name == name))
+    }
+}
+
+fn diagnostic(range: Range, severity: i64, message: String) -> Value {
+    json!({ "range": range.to_json(), "severity": severity, "source": "rustlisp", "message": message })
+}
+
+/// names installed by add_builtins
+fn builtin_names() -> BTreeSet<String> {
+    let env = Rc::new(RefCell::new(Lenv::new()));
+    add_builtins(env.clone());
+    let names = env.borrow().data.keys().map(|k| k.name().to_string()).collect();
+    names
+}
+
+fn position(params: &Value) -> Pos {
+    Pos {
+        line: params["position"]["line"].as_u64().unwrap_or(0) as usize,
+        character: params["position"]["character"].as_u64().unwrap_or(0) as usize,
+    }
+}
+
+/// serves one client, returning after the exit notification or at end of input
+pub fn serve(mut input: Box<dyn BufRead>, mut output: Box<dyn Write>) -> io::Result<()> {
+    let mut documents: HashMap<String, Analysis> = HashMap::new();
+
+    while let Some(message) = read_message(&mut input)? {
+        let params = &message["params"];
+        let uri = params["textDocument"]["uri"].as_str().unwrap_or("").to_string();
+        let method = message["method"].as_str().unwrap_or("");
+
+        // notifications
+        let changed = match method {
+            "textDocument/didOpen" => Some(params["textDocument"]["text"].as_str().unwrap_or("")),
+            // full document sync: the last change holds the whole text
+            "textDocument/didChange" => params["contentChanges"].as_array()
+                .and_then(|c| c.last())
+                .and_then(|c| c["text"].as_str()),
+            "textDocument/didClose" => {
+                documents.remove(&uri);
+                publish(&mut output, &uri, Vec::new())?;
+                continue;
+            },
+            "exit" => return Ok(()),
+            _ => None,
+        };
+        if let Some(text) = changed {
+            let analysis = Analysis::new(text);
+            publish(&mut output, &uri, analysis.diagnostics.clone())?;
+            documents.insert(uri, analysis);
+            continue;
+        }
+
+        // requests
+        let id = match message.get("id") {
+            Some(id) => id.clone(),
+            None => continue,
+        };
+        let doc = documents.get(&uri);
+        let result = match method {
+            "initialize" => json!({
+                "capabilities": {
+                    "textDocumentSync": 1,
+                    "definitionProvider": true,
+                    "hoverProvider": true,
+                    "completionProvider": {},
+                    "documentSymbolProvider": true,
+                },
+                "serverInfo": { "name": "rustlisp" },
+            }),
+            "shutdown" => Value::Null,
+            "textDocument/definition" => doc.and_then(|doc| {
+                let (name, _) = doc.symbol_at(position(params))?;
+                let def = doc.definition(name, position(params))?;
+                Some(json!({ "uri": uri, "range": def.name_range.to_json() }))
+            }).unwrap_or(Value::Null),
+            "textDocument/hover" => doc.and_then(|doc| hover(doc, position(params))).unwrap_or(Value::Null),
+            "textDocument/completion" => completion(doc),
+            "textDocument/documentSymbol" => doc.map_or(json!([]), document_symbols),
+            _ => {
+                write_message(&mut output, &json!({
+                    "jsonrpc": "2.0",
+                    "id": id,
+                    "error": { "code": -32601, "message": format!("Method not found: {}", method) },
+                }))?;
+                continue;
+            },
+        };
+        write_message(&mut output, &json!({ "jsonrpc": "2.0", "id": id, "result": result }))?;
+    }
+    Ok(())
+}
+
+fn publish(output: &mut dyn Write, uri: &str, diagnostics: Vec<Value>) -> io::Result<()> {
+    write_message(output, &json!({
+        "jsonrpc": "2.0",
+        "method": "textDocument/publishDiagnostics",
+        "params": { "uri": uri, "diagnostics": diagnostics },
+    }))
+}
+
+fn hover(doc: &Analysis, pos: Pos) -> Option<Value> {
+    let (name, range) = doc.symbol_at(pos)?;
+    let text = match doc.definition(name, pos) {
+        Some(def) if def.kind == DefKind::Function => {
+            let mut call = vec![name.clone()];
+            call.extend(def.formals.iter().cloned());
+            format!("```lisp\n({})\n```", call.join(" "))
+        },
+        Some(def) if def.kind == DefKind::Parameter => format!("parameter `{}`", name),
+        Some(_) => format!("variable `{}`", name),
+        None if crate::builtin_profile(name).is_some() => format!("builtin function `{}`", name),
+        None if is_special_form(name) => format!("special form `{}`", name),
+        None => return None,
+    };
+    Some(json!({ "contents": { "kind": "markdown", "value": text }, "range": range.to_json() }))
+}
+
+fn completion(doc: Option<&Analysis>) -> Value {
+    let mut items = Vec::new();
+    for name in builtin_names() {
+        items.push(json!({ "label": name, "kind": COMPLETION_FUNCTION, "detail": "builtin" }));
+    }
+    for (name, _, _) in SPECIAL_FORMS {
+        items.push(json!({ "label": name, "kind": COMPLETION_KEYWORD, "detail": "special form" }));
+    }
+    let mut seen = BTreeSet::new();
+    for def in doc.iter().flat_map(|d| &d.definitions) {
+        if def.kind == DefKind::Parameter || !seen.insert(def.name.as_str()) { continue; }
+        let kind = if def.kind == DefKind::Function { COMPLETION_FUNCTION } else { COMPLETION_VARIABLE };
+        items.push(json!({ "label": def.name, "kind": kind }));
+    }
+    json!(items)
+}
+
+fn document_symbols(doc: &Analysis) -> Value {
+    let symbols: Vec<Value> = doc.definitions.iter()
+        .filter(|d| d.kind != DefKind::Parameter)
+        .map(|d| json!({
+            "name": d.name,
+            "detail": if d.kind == DefKind::Function { format!("({})", d.formals.join(" ")) } else { String::new() },
+            "kind": if d.kind == DefKind::Function { SYMBOL_FUNCTION } else { SYMBOL_VARIABLE },
+            "range": d.form_range.to_json(),
+            "selectionRange": d.name_range.to_json(),
+        }))
+        .collect();
+    json!(symbols)
+}
@@ -1,165 +1,264 @@
 // Application entry point. Handles Read-Eval-Print Loop (REPL)
-// initializes the enrionment, and parses input using Pest parser
+// initializes the enrionment, and reads input through rustyline
 
-extern crate pest;
-#[macro_use]
-extern crate pest_derive;
-
-// Pest is a popular Parser Generator library. Turns raww text into a structured Abstract Syntax Tree (AST)
-use pest::Parser;
+use rustlisp::debug::set_debugger;
+use rustlisp::eval::interrupt;
+use rustlisp::limits::set_limits;
+use rustlisp::types::{Lenv, Lval};
+use rustlisp::vm::Vm;
+use rustlisp::{add_builtins_with, eval_source};
 use rustyline::error::ReadlineError;
-use rustyline::DefaultEditor;
+use rustyline::history::DefaultHistory;
+use rustyline::{Editor, EventHandler, KeyEvent};
+use std::path::Path;
 use std::rc::Rc;
 use std::cell::RefCell;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
 
-mod types;
-mod eval;
-
-use types::{Lval, Lenv};
-use eval::*;
-
-// magic macro for pest
-// compiles code for parser at compile-time
-#[derive(Parser)]
-#[grammar = "grammar.pest"]
-pub struct LispyParser;
-
-/// converts pest parser output into lval for evaluation looks at the 
-/// rules generated by pest and creates the correct corresponding Lval type
-fn read(pair: pest::iterators::Pair<Rule>) -> Lval {
-    match pair.as_rule() {
-        Rule::number => {
-            let n = pair.as_str().parse::<i64>().unwrap();
-            Lval::Num(n)
-        },
-        Rule::symbol => {
-            Lval::Sym(pair.as_str().to_string())
-        },
-        Rule::string => {
-            let s = pair.as_str();
-            Lval::Qexpr(vec![Lval::Sym(s[1..s.len()-1].to_string())])
+mod repl;
+mod commands;
+mod config;
+mod debugger;
+mod fmt;
+mod lint;
+
+use repl::{is_complete, InterruptWatch, LispHelper, CONTINUATION_PROMPT};
+use commands::{load_file, run_command, Command};
+use config::{Backend, Config, USAGE};
+use debugger::ReplDebugger;
+
+/// the evaluator chosen with --backend
+enum Evaluator {
+    Tree(Rc<RefCell<Lenv>>),
+    Ast(rustlisp::ast::Evaluator),
+    Vm(Vm),
+}
+
+impl Evaluator {
+    fn new(backend: Backend, env: &Rc<RefCell<Lenv>>) -> Self {
+        match backend {
+            Backend::Tree => Evaluator::Tree(env.clone()),
+            Backend::Ast => Evaluator::Ast(rustlisp::ast::Evaluator::new(env.clone())),
+            Backend::Vm => Evaluator::Vm(Vm::new(env.clone())),
+        }
+    }
+
+    /// evaluates every form in src, handing each result to on_result
+    fn eval_source(&mut self, src: &str, on_result: impl FnMut(Lval)) -> Result<(), String> {
+        match self {
+            Evaluator::Tree(env) => eval_source(env, src, on_result),
+            Evaluator::Ast(ast) => ast.eval_source(src, on_result),
+            Evaluator::Vm(vm) => vm.eval_source(src, on_result),
+        }
+    }
+}
+
+/// outcome of reading one line at the prompt
+enum Input {
+    Line(String),
+    Interrupted,
+    Exit,
+}
+
+/// reads one line of input with the given prompt
+fn read_line(rl: &mut Editor<LispHelper, DefaultHistory>, prompt: &str) -> Input {
+    match rl.readline(prompt) {
+        Ok(l) => Input::Line(l),
+        Err(ReadlineError::Interrupted) => Input::Interrupted,
+        Err(ReadlineError::Eof) => {
+            println!("CTRL-D");
+            Input::Exit
         },
-        Rule::sexpr => {
-            let mut cells = Vec::new();
-            for inner_pair in pair.into_inner() {
-                cells.push(read(inner_pair));
+        Err(ReadlineError::Io(ref e)) if e.kind() == std::io::ErrorKind::PermissionDenied => {
+            // Fallback for debuggers/environments where rustyline cannot access the console
+            use std::io::{self, Write};
+            print!("{}", prompt);
+            let _ = io::stdout().flush();
+            let mut buffer = String::new();
+            match io::stdin().read_line(&mut buffer) {
+                Ok(0) | Err(_) => Input::Exit,
+                Ok(_) => Input::Line(buffer.trim().to_string()),
             }
-            Lval::Sexpr(cells)
-        },
-        Rule::qexpr => {
-            let inner_pair = pair.into_inner().next().unwrap();
-            let val = read(inner_pair);
-            Lval::Qexpr(vec![val])
         },
-        Rule::expr => {
-            read(pair.into_inner().next().unwrap())
-        },
-        Rule::lispy => {
-            Lval::Err("Should not call read on lispy rule directly".to_string())
-        },
-        _ => Lval::Err("Unknown rule".to_string()),
+        Err(err) => {
+            println!("Error: {:?}", err);
+            Input::Exit
+        }
     }
 }
 
+// Lisp recursion runs on the Rust stack, so the REPL gets a thread with room
+// for the default recursion depth limit
+const STACK_SIZE: usize = 256 << 20;
+
 fn main() {
-    // initialize rustyline
-    let mut rl = DefaultEditor::new().unwrap();
-    if rl.load_history("history.txt").is_err() {
-        println!("No previous history.");
+    // `rustlisp dap` and `rustlisp lsp` serve editor protocols on stdio,
+    // `rustlisp fmt` formats files and `rustlisp lint` checks them, instead
+    // of starting the REPL
+    let run: fn() = match std::env::args().nth(1).as_deref() {
+        Some("dap") => run_dap,
+        Some("lsp") => run_lsp,
+        Some("fmt") => run_fmt,
+        Some("lint") => run_lint,
+        _ => run_repl,
+    };
+    let repl = std::thread::Builder::new()
+        .stack_size(STACK_SIZE)
+        .spawn(run)
+        .expect("could not start interpreter thread");
+    if repl.join().is_err() {
+        std::process::exit(101);
+    }
+}
+
+fn run_dap() {
+    let input = Box::new(std::io::stdin().lock());
+    let output = Box::new(std::io::stdout());
+    if let Err(e) = rustlisp::dap::serve(input, output) {
+        eprintln!("Error: {}", e);
+        std::process::exit(1);
     }
+}
+
+fn run_lsp() {
+    let input = Box::new(std::io::stdin().lock());
+    let output = Box::new(std::io::stdout());
+    if let Err(e) = rustlisp::lsp::serve(input, output) {
+        eprintln!("Error: {}", e);
+        std::process::exit(1);
+    }
+}
+
+fn run_fmt() {
+    std::process::exit(fmt::run(std::env::args().skip(2)));
+}
+
+fn run_lint() {
+    std::process::exit(lint::run(std::env::args().skip(2)));
+}
+
+fn run_repl() {
+    let config = match Config::from_args(std::env::args().skip(1), |name| std::env::var_os(name)) {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("Error: {}\n\n{}", e, USAGE);
+            std::process::exit(2);
+        },
+    };
+    if config.help {
+        println!("{}", USAGE);
+        return;
+    }
+
+    set_limits(config.limits);
 
     // Create global environment to store variables and functions
     let env = Rc::new(RefCell::new(Lenv::new()));
-    add_builtins(env.clone());
+    add_builtins_with(env.clone(), config.profile);
+    // (break) and :step pause into the debug> prompt. The debugger steps through
+    // lval_eval, so the ast and vm backends run without one and (break) does nothing
+    if config.backend == Backend::Tree {
+        set_debugger(Some(Box::new(ReplDebugger::new(env.clone()))));
+    }
+
+    // Ctrl-C while evaluating aborts the evaluation instead of the process.
+    // At the prompt rustyline reads the key itself and reports Interrupted.
+    if let Err(e) = ctrlc::set_handler(interrupt) {
+        println!("Warning: could not install Ctrl+c handler: {}", e);
+    }
+
+    // initialize rustyline
+    let mut rl: Editor<LispHelper, DefaultHistory> = Editor::new().unwrap();
+    rl.set_helper(Some(LispHelper::new(env.clone())));
+    let empty_interrupt = Arc::new(AtomicBool::new(true));
+    rl.bind_sequence(KeyEvent::ctrl('C'), EventHandler::Conditional(Box::new(InterruptWatch(empty_interrupt.clone()))));
+    if let Some(ref path) = config.history {
+        if rl.load_history(path).is_err() {
+            println!("No previous history.");
+        }
+    }
 
     println!("Lispy Version 0.1.0");
-    println!("Press Ctrl+c to exit\n");
+    println!("Press Ctrl+c twice or Ctrl+d to exit\n");
+
+    // the ast and vm backends keep analyzed and compiled functions between lines
+    let mut evaluator = Evaluator::new(config.backend, &env);
+
+    // user init file predefines helpers; a missing file is not an error
+    if let Some(ref path) = config.init_file {
+        if path.exists() {
+            if let Err(e) = load_file(&mut evaluator, config.profile, path) {
+                println!("Error: {}", e);
+            }
+        }
+    }
 
     // REPL
-    loop {
-
-        // Read line of input
-        let readline = rl.readline("lispy> ");
-        let line = match readline {
-            Ok(l) => {
-                let _ = rl.add_history_entry(l.as_str());
-                l
+    let mut exit_requested = false;
+    'repl: loop {
+        let mut line = match read_line(&mut rl, "lispy> ") {
+            Input::Line(l) => l,
+            // Ctrl-C on a half-typed line only discards it
+            Input::Interrupted if !empty_interrupt.load(Ordering::Relaxed) => {
+                exit_requested = false;
+                continue;
             },
-            Err(ReadlineError::Interrupted) => {
+            Input::Interrupted if exit_requested => {
                 println!("CTRL-C");
                 break;
             },
-            Err(ReadlineError::Eof) => {
-                println!("CTRL-D");
-                break;
-            },
-            Err(ReadlineError::Io(ref e)) if e.kind() == std::io::ErrorKind::PermissionDenied => {
-                // Fallback for debuggers/environments where rustyline cannot access the console
-                use std::io::{self, Write};
-                print!("lispy> ");
-                let _ = io::stdout().flush();
-                let mut buffer = String::new();
-                match io::stdin().read_line(&mut buffer) {
-                    Ok(_) => buffer.trim().to_string(),
-                    Err(_) => break,
-                }
+            Input::Interrupted => {
+                println!("(press Ctrl+c again to exit)");
+                exit_requested = true;
+                continue;
             },
-            Err(err) => {
-                println!("Error: {:?}", err);
-                break;
-            }
+            Input::Exit => break,
         };
+        exit_requested = false;
 
-        // Prase line of input
-        let parse_result = LispyParser::parse(Rule::lispy, &line);
-        match parse_result {
-            Ok(mut pairs) => {
-                let lispy_pair = pairs.next().unwrap(); // lispy rule
-                for pair in lispy_pair.into_inner() {
-                    if pair.as_rule() == Rule::EOI { continue; }
-                    
-                    // convert parse tree to Lval using read
-                    let lval = read(pair);
-
-                    // evaluate Lval 
-                    let result = lval_eval(env.clone(), lval);
-    
-                    // print line output
-                    println!("{}", result);
-                }
-            },
-            Err(e) => println!("Error: {}", e),
+        // Read a complete form. The validator keeps an interactive editor open until
+        // parens and strings are balanced; piped input is joined line by line here.
+        while !is_complete(&line) {
+            match read_line(&mut rl, CONTINUATION_PROMPT) {
+                Input::Line(more) => {
+                    line.push('\n');
+                    line.push_str(&more);
+                },
+                // Ctrl-C drops the unfinished form
+                Input::Interrupted => continue 'repl,
+                Input::Exit => break 'repl,
+            }
+        }
+        let _ = rl.add_history_entry(line.as_str());
+
+        // colon commands inspect the session instead of being evaluated
+        if line.trim_start().starts_with(':') {
+            match run_command(&env, &mut evaluator, config.profile, &line) {
+                Command::Continue => continue,
+                Command::Quit => break,
+            }
+        }
+
+        // evaluate every form on the line and print its output
+        if let Err(e) = evaluator.eval_source(&line, |result| println!("{}", result)) {
+            println!("Error: {}", e);
         }
     }
-    rl.save_history("history.txt").unwrap();
+    if let Some(ref path) = config.history {
+        save_history(&mut rl, path);
+    }
 }
 
-
-// Registers bult-in functions 
-// by getting exclusive wwrite acces to the environment (lenv)
-fn add_builtins(e: Rc<RefCell<Lenv>>) {
-    e.borrow_mut().put("eval".to_string(), Lval::Fun(builtin_eval));
-    
-    e.borrow_mut().put("+".to_string(), Lval::Fun(builtin_add));
-    e.borrow_mut().put("-".to_string(), Lval::Fun(builtin_sub));
-    e.borrow_mut().put("*".to_string(), Lval::Fun(builtin_mul));
-    e.borrow_mut().put("/".to_string(), Lval::Fun(builtin_div));
-    
-    e.borrow_mut().put("def".to_string(), Lval::Fun(builtin_def));
-    e.borrow_mut().put("=".to_string(), Lval::Fun(builtin_put));
-    e.borrow_mut().put("set".to_string(), Lval::Fun(builtin_put));
-    e.borrow_mut().put("setq".to_string(), Lval::Fun(builtin_putq));
-    e.borrow_mut().put("\\".to_string(), Lval::Fun(builtin_lambda));
-    
-    e.borrow_mut().put("car".to_string(), Lval::Fun(builtin_head));
-    e.borrow_mut().put("cdr".to_string(), Lval::Fun(builtin_tail));
-    e.borrow_mut().put("cons".to_string(), Lval::Fun(builtin_cons));
-    e.borrow_mut().put("defun".to_string(), Lval::Fun(builtin_lambda));
-    e.borrow_mut().put("eq".to_string(), Lval::Fun(builtin_eq));
-    e.borrow_mut().put("equal".to_string(), Lval::Fun(builtin_equal));
-    e.borrow_mut().put("neq".to_string(), Lval::Fun(builtin_ne));
-    e.borrow_mut().put("null".to_string(), Lval::Fun(builtin_null));
-    e.borrow_mut().put("cond".to_string(), Lval::Fun(builtin_cond));
-    e.borrow_mut().put("quote".to_string(), Lval::Fun(builtin_quote));
-    e.borrow_mut().put("print".to_string(), Lval::Fun(builtin_print));
+/// writes history, creating its directory on first use
+fn save_history(rl: &mut Editor<LispHelper, DefaultHistory>, path: &Path) {
+    if let Some(dir) = path.parent() {
+        if let Err(e) = std::fs::create_dir_all(dir) {
+            println!("Warning: could not create '{}': {}", dir.display(), e);
+            return;
+        }
+    }
+    if let Err(e) = rl.save_history(path) {
+        println!("Warning: could not save history to '{}': {}", path.display(), e);
+    }
 }
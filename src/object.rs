@@ -0,0 +1,562 @@
+// Classes and generic functions, a small part of CLOS.
+//
+// (defclass circle (shape) ((r :initarg :r :initform 1 :accessor circle-r)))
+// defines a class with its superclasses and slots. A class has the slots of
+// its superclasses as well as its own, and make-instance fills each from its
+// initarg or else its initform. Superclasses are looked up by name whenever an
+// instance is made or a method chosen, so redefining one reaches the classes
+// inheriting from it. An instance is an Lval::Object on the gc heap that every
+// copy of the value shares, like a structure.
+//
+// A generic function is an Lval::Generic holding methods, each specialized on
+// a class for every required argument. Calling it finds the methods whose
+// classes the arguments belong to and orders them most specific first,
+// comparing the arguments left to right by how far up each argument's class
+// precedence list the method's class is. The :before methods run first, most
+// specific first, then the most specific primary method, whose result is the
+// call's, then the :after methods, least specific first. call-next-method
+// calls the next primary method. Values that are not instances belong to the
+// class type-of names and to t; lists also belong to list and vectors to array.
+
+use crate::eval::{lval_call_named, lval_eval, type_of};
+use crate::gc::{self, Trace, Tracer};
+use crate::record::root;
+use crate::symbol::{self, Symbol};
+use crate::types::{Lenv, Lval};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::mem::size_of;
+use std::rc::Rc;
+
+pub struct Class {
+    pub name: Symbol,
+    /// the direct superclasses, leftmost first
+    supers: Vec<Symbol>,
+    /// the slots it defines itself
+    slots: Vec<Slot>,
+}
+
+impl Class {
+    /// the class and its superclasses as now defined, depth first and left to
+    /// right, without t
+    fn precedence(&self) -> Vec<Symbol> {
+        let mut precedence = vec![self.name];
+        for sup in self.supers.iter().filter_map(|s| find_class(*s)) {
+            for c in sup.precedence() {
+                if !precedence.contains(&c) { precedence.push(c); }
+            }
+        }
+        precedence
+    }
+
+    /// its own slots and those it inherits from its superclasses as now defined
+    fn all_slots(&self) -> Vec<Slot> {
+        let mut slots = Vec::new();
+        // the leftmost superclass's slot options win over those further right
+        for sup in self.supers.iter().rev().filter_map(|s| find_class(*s)) {
+            for slot in sup.all_slots() { merge_slot(&mut slots, slot); }
+        }
+        for slot in &self.slots { merge_slot(&mut slots, slot.clone()); }
+        slots
+    }
+}
+
+#[derive(Clone)]
+struct Slot {
+    name: Symbol,
+    /// the keywords make-instance takes for the slot
+    initargs: Vec<Symbol>,
+    /// the form giving the slot's value when no initarg does
+    initform: Option<Lval>,
+}
+
+pub struct Instance {
+    pub class: Rc<Class>,
+    /// the slots the class had when the instance was made
+    names: Vec<Symbol>,
+    values: Vec<Lval>,
+}
+
+impl Instance {
+    fn position(&self, slot: Symbol) -> Option<usize> {
+        self.names.iter().position(|s| *s == slot)
+    }
+
+    pub fn slot(&self, i: usize) -> Lval {
+        self.values[i].clone()
+    }
+
+    pub fn set_slot(&mut self, i: usize, v: Lval) {
+        self.values[i] = v;
+    }
+
+    /// each slot's name with its value
+    pub fn slots(&self) -> impl Iterator<Item = (Symbol, &Lval)> {
+        self.names.iter().copied().zip(&self.values)
+    }
+}
+
+impl Trace for Instance {
+    fn trace(&self, tracer: &mut Tracer) {
+        for v in &self.values { tracer.value(v); }
+    }
+
+    fn clear(&mut self) {
+        for v in &mut self.values { *v = Lval::NIL; }
+    }
+
+    fn bytes(&self) -> usize {
+        size_of::<Self>() + self.values.len() * size_of::<Lval>()
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Qualifier {
+    Primary,
+    Before,
+    After,
+}
+
+#[derive(Clone)]
+enum Function {
+    Lambda(Lval),
+    /// reads a slot, for the :reader and :accessor slot options
+    Reader(Symbol),
+}
+
+#[derive(Clone)]
+struct Method {
+    qualifier: Qualifier,
+    /// the class each required argument must belong to
+    specializers: Vec<Symbol>,
+    function: Function,
+}
+
+pub struct Generic {
+    pub name: Symbol,
+    /// the number of required arguments
+    arity: usize,
+    methods: Vec<Method>,
+}
+
+impl Generic {
+    pub fn arity(&self) -> usize {
+        self.arity
+    }
+
+    /// adds a method, replacing one with the same qualifier and specializers
+    fn add(&mut self, method: Method) {
+        let same = |m: &&mut Method| m.qualifier == method.qualifier && m.specializers == method.specializers;
+        match self.methods.iter_mut().find(same) {
+            Some(m) => *m = method,
+            None => self.methods.push(method),
+        }
+    }
+}
+
+impl Trace for Generic {
+    fn trace(&self, tracer: &mut Tracer) {
+        for m in &self.methods {
+            if let Function::Lambda(f) = &m.function { tracer.value(f); }
+        }
+    }
+
+    fn clear(&mut self) {
+        self.methods.clear();
+    }
+}
+
+/// the rest of the primary methods of a call in progress, for call-next-method
+#[derive(Clone)]
+struct Next {
+    name: Symbol,
+    methods: Vec<Method>,
+    args: Vec<Lval>,
+}
+
+thread_local! {
+    /// every class defclass has defined, by name
+    static CLASSES: RefCell<HashMap<Symbol, Rc<Class>>> = RefCell::new(HashMap::new());
+    /// one entry for each method running, innermost last
+    static NEXT: RefCell<Vec<Next>> = const { RefCell::new(Vec::new()) };
+}
+
+fn find_class(name: Symbol) -> Option<Rc<Class>> {
+    CLASSES.with(|c| c.borrow().get(&name).cloned())
+}
+
+/// the elements of a list form, where () and NIL are empty
+fn form_items(v: &Lval) -> Option<&[Lval]> {
+    match v {
+        Lval::NIL => Some(&[]),
+        Lval::Sexpr(cells) => Some(cells),
+        _ => None,
+    }
+}
+
+/// the generic function bound to `name`, made and defined globally if there
+/// is none. One taking a different number of arguments is replaced
+fn ensure_generic(e: &Rc<RefCell<Lenv>>, name: Symbol, arity: usize) -> Rc<RefCell<Generic>> {
+    if let Some(Lval::Generic(generic)) = e.borrow().get(name) {
+        if generic.borrow().arity == arity { return generic; }
+    }
+    let generic = gc::alloc("generic-functions", Generic { name, arity, methods: Vec::new() });
+    e.borrow_mut().def(name, Lval::Generic(generic.clone()));
+    generic
+}
+
+/// reads a slot specifier: a name, or (name option value ...). Returns the
+/// slot with the names of its readers
+fn read_slot(spec: &Lval) -> Result<(Slot, Vec<Symbol>), Lval> {
+    let malformed = || Lval::Err(format!("Malformed slot {} in defclass", spec));
+    let (name, options) = match spec {
+        Lval::Sym(name) => (*name, &[][..]),
+        Lval::Sexpr(cells) => match cells.split_first() {
+            Some((Lval::Sym(name), options)) if options.len() % 2 == 0 => (*name, options),
+            _ => return Err(malformed()),
+        },
+        _ => return Err(malformed()),
+    };
+    let mut slot = Slot { name, initargs: Vec::new(), initform: None };
+    let mut readers = Vec::new();
+    for pair in options.chunks(2) {
+        match pair {
+            [Lval::Sym(k), Lval::Sym(arg)] if k.name() == ":initarg" && arg.is_keyword() => slot.initargs.push(*arg),
+            [Lval::Sym(k), form] if k.name() == ":initform" => slot.initform = Some(form.clone()),
+            [Lval::Sym(k), Lval::Sym(reader)] if k.name() == ":accessor" || k.name() == ":reader" => readers.push(*reader),
+            _ => return Err(malformed()),
+        }
+    }
+    Ok((slot, readers))
+}
+
+/// adds a slot to a class's slots, merging it into an inherited one of the same name
+fn merge_slot(slots: &mut Vec<Slot>, slot: Slot) {
+    match slots.iter_mut().find(|s| s.name == slot.name) {
+        Some(s) => {
+            s.initargs.extend(slot.initargs);
+            if slot.initform.is_some() { s.initform = slot.initform; }
+        },
+        None => slots.push(slot),
+    }
+}
+
+/// (defclass name (superclass ...) (slot ...)) defines a class and returns
+/// its name. A slot is a name or (name option value ...), the options being
+/// :initarg :keyword, :initform form, and :accessor or :reader naming a
+/// generic function that gets a method reading the slot
+pub fn builtin_defclass(e: Rc<RefCell<Lenv>>, args: Vec<Lval>) -> Lval {
+    if args.len() != 4 { return Lval::Err("Function 'defclass' passed incorrect number of arguments.".to_string()); }
+    let Lval::Sym(name) = args[1] else { return Lval::Err("First argument to defclass must be a symbol".to_string()) };
+    let (Some(supers), Some(specs)) = (form_items(&args[2]), form_items(&args[3])) else {
+        return Lval::Err("Superclasses and slots given to defclass must be lists".to_string());
+    };
+
+    let mut inherited = Vec::new();
+    for sup in supers {
+        let class = match sup {
+            Lval::Sym(s) => find_class(*s).ok_or_else(|| format!("Unknown class {}", s)),
+            _ => Err(format!("Malformed superclass {} in defclass", sup)),
+        };
+        match class {
+            Ok(class) if class.precedence().contains(&name) => {
+                return Lval::Err(format!("Class {} cannot inherit from itself through {}", name, class.name));
+            },
+            Ok(class) => inherited.push(class.name),
+            Err(message) => return Lval::Err(message),
+        }
+    }
+    let mut slots = Vec::new();
+    let mut readers = Vec::new();
+    for spec in specs {
+        match read_slot(spec) {
+            Ok((slot, names)) => {
+                readers.extend(names.into_iter().map(|r| (r, slot.name)));
+                merge_slot(&mut slots, slot);
+            },
+            Err(err) => return err,
+        }
+    }
+
+    CLASSES.with(|c| c.borrow_mut().insert(name, Rc::new(Class { name, supers: inherited, slots })));
+    for (reader, slot) in readers {
+        let method = Method { qualifier: Qualifier::Primary, specializers: vec![name], function: Function::Reader(slot) };
+        ensure_generic(&e, reader, 1).borrow_mut().add(method);
+    }
+    Lval::Sym(name)
+}
+
+/// (defgeneric name (arg ...)) defines a generic function without methods
+/// and returns its name. Methods of an existing one taking as many arguments are kept
+pub fn builtin_defgeneric(e: Rc<RefCell<Lenv>>, args: Vec<Lval>) -> Lval {
+    if args.len() != 3 { return Lval::Err("Function 'defgeneric' passed incorrect number of arguments.".to_string()); }
+    let Lval::Sym(name) = args[1] else { return Lval::Err("First argument to defgeneric must be a symbol".to_string()) };
+    let Some(formals) = form_items(&args[2]) else { return Lval::Err("Second argument to defgeneric must be a list".to_string()) };
+    ensure_generic(&e, name, formals.len());
+    Lval::Sym(name)
+}
+
+/// (defmethod name [:before|:after] (arg ...) body) adds a method to a
+/// generic function, defining the function if need be, and returns its name.
+/// An argument is a name, specialized on t, or (name class)
+pub fn builtin_defmethod(e: Rc<RefCell<Lenv>>, args: Vec<Lval>) -> Lval {
+    let (qualifier, rest) = match args.get(2) {
+        Some(Lval::Sym(q)) if q.name() == ":before" => (Qualifier::Before, &args[3..]),
+        Some(Lval::Sym(q)) if q.name() == ":after" => (Qualifier::After, &args[3..]),
+        Some(Lval::Sym(q)) if q.is_keyword() => return Lval::Err(format!("Unknown method qualifier {}", q)),
+        _ => (Qualifier::Primary, args.get(2..).unwrap_or(&[])),
+    };
+    let (formals, body) = match rest {
+        [formals, body] => (formals, body),
+        // as with defun, the body is a single form
+        [_, forms @ ..] if forms.len() > 1 => {
+            return Lval::Err(format!("defmethod takes one body form, got {}", forms.len()));
+        },
+        _ => return Lval::Err("Function 'defmethod' passed incorrect number of arguments.".to_string()),
+    };
+    let Lval::Sym(name) = args[1] else { return Lval::Err("First argument to defmethod must be a symbol".to_string()) };
+    let Some(formals) = form_items(formals) else { return Lval::Err("Arguments given to defmethod must be a list".to_string()) };
+
+    let (mut params, mut specializers) = (Vec::new(), Vec::new());
+    for formal in formals {
+        let (param, class) = match formal {
+            Lval::Sym(p) => (*p, symbol::T_LOWER),
+            Lval::Sexpr(cells) => match cells.as_slice() {
+                [Lval::Sym(p), Lval::Sym(class)] => (*p, *class),
+                [Lval::Sym(p), Lval::T] => (*p, symbol::T_LOWER),
+                _ => return Lval::Err(format!("Malformed argument {} in defmethod", formal)),
+            },
+            _ => return Lval::Err(format!("Malformed argument {} in defmethod", formal)),
+        };
+        params.push(Lval::Sym(param));
+        specializers.push(class);
+    }
+
+    // the body is run as a defun's is; a lone atom is evaluated as it is
+    // rather than as a one element list, which would call a function it names.
+    // A quoted body is kept in a list, since a Qexpr body is the list it holds
+    let body = match body {
+        Lval::Sexpr(cells) => Lval::Qexpr(cells.clone()),
+        quoted @ Lval::Qexpr(_) => Lval::Qexpr(vec![quoted.clone()]),
+        atom => atom.clone(),
+    };
+    let lambda = Lval::Lambda(Lenv::new(), Rc::new(Lval::Qexpr(params)), Rc::new(body));
+    // a method must take the arguments its generic function does
+    if let Some(Lval::Generic(generic)) = e.borrow().get(name) {
+        let arity = generic.borrow().arity;
+        if arity != specializers.len() {
+            return Lval::Err(format!("Method for {} takes {} arguments, but the generic function takes {}", name, specializers.len(), arity));
+        }
+    }
+    let generic = ensure_generic(&e, name, specializers.len());
+    generic.borrow_mut().add(Method { qualifier, specializers, function: Function::Lambda(lambda) });
+    Lval::Sym(name)
+}
+
+/// the classes a value belongs to, most specific first
+fn classes(v: &Lval) -> Vec<Symbol> {
+    let mut classes = match v {
+        Lval::Object(instance) => instance.borrow().class.precedence(),
+        v => {
+            let name = type_of(v);
+            match name.name() {
+                "cons" | "null" => vec![name, Symbol::intern("list")],
+                "vector" => vec![name, Symbol::intern("array")],
+                _ => vec![name],
+            }
+        },
+    };
+    classes.push(symbol::T_LOWER);
+    classes
+}
+
+/// the methods applicable to arguments belonging to `classes`, most specific first
+fn applicable(methods: &[Method], classes: &[Vec<Symbol>]) -> Vec<Method> {
+    let mut found: Vec<(Vec<usize>, &Method)> = methods.iter().filter_map(|m| {
+        let rank = m.specializers.iter().zip(classes)
+            .map(|(class, of_arg)| of_arg.iter().position(|c| c == class))
+            .collect::<Option<Vec<usize>>>()?;
+        Some((rank, m))
+    }).collect();
+    found.sort_by(|a, b| a.0.cmp(&b.0));
+    found.into_iter().map(|(_, m)| m.clone()).collect()
+}
+
+/// calls a generic function
+pub fn call(e: Rc<RefCell<Lenv>>, generic: &Rc<RefCell<Generic>>, args: Vec<Lval>) -> Lval {
+    let (name, methods) = {
+        let generic = generic.borrow();
+        if args.len() != generic.arity {
+            return Lval::Err(format!("Function '{}' passed incorrect number of arguments.", generic.name));
+        }
+        let classes: Vec<_> = args.iter().map(classes).collect();
+        (generic.name, applicable(&generic.methods, &classes))
+    };
+    let of = |q| methods.iter().filter(|m| m.qualifier == q).cloned().collect::<Vec<_>>();
+    let (before, primary, after) = (of(Qualifier::Before), of(Qualifier::Primary), of(Qualifier::After));
+    if primary.is_empty() {
+        return Lval::Err(format!("No applicable method for {} with arguments {}", name, Lval::Sexpr(args)));
+    }
+
+    for method in &before {
+        if let err @ Lval::Err(_) = run(&e, name, method, args.clone(), Vec::new()) { return err; }
+    }
+    let result = run_primary(&e, name, primary, args.clone());
+    if let Lval::Err(_) = result { return result; }
+    for method in after.iter().rev() {
+        if let err @ Lval::Err(_) = run(&e, name, method, args.clone(), Vec::new()) { return err; }
+    }
+    result
+}
+
+/// runs the first of `methods`, with the rest available to call-next-method
+fn run_primary(e: &Rc<RefCell<Lenv>>, name: Symbol, mut methods: Vec<Method>, args: Vec<Lval>) -> Lval {
+    let method = methods.remove(0);
+    run(e, name, &method, args, methods)
+}
+
+fn run(e: &Rc<RefCell<Lenv>>, name: Symbol, method: &Method, args: Vec<Lval>, next: Vec<Method>) -> Lval {
+    match &method.function {
+        Function::Reader(slot) => match instance_arg(&args[0], name.name()) {
+            Ok(instance) => slot_value(&instance.borrow(), *slot),
+            Err(err) => err,
+        },
+        Function::Lambda(f) => {
+            NEXT.with(|n| n.borrow_mut().push(Next { name, methods: next, args: args.clone() }));
+            let result = lval_call_named(e.clone(), name.name(), f.clone(), args);
+            NEXT.with(|n| n.borrow_mut().pop());
+            result
+        },
+    }
+}
+
+/// (call-next-method [arg ...]) calls the next most specific primary method
+/// with the arguments the running method was given, or with new ones
+pub fn builtin_call_next_method(e: Rc<RefCell<Lenv>>, args: Vec<Lval>) -> Lval {
+    let Some(next) = NEXT.with(|n| n.borrow().last().cloned()) else {
+        return Lval::Err("call-next-method used outside a method".to_string());
+    };
+    if next.methods.is_empty() { return Lval::Err(format!("No next method for {}", next.name)); }
+    let args = if args.is_empty() { next.args } else { args };
+    run_primary(&e, next.name, next.methods, args)
+}
+
+/// the instance in a builtin's argument
+fn instance_arg(v: &Lval, func: &str) -> Result<Rc<RefCell<Instance>>, Lval> {
+    match v {
+        Lval::Object(instance) => Ok(instance.clone()),
+        _ => Err(Lval::Err(format!("Argument to {} must be an instance", func))),
+    }
+}
+
+fn slot_position(instance: &Instance, slot: Symbol) -> Result<usize, Lval> {
+    instance.position(slot).ok_or_else(|| Lval::Err(format!("Instance of {} has no slot {}", instance.class.name, slot)))
+}
+
+fn slot_value(instance: &Instance, slot: Symbol) -> Lval {
+    match slot_position(instance, slot) {
+        Ok(i) => instance.slot(i),
+        Err(err) => err,
+    }
+}
+
+/// (make-instance 'class :initarg value ...) returns a new instance. A slot
+/// no initarg is given for gets its initform, evaluated in the global
+/// environment, or NIL
+pub fn builtin_make_instance(e: Rc<RefCell<Lenv>>, args: Vec<Lval>) -> Lval {
+    let Some((class, initargs)) = args.split_first() else {
+        return Lval::Err("Function 'make-instance' passed incorrect number of arguments.".to_string());
+    };
+    let class = match class.clone().unquote() {
+        Lval::Sym(name) => match find_class(name) {
+            Some(class) => class,
+            None => return Lval::Err(format!("Unknown class {}", name)),
+        },
+        _ => return Lval::Err("First argument to make-instance must be a class name".to_string()),
+    };
+    if !initargs.len().is_multiple_of(2) {
+        return Lval::Err("Function 'make-instance' passed incorrect arguments.".to_string());
+    }
+
+    let slots = class.all_slots();
+    let mut given = vec![None; slots.len()];
+    for pair in initargs.chunks(2) {
+        let slot = match &pair[0] {
+            Lval::Sym(k) if k.is_keyword() => slots.iter().position(|s| s.initargs.contains(k)),
+            _ => None,
+        };
+        match slot {
+            Some(i) => given[i] = Some(pair[1].clone()),
+            None => return Lval::Err(format!("Unknown initarg {} to make-instance of {}", pair[0], class.name)),
+        }
+    }
+    let globals = root(e);
+    let mut values = Vec::with_capacity(given.len());
+    for (v, slot) in given.into_iter().zip(&slots) {
+        let v = v.or_else(|| slot.initform.clone().map(|form| lval_eval(globals.clone(), form)));
+        match v.unwrap_or(Lval::NIL) {
+            err @ Lval::Err(_) => return err,
+            v => values.push(v.unquote()),
+        }
+    }
+    let names = slots.iter().map(|s| s.name).collect();
+    Lval::Object(gc::alloc("instances", Instance { class, names, values }))
+}
+
+/// the slot name in a slot-value argument, which may be quoted
+fn slot_arg(v: &Lval) -> Result<Symbol, Lval> {
+    match v.clone().unquote() {
+        Lval::Sym(slot) => Ok(slot),
+        _ => Err(Lval::Err("Slot name given to slot-value must be a symbol".to_string())),
+    }
+}
+
+/// (slot-value instance 'slot) is the value of one of an instance's slots
+pub fn builtin_slot_value(_e: Rc<RefCell<Lenv>>, args: Vec<Lval>) -> Lval {
+    if args.len() != 2 { return Lval::Err("Function 'slot-value' passed incorrect number of arguments.".to_string()); }
+    let found = instance_arg(&args[0], "slot-value")
+        .and_then(|instance| slot_arg(&args[1]).map(|slot| slot_value(&instance.borrow(), slot)));
+    found.unwrap_or_else(|err| err)
+}
+
+/// evaluates the instance and slot of a (slot-value instance 'slot) place,
+/// returning the instance with the slot's position
+pub fn place_args(e: &Rc<RefCell<Lenv>>, object: &Lval, slot: &Lval) -> Result<(Rc<RefCell<Instance>>, usize), Lval> {
+    let eval = |form: &Lval| match lval_eval(e.clone(), form.clone()) {
+        err @ Lval::Err(_) => Err(err),
+        v => Ok(v),
+    };
+    let instance = instance_arg(&eval(object)?, "slot-value")?;
+    let slot = slot_arg(&eval(slot)?)?;
+    let i = slot_position(&instance.borrow(), slot)?;
+    Ok((instance, i))
+}
+
+/// the generic function of an (accessor object) form, with the unevaluated
+/// object form, when accessor names one with a method reading a slot
+pub fn place<'a>(e: &Rc<RefCell<Lenv>>, v: &'a Lval) -> Option<(Rc<RefCell<Generic>>, &'a Lval)> {
+    let Lval::Sexpr(cells) = v else { return None };
+    let [Lval::Sym(head), object] = cells.as_slice() else { return None };
+    let Some(Lval::Generic(generic)) = e.borrow().get(*head) else { return None };
+    let reads = generic.borrow().methods.iter().any(|m| matches!(m.function, Function::Reader(_)));
+    reads.then_some((generic, object))
+}
+
+/// evaluates the object of an accessor place, returning the instance with the
+/// position of the slot the accessor's most specific method reads
+pub fn place_slot(e: &Rc<RefCell<Lenv>>, generic: &Rc<RefCell<Generic>>, object: &Lval) -> Result<(Rc<RefCell<Instance>>, usize), Lval> {
+    let object = match lval_eval(e.clone(), object.clone()) {
+        err @ Lval::Err(_) => return Err(err),
+        v => v,
+    };
+    let generic = generic.borrow();
+    let methods = applicable(&generic.methods, &[classes(&object)]);
+    match methods.iter().find(|m| m.qualifier == Qualifier::Primary).map(|m| &m.function) {
+        Some(Function::Reader(slot)) => {
+            let instance = instance_arg(&object, generic.name.name())?;
+            let i = slot_position(&instance.borrow(), *slot)?;
+            Ok((instance, i))
+        },
+        _ => Err(Lval::Err(format!("({} {}) is not a place", generic.name, object))),
+    }
+}
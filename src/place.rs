@@ -0,0 +1,352 @@
+// Generalized places, for setf and the forms built on it. A place is a
+// variable, an accessor applied to another place: (car p), (cdr p) or
+// (nth i p), a table entry: (gethash key table [default]), an array element:
+// (aref array index ...), a structure slot: (point-x p) for an accessor
+// defstruct defined, or an instance slot: (slot-value obj 'slot), or
+// (circle-r obj) for an accessor defclass defined. Tables, arrays, structures
+// and instances are changed in place, and so are lists made of cons cells:
+// setting the car, cdr or nth of one changes the cell, so every variable
+// holding the list sees the change. push and pop make or drop a cell at the
+// front and store the new list in the place. A list the reader built, such
+// as one inside an array literal, is a value instead, so changing it builds
+// the changed list and stores it back into the place it came from. The list
+// under car, cdr or nth need not be a place itself: in (setf (car (f)) 5)
+// the cell (f) returns is changed.
+// Subforms are evaluated once, left to right: the place's own subforms
+// first, then the new value.
+
+use crate::cons;
+use crate::eval::lval_eval;
+use crate::{array, hash, object, record};
+use crate::types::{Lenv, Lval};
+use std::cell::RefCell;
+use std::rc::Rc;
+
+/// what a form does with the value in a place: returns the value to store
+/// and the form's result, or an error that leaves the place unchanged
+type Change<'a> = Box<dyn FnOnce(Lval) -> Result<(Lval, Lval), Lval> + 'a>;
+
+fn not_a_list(v: &Lval) -> Lval {
+    Lval::Err(format!("{} is not a list", v))
+}
+
+/// the elements of a list, and whether it is a quoted one, so a changed
+/// copy keeps its shape. An unbound variable's error passes through
+fn elements(v: Lval) -> Result<(Vec<Lval>, bool), Lval> {
+    match v {
+        Lval::NIL => Ok((Vec::new(), false)),
+        Lval::Sexpr(cells) => Ok((cells, false)),
+        Lval::Qexpr(ref cells) => match cells.as_slice() {
+            [Lval::Sexpr(inner)] => Ok((inner.clone(), true)),
+            _ => Err(not_a_list(&v)),
+        },
+        Lval::Err(_) => Err(v),
+        _ => Err(not_a_list(&v)),
+    }
+}
+
+fn list(items: Vec<Lval>, quoted: bool) -> Lval {
+    if quoted { Lval::Qexpr(vec![Lval::Sexpr(items)]) } else { Lval::Sexpr(items) }
+}
+
+/// a list of cells in the same shape as the list it replaces; the end of
+/// the list is ()
+fn chain(l: Lval, quoted: bool) -> Lval {
+    let l = cons::rest(&l);
+    if quoted { Lval::Qexpr(vec![l]) } else { l }
+}
+
+/// whether a list of cells is quoted, or None if `v` is not one
+fn chain_quoted(v: &Lval) -> Option<bool> {
+    match v {
+        Lval::Cons(_) => Some(false),
+        Lval::Qexpr(cells) => matches!(cells.as_slice(), [Lval::Cons(_)]).then_some(true),
+        _ => None,
+    }
+}
+
+/// the accessor of a place form with its arguments, if `v` is one
+fn accessor(v: &Lval) -> Option<(&'static str, &[Lval])> {
+    let Lval::Sexpr(cells) = v else { return None };
+    let Some(Lval::Sym(head)) = cells.first() else { return None };
+    match (head.name(), cells.len()) {
+        (name @ ("car" | "cdr"), 2) | (name @ ("nth" | "slot-value"), 3) | (name @ "gethash", 3 | 4) => Some((name, &cells[1..])),
+        (name @ "aref", n) if n >= 3 => Some((name, &cells[1..])),
+        _ => None,
+    }
+}
+
+fn is_place(e: &Rc<RefCell<Lenv>>, v: &Lval) -> bool {
+    matches!(v, Lval::Sym(_)) || accessor(v).is_some() || record::place(e, v).is_some() || object::place(e, v).is_some()
+}
+
+/// applies `change` to the value in `place` and stores what it returns there
+fn update(e: &Rc<RefCell<Lenv>>, place: &Lval, change: Change) -> Lval {
+    if let Lval::Sym(s) = place {
+        let old = e.borrow().get(*s).unwrap_or_else(|| Lval::Err(format!("Unbound symbol '{}'", s)));
+        return match change(old) {
+            Ok((new, result)) => {
+                e.borrow_mut().set(*s, new);
+                result
+            },
+            Err(err) => err,
+        };
+    }
+    match accessor(place) {
+        Some(("car", [inner])) => update_list(e, inner, Box::new(|v| {
+            if let Some(cell) = cons::cell(&v) {
+                let old = cell.borrow().car.clone();
+                let (new, result) = change(old)?;
+                cell.borrow_mut().car = new.unquote();
+                return Ok((v, result));
+            }
+            let (mut items, quoted) = elements(v)?;
+            let Some(first) = items.first_mut() else {
+                return Err(Lval::Err("Cannot set the car of an empty list".to_string()));
+            };
+            let (new, result) = change(std::mem::replace(first, Lval::NIL))?;
+            *first = new.unquote();
+            Ok((list(items, quoted), result))
+        })),
+        Some(("cdr", [inner])) => update_list(e, inner, Box::new(|v| {
+            if let Some(cell) = cons::cell(&v) {
+                let old = cons::rest(&cell.borrow().cdr);
+                let (new, result) = change(old)?;
+                cell.borrow_mut().cdr = cons::tail(new.unquote());
+                return Ok((v, result));
+            }
+            let (mut items, quoted) = elements(v)?;
+            if items.is_empty() { return Err(Lval::Err("Cannot set the cdr of an empty list".to_string())); }
+            let (new, result) = change(Lval::Sexpr(items.split_off(1)))?;
+            items.extend(elements(new)?.0);
+            Ok((list(items, quoted), result))
+        })),
+        Some(("nth", [index, inner])) => {
+            let i = match lval_eval(e.clone(), index.clone()) {
+                Lval::Num(i) => i,
+                err @ Lval::Err(_) => return err,
+                _ => return Lval::Err("Index given to nth must be a number".to_string()),
+            };
+            update_list(e, inner, Box::new(move |v| {
+                if let Some(cell) = cons::cell(&v) {
+                    let Some(cell) = usize::try_from(i).ok().and_then(|i| cons::nth_cell(&cell, i)) else {
+                        return Err(Lval::Err(format!("Index {} is out of range", i)));
+                    };
+                    let old = cell.borrow().car.clone();
+                    let (new, result) = change(old)?;
+                    cell.borrow_mut().car = new.unquote();
+                    return Ok((v, result));
+                }
+                let (mut items, quoted) = elements(v)?;
+                let Some(slot) = usize::try_from(i).ok().and_then(|i| items.get_mut(i)) else {
+                    return Err(Lval::Err(format!("Index {} is out of range", i)));
+                };
+                let (new, result) = change(std::mem::replace(slot, Lval::NIL))?;
+                *slot = new.unquote();
+                Ok((list(items, quoted), result))
+            }))
+        },
+        Some(("gethash", [key, table, default @ ..])) => {
+            let (key, table) = match hash::place_args(e, key, table) {
+                Ok(args) => args,
+                Err(err) => return err,
+            };
+            let found = table.borrow().get(&key).cloned();
+            let old = match (found, default.first()) {
+                (Some(v), _) => v,
+                (None, Some(d)) => lval_eval(e.clone(), d.clone()),
+                (None, None) => Lval::NIL,
+            };
+            match change(old) {
+                Ok((new, result)) => {
+                    table.borrow_mut().insert(key, new.unquote());
+                    result
+                },
+                Err(err) => err,
+            }
+        },
+        Some(("aref", [array, indices @ ..])) => {
+            let (array, indices) = match array::place_args(e, array, indices) {
+                Ok(args) => args,
+                Err(err) => return err,
+            };
+            let old = array.borrow().get(&indices);
+            match old.and_then(change) {
+                Ok((new, result)) => match array.borrow_mut().set(&indices, new.unquote()) {
+                    Ok(()) => result,
+                    Err(err) => err,
+                },
+                Err(err) => err,
+            }
+        },
+        Some(("slot-value", [instance, slot])) => match object::place_args(e, instance, slot) {
+            Ok((instance, i)) => update_instance(&instance, i, change),
+            Err(err) => err,
+        },
+        _ => if let Some((kind, i, object)) = record::place(e, place) {
+            let record = match record::place_record(e, &kind, i, object) {
+                Ok(record) => record,
+                Err(err) => return err,
+            };
+            let old = record.borrow().slot(i);
+            match change(old) {
+                Ok((new, result)) => {
+                    record.borrow_mut().set_slot(i, new.unquote());
+                    result
+                },
+                Err(err) => err,
+            }
+        } else if let Some((generic, instance)) = object::place(e, place) {
+            match object::place_slot(e, &generic, instance) {
+                Ok((instance, i)) => update_instance(&instance, i, change),
+                Err(err) => err,
+            }
+        } else {
+            Lval::Err(format!("{} is not a place", place))
+        },
+    }
+}
+
+/// applies `change` to the list `form` holds: through `update` when it is a
+/// place, or to the value of any other form, whose cells are changed while
+/// the value itself is not stored anywhere
+fn update_list(e: &Rc<RefCell<Lenv>>, form: &Lval, change: Change) -> Lval {
+    if is_place(e, form) { return update(e, form, change); }
+    match eval_for(e.clone(), form.clone()).and_then(change) {
+        Ok((_, result)) => result,
+        Err(err) => err,
+    }
+}
+
+fn update_instance(instance: &Rc<RefCell<object::Instance>>, i: usize, change: Change) -> Lval {
+    let old = instance.borrow().slot(i);
+    match change(old) {
+        Ok((new, result)) => {
+            instance.borrow_mut().set_slot(i, new.unquote());
+            result
+        },
+        Err(err) => err,
+    }
+}
+
+/// evaluates a form for a Change, turning an error into Err
+fn eval_for(e: Rc<RefCell<Lenv>>, form: Lval) -> Result<Lval, Lval> {
+    match lval_eval(e, form) {
+        err @ Lval::Err(_) => Err(err),
+        v => Ok(v),
+    }
+}
+
+/// (setf place value ...) stores each value in its place and returns the last.
+/// setf and the forms below are special forms, since a place is not evaluated
+pub fn builtin_setf(e: Rc<RefCell<Lenv>>, args: Vec<Lval>) -> Lval {
+    if args.len() < 3 || args.len().is_multiple_of(2) {
+        return Lval::Err("Function 'setf' passed incorrect number of arguments.".to_string());
+    }
+    let mut result = Lval::NIL;
+    for pair in args[1..].chunks(2) {
+        let (env, value) = (e.clone(), pair[1].clone());
+        result = update(&e, &pair[0], Box::new(|_| {
+            let v = eval_for(env, value)?;
+            Ok((v.clone(), v))
+        }));
+        if let Lval::Err(_) = result { break; }
+    }
+    result
+}
+
+/// (push item place) puts a new cell holding item in front of the list in
+/// place, stores the longer list there and returns it
+pub fn builtin_push(e: Rc<RefCell<Lenv>>, args: Vec<Lval>) -> Lval {
+    if args.len() != 3 { return Lval::Err("Function 'push' passed incorrect number of arguments.".to_string()); }
+    let item = lval_eval(e.clone(), args[1].clone());
+    if let Lval::Err(_) = item { return item; }
+    update(&e, &args[2], Box::new(|v| {
+        let (l, quoted) = match chain_quoted(&v) {
+            Some(quoted) => (v.unquote(), quoted),
+            None => {
+                let (items, quoted) = elements(v)?;
+                (Lval::Sexpr(items), quoted)
+            },
+        };
+        let l = chain(cons::cons(item.unquote(), l), quoted);
+        Ok((l.clone(), l))
+    }))
+}
+
+/// (pop place) removes the first element of the list in place and returns it,
+/// or NIL when the list is empty
+pub fn builtin_pop(e: Rc<RefCell<Lenv>>, args: Vec<Lval>) -> Lval {
+    if args.len() != 2 { return Lval::Err("Function 'pop' passed incorrect number of arguments.".to_string()); }
+    update(&e, &args[1], Box::new(|v| {
+        if let (Some(cell), Some(quoted)) = (cons::cell(&v), chain_quoted(&v)) {
+            let cell = cell.borrow();
+            return Ok((chain(cell.cdr.clone(), quoted), cell.car.clone()));
+        }
+        let (mut items, quoted) = elements(v)?;
+        let first = if items.is_empty() { Lval::NIL } else { items.remove(0) };
+        Ok((list(items, quoted), first))
+    }))
+}
+
+pub fn builtin_incf(e: Rc<RefCell<Lenv>>, args: Vec<Lval>) -> Lval { builtin_step(e, args, "incf", 1) }
+pub fn builtin_decf(e: Rc<RefCell<Lenv>>, args: Vec<Lval>) -> Lval { builtin_step(e, args, "decf", -1) }
+
+/// (incf place [delta]) and (decf place [delta]) add or subtract delta, 1 by
+/// default, from the number in place and return the new number
+fn builtin_step(e: Rc<RefCell<Lenv>>, args: Vec<Lval>, name: &str, sign: i64) -> Lval {
+    if args.len() != 2 && args.len() != 3 {
+        return Lval::Err(format!("Function '{}' passed incorrect number of arguments.", name));
+    }
+    let (env, delta) = (e.clone(), args.get(2).cloned());
+    update(&e, &args[1], Box::new(move |v| {
+        let n = match v {
+            Lval::Num(n) => n,
+            Lval::Err(_) => return Err(v),
+            _ => return Err(Lval::Err(format!("{} is not a number", v))),
+        };
+        let delta = match delta.map(|d| eval_for(env, d)).transpose()? {
+            None => 1,
+            Some(Lval::Num(d)) => d,
+            Some(_) => return Err(Lval::Err(format!("Amount given to {} must be a number", name))),
+        };
+        let Some(new) = delta.checked_mul(sign).and_then(|d| n.checked_add(d)) else {
+            return Err(Lval::Err(format!("Integer overflow in {}", name)));
+        };
+        let new = Lval::Num(new);
+        Ok((new.clone(), new))
+    }))
+}
+
+pub fn builtin_rplaca(e: Rc<RefCell<Lenv>>, args: Vec<Lval>) -> Lval { replace(e, args, "rplaca") }
+pub fn builtin_rplacd(e: Rc<RefCell<Lenv>>, args: Vec<Lval>) -> Lval { replace(e, args, "rplacd") }
+
+/// (rplaca list value) and (rplacd list value) replace the car or the cdr of
+/// a list's first cell and return the list, which every variable holding it
+/// sees changed. A list the reader built is copied instead, and the changed
+/// copy stored back when `list` is a place
+fn replace(e: Rc<RefCell<Lenv>>, args: Vec<Lval>, name: &str) -> Lval {
+    if args.len() != 3 {
+        return Lval::Err(format!("Function '{}' passed incorrect number of arguments.", name));
+    }
+    let (env, value) = (e.clone(), args[2].clone());
+    let change: Change = Box::new(|v| {
+        if let Some(cell) = cons::cell(&v) {
+            let value = eval_for(env, value)?.unquote();
+            if name == "rplaca" { cell.borrow_mut().car = value; } else { cell.borrow_mut().cdr = cons::tail(value); }
+            return Ok((v.clone(), v));
+        }
+        let (mut items, quoted) = elements(v)?;
+        if items.is_empty() { return Err(Lval::Err(format!("Cannot {} an empty list", name))); }
+        let value = eval_for(env, value)?;
+        if name == "rplaca" {
+            items[0] = value.unquote();
+        } else {
+            items.truncate(1);
+            items.extend(elements(value)?.0);
+        }
+        let l = list(items, quoted);
+        Ok((l.clone(), l))
+    });
+    update_list(&e, &args[1], change)
+}
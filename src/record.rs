@@ -0,0 +1,266 @@
+// Structures defined by defstruct. (defstruct point x (y 0)) records the
+// type point with its slots and the forms giving their defaults, and defines
+// functions for it: make-point taking :x and :y, copy-point, point-p and an
+// accessor per slot, point-x and point-y. Those are Lval::StructFn values
+// naming the type and what they do, which apply calls like builtins.
+//
+// An instance is an Lval::Struct on the gc heap that every copy of the value
+// shares, like a hash table, so (setf (point-x p) 3) changes it in place. It
+// prints as #S(point :x 1 :y 0), which the reader reads back as a new
+// instance while the type is defined.
+
+use crate::eval::lval_eval;
+use crate::gc::{self, Trace, Tracer};
+use crate::symbol::Symbol;
+use crate::types::{Lenv, Lval};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::mem::size_of;
+use std::rc::{Rc, Weak};
+
+pub struct StructType {
+    pub name: Symbol,
+    pub slots: Vec<Symbol>,
+    /// the form giving each slot's value when the constructor is not given one
+    defaults: Vec<Lval>,
+    /// the global environment the type was defined in, where the reader
+    /// evaluates defaults
+    globals: Weak<RefCell<Lenv>>,
+}
+
+/// what a function defstruct defines does
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StructOp {
+    Make,
+    Copy,
+    Test,
+    /// reads the slot at this position
+    Slot(usize),
+}
+
+impl StructType {
+    /// the name defstruct gives the function doing `op`
+    pub fn function_name(&self, op: StructOp) -> String {
+        match op {
+            StructOp::Make => format!("make-{}", self.name),
+            StructOp::Copy => format!("copy-{}", self.name),
+            StructOp::Test => format!("{}-p", self.name),
+            StructOp::Slot(i) => format!("{}-{}", self.name, self.slots[i]),
+        }
+    }
+}
+
+pub struct Record {
+    pub kind: Rc<StructType>,
+    values: Vec<Lval>,
+}
+
+impl Record {
+    pub fn slot(&self, i: usize) -> Lval {
+        self.values[i].clone()
+    }
+
+    pub fn set_slot(&mut self, i: usize, v: Lval) {
+        self.values[i] = v;
+    }
+
+    /// each slot's name with its value
+    pub fn slots(&self) -> impl Iterator<Item = (Symbol, &Lval)> {
+        self.kind.slots.iter().copied().zip(&self.values)
+    }
+}
+
+impl Trace for Record {
+    fn trace(&self, tracer: &mut Tracer) {
+        for v in &self.values { tracer.value(v); }
+    }
+
+    fn clear(&mut self) {
+        // slots read NIL from here on, so nothing that still reaches the
+        // record by accident indexes past the end
+        for v in &mut self.values { *v = Lval::NIL; }
+    }
+
+    fn bytes(&self) -> usize {
+        size_of::<Self>() + self.values.len() * size_of::<Lval>()
+    }
+}
+
+thread_local! {
+    /// every type defstruct has defined, by name, for the reader
+    static TYPES: RefCell<HashMap<Symbol, Rc<StructType>>> = RefCell::new(HashMap::new());
+}
+
+fn new_record(kind: Rc<StructType>, values: Vec<Lval>) -> Lval {
+    Lval::Struct(gc::alloc("structures", Record { kind, values }))
+}
+
+/// the names of the functions defstruct defines for a type with these slots,
+/// with the number of arguments each takes when it is fixed
+pub fn function_names(name: &str, slots: &[&str]) -> Vec<(String, Option<usize>)> {
+    let mut names = vec![
+        (format!("make-{}", name), None),
+        (format!("copy-{}", name), Some(1)),
+        (format!("{}-p", name), Some(1)),
+    ];
+    names.extend(slots.iter().map(|slot| (format!("{}-{}", name, slot), Some(1))));
+    names
+}
+
+/// (defstruct name slot ...) defines a structure type and its functions and
+/// returns the name. A slot is a symbol, or (symbol default) where default
+/// is a form evaluated each time the constructor is not given the slot
+pub fn builtin_defstruct(e: Rc<RefCell<Lenv>>, args: Vec<Lval>) -> Lval {
+    let name = match args.get(1) {
+        Some(Lval::Sym(name)) => *name,
+        Some(_) => return Lval::Err("First argument to defstruct must be a symbol".to_string()),
+        None => return Lval::Err("Function 'defstruct' passed incorrect number of arguments.".to_string()),
+    };
+    let (mut slots, mut defaults) = (Vec::new(), Vec::new());
+    for slot in &args[2..] {
+        let (slot, default) = match slot {
+            Lval::Sym(s) => (*s, Lval::NIL),
+            Lval::Sexpr(cells) => match cells.as_slice() {
+                [Lval::Sym(s), default] => (*s, default.clone()),
+                _ => return Lval::Err(format!("Malformed slot {} in defstruct", slot)),
+            },
+            _ => return Lval::Err(format!("Malformed slot {} in defstruct", slot)),
+        };
+        if slots.contains(&slot) { return Lval::Err(format!("Slot {} is given twice in defstruct", slot)); }
+        slots.push(slot);
+        defaults.push(default);
+    }
+
+    let globals = Rc::downgrade(&root(e.clone()));
+    let kind = Rc::new(StructType { name, slots, defaults, globals });
+    TYPES.with(|t| t.borrow_mut().insert(name, kind.clone()));
+    let ops = [StructOp::Make, StructOp::Copy, StructOp::Test].into_iter()
+        .chain((0..kind.slots.len()).map(StructOp::Slot));
+    let mut e = e.borrow_mut();
+    for op in ops {
+        e.def(Symbol::intern(&kind.function_name(op)), Lval::StructFn(kind.clone(), op));
+    }
+    Lval::Sym(name)
+}
+
+/// whether a record is an instance of `kind`, or of an identical earlier definition of it
+fn is_instance(record: &Record, kind: &StructType) -> bool {
+    record.kind.name == kind.name && record.kind.slots == kind.slots
+}
+
+/// the record in the argument of a function defined for `kind`
+fn record_arg(v: &Lval, kind: &StructType, op: StructOp) -> Result<Rc<RefCell<Record>>, Lval> {
+    match v {
+        Lval::Struct(record) if is_instance(&record.borrow(), kind) => Ok(record.clone()),
+        _ => Err(Lval::Err(format!("Argument to {} must be a {}", kind.function_name(op), kind.name))),
+    }
+}
+
+/// calls a function defstruct defined. Defaults are evaluated in the global
+/// environment, where the type was defined
+pub fn call(e: Rc<RefCell<Lenv>>, kind: &Rc<StructType>, op: StructOp, args: Vec<Lval>) -> Lval {
+    if op != StructOp::Make && args.len() != 1 {
+        return Lval::Err(format!("Function '{}' passed incorrect number of arguments.", kind.function_name(op)));
+    }
+    match op {
+        StructOp::Make => {
+            let mut given = vec![None; kind.slots.len()];
+            if !args.len().is_multiple_of(2) {
+                return Lval::Err(format!("Function '{}' passed incorrect arguments.", kind.function_name(op)));
+            }
+            for pair in args.chunks(2) {
+                let slot = match &pair[0] {
+                    Lval::Sym(k) if k.is_keyword() => kind.slots.iter().position(|s| s.name() == &k.name()[1..]),
+                    _ => None,
+                };
+                match slot {
+                    Some(i) => given[i] = Some(pair[1].clone()),
+                    None => return Lval::Err(format!("Unknown keyword argument {} to {}", pair[0], kind.function_name(op))),
+                }
+            }
+            let globals = root(e);
+            let mut values = Vec::with_capacity(given.len());
+            for (v, default) in given.into_iter().zip(&kind.defaults) {
+                match v.unwrap_or_else(|| lval_eval(globals.clone(), default.clone())) {
+                    err @ Lval::Err(_) => return err,
+                    v => values.push(v.unquote()),
+                }
+            }
+            new_record(kind.clone(), values)
+        },
+        StructOp::Copy => match record_arg(&args[0], kind, op) {
+            Ok(record) => {
+                let record = record.borrow();
+                new_record(record.kind.clone(), record.values.clone())
+            },
+            Err(err) => err,
+        },
+        StructOp::Test => match &args[0] {
+            Lval::Struct(record) if is_instance(&record.borrow(), kind) => Lval::T,
+            _ => Lval::NIL,
+        },
+        StructOp::Slot(i) => match record_arg(&args[0], kind, op) {
+            Ok(record) => record.borrow().slot(i),
+            Err(err) => err,
+        },
+    }
+}
+
+/// the global environment, at the end of `e`'s chain of parents
+pub fn root(e: Rc<RefCell<Lenv>>) -> Rc<RefCell<Lenv>> {
+    let par = e.borrow().par.clone();
+    match par {
+        Some(par) => root(par),
+        None => e,
+    }
+}
+
+/// the instance read from #S(name :slot value ...), given the elements after
+/// #S(. A slot the literal leaves out takes its default, as with the constructor
+pub fn read_record(name: Symbol, options: &[Lval]) -> Lval {
+    let kind = TYPES.with(|t| t.borrow().get(&name).cloned());
+    let Some((kind, globals)) = kind.and_then(|kind| Some((kind.clone(), kind.globals.upgrade()?))) else {
+        return Lval::Err(format!("Unknown structure type {}", name));
+    };
+    let mut given = vec![None; kind.slots.len()];
+    for pair in options.chunks(2) {
+        let slot = match pair {
+            [Lval::Sym(k), _] if k.is_keyword() => kind.slots.iter().position(|s| s.name() == &k.name()[1..]),
+            _ => None,
+        };
+        match slot {
+            Some(i) => given[i] = Some(pair[1].clone()),
+            None => return Lval::Err(format!("Malformed {} literal", name)),
+        }
+    }
+    let mut values = Vec::with_capacity(given.len());
+    for (v, default) in given.into_iter().zip(&kind.defaults) {
+        match v {
+            Some(v) => values.push(v),
+            None => match lval_eval(globals.clone(), default.clone()) {
+                err @ Lval::Err(_) => return err,
+                v => values.push(v.unquote()),
+            },
+        }
+    }
+    new_record(kind, values)
+}
+
+/// the slot an (accessor object) form reads, when accessor is one defstruct
+/// defined, with the unevaluated object form
+pub fn place<'a>(e: &Rc<RefCell<Lenv>>, v: &'a Lval) -> Option<(Rc<StructType>, usize, &'a Lval)> {
+    let Lval::Sexpr(cells) = v else { return None };
+    let [Lval::Sym(head), object] = cells.as_slice() else { return None };
+    match e.borrow().get(*head) {
+        Some(Lval::StructFn(kind, StructOp::Slot(i))) => Some((kind, i, object)),
+        _ => None,
+    }
+}
+
+/// evaluates the object of a slot place
+pub fn place_record(e: &Rc<RefCell<Lenv>>, kind: &StructType, i: usize, object: &Lval) -> Result<Rc<RefCell<Record>>, Lval> {
+    match lval_eval(e.clone(), object.clone()) {
+        err @ Lval::Err(_) => Err(err),
+        v => record_arg(&v, kind, StructOp::Slot(i)),
+    }
+}
@@ -0,0 +1,447 @@
+// Line editor support for the REPL.
+// Decides when a typed form is complete so it can span several lines, completes
+// symbols from the live environment, colors the input and hints argument lists.
+
+use rustlisp::eval::SPECIAL_FORMS;
+use rustlisp::symbol::Symbol;
+use rustlisp::types::{Lenv, Lval};
+use rustlisp::{builtin_signature, BUILTINS};
+use rustyline::completion::Completer;
+use rustyline::highlight::Highlighter;
+use rustyline::hint::{Hint, Hinter};
+use rustyline::validate::{ValidationContext, ValidationResult, Validator};
+use rustyline::{Cmd, ConditionalEventHandler, Context, Event, EventContext, Helper, RepeatCount};
+use std::borrow::Cow;
+use std::cell::RefCell;
+use std::collections::BTreeSet;
+use std::rc::Rc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+/// prompt shown while the current form is still open
+pub const CONTINUATION_PROMPT: &str = "  ...> ";
+
+const COLOR_STRING: &str = "\x1b[32m";
+const COLOR_NUMBER: &str = "\x1b[33m";
+const COLOR_COMMENT: &str = "\x1b[90m";
+const COLOR_PAREN: &str = "\x1b[1;36m";
+const COLOR_HINT: &str = "\x1b[2m";
+const COLOR_RESET: &str = "\x1b[0m";
+
+/// symbols lval_eval resolves itself, besides keywords and special forms
+const CONSTANTS: &[&str] = &["t", "nil"];
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum TokenKind {
+    Open,
+    Close,
+    Quote,
+    Str { closed: bool },
+    Comment,
+    Num,
+    Sym,
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Token {
+    kind: TokenKind,
+    start: usize,
+    end: usize,
+}
+
+/// splits REPL input into tokens using the same lexical rules as grammar.pest.
+/// Unlike the parser it never fails, so it can run on half-typed input.
+fn tokenize(input: &str) -> Vec<Token> {
+    let mut tokens = Vec::new();
+    let mut chars = input.char_indices().peekable();
+
+    while let Some((start, c)) = chars.next() {
+        let kind = match c {
+            '(' => TokenKind::Open,
+            ')' => TokenKind::Close,
+            '\'' => TokenKind::Quote,
+            '"' => {
+                let mut closed = false;
+                while let Some((_, c)) = chars.next() {
+                    match c {
+                        '\\' => { chars.next(); },
+                        '"' => { closed = true; break; },
+                        _ => {},
+                    }
+                }
+                TokenKind::Str { closed }
+            },
+            ';' if matches!(chars.peek(), Some((_, ';'))) => {
+                while let Some(&(_, c)) = chars.peek() {
+                    if c == '\n' { break; }
+                    chars.next();
+                }
+                TokenKind::Comment
+            },
+            c if c.is_whitespace() => continue,
+            // #\( and #\" are characters, and #\Space names one
+            '#' if matches!(chars.peek(), Some((_, '\\'))) => {
+                chars.next();
+                match chars.next() {
+                    Some((_, c)) if c.is_ascii_alphabetic() => {
+                        while let Some(&(_, c)) = chars.peek() {
+                            if !c.is_ascii_alphanumeric() { break; }
+                            chars.next();
+                        }
+                    },
+                    _ => {},
+                }
+                TokenKind::Sym
+            },
+            _ => {
+                while let Some(&(_, c)) = chars.peek() {
+                    if c.is_whitespace() || matches!(c, '(' | ')' | '\'' | '"') { break; }
+                    chars.next();
+                }
+                TokenKind::Sym
+            },
+        };
+        let end = chars.peek().map(|&(i, _)| i).unwrap_or(input.len());
+        let kind = if kind == TokenKind::Sym && input[start..end].parse::<i64>().is_ok() {
+            TokenKind::Num
+        } else {
+            kind
+        };
+        tokens.push(Token { kind, start, end });
+    }
+    tokens
+}
+
+/// checks whether the input contains only whole forms: every paren is
+/// closed, no string is left open and no quote is waiting for its expression.
+/// Parens inside strings and `;;` comments are ignored.
+pub fn is_complete(input: &str) -> bool {
+    let mut depth: i64 = 0;
+    let mut pending_quote = false;
+
+    for tok in tokenize(input) {
+        match tok.kind {
+            TokenKind::Open => depth += 1,
+            TokenKind::Close => depth -= 1,
+            TokenKind::Str { closed: false } => return false,
+            TokenKind::Comment => continue,
+            _ => {},
+        }
+        pending_quote = tok.kind == TokenKind::Quote;
+    }
+
+    // extra closing parens are left for the parser to report
+    !pending_quote && depth <= 0
+}
+
+/// finds the paren matching the one at or just before the cursor
+fn matching_paren(tokens: &[Token], pos: usize) -> Option<(usize, usize)> {
+    let at = tokens.iter().position(|t| {
+        matches!(t.kind, TokenKind::Open | TokenKind::Close) && (t.start == pos || t.end == pos)
+    })?;
+
+    let mut depth = 0;
+    if tokens[at].kind == TokenKind::Open {
+        for t in &tokens[at..] {
+            match t.kind {
+                TokenKind::Open => depth += 1,
+                TokenKind::Close => depth -= 1,
+                _ => {},
+            }
+            if depth == 0 { return Some((tokens[at].start, t.start)); }
+        }
+    } else {
+        for t in tokens[..=at].iter().rev() {
+            match t.kind {
+                TokenKind::Close => depth += 1,
+                TokenKind::Open => depth -= 1,
+                _ => {},
+            }
+            if depth == 0 { return Some((t.start, tokens[at].start)); }
+        }
+    }
+    None
+}
+
+/// names the function of the innermost call left open before the cursor
+fn enclosing_call(line: &str, pos: usize) -> Option<&str> {
+    let tokens = tokenize(&line[..pos]);
+    let mut open = Vec::new();
+    for (i, t) in tokens.iter().enumerate() {
+        match t.kind {
+            TokenKind::Open => open.push(i),
+            TokenKind::Close => { open.pop(); },
+            _ => {},
+        }
+    }
+    let head = tokens.get(open.pop()? + 1)?;
+    if head.kind == TokenKind::Sym { Some(&line[head.start..head.end]) } else { None }
+}
+
+/// hint text shown after the cursor; it is never inserted into the line
+pub struct ArgHint(String);
+
+impl Hint for ArgHint {
+    fn display(&self) -> &str {
+        &self.0
+    }
+
+    fn completion(&self) -> Option<&str> {
+        None
+    }
+}
+
+/// rustyline helper for the REPL. Holds the global environment so completion
+/// and hints see every binding made during the session.
+pub struct LispHelper {
+    env: Rc<RefCell<Lenv>>,
+}
+
+impl LispHelper {
+    pub fn new(env: Rc<RefCell<Lenv>>) -> Self {
+        LispHelper { env }
+    }
+
+    /// every builtin, special form and constant, and all symbols bound
+    /// anywhere in the environment chain. Builtins the profile left out are
+    /// included, so completing one leads to the error saying which profile it needs
+    fn bound_symbols(&self) -> BTreeSet<String> {
+        let mut names: BTreeSet<String> = SPECIAL_FORMS.iter().map(|(name, _, _)| name).chain(CONSTANTS)
+            .chain(BUILTINS.iter().map(|(name, _, _, _)| name))
+            .map(|s| s.to_string())
+            .collect();
+        let mut env = Some(self.env.clone());
+        while let Some(e) = env {
+            names.extend(e.borrow().data.keys().map(|k| k.name().to_string()));
+            env = e.borrow().par.clone();
+        }
+        names
+    }
+
+    /// argument list of a builtin or user defined function
+    fn signature(&self, name: &str) -> Option<String> {
+        match Symbol::lookup(name).and_then(|sym| self.env.borrow().get(sym)) {
+            Some(Lval::Lambda(_, formals, _)) => {
+                let args = match &*formals {
+                    Lval::Qexpr(cells) => cells.iter().map(|c| c.to_string()).collect::<Vec<_>>().join(" "),
+                    other => other.to_string(),
+                };
+                Some(args)
+            },
+            Some(Lval::Fun(_)) | None => builtin_signature(name).map(|args| args.to_string()),
+            Some(_) => None,
+        }
+    }
+}
+
+impl Validator for LispHelper {
+    fn validate(&self, ctx: &mut ValidationContext) -> rustyline::Result<ValidationResult> {
+        if is_complete(ctx.input()) {
+            Ok(ValidationResult::Valid(None))
+        } else {
+            Ok(ValidationResult::Incomplete)
+        }
+    }
+}
+
+impl Completer for LispHelper {
+    type Candidate = String;
+
+    fn complete(&self, line: &str, pos: usize, _ctx: &Context<'_>) -> rustyline::Result<(usize, Vec<String>)> {
+        let start = line[..pos]
+            .rfind(|c: char| c.is_whitespace() || matches!(c, '(' | ')' | '\'' | '"'))
+            .map(|i| i + 1)
+            .unwrap_or(0);
+        let prefix = &line[start..pos];
+        let candidates = self.bound_symbols()
+            .into_iter()
+            .filter(|name| name.starts_with(prefix))
+            .collect();
+        Ok((start, candidates))
+    }
+}
+
+impl Hinter for LispHelper {
+    type Hint = ArgHint;
+
+    fn hint(&self, line: &str, pos: usize, _ctx: &Context<'_>) -> Option<ArgHint> {
+        // only hint at the end of the input so the hint never covers typed text
+        if pos < line.len() { return None; }
+        let name = enclosing_call(line, pos)?;
+        let args = self.signature(name)?;
+        Some(ArgHint(format!("  ({} {})", name, args)))
+    }
+}
+
+impl Highlighter for LispHelper {
+    fn highlight<'l>(&self, line: &'l str, pos: usize) -> Cow<'l, str> {
+        let tokens = tokenize(line);
+        let parens = matching_paren(&tokens, pos);
+
+        let mut out = String::with_capacity(line.len() * 2);
+        let mut last = 0;
+        for t in &tokens {
+            let color = match t.kind {
+                TokenKind::Str { .. } => COLOR_STRING,
+                TokenKind::Num => COLOR_NUMBER,
+                TokenKind::Comment => COLOR_COMMENT,
+                TokenKind::Open | TokenKind::Close
+                    if parens.is_some_and(|(a, b)| t.start == a || t.start == b) => COLOR_PAREN,
+                _ => continue,
+            };
+            out.push_str(&line[last..t.start]);
+            out.push_str(color);
+            out.push_str(&line[t.start..t.end]);
+            out.push_str(COLOR_RESET);
+            last = t.end;
+        }
+        if last == 0 { return Cow::Borrowed(line); }
+        out.push_str(&line[last..]);
+        Cow::Owned(out)
+    }
+
+    fn highlight_hint<'h>(&self, hint: &'h str) -> Cow<'h, str> {
+        Cow::Owned(format!("{}{}{}", COLOR_HINT, hint, COLOR_RESET))
+    }
+
+    fn highlight_char(&self, _line: &str, _pos: usize) -> bool {
+        // paren matching depends on the cursor, so redraw on every move
+        true
+    }
+}
+
+impl Helper for LispHelper {}
+
+/// bound to Ctrl-C at the prompt: records whether the line was empty, then
+/// lets rustyline interrupt the read as usual. Only a Ctrl-C at an empty
+/// prompt counts towards exiting
+pub struct InterruptWatch(pub Arc<AtomicBool>);
+
+impl ConditionalEventHandler for InterruptWatch {
+    fn handle(&self, _evt: &Event, _n: RepeatCount, _positive: bool, ctx: &EventContext) -> Option<Cmd> {
+        self.0.store(ctx.line().is_empty(), Ordering::Relaxed);
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn kinds(input: &str) -> Vec<TokenKind> {
+        tokenize(input).iter().map(|t| t.kind).collect()
+    }
+
+    #[test]
+    fn escaped_quotes_stay_in_the_string() {
+        assert!(is_complete(r#"(print "say \"hi\"")"#));
+        assert!(is_complete(r#"(print "a \\")"#));
+        assert!(!is_complete(r#"(print "say \")"#));
+        assert!(!is_complete(r#"(print "unclosed"#));
+        assert_eq!(kinds(r#""a \" (" x"#), [TokenKind::Str { closed: true }, TokenKind::Sym]);
+    }
+
+    #[test]
+    fn comments_hide_their_parens() {
+        assert!(is_complete("(+ 1 2) ;; (unbalanced"));
+        assert!(is_complete("(+ 1 ;; ) not a close\n 2)"));
+        assert!(!is_complete("(+ 1 ;; )\n 2"));
+        assert_eq!(kinds(";; (a)\n(b)"), [TokenKind::Comment, TokenKind::Open, TokenKind::Sym, TokenKind::Close]);
+    }
+
+    #[test]
+    fn a_pending_quote_needs_its_form() {
+        assert!(!is_complete("'"));
+        assert!(!is_complete("(f) '"));
+        assert!(!is_complete("' ;; the form comes next"));
+        assert!(is_complete("'a"));
+        assert!(is_complete("'(1 2)"));
+    }
+
+    #[test]
+    fn extra_closing_parens_are_complete() {
+        assert!(is_complete(")"));
+        assert!(is_complete("(+ 1 2))"));
+    }
+
+    #[test]
+    fn character_literals_are_atoms() {
+        assert!(is_complete("(char-code #\\()"));
+        assert!(is_complete("(char-code #\\))"));
+        assert!(is_complete("(list #\\\" #\\;)"));
+        assert!(is_complete("(list #\\Space #\\a)"));
+        assert!(!is_complete("(char-code #\\)"));
+        let kinds: Vec<_> = tokenize("(f #\\Space #\\ab)").iter().map(|t| t.kind).collect();
+        assert_eq!(kinds, [TokenKind::Open, TokenKind::Sym, TokenKind::Sym, TokenKind::Sym, TokenKind::Close]);
+    }
+
+    fn helper() -> LispHelper {
+        let env = Rc::new(RefCell::new(Lenv::new()));
+        rustlisp::add_builtins_with(env.clone(), rustlisp::capability::Profile::Pure);
+        rustlisp::eval_source(&env, "(defun square (x) (* x x)) (setq sqrt-two 1)", |_| {}).unwrap();
+        LispHelper::new(env)
+    }
+
+    fn complete(helper: &LispHelper, line: &str) -> (usize, Vec<String>) {
+        let history = rustyline::history::DefaultHistory::new();
+        helper.complete(line, line.len(), &Context::new(&history)).unwrap()
+    }
+
+    fn hint(helper: &LispHelper, line: &str) -> Option<String> {
+        let history = rustyline::history::DefaultHistory::new();
+        helper.hint(line, line.len(), &Context::new(&history)).map(|h| h.0)
+    }
+
+    #[test]
+    fn completion_offers_builtins_special_forms_and_definitions() {
+        let helper = helper();
+        assert_eq!(complete(&helper, "(sq"), (1, vec!["sqrt-two".to_string(), "square".to_string()]));
+        assert_eq!(complete(&helper, "(setq x (car"), (9, vec!["car".to_string()]));
+        let (_, defs) = complete(&helper, "'def");
+        assert_eq!(defs, ["def", "defclass", "defgeneric", "defmethod", "defstruct", "defun"]);
+        // left out by the pure profile, but still a name the reader may mean
+        assert_eq!(complete(&helper, "(she").1, ["shell"]);
+        for name in SPECIAL_FORMS.iter().map(|(name, _, _)| name).chain(BUILTINS.iter().map(|(name, _, _, _)| name)) {
+            assert!(complete(&helper, name).1.contains(&name.to_string()), "{} is not completed", name);
+        }
+    }
+
+    #[test]
+    fn hints_show_the_innermost_call() {
+        let helper = helper();
+        assert_eq!(hint(&helper, "(gethash "), Some("  (gethash key table &optional default)".to_string()));
+        assert_eq!(hint(&helper, "(print (square "), Some("  (square x)".to_string()));
+        assert_eq!(hint(&helper, "(print (square 2) "), Some("  (print &rest values)".to_string()));
+        assert_eq!(hint(&helper, "(sqrt-two "), None);
+        assert_eq!(hint(&helper, "(undefined "), None);
+        // asking about a name does not intern it
+        assert_eq!(Symbol::lookup("never-typed-before"), None);
+        assert_eq!(hint(&helper, "(never-typed-before "), None);
+        assert_eq!(Symbol::lookup("never-typed-before"), None);
+        assert_eq!(hint(&helper, "(+ 1 2)"), None);
+        // only at the end of the line
+        let history = rustyline::history::DefaultHistory::new();
+        assert!(helper.hint("(car x)", 4, &Context::new(&history)).is_none());
+    }
+
+    #[test]
+    fn every_builtin_and_special_form_has_a_signature() {
+        for name in SPECIAL_FORMS.iter().map(|(name, _, _)| name).chain(BUILTINS.iter().map(|(name, _, _, _)| name)) {
+            assert!(builtin_signature(name).is_some(), "no signature for {}", name);
+        }
+    }
+
+    #[test]
+    fn parens_match_from_either_side() {
+        let line = "(a (b \"(\" c) ;; (\n d)";
+        let tokens = tokenize(line);
+        assert_eq!(matching_paren(&tokens, 0), Some((0, 20)));
+        assert_eq!(matching_paren(&tokens, 21), Some((0, 20)));
+        assert_eq!(matching_paren(&tokens, 3), Some((3, 11)));
+        assert_eq!(matching_paren(&tokens, 12), Some((3, 11)));
+        assert_eq!(matching_paren(&tokens, 2), None);
+        assert_eq!(matching_paren(&tokenize("(a (b)"), 0), None);
+        let highlighted = helper().highlight("(f)", 0);
+        assert_eq!(highlighted, format!("{p}({r}f{p}){r}", p = COLOR_PAREN, r = COLOR_RESET));
+    }
+}
@@ -0,0 +1,85 @@
+// Functions over sequences: lists, quoted or not, and vectors. Each accepts
+// either kind, and mapcar returns the kind of its first sequence.
+
+use crate::array::{new_array, Array};
+use crate::cons;
+use crate::eval::lval_call;
+use crate::types::{Lenv, Lval};
+use std::borrow::Cow;
+use std::cell::RefCell;
+use std::rc::Rc;
+
+/// the elements of a list or of the part of a vector in use, or None if the
+/// value is neither
+pub fn items(v: &Lval) -> Option<Vec<Lval>> {
+    match v {
+        Lval::Array(array) => {
+            let array = array.borrow();
+            (array.dims().len() == 1).then(|| array.elements().to_vec())
+        },
+        _ => v.list_items().map(Cow::into_owned),
+    }
+}
+
+fn items_arg(v: &Lval, func: &str) -> Result<Vec<Lval>, Lval> {
+    items(v).ok_or_else(|| Lval::Err(format!("Argument to {} must be a list or a vector", func)))
+}
+
+/// (length seq) is the number of elements in a list or vector, or of characters in a string
+pub fn builtin_length(_e: Rc<RefCell<Lenv>>, args: Vec<Lval>) -> Lval {
+    match args.as_slice() {
+        [Lval::Str(s)] => Lval::Num(s.chars().count() as i64),
+        [seq] => match items_arg(seq, "length") {
+            Ok(items) => Lval::Num(items.len() as i64),
+            Err(err) => err,
+        },
+        _ => Lval::Err("Function 'length' passed incorrect number of arguments.".to_string()),
+    }
+}
+
+/// (mapcar f seq ...) calls f with the first element of each sequence, then
+/// the second, and so on until the shortest runs out, and returns the results
+/// as a list, or as a vector when the first sequence is one
+pub fn builtin_mapcar(e: Rc<RefCell<Lenv>>, args: Vec<Lval>) -> Lval {
+    if args.len() < 2 { return Lval::Err("Function 'mapcar' passed incorrect number of arguments.".to_string()); }
+    let seqs = match args[1..].iter().map(|seq| items_arg(seq, "mapcar")).collect::<Result<Vec<_>, _>>() {
+        Ok(seqs) => seqs,
+        Err(err) => return err,
+    };
+    let n = seqs.iter().map(Vec::len).min().unwrap_or(0);
+    let mut results = Vec::with_capacity(n);
+    for i in 0..n {
+        let call_args = seqs.iter().map(|seq| seq[i].clone()).collect();
+        match lval_call(e.clone(), args[0].clone(), call_args) {
+            err @ Lval::Err(_) => return err,
+            v => results.push(v.unquote()),
+        }
+    }
+    match args[1] {
+        Lval::Array(_) => new_array(Array::vector(results)),
+        _ => cons::list(results),
+    }
+}
+
+/// (reduce f seq [:initial-value v]) combines the elements with f from the
+/// left, starting from v when it is given. An empty sequence gives v, or the
+/// result of calling f with no arguments
+pub fn builtin_reduce(e: Rc<RefCell<Lenv>>, args: Vec<Lval>) -> Lval {
+    let initial = match args.as_slice() {
+        [_, _] => None,
+        [_, _, Lval::Sym(k), v] if k.name() == ":initial-value" => Some(v.clone()),
+        _ => return Lval::Err("Function 'reduce' passed incorrect number of arguments.".to_string()),
+    };
+    let mut items = match items_arg(&args[1], "reduce") {
+        Ok(items) => items.into_iter(),
+        Err(err) => return err,
+    };
+    let Some(mut acc) = initial.or_else(|| items.next()) else {
+        return lval_call(e, args[0].clone(), Vec::new());
+    };
+    for item in items {
+        acc = lval_call(e.clone(), args[0].clone(), vec![acc, item]);
+        if let Lval::Err(_) = acc { break; }
+    }
+    acc
+}
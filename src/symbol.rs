@@ -0,0 +1,217 @@
+// Interned symbols. The reader turns every symbol name into a Symbol, a small
+// integer ID, so comparing symbols and hashing them as environment keys never
+// touches the string. Names are kept for the life of the process in one
+// table shared by all threads, and each thread copies the names it looks up
+// so that printing a symbol or calling a function by name takes no lock.
+// Since a name is never freed, names a program makes at run time, with intern,
+// gensym or json-parse, count against its memory limit. Keywords, symbols whose name starts with a
+// colon, evaluate to themselves; a bit in the ID marks them so that check is
+// as cheap as the ones for T and NIL.
+
+use crate::eval::SPECIAL_FORMS;
+use crate::limits::track_bytes;
+use crate::types::{Builtin, Lenv, Lval};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt;
+use std::rc::Rc;
+use std::sync::{LazyLock, Mutex};
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Symbol(u32);
+
+/// names interned first when the table is created, so the evaluator can
+/// match them as the constants below; the order must match the constants.
+/// The special forms come right after them, in SPECIAL_FORMS order
+const CONSTANTS: &[&str] = &["T", "t", "NIL", "nil"];
+
+pub const T_UPPER: Symbol = Symbol(0);
+pub const T_LOWER: Symbol = Symbol(1);
+pub const NIL_UPPER: Symbol = Symbol(2);
+pub const NIL_LOWER: Symbol = Symbol(3);
+pub const QUOTE: Symbol = special("quote");
+pub const SETQ: Symbol = special("setq");
+pub const DEFUN: Symbol = special("defun");
+pub const COND: Symbol = special("cond");
+
+/// the symbol of a special form, found in SPECIAL_FORMS when compiling
+const fn special(name: &str) -> Symbol {
+    let mut i = 0;
+    while i < SPECIAL_FORMS.len() {
+        let form = SPECIAL_FORMS[i].0.as_bytes();
+        let (name, mut j) = (name.as_bytes(), 0);
+        while j < name.len() && j < form.len() && name[j] == form[j] { j += 1; }
+        if j == name.len() && j == form.len() { return Symbol((CONSTANTS.len() + i) as u32); }
+        i += 1;
+    }
+    panic!("not a special form");
+}
+
+/// set in the ID of a keyword
+const KEYWORD: u32 = 1 << 31;
+
+struct Table {
+    names: Vec<&'static str>,
+    ids: HashMap<&'static str, Symbol>,
+    /// number of symbols gensym has made
+    generated: u64,
+}
+
+static TABLE: LazyLock<Mutex<Table>> = LazyLock::new(|| {
+    let mut table = Table { names: Vec::new(), ids: HashMap::new(), generated: 0 };
+    for name in CONSTANTS.iter().chain(SPECIAL_FORMS.iter().map(|(name, _, _)| name)) {
+        table.add(name, true);
+    }
+    Mutex::new(table)
+});
+
+thread_local! {
+    /// this thread's copy of the table's names. Names are only ever added,
+    /// so the copy never goes stale and only has to catch up
+    static NAMES: RefCell<Vec<&'static str>> = const { RefCell::new(Vec::new()) };
+}
+
+impl Table {
+    fn add(&mut self, name: &str, interned: bool) -> Symbol {
+        // names live as long as the table, which is as long as the process
+        let name: &'static str = Box::leak(name.to_string().into_boxed_str());
+        let index = self.names.len() as u32;
+        let sym = Symbol(if name.starts_with(':') { index | KEYWORD } else { index });
+        self.names.push(name);
+        if interned { self.ids.insert(name, sym); }
+        sym
+    }
+}
+
+impl Symbol {
+    /// the symbol with this name, the same one every time
+    pub fn intern(name: &str) -> Symbol {
+        let mut table = TABLE.lock().unwrap();
+        match table.ids.get(name) {
+            Some(&sym) => sym,
+            None => table.add(name, true),
+        }
+    }
+
+    /// the symbol with this name if it has been interned, without interning
+    /// it, for names a user is only asking about
+    pub fn lookup(name: &str) -> Option<Symbol> {
+        TABLE.lock().unwrap().ids.get(name).copied()
+    }
+
+    /// interns like intern, charging a name not seen before to the memory
+    /// limit, for names a program makes at run time
+    pub fn intern_charged(name: &str) -> Result<Symbol, Lval> {
+        let (sym, added) = {
+            let mut table = TABLE.lock().unwrap();
+            match table.ids.get(name) {
+                Some(&sym) => (sym, false),
+                None => (table.add(name, true), true),
+            }
+        };
+        if added { track_bytes(name.len())?; }
+        Ok(sym)
+    }
+
+    pub fn name(self) -> &'static str {
+        let index = (self.0 & !KEYWORD) as usize;
+        NAMES.with(|names| {
+            let cached = names.borrow().get(index).copied();
+            cached.unwrap_or_else(|| {
+                let mut names = names.borrow_mut();
+                let table = TABLE.lock().unwrap();
+                let known = names.len();
+                names.extend_from_slice(&table.names[known..]);
+                names[index]
+            })
+        })
+    }
+
+    /// true for keywords such as :test, which evaluate to themselves
+    pub fn is_keyword(self) -> bool {
+        self.0 & KEYWORD != 0
+    }
+
+    /// true for T and t, which evaluate to T
+    pub fn is_t(self) -> bool {
+        self == T_UPPER || self == T_LOWER
+    }
+
+    /// true for NIL and nil, which evaluate to NIL
+    pub fn is_nil(self) -> bool {
+        self == NIL_UPPER || self == NIL_LOWER
+    }
+
+    /// the special form this symbol names, if it names one
+    pub fn special_form(self) -> Option<Builtin> {
+        let index = (self.0 as usize).checked_sub(CONSTANTS.len())?;
+        SPECIAL_FORMS.get(index).map(|(_, _, form)| *form)
+    }
+}
+
+/// a fresh symbol no other symbol is equal to, not even one interned with the same name
+pub fn gensym(prefix: &str) -> Symbol {
+    let mut table = TABLE.lock().unwrap();
+    table.generated += 1;
+    let name = format!("{}{}", prefix, table.generated);
+    table.add(&name, false)
+}
+
+impl fmt::Display for Symbol {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+impl fmt::Debug for Symbol {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+/// the symbol in a builtin's argument: quoting a symbol gives a one element Qexpr
+fn symbol_arg(v: &Lval) -> Option<Symbol> {
+    match v {
+        Lval::Sym(s) => Some(*s),
+        Lval::Qexpr(cells) => match cells.as_slice() {
+            [Lval::Sym(s)] => Some(*s),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// (intern "name") returns the symbol with that name
+pub fn builtin_intern(_e: Rc<RefCell<Lenv>>, args: Vec<Lval>) -> Lval {
+    match args.as_slice() {
+        [Lval::Str(name)] => Symbol::intern_charged(name).map_or_else(|err| err, Lval::Sym),
+        [_] => Lval::Err("Argument to intern must be a string".to_string()),
+        _ => Lval::Err("Function 'intern' passed incorrect number of arguments.".to_string()),
+    }
+}
+
+/// (symbol-name 'sym) returns the symbol's name as a string
+pub fn builtin_symbol_name(_e: Rc<RefCell<Lenv>>, args: Vec<Lval>) -> Lval {
+    match args.as_slice() {
+        [arg] => match symbol_arg(arg) {
+            Some(sym) => Lval::string(sym.name().to_string()),
+            None => Lval::Err("Argument to symbol-name must be a symbol".to_string()),
+        },
+        _ => Lval::Err("Function 'symbol-name' passed incorrect number of arguments.".to_string()),
+    }
+}
+
+/// (gensym) or (gensym "prefix") returns a new symbol, G1, G2, ... by default
+pub fn builtin_gensym(_e: Rc<RefCell<Lenv>>, args: Vec<Lval>) -> Lval {
+    let prefix = match args.as_slice() {
+        [] => "G",
+        [Lval::Str(prefix)] => prefix,
+        [_] => return Lval::Err("Prefix given to gensym must be a string".to_string()),
+        _ => return Lval::Err("Function 'gensym' passed incorrect number of arguments.".to_string()),
+    };
+    let sym = gensym(prefix);
+    match track_bytes(sym.name().len()) {
+        Ok(()) => Lval::Sym(sym),
+        Err(err) => err,
+    }
+}
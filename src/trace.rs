@@ -0,0 +1,105 @@
+// Call tracing for user functions, in the style of Common Lisp's trace.
+// (trace rev) makes every call to rev print an entry line with its arguments
+// and an exit line with its return value, indented by nesting depth:
+//
+//   0: (rev (a b) NIL)
+//     1: (rev (b) (a))
+//     1: rev returned (b a)
+//   0: rev returned (b a)
+
+use crate::eval::lval_call_named;
+use crate::io::write_output;
+use crate::symbol::Symbol;
+use crate::types::{Lval, Lenv};
+use std::cell::{Cell, RefCell};
+use std::collections::BTreeSet;
+use std::rc::Rc;
+
+thread_local! {
+    static TRACED: RefCell<BTreeSet<Symbol>> = const { RefCell::new(BTreeSet::new()) };
+    // number of traced calls currently in progress
+    static TRACE_DEPTH: Cell<usize> = const { Cell::new(0) };
+}
+
+pub fn is_traced(name: Symbol) -> bool {
+    TRACED.with(|t| t.borrow().contains(&name))
+}
+
+pub fn untrace_all() {
+    TRACED.with(|t| t.borrow_mut().clear());
+}
+
+/// currently traced names as a list, in alphabetical order
+fn traced_list() -> Lval {
+    let mut names: Vec<Symbol> = TRACED.with(|t| t.borrow().iter().copied().collect());
+    names.sort_by_key(|n| n.name());
+    Lval::Sexpr(names.into_iter().map(Lval::Sym).collect())
+}
+
+/// the function names given to trace/untrace; 'rev is accepted as well as rev
+fn names(args: &[Lval], func: &str) -> Result<Vec<Symbol>, Lval> {
+    args.iter().map(|a| match a {
+        Lval::Sym(s) => Ok(*s),
+        Lval::Qexpr(cells) => match cells.as_slice() {
+            [Lval::Sym(s)] => Ok(*s),
+            _ => Err(Lval::Err(format!("Arguments to {} must be function names", func))),
+        },
+        _ => Err(Lval::Err(format!("Arguments to {} must be function names", func))),
+    }).collect()
+}
+
+/// (trace fn ...) special form. With no arguments lists the traced functions
+pub fn builtin_trace(e: Rc<RefCell<Lenv>>, args: Vec<Lval>) -> Lval {
+    let names = match names(&args[1..], "trace") {
+        Ok(n) => n,
+        Err(err) => return err,
+    };
+    for name in &names {
+        match e.borrow().get(*name) {
+            Some(Lval::Lambda(..)) => {},
+            Some(_) => return Lval::Err(format!("trace: '{}' is not a user function", name)),
+            None => return Lval::Err(format!("trace: '{}' is not defined", name)),
+        }
+    }
+    TRACED.with(|t| t.borrow_mut().extend(names));
+    traced_list()
+}
+
+/// (untrace fn ...) special form. With no arguments stops tracing everything
+pub fn builtin_untrace(_e: Rc<RefCell<Lenv>>, args: Vec<Lval>) -> Lval {
+    if args.len() == 1 {
+        untrace_all();
+        return traced_list();
+    }
+    let names = match names(&args[1..], "untrace") {
+        Ok(n) => n,
+        Err(err) => return err,
+    };
+    TRACED.with(|t| {
+        let mut traced = t.borrow_mut();
+        for name in &names {
+            traced.remove(name);
+        }
+    });
+    traced_list()
+}
+
+/// calls a traced function, printing its entry and exit lines
+pub fn traced_call(e: Rc<RefCell<Lenv>>, name: &str, f: Lval, args: Vec<Lval>) -> Lval {
+    let depth = TRACE_DEPTH.with(|d| d.get());
+    let indent = depth * 2 + 2;
+
+    let shown: Vec<String> = args.iter().map(|a| a.to_string()).collect();
+    if shown.is_empty() {
+        write_output(&format!("{:indent$}{}: ({})\n", "", depth, name));
+    } else {
+        write_output(&format!("{:indent$}{}: ({} {})\n", "", depth, name, shown.join(" ")));
+    }
+
+    TRACE_DEPTH.with(|d| d.set(depth + 1));
+    let result = lval_call_named(e, name, f, args);
+    TRACE_DEPTH.with(|d| d.set(depth));
+
+    write_output(&format!("{:indent$}{}: {} returned {}\n", "", depth, name, result));
+    result
+}
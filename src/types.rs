@@ -1,8 +1,18 @@
 // Defines the "atoms" of the language
 
+use crate::array::Array;
+use crate::character;
+use crate::cons::{self, Cons};
+use crate::hash::HashTable;
+use crate::limits::charge;
+use crate::object::{Generic, Instance};
+use crate::record::{Record, StructOp, StructType};
+use crate::symbol::Symbol;
 use std::cell::RefCell;
-use std::collections::HashMap;
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
 use std::fmt;
+use std::ptr::fn_addr_eq;
 use std::rc::Rc;
 
 
@@ -12,14 +22,24 @@ use std::rc::Rc;
 
 // Represents any value in the Lisp Language
 // in rust each variant of an enum can hold different kinds of data
+#[allow(clippy::upper_case_acronyms)]
 pub enum Lval {
     Num(i64),
-    Sym(String),
+    Sym(Symbol),
+    Str(String),
+    Char(char),
     Err(String),
     Fun(Builtin),
-    Lambda(Lenv, Box<Lval>, Box<Lval>), // Env, Formals, Body
+    Lambda(Lenv, Rc<Lval>, Rc<Lval>), // Env, Formals, Body; shared by copies
     Sexpr(Vec<Lval>),
     Qexpr(Vec<Lval>),
+    Cons(Rc<RefCell<Cons>>),      // a cell of a list built at run time, shared like Hash
+    Hash(Rc<RefCell<HashTable>>), // shared, so changes are seen through every copy
+    Array(Rc<RefCell<Array>>),    // shared like Hash
+    Struct(Rc<RefCell<Record>>),  // an instance of a defstruct type, shared like Hash
+    StructFn(Rc<StructType>, StructOp), // a function defstruct defined
+    Object(Rc<RefCell<Instance>>), // an instance of a defclass class, shared like Hash
+    Generic(Rc<RefCell<Generic>>), // a generic function, shared so defmethod adds to every copy
     T,
     NIL,
     Void,
@@ -27,12 +47,70 @@ pub enum Lval {
 
 pub type Builtin = fn(Rc<RefCell<Lenv>>, Vec<Lval>) -> Lval;
 
+impl Lval {
+    /// the datum a value stands for when it is kept in a list or a table:
+    /// 'a evaluates to a one element Qexpr, but the list holds the symbol,
+    /// as car would return it
+    pub fn unquote(self) -> Lval {
+        match self {
+            Lval::Qexpr(mut cells) if cells.len() == 1 => cells.remove(0),
+            other => other,
+        }
+    }
+
+    /// a string built while a program runs, charged to the memory limit
+    pub fn string(s: String) -> Lval {
+        charge(s.len());
+        Lval::Str(s)
+    }
+
+    /// true for functions that can be called without arguments: builtins
+    /// whose argument list starts with &optional, &rest or &key or is empty,
+    /// lambdas without formals, structure constructors and generic functions
+    /// of no arguments. (f) calls these; any other value is its own
+    pub fn takes_no_arguments(&self) -> bool {
+        match self {
+            Lval::Fun(func) => crate::BUILTINS.iter()
+                .find(|(_, _, f, _)| fn_addr_eq(*f, *func))
+                .is_some_and(|(_, args, ..)| args.split_whitespace().next().is_none_or(|a| a.starts_with('&'))),
+            Lval::Lambda(_, formals, _) => !matches!(&**formals, Lval::Qexpr(cells) if !cells.is_empty()),
+            Lval::StructFn(_, op) => *op == StructOp::Make,
+            Lval::Generic(generic) => generic.borrow().arity() == 0,
+            _ => false,
+        }
+    }
+
+    /// the elements of a list, which may be quoted, or None if the value is
+    /// not one. A dotted or circular chain of cells is not a list here
+    pub fn list_items(&self) -> Option<Cow<'_, [Lval]>> {
+        match self {
+            Lval::NIL => Some(Cow::Borrowed(&[])),
+            Lval::Sexpr(cells) => Some(Cow::Borrowed(cells)),
+            Lval::Cons(cell) => match cons::walk(cell) {
+                Some((items, Lval::NIL)) => Some(Cow::Owned(items)),
+                _ => None,
+            },
+            Lval::Qexpr(cells) => match cells.as_slice() {
+                [inner @ (Lval::Sexpr(_) | Lval::Cons(_))] => inner.list_items(),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+}
+
 // Represents the scope/environment
 #[derive(Clone)]
 pub struct Lenv {
     // rust sandwich
     pub par: Option<Rc<RefCell<Lenv>>>,     // Option states that it might not exist. RC states that it has multiple owners. RefCell states that I can change it, even if its shared. 
-    pub data: HashMap<String, Lval>,
+    pub data: HashMap<Symbol, Lval>,
+}
+
+impl Default for Lenv {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 // In rust you define the data (struct) and behavior (Impl) separately.
@@ -45,8 +123,8 @@ impl Lenv {
         }
     }
     
-    pub fn get(&self, k: &str) -> Option<Lval> {
-        if let Some(val) = self.data.get(k) {
+    pub fn get(&self, k: Symbol) -> Option<Lval> {
+        if let Some(val) = self.data.get(&k) {
             Some(val.clone())
         } else if let Some(ref par) = self.par {
             par.borrow().get(k)
@@ -55,11 +133,34 @@ impl Lenv {
         }
     }
 
-    pub fn put(&mut self, k: String, v: Lval) {
+    /// looks a name up like get, handing the value to `f` without copying it
+    pub fn with<R>(&self, k: Symbol, f: impl FnOnce(Option<&Lval>) -> R) -> R {
+        match (self.data.get(&k), &self.par) {
+            (Some(v), _) => f(Some(v)),
+            (None, Some(par)) => par.borrow().with(k, f),
+            (None, None) => f(None),
+        }
+    }
+
+    pub fn put(&mut self, k: Symbol, v: Lval) {
         self.data.insert(k, v);
     }
 
-    pub fn def(&mut self, k: String, v: Lval) {
+    /// assigns to the variable in the environment that binds it, walking up
+    /// the parents as get does, or binds it here when it is bound nowhere
+    pub fn set(&mut self, k: Symbol, v: Lval) {
+        if !self.data.contains_key(&k) {
+            if let Some(ref par) = self.par {
+                if par.borrow().with(k, |found| found.is_some()) {
+                    par.borrow_mut().set(k, v);
+                    return;
+                }
+            }
+        }
+        self.put(k, v);
+    }
+
+    pub fn def(&mut self, k: Symbol, v: Lval) {
         if let Some(ref par) = self.par {
             par.borrow_mut().def(k, v);
         } else {
@@ -75,38 +176,174 @@ impl Lenv {
     }
 }
 
-/// function for converting an Lval into a user-friendly String.
+/// function for converting an Lval into a String the reader can read back,
+/// as prin1 does: strings are quoted and escaped, quoted lists keep their parens.
 /// This is equivalent to overriding toString()
 impl fmt::Display for Lval {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        match self {
-            Lval::Num(n) => write!(f, "{}", n),
-            Lval::Sym(s) => write!(f, "{}", s),
-            Lval::Err(e) => write!(f, "Error: {}", e),
-            Lval::Fun(_) => write!(f, "<function>"),
-            Lval::Lambda(env, formals, body) => {
-                write!(f, "(\\ {})", formals) // Simplified printing for lambda
-            },
-            Lval::Sexpr(cell) => {
-                write!(f, "(")?;
-                for (i, c) in cell.iter().enumerate() {
-                    if i > 0 { write!(f, " ")?; }
-                    write!(f, "{}", c)?;
-                }
-                write!(f, ")")
-            },
-            Lval::Qexpr(cell) => {
-                write!(f, "'")?;
-                for (i, c) in cell.iter().enumerate() {
-                    if i > 0 { write!(f, " ")?; }
-                    write!(f, "{}", c)?;
-                }
-                Ok(())
-            },
-            Lval::T => write!(f, "T"),
-            Lval::NIL => write!(f, "NIL"),
-            Lval::Void => Ok(()),
+        write_lval(f, self, true)
+    }
+}
+
+thread_local! {
+    /// the shared objects being printed, so that one holding itself does not print forever
+    static PRINTING: RefCell<HashSet<usize>> = RefCell::new(HashSet::new());
+}
+
+/// prints a shared object with `body`, or a marker if it is already being
+/// printed further up
+fn write_shared<T>(f: &mut fmt::Formatter, object: &Rc<RefCell<T>>, kind: &str,
+                   body: impl FnOnce(&mut fmt::Formatter, &T) -> fmt::Result) -> fmt::Result {
+    let address = Rc::as_ptr(object) as usize;
+    if PRINTING.with(|p| p.borrow().contains(&address)) { return write!(f, "#<circular {}>", kind); }
+    PRINTING.with(|p| p.borrow_mut().insert(address));
+    let result = body(f, &object.borrow());
+    PRINTING.with(|p| p.borrow_mut().remove(&address));
+    result
+}
+
+/// prints a chain of cells as a list, (a b . c) when it ends in something
+/// other than NIL. A cell already being printed, further up or earlier in
+/// the chain, prints as a marker
+fn write_cons(f: &mut fmt::Formatter, cell: &Rc<RefCell<Cons>>, readably: bool) -> fmt::Result {
+    let address = |cell: &Rc<RefCell<Cons>>| Rc::as_ptr(cell) as usize;
+    let printing = |cell: &Rc<RefCell<Cons>>| PRINTING.with(|p| p.borrow().contains(&address(cell)));
+    if printing(cell) { return write!(f, "#<circular cons>"); }
+    let mut shown = Vec::new();
+    let mut current = cell.clone();
+    let mut body = || -> fmt::Result {
+        write!(f, "(")?;
+        loop {
+            PRINTING.with(|p| p.borrow_mut().insert(address(&current)));
+            shown.push(address(&current));
+            let cdr = current.borrow().cdr.clone();
+            write_lval(f, &current.borrow().car, readably)?;
+            match cdr {
+                Lval::NIL => break,
+                Lval::Cons(next) if printing(&next) => {
+                    write!(f, " . #<circular cons>")?;
+                    break;
+                },
+                Lval::Cons(next) => {
+                    write!(f, " ")?;
+                    current = next;
+                },
+                other => {
+                    write!(f, " . ")?;
+                    write_lval(f, &other, readably)?;
+                    break;
+                },
+            }
+        }
+        write!(f, ")")
+    };
+    let result = body();
+    PRINTING.with(|p| for a in &shown { p.borrow_mut().remove(a); });
+    result
+}
+
+/// displays a value for people, as princ does: strings appear without quotes or escapes
+pub struct Princ<'a>(pub &'a Lval);
+
+impl fmt::Display for Princ<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write_lval(f, self.0, false)
+    }
+}
+
+fn write_lval(f: &mut fmt::Formatter, v: &Lval, readably: bool) -> fmt::Result {
+    let list = |f: &mut fmt::Formatter, cells: &[Lval]| -> fmt::Result {
+        write!(f, "(")?;
+        for (i, c) in cells.iter().enumerate() {
+            if i > 0 { write!(f, " ")?; }
+            write_lval(f, c, readably)?;
         }
+        write!(f, ")")
+    };
+    match v {
+        Lval::Num(n) => write!(f, "{}", n),
+        Lval::Sym(s) => write!(f, "{}", s),
+        Lval::Str(s) if readably => {
+            write!(f, "\"")?;
+            for c in s.chars() {
+                match c {
+                    '"' => write!(f, "\\\"")?,
+                    '\\' => write!(f, "\\\\")?,
+                    '\n' => write!(f, "\\n")?,
+                    '\t' => write!(f, "\\t")?,
+                    c => write!(f, "{}", c)?,
+                }
+            }
+            write!(f, "\"")
+        },
+        Lval::Str(s) => write!(f, "{}", s),
+        Lval::Char(c) if readably => match character::name(*c) {
+            Some(name) => write!(f, "#\\{}", name),
+            None => write!(f, "#\\{}", c),
+        },
+        Lval::Char(c) => write!(f, "{}", c),
+        Lval::Err(e) => write!(f, "Error: {}", e),
+        Lval::Fun(_) => write!(f, "<function>"),
+        Lval::StructFn(kind, op) => write!(f, "<function {}>", kind.function_name(*op)),
+        Lval::Generic(generic) => write!(f, "<generic-function {}>", generic.borrow().name),
+        // reads back as the lambda expression that builds an equivalent function
+        Lval::Lambda(_env, formals, body) => {
+            write!(f, "(\\ ")?;
+            write_lval(f, formals, readably)?;
+            write!(f, " ")?;
+            write_lval(f, body, readably)?;
+            write!(f, ")")
+        },
+        Lval::Sexpr(cells) => list(f, cells),
+        Lval::Cons(cell) => write_cons(f, cell, readably),
+        // the reader makes 'x a one element Qexpr; longer ones print as a quoted list
+        Lval::Qexpr(cells) => {
+            write!(f, "'")?;
+            match cells.as_slice() {
+                [single] => write_lval(f, single, readably),
+                _ => list(f, cells),
+            }
+        },
+        // reads back as a new table with the same test and entries
+        Lval::Hash(table) => write_shared(f, table, "hash-table", |f, table| {
+            write!(f, "#S(hash-table :test {} :data (", table.test.name())?;
+            for (i, (k, v)) in table.entries().iter().enumerate() {
+                if i > 0 { write!(f, " ")?; }
+                write_lval(f, k, readably)?;
+                write!(f, " ")?;
+                write_lval(f, v, readably)?;
+            }
+            write!(f, "))")
+        }),
+        // #(1 2 3) for a vector, #2A((1 2) (3 4)) for an array of two dimensions
+        Lval::Array(array) => write_shared(f, array, "array", |f, array| {
+            match array.dims().len() {
+                1 => write!(f, "#")?,
+                rank => write!(f, "#{}A", rank)?,
+            }
+            list(f, &array.rows())
+        }),
+        // reads back as a new instance while the type is defined
+        Lval::Struct(record) => write_shared(f, record, "structure", |f, record| {
+            write!(f, "#S({}", record.kind.name)?;
+            for (slot, v) in record.slots() {
+                write!(f, " :{} ", slot)?;
+                write_lval(f, v, readably)?;
+            }
+            write!(f, ")")
+        }),
+        // instances have no literal syntax, so this does not read back
+        Lval::Object(instance) => write_shared(f, instance, "instance", |f, instance| {
+            write!(f, "#<{}", instance.class.name)?;
+            for (slot, v) in instance.slots() {
+                write!(f, " :{} ", slot)?;
+                write_lval(f, v, readably)?;
+            }
+            write!(f, ">")
+        }),
+        Lval::T => write!(f, "T"),
+        Lval::NIL => write!(f, "NIL"),
+        Lval::Void => Ok(()),
     }
 }
 
@@ -123,9 +360,19 @@ impl PartialEq for Lval {
         match (self, other) {
             (Lval::Num(a), Lval::Num(b)) => a == b,
             (Lval::Sym(a), Lval::Sym(b)) => a == b,
+            (Lval::Str(a), Lval::Str(b)) => a == b,
+            (Lval::Char(a), Lval::Char(b)) => a == b,
             (Lval::Err(a), Lval::Err(b)) => a == b,
             (Lval::Sexpr(a), Lval::Sexpr(b)) => a == b,
             (Lval::Qexpr(a), Lval::Qexpr(b)) => a == b,
+            (Lval::Cons(a), Lval::Cons(b)) => Rc::ptr_eq(a, b) || cons::equal(a, b),
+            // a list of cells equals one the reader built with the same elements
+            (Lval::Cons(_), Lval::Sexpr(_)) | (Lval::Sexpr(_), Lval::Cons(_)) => self.list_items() == other.list_items(),
+            (Lval::Hash(a), Lval::Hash(b)) => Rc::ptr_eq(a, b),
+            (Lval::Array(a), Lval::Array(b)) => Rc::ptr_eq(a, b),
+            (Lval::Struct(a), Lval::Struct(b)) => Rc::ptr_eq(a, b),
+            (Lval::Object(a), Lval::Object(b)) => Rc::ptr_eq(a, b),
+            (Lval::Generic(a), Lval::Generic(b)) => Rc::ptr_eq(a, b),
             (Lval::T, Lval::T) => true,
             (Lval::NIL, Lval::NIL) => true,
             (Lval::Void, Lval::Void) => true,
@@ -0,0 +1,338 @@
+// Stack virtual machine for the bytecode built by compile.rs. It runs in a
+// loop over an explicit frame stack instead of recursing on the Rust stack,
+// and a call in tail position replaces the caller's frame.
+//
+// Values are still Lvals, so lambdas, builtins and the global environment are
+// shared with the tree-walking evaluator. A frame keeps its parameters in
+// slots followed by any other variables bound in the call: arguments captured
+// by partial application, variables set with setq, and for a frame reused by
+// a tail call the caller's variables the callee does not rebind, which the
+// callee could still see under dynamic scoping. Builtins that work on the
+// calling environment get the frames converted to an Lenv chain and their
+// changes copied back.
+//
+// Function bodies are compiled when first called and cached by the name they
+// are called under; a cached body is reused while the function bound to the
+// name is the same lambda, told by the identity of its formals and body, so a
+// hit costs two pointer comparisons however large the body is.
+
+use crate::capability::denied;
+use crate::compile::{compile_function, compile_toplevel, Op, Proto};
+use crate::cons;
+use crate::eval::{clear_interrupt, is_interrupted, lval_call, lval_eval};
+use crate::limits::{begin_evaluation, count_step, end_evaluation, enter_call, exit_call};
+use crate::symbol::Symbol;
+use crate::trace::{is_traced, traced_call};
+use crate::types::{Lenv, Lval};
+use crate::{builtin_profile, debug, read_forms};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+/// the function of a call, resolved before its arguments are evaluated
+enum Callee {
+    Lambda { proto: Rc<Proto>, captured: Lenv },
+    Value(Lval),
+}
+
+struct Frame {
+    proto: Rc<Proto>,
+    pc: usize,
+    /// height of the value stack when the frame started
+    base: usize,
+    /// parameters first, in slot order, then other variables bound in the call
+    vars: Vec<(Symbol, Lval)>,
+    /// false for a top level form, whose variables live in the global environment
+    scope: bool,
+}
+
+pub struct Vm {
+    globals: Rc<RefCell<Lenv>>,
+    frames: Vec<Frame>,
+    stack: Vec<Lval>,
+    callees: Vec<Callee>,
+    /// compiled bodies by the name their function was last called under
+    cache: HashMap<Symbol, Rc<Proto>>,
+}
+
+impl Vm {
+    /// a VM whose top level forms run in `globals`
+    pub fn new(globals: Rc<RefCell<Lenv>>) -> Self {
+        Vm { globals, frames: Vec::new(), stack: Vec::new(), callees: Vec::new(), cache: HashMap::new() }
+    }
+
+    /// compiles and runs one top level form
+    pub fn eval(&mut self, form: &Lval) -> Lval {
+        let proto = Rc::new(compile_toplevel(form));
+        self.frames.push(Frame { proto, pc: 0, base: self.stack.len(), vars: Vec::new(), scope: false });
+        self.run()
+    }
+
+    /// parses source text and runs each top level form in order, handing
+    /// every result to `on_result`, as eval_source does with the tree-walker
+    pub fn eval_source(&mut self, src: &str, mut on_result: impl FnMut(Lval)) -> Result<(), String> {
+        let forms = read_forms(src)?;
+        // stepping and breakpoints pause in lval_eval, which compiled code does not go through
+        if debug::is_hooked() {
+            debug::reset();
+            return Err("the debugger needs the tree backend".to_string());
+        }
+        clear_interrupt();
+        for form in forms {
+            begin_evaluation();
+            on_result(end_evaluation(self.eval(&form)));
+            if is_interrupted() { break; }
+        }
+        clear_interrupt();
+        debug::reset();
+        Ok(())
+    }
+
+    /// runs until the frame on top when called returns
+    fn run(&mut self) -> Lval {
+        let depth = self.frames.len();
+        loop {
+            let frame = self.frames.last_mut().unwrap();
+            let proto = frame.proto.clone();
+            let op = proto.code[frame.pc];
+            frame.pc += 1;
+            match op {
+                Op::Const(i) => self.stack.push(proto.constants[i].clone()),
+                Op::Quote(i) => self.stack.push(cons::quoted(&proto.constants[i])),
+                Op::Local(i) => self.stack.push(frame.vars[i].1.clone()),
+                Op::Var(i) => {
+                    let v = lookup(&self.frames, &self.globals, proto.names[i], Lval::clone);
+                    self.stack.push(v);
+                },
+                Op::SetLocal(i) => {
+                    let v = self.stack.pop().unwrap();
+                    let result = match v {
+                        Lval::Err(_) => v,
+                        _ => {
+                            self.frames.last_mut().unwrap().vars[i].1 = v;
+                            Lval::Sexpr(Vec::new())
+                        },
+                    };
+                    self.stack.push(result);
+                },
+                Op::SetVar(i) => {
+                    let v = self.stack.pop().unwrap();
+                    let result = match v {
+                        Lval::Err(_) => v,
+                        _ => {
+                            self.bind(proto.names[i], v);
+                            Lval::Sexpr(Vec::new())
+                        },
+                    };
+                    self.stack.push(result);
+                },
+                Op::Define(i, j) => {
+                    self.globals.borrow_mut().def(proto.names[i], proto.constants[j].clone());
+                    self.stack.push(Lval::Sexpr(Vec::new()));
+                },
+                Op::CalleeVar(i) => {
+                    let name = proto.sites[i].name.unwrap();
+                    let cache = &mut self.cache;
+                    let callee = lookup(&self.frames, &self.globals, name, |v| callee_of(cache, Some(name), v));
+                    self.callees.push(callee);
+                },
+                Op::Callee => {
+                    let v = self.stack.pop().unwrap();
+                    let callee = callee_of(&mut self.cache, None, &v);
+                    self.callees.push(callee);
+                },
+                Op::Call(i) | Op::TailCall(i) => {
+                    let tail = matches!(op, Op::TailCall(_));
+                    if let Some(result) = self.call(&proto, i, tail) {
+                        self.stack.push(result);
+                    }
+                },
+                Op::Test { next, end } => {
+                    let frame = self.frames.last_mut().unwrap();
+                    match self.stack.pop().unwrap() {
+                        err @ Lval::Err(_) => {
+                            self.stack.push(err);
+                            frame.pc = end;
+                        },
+                        Lval::NIL | Lval::Num(0) => frame.pc = next,
+                        _ => {},
+                    }
+                },
+                Op::Jump(target) => self.frames.last_mut().unwrap().pc = target,
+                Op::Walk(i) => {
+                    let env = self.scope_env();
+                    let result = lval_eval(env.clone(), proto.constants[i].clone());
+                    self.write_back(&env);
+                    self.stack.push(result);
+                },
+                Op::Return => {
+                    let result = self.stack.pop().unwrap();
+                    let frame = self.frames.pop().unwrap();
+                    self.stack.truncate(frame.base);
+                    if self.frames.len() < depth { return result; }
+                    exit_call();
+                    self.stack.push(result);
+                },
+            }
+        }
+    }
+
+    /// calls the pending function with the arguments on the stack. Returns
+    /// the result, or None when a lambda's frame was pushed and will return it
+    fn call(&mut self, proto: &Proto, site: usize, tail: bool) -> Option<Lval> {
+        let site = &proto.sites[site];
+        let args = self.stack.split_off(self.stack.len() - site.argc);
+        let callee = self.callees.pop().unwrap();
+
+        if let Err(err) = count_step() { return Some(err); }
+        if is_interrupted() { return Some(Lval::Err("Evaluation interrupted".to_string())); }
+
+        // the first error among the function and its arguments is the result
+        if let Callee::Value(err @ Lval::Err(_)) = callee { return Some(err); }
+        if let Some(err) = args.iter().find(|a| matches!(a, Lval::Err(_))) { return Some(err.clone()); }
+
+        let (proto, captured) = match callee {
+            // (x) is x's value unless x is a function taking no arguments, as in lval_eval
+            Callee::Value(v) if args.is_empty() && !v.takes_no_arguments() => return Some(v),
+            Callee::Lambda { proto, captured } if args.is_empty() && !proto.params.is_empty() => {
+                return Some(Lval::Lambda(captured, proto.formals.clone(), proto.body.clone()));
+            },
+            Callee::Value(Lval::Fun(func)) => {
+                if !site.needs_env { return Some(func(self.globals.clone(), args)); }
+                let env = self.scope_env();
+                let result = func(env.clone(), args);
+                self.write_back(&env);
+                return Some(result);
+            },
+            // structure functions, or a value that is not a function
+            Callee::Value(f) => return Some(lval_call(self.globals.clone(), f, args)),
+            Callee::Lambda { proto, captured } => (proto, captured),
+        };
+
+        if let Some(name) = site.name.filter(|&n| is_traced(n)) {
+            let f = Lval::Lambda(captured, proto.formals.clone(), proto.body.clone());
+            let env = self.scope_env();
+            let result = traced_call(env.clone(), name.name(), f, args);
+            self.write_back(&env);
+            return Some(result);
+        }
+
+        // bind the arguments as apply does, failing at the first one without a formal
+        let formals = match *proto.formals {
+            Lval::Qexpr(ref cells) => cells.as_slice(),
+            _ => &[],
+        };
+        for i in 0..args.len() {
+            match formals.get(i) {
+                Some(Lval::Sym(_)) => {},
+                Some(_) => return Some(Lval::Err("Formal should be a symbol".to_string())),
+                None => return Some(Lval::Err(format!("Function passed too many arguments. Got {}, Expected {}.", args.len(), formals.len()))),
+            }
+        }
+        if args.len() < formals.len() {
+            let rest = Lval::Qexpr(formals[args.len()..].to_vec());
+            let mut env = captured;
+            for (formal, arg) in formals.iter().zip(args) {
+                if let Lval::Sym(s) = formal { env.put(*s, arg); }
+            }
+            return Some(Lval::Lambda(env, Rc::new(rest), proto.body.clone()));
+        }
+
+        let mut vars: Vec<(Symbol, Lval)> = proto.params.iter().copied().zip(args).collect();
+        for (name, v) in captured.data {
+            if !vars.iter().any(|(n, _)| *n == name) { vars.push((name, v)); }
+        }
+        let frame = self.frames.last_mut().unwrap();
+        if tail && frame.scope {
+            // the caller's variables stay visible unless the callee rebinds them
+            for (name, v) in std::mem::take(&mut frame.vars) {
+                if !vars.iter().any(|(n, _)| *n == name) { vars.push((name, v)); }
+            }
+            self.stack.truncate(frame.base);
+            frame.proto = proto;
+            frame.pc = 0;
+            frame.vars = vars;
+            return None;
+        }
+        if let Err(err) = enter_call() { return Some(err); }
+        self.frames.push(Frame { proto, pc: 0, base: self.stack.len(), vars, scope: true });
+        None
+    }
+
+    /// binds a variable in the innermost scope, as Lenv::put does
+    fn bind(&mut self, name: Symbol, v: Lval) {
+        match self.frames.iter_mut().rev().find(|fr| fr.scope) {
+            Some(frame) => match frame.vars.iter_mut().find(|(n, _)| *n == name) {
+                Some(var) => var.1 = v,
+                None => frame.vars.push((name, v)),
+            },
+            None => self.globals.borrow_mut().put(name, v),
+        }
+    }
+
+    /// the frames as the Lenv chain the tree-walker would have built, innermost last
+    fn scope_env(&self) -> Rc<RefCell<Lenv>> {
+        let mut env = self.globals.clone();
+        for frame in self.frames.iter().filter(|fr| fr.scope) {
+            let data = frame.vars.iter().map(|(n, v)| (*n, v.clone())).collect();
+            env = Rc::new(RefCell::new(Lenv { par: Some(env), data }));
+        }
+        env
+    }
+
+    /// copies variables set through a scope_env chain back into the frames
+    fn write_back(&mut self, env: &Rc<RefCell<Lenv>>) {
+        let mut env = env.clone();
+        for i in (0..self.frames.len()).rev() {
+            if !self.frames[i].scope { continue; }
+            let data = std::mem::take(&mut env.borrow_mut().data);
+            let frame = &mut self.frames[i];
+            for (name, v) in data {
+                match frame.vars.iter_mut().find(|(n, _)| *n == name) {
+                    Some(var) => var.1 = v,
+                    None => frame.vars.push((name, v)),
+                }
+            }
+            let par = env.borrow().par.clone().unwrap();
+            env = par;
+        }
+    }
+}
+
+/// finds a variable the way Lenv::get does along the tree-walker's chain
+/// of calling environments, handing it to `f` without copying it
+fn lookup<R>(frames: &[Frame], globals: &Rc<RefCell<Lenv>>, name: Symbol, f: impl FnOnce(&Lval) -> R) -> R {
+    for frame in frames.iter().rev().filter(|fr| fr.scope) {
+        if let Some((_, v)) = frame.vars.iter().find(|(n, _)| *n == name) {
+            return f(v);
+        }
+    }
+    globals.borrow().with(name, |v| match v {
+        Some(v) => f(v),
+        None => match builtin_profile(name.name()) {
+            // a builtin that exists but was left out by the capability profile
+            Some(required) => f(&denied(name.name(), required)),
+            None => f(&Lval::Err(format!("Unbound symbol '{}'", name))),
+        },
+    })
+}
+
+/// resolves a function value, compiling a lambda's body unless the cache
+/// holds it under the name. A lambda shares its formals and body with its
+/// copies, so the cached Proto is the right one while those are the same
+/// allocation; the cache keeps them alive, so the address cannot be reused
+fn callee_of(cache: &mut HashMap<Symbol, Rc<Proto>>, name: Option<Symbol>, v: &Lval) -> Callee {
+    let Lval::Lambda(env, formals, body) = v else {
+        return Callee::Value(v.clone());
+    };
+    let cached = name.and_then(|n| cache.get(&n)).filter(|p| Rc::ptr_eq(&p.formals, formals) && Rc::ptr_eq(&p.body, body));
+    let proto = match cached {
+        Some(proto) => proto.clone(),
+        None => {
+            let proto = Rc::new(compile_function(formals, body));
+            if let Some(n) = name { cache.insert(n, proto.clone()); }
+            proto
+        },
+    };
+    Callee::Lambda { proto, captured: env.clone() }
+}
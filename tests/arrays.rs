@@ -0,0 +1,81 @@
+// Tests for vectors, multi-dimensional arrays and the sequence functions.
+
+mod common;
+
+use common::{eval_all, last};
+
+#[test]
+fn vector_literals_and_access() {
+    assert_eq!(last("#(1 a \"s\" (2 3))"), "#(1 a \"s\" (2 3))");
+    assert_eq!(last("(aref #(10 20 30) 2)"), "30");
+    assert_eq!(last("(setq v (vector 'a 'b)) (setf (aref v 1) '(c)) v"), "#(a (c))");
+    assert_eq!(last("(aref #(1) 1)"), "Error: Index 1 is out of range");
+    assert_eq!(last("(aref '(1) 0)"), "Error: Argument to aref must be an array");
+}
+
+#[test]
+fn multi_dimensional_arrays() {
+    let results = eval_all("
+        (setq m (make-array '(2 3) :initial-element 0))
+        (setf (aref m 1 2) 5)
+        (incf (aref m 0 1))
+        m
+        (array-dimensions m)
+        (aref m 1)
+    ");
+    assert_eq!(results[3..], ["#2A((0 1 0) (0 0 5))", "(2 3)", "Error: Array of rank 2 accessed with 1 indices"]);
+    assert_eq!(last("(aref #2A((1 2) (3 4)) 1 0)"), "3");
+    assert_eq!(last("#3A(((1 2)) ((3 4)))"), "#3A(((1 2)) ((3 4)))");
+    assert_eq!(last("#2A((1 2) (3))"), "Error: Malformed array literal");
+    assert_eq!(last("(make-array '(2 2) :initial-contents '((a b) (c d)))"), "#2A((a b) (c d))");
+    assert_eq!(last("(make-array '(2 2) :initial-contents '(a b))"), "Error: Initial contents do not match the array's dimensions");
+}
+
+#[test]
+fn fill_pointers() {
+    let results = eval_all("
+        (setq v (make-array 2 :fill-pointer 0))
+        (vector-push 'a v)
+        (vector-push 'b v)
+        (vector-push 'c v)
+        (vector-push-extend 'c v)
+        v
+        (length v)
+        (vector-push 1 #(1 2))
+    ");
+    assert_eq!(results[1..], ["0", "1", "NIL", "2", "#(a b c)", "3", "Error: Vector has no fill pointer"]);
+}
+
+#[test]
+fn arrays_are_shared_between_variables() {
+    assert_eq!(last("(setq a (vector 1 2)) (setq b a) (setf (aref b 0) 9) a"), "#(9 2)");
+    assert_eq!(last("(setq a #(1)) (setq b a) (eq a b)"), "T");
+}
+
+#[test]
+fn sequence_functions_take_lists_and_vectors() {
+    assert_eq!(eval_all("(length '(1 2 3)) (length #(1 2)) (length \"abc\") (length nil)"), ["3", "2", "3", "0"]);
+    assert_eq!(last("(mapcar (\\ '(x) '(* x x)) '(1 2 3))"), "(1 4 9)");
+    assert_eq!(last("(mapcar (\\ '(x) '(* x x)) #(1 2 3))"), "#(1 4 9)");
+    assert_eq!(last("(mapcar + '(1 2 3) #(10 20))"), "(11 22)");
+    assert_eq!(last("(reduce + #(1 2 3 4))"), "10");
+    assert_eq!(last("(reduce - '(1 2) :initial-value 10)"), "7");
+    assert_eq!(last("(reduce + '() :initial-value 7)"), "7");
+    assert_eq!(last("(mapcar + 1)"), "Error: Argument to mapcar must be a list or a vector");
+}
+
+#[test]
+fn vector_without_arguments_is_empty() {
+    assert_eq!(last("(vector)"), "#()");
+    assert_eq!(last("(length (vector))"), "0");
+    assert_eq!(last("(reduce - (vector) :initial-value 5)"), "5");
+    // an empty sequence calls the function with no arguments, as for lists
+    assert_eq!(last("(reduce - (vector))"), last("(reduce - '())"));
+}
+
+#[test]
+fn arrays_too_large_to_allocate_are_errors() {
+    assert_eq!(last("(make-array 10000000000000)"), "Error: Array is too large");
+    assert_eq!(last("(make-array '(100000000 100000000) :initial-element 0)"), "Error: Array is too large");
+    assert_eq!(last("(length (make-array 3))"), "3");
+}
@@ -0,0 +1,199 @@
+// Differential tests: every program runs on the tree-walking evaluator, the
+// pre-resolved AST evaluator and the bytecode VM, and all of them must print
+// the same results and output.
+
+mod common;
+
+use common::{eval_with, results, session, Backend};
+use proptest::prelude::*;
+use rustlisp::capability::Profile;
+use rustlisp::io::set_output;
+use rustlisp::limits::{set_limits, Limits};
+use rustlisp::types::Lval;
+use std::cell::RefCell;
+use std::rc::Rc;
+
+/// each top level result followed by anything the program printed
+fn run(src: &str, backend: Backend) -> Vec<String> {
+    let out = Rc::new(RefCell::new(Vec::new()));
+    let sink = out.clone();
+    set_output(Some(Box::new(move |text| sink.borrow_mut().push(text.to_string()))));
+    eval_with(&session(Profile::Full), src, backend, |r: Lval| out.borrow_mut().push(format!("=> {}", r)));
+    set_output(None);
+    out.take()
+}
+
+fn agree(src: &str) {
+    let expected = run(src, Backend::Tree);
+    for backend in [Backend::Ast, Backend::Vm] {
+        assert_eq!(run(src, backend), expected, "{:?} disagrees on {}", backend, src);
+    }
+}
+
+#[test]
+fn arithmetic_and_atoms() {
+    agree("(+ 1 2) (- 5) (* 2 3 4) (/ 7 2) (/ 1 0) 42 \"str\" 'sym '(1 2) T nil () (+ 1 'a)");
+}
+
+#[test]
+fn duplicate_formals_are_refused() {
+    agree("((\\ '(x x) '(x)) 1 2) (defun f (x x) (+ x 0)) (f 1 2) ((\\ '(x y x) '(y)) 1 2 3)");
+    assert_eq!(run("(defun f (x x) (+ x 0))", Backend::Vm), ["=> Error: Formal 'x' appears more than once"]);
+}
+
+#[test]
+fn recursion() {
+    agree("(defun fib (n) (cond ((eq n 0) 0) ((eq n 1) 1) (t (+ (fib (- n 1)) (fib (- n 2)))))) (fib 15)");
+    agree("(defun len (l) (cond ((null l) 0) (t (+ 1 (len (cdr l)))))) (len '(a b c d))");
+}
+
+#[test]
+fn tail_calls_keep_the_callers_variables_visible() {
+    agree("(defun count (n acc) (cond ((eq n 0) acc) (t (count (- n 1) (+ acc 1))))) (count 100 0)");
+    agree("(defun inner (y) (+ x y)) (defun outer (x) (inner 1)) (outer 41)");
+    agree("(defun b (y) (cond ((eq y 0) x) (t (b (- y 1))))) (defun a (x) (b 3)) (a 7)");
+}
+
+#[test]
+fn dynamic_scope_and_setq() {
+    agree("(setq x 1) (defun show () x) (defun shadow (x) (show 0)) (shadow 5) x");
+    agree("(defun f (a) ((setq a 10) a)) (f 1)");
+    agree("(defun g (a) ((setq b (+ a 1)) b)) (g 1) b");
+    agree("(defun h (a) (setq a (car '()))) (h 1)");
+}
+
+#[test]
+fn lambdas_and_partial_application() {
+    agree("(def 'add (\\ '(x y) '(+ x y))) (add 1 2) ((add 1) 2) (add 1)");
+    agree("(def 'k (\\ '(x) 'x)) (k 5) ((\\ '(a b) '(* a b)) 6 7)");
+    agree("(defun twice (f v) (f (f v))) (twice (\\ '(n) '(+ n 3)) 1)");
+    agree("(defun add3 (a b c) (+ a b c)) (def 'p (add3 1 2)) (p 3) p");
+}
+
+#[test]
+fn calls_without_arguments() {
+    agree("(defun greet () 'hi) (greet) (def 'thunk (\\ '() '(+ 1 2))) (thunk) (defun two (a b) a) (two)");
+    // a one element list that does not name a function is its element's value
+    agree("(setq v 5) (v) (t) (:k) ((+ 1 2)) (vector) (length (vector))");
+    // nor does one naming a function that needs arguments, so a body can return a function
+    let src = "(defun k (f) (f)) (k car) ((\\ '(f) '(f)) car) (defstruct pt x) (pt-x ((\\ '(f) '(f)) make-pt)) (k pt-x)";
+    agree(src);
+    let results = results(&session(Profile::Full), src, Backend::Tree);
+    assert_eq!(results[1..3], ["<function>", "<function>"]);
+    assert_eq!(results[4], "NIL");
+}
+
+#[test]
+fn errors() {
+    agree("(undefined 1) (car '()) (+ (car '()) (print 1)) ((add 1 2 3))");
+    agree("(def 'one (\\ '(x) '(x))) (one 1 2) (defun bad (1 x) x) (bad 1)");
+    agree("(5 1) (\"s\" 2) (t 1) (cond (1)) (cond 3) (cond ((car '()) 1))");
+    agree("(setq 1 2) (setq a) (defun) (quote) (quote a b)");
+}
+
+#[test]
+fn environment_builtins_see_the_frames() {
+    agree("(defun ev (x) (eval '(+ x 1))) (ev 4)");
+    agree("(defun put (x) ((= '(y) (+ x 1)) y)) (put 4) (def 'g 3) g");
+    agree("(defun sets () (set '(z) 9)) (sets) z");
+}
+
+#[test]
+fn cond_forms() {
+    agree("(cond ((eq 1 2) 'a) ('((eq 1 1) 'b)) (t 'c)) (cond) (cond (nil 1)) (cond (0 1) (2 3))");
+    agree("(defun sign (n) (cond ((eq n 0) 'zero) (t 'nonzero))) (sign 0) (sign 3)");
+}
+
+#[test]
+fn places() {
+    agree("(setq l '(1 2 3)) (setf (car l) 9 (nth 2 l) 'c) (push 0 l) (pop l) (incf (nth 1 l) 5) (rplacd l '(x)) l");
+    agree("(defun bump (n) ((incf n 2) n)) (bump 1) (defun grow (l) ((push 'a l) l)) (grow '(b)) (decf undefined)");
+    agree("(setq counter 0) (defun bump (by) (incf counter by)) (bump 1) (bump 2) counter (setq a '(1 2)) (setq b a) (rplaca a 9) b");
+}
+
+#[test]
+fn hash_tables() {
+    agree("(setq h (make-hash-table :test equal)) (setf (gethash \"a\" h) 1) (incf (gethash 'b h 0) 2) (remhash \"a\" h) h :key");
+    agree("(defun scaled (h scale) (maphash (\\ '(k v) '(print k (* v scale))) h)) (setq t2 (make-hash-table)) (puthash 'x 3 t2) (scaled t2 10)");
+}
+
+#[test]
+fn arrays_and_sequences() {
+    agree("(setq m (make-array '(2 2) :initial-element 1)) (incf (aref m 1 1) 4) m (aref #(a b) 1) (length #2A((1)))");
+    agree("(defun scale (s k) (mapcar (\\ '(x) '(* x k)) s)) (scale #(1 2) 3) (scale '(4) 2) (reduce + (scale '(1 2 3) 2))");
+}
+
+#[test]
+fn structures() {
+    agree("(defstruct point x (y 0)) (setq p (make-point :x 3)) (incf (point-y p) 4) (defun norm (q) (+ (* (point-x q) (point-x q)) (* (point-y q) (point-y q)))) (norm p) (point-p (copy-point p)) (type-of p) (point-x 1)");
+}
+
+#[test]
+fn objects() {
+    agree("(defclass shape () ((name :initarg :name :reader shape-name))) (defclass circle (shape) ((r :initarg :r :initform 1 :accessor circle-r))) (defmethod area ((s shape)) 0) (defmethod area ((c circle)) (+ 1 (call-next-method))) (setq c (make-instance 'circle :name 'c)) (setf (circle-r c) 3) (area c) (area (make-instance 'shape)) (shape-name c) (type-of c) (area 1)");
+}
+
+#[test]
+fn characters() {
+    agree("(setq s \"Lisp 42\") (defun up (i) (char-upcase (char s i))) (up 1) (char-code (aref s 0)) (digit-char-p (char s 5)) (char< #\\a (up 2)) (alpha-char-p #\\Space) (code-char 955) (char s 9)");
+}
+
+#[test]
+fn string_literals() {
+    // strings are values of their own that evaluate to themselves, not quoted symbols
+    let src = "\"a\\\"b\\n\" (type-of \"x\") (eq \"x\" 'x) (equal \"ab\" \"ab\") (symbol-name 'x)";
+    assert_eq!(run(src, Backend::Tree), ["=> \"a\\\"b\\n\"", "=> string", "=> NIL", "=> T", "=> \"x\""]);
+    agree(src);
+}
+
+#[test]
+fn printing_and_tracing() {
+    agree("(print 1 \"a\") (princ \"b\") (defun sq (x) (* x x)) (trace sq) (defun go (n) (sq n)) (go 3) (untrace sq) (go 4)");
+}
+
+/// a random expression over a few variables, numbers and the functions defined in PRELUDE
+fn expr() -> impl Strategy<Value = String> {
+    let leaf = prop_oneof![
+        (-5i64..20).prop_map(|n| n.to_string()),
+        Just("x".to_string()),
+        Just("y".to_string()),
+        Just("nil".to_string()),
+        Just("'(1 2)".to_string()),
+    ];
+    leaf.prop_recursive(4, 40, 3, |inner| prop_oneof![
+        (prop::sample::select(vec!["+", "-", "*", "/", "eq", "add", "pick", "mul", "cons"]), prop::collection::vec(inner.clone(), 0..4))
+            .prop_map(|(f, args)| format!("({} {})", f, args.join(" "))),
+        (inner.clone(), inner.clone(), inner.clone())
+            .prop_map(|(t, a, b)| format!("(cond ({} {}) (t {}))", t, a, b)),
+        inner.clone().prop_map(|v| format!("((setq x {}) x)", v)),
+        inner.prop_map(|v| format!("(apply-to (\\ '(x) '{}) 3)", v)),
+    ])
+}
+
+const PRELUDE: &str = "
+    (setq x 2) (setq y 7)
+    (defun add (a b) (+ a b))
+    (defun mul (a b) (cond ((eq a 0) 0) (t (+ b (mul (- a 1) b)))))
+    (defun pick (a b c) (cond (a b) (t c)))
+    (defun apply-to (f v) (f v))
+";
+
+proptest! {
+    #[test]
+    fn random_programs_agree(body in prop::collection::vec(expr(), 1..4)) {
+        // mul recurses forever on negative numbers; both backends must stop
+        // with the same error well before the test thread's stack runs out
+        set_limits(Limits { max_depth: Some(100), ..Limits::default() });
+        let src = format!("{} {}", PRELUDE, body.join(" "));
+        let expected = run(&src, Backend::Tree);
+        for backend in [Backend::Ast, Backend::Vm] {
+            prop_assert_eq!(run(&src, backend), expected.clone(), "{:?} disagrees on {}", backend, src);
+        }
+    }
+}
+
+#[test]
+fn tail_calls_run_in_constant_depth() {
+    let src = "(defun count (n acc) (cond ((eq n 0) acc) (t (count (- n 1) (+ acc 1))))) (count 100000 0)";
+    assert_eq!(results(&session(Profile::Full), src, Backend::Vm), ["()", "100000"]);
+}
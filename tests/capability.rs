@@ -0,0 +1,59 @@
+// Tests for the capability profiles withholding file and shell builtins.
+
+mod common;
+
+use common::{results, session, Backend, BACKENDS};
+use rustlisp::capability::Profile;
+
+/// the printed result of each top level form with the builtins of a profile
+fn run(src: &str, profile: Profile, backend: Backend) -> Vec<String> {
+    results(&session(profile), src, backend)
+}
+
+/// a file in the temp directory holding `text`, named for the test using it
+fn temp_file(name: &str, text: &str) -> String {
+    let path = std::env::temp_dir().join(format!("rustlisp-{}-{}", name, std::process::id()));
+    std::fs::write(&path, text).unwrap();
+    path.to_str().unwrap().to_string()
+}
+
+#[test]
+fn pure_denies_files_and_shell() {
+    let path = temp_file("pure", "(+ 1 2)");
+    let src = format!("(load {0:?}) (read-file {0:?}) (shell \"echo hi\") (write-file {0:?} \"x\") (+ 1 2)", path);
+    for backend in BACKENDS {
+        assert_eq!(run(&src, Profile::Pure, backend), [
+            "Error: Capability denied: 'load' requires the 'io-read' profile",
+            "Error: Capability denied: 'read-file' requires the 'io-read' profile",
+            "Error: Capability denied: 'shell' requires the 'full' profile",
+            "Error: Capability denied: 'write-file' requires the 'full' profile",
+            "3",
+        ], "{:?}", backend);
+    }
+    assert_eq!(std::fs::read_to_string(&path).unwrap(), "(+ 1 2)");
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn io_read_reads_but_does_not_write() {
+    let path = temp_file("io-read", "(setq loaded 7)");
+    let src = format!("(read-file {0:?}) (load {0:?}) loaded (write-file {0:?} \"x\") (shell \"echo hi\")", path);
+    for backend in BACKENDS {
+        assert_eq!(run(&src, Profile::IoRead, backend), [
+            "\"(setq loaded 7)\"",
+            "T",
+            "7",
+            "Error: Capability denied: 'write-file' requires the 'full' profile",
+            "Error: Capability denied: 'shell' requires the 'full' profile",
+        ], "{:?}", backend);
+    }
+    assert_eq!(std::fs::read_to_string(&path).unwrap(), "(setq loaded 7)");
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn profiles_by_name() {
+    assert_eq!(Profile::from_name("io-read"), Some(Profile::IoRead));
+    assert_eq!(Profile::from_name("root"), None);
+    assert!(Profile::Pure < Profile::IoRead && Profile::IoRead < Profile::Full);
+}
@@ -0,0 +1,49 @@
+// Tests for characters and indexing strings.
+
+mod common;
+
+use common::{eval_all, last};
+use rustlisp::types::{Lval, Princ};
+
+#[test]
+fn reading_and_printing() {
+    let results = eval_all("#\\a #\\Space #\\space #\\Newline #\\Tab #\\( #\\\\ '(#\\a #\\) #\\b) (type-of #\\a) #\\ab");
+    assert_eq!(results, ["#\\a", "#\\Space", "#\\Space", "#\\Newline", "#\\Tab", "#\\(", "#\\\\", "'(#\\a #\\) #\\b)", "character", "Error: Unknown character name ab"]);
+    // princ shows the character itself
+    assert_eq!(Princ(&Lval::Char('a')).to_string(), "a");
+    assert_eq!(Princ(&Lval::Char(' ')).to_string(), " ");
+}
+
+#[test]
+fn indexing_strings() {
+    let results = eval_all("(char \"hello\" 1) (aref \"héllo\" 1) (char \"abc\" 3) (char \"abc\" -1) (aref \"abc\" 0 0) (char '(1) 0)");
+    assert_eq!(results, ["#\\e", "#\\é", "Error: Index 3 is out of range", "Error: Index -1 is out of range",
+        "Error: String accessed with 2 indices", "Error: Argument to char must be a string"]);
+}
+
+#[test]
+fn codes_and_case() {
+    let results = eval_all("(char-code #\\A) (code-char 97) (code-char -1) (char-upcase #\\a) (char-upcase #\\1) (char-downcase #\\Q) (char-upcase #\\ß) (char-code 1)");
+    assert_eq!(results, ["65", "#\\a", "NIL", "#\\A", "#\\1", "#\\q", "#\\ß", "Error: Argument to char-code must be a character"]);
+}
+
+#[test]
+fn predicates() {
+    let results = eval_all("(alpha-char-p #\\z) (alpha-char-p #\\7) (digit-char-p #\\7) (digit-char-p #\\z) (digit-char-p #\\f 16) (digit-char-p #\\2 2)");
+    assert_eq!(results, ["T", "NIL", "7", "NIL", "15", "NIL"]);
+}
+
+#[test]
+fn comparisons() {
+    let results = eval_all("(char= #\\a #\\a #\\a) (char= #\\a #\\A) (char< #\\a #\\b #\\c) (char< #\\a #\\c #\\b) (char>= #\\c #\\c #\\a)
+        (char/= #\\a #\\b #\\a) (char/= #\\a #\\b #\\c) (char> #\\b) (char< #\\a \"b\")");
+    assert_eq!(results, ["T", "NIL", "T", "NIL", "T", "NIL", "T", "T", "Error: Argument to char< must be a character"]);
+}
+
+#[test]
+fn equality_and_tables() {
+    let results = eval_all("(eq #\\a (char \"a\" 0)) (equal '(#\\a) '(#\\a)) (setq h (make-hash-table)) (puthash #\\x 1 h) (gethash (char \"x\" 0) h)");
+    assert_eq!(results[..2], ["T", "T"]);
+    assert_eq!(results[4], "1");
+    assert_eq!(last("(json-stringify (char \"a\" 0))"), "\"\\\"a\\\"\"");
+}
@@ -0,0 +1,54 @@
+// Fixtures shared by the integration tests: fresh sessions, and evaluating
+// source on each backend. Each test file uses only some of them.
+#![allow(dead_code)]
+
+use rustlisp::add_builtins_with;
+use rustlisp::ast::Evaluator;
+use rustlisp::capability::Profile;
+use rustlisp::eval_source;
+use rustlisp::types::{Lenv, Lval};
+use rustlisp::vm::Vm;
+use std::cell::RefCell;
+use std::rc::Rc;
+
+#[derive(Clone, Copy, Debug)]
+pub enum Backend {
+    Tree,
+    Ast,
+    Vm,
+}
+
+pub const BACKENDS: [Backend; 3] = [Backend::Tree, Backend::Ast, Backend::Vm];
+
+/// a global environment with the builtins of a profile
+pub fn session(profile: Profile) -> Rc<RefCell<Lenv>> {
+    let env = Rc::new(RefCell::new(Lenv::new()));
+    add_builtins_with(env.clone(), profile);
+    env
+}
+
+/// evaluates each top level form of `src` in `env` on a backend
+pub fn eval_with(env: &Rc<RefCell<Lenv>>, src: &str, backend: Backend, on_result: impl FnMut(Lval)) {
+    match backend {
+        Backend::Tree => eval_source(env, src, on_result),
+        Backend::Ast => Evaluator::new(env.clone()).eval_source(src, on_result),
+        Backend::Vm => Vm::new(env.clone()).eval_source(src, on_result),
+    }.unwrap();
+}
+
+/// the printed result of each top level form, evaluated in `env` on a backend
+pub fn results(env: &Rc<RefCell<Lenv>>, src: &str, backend: Backend) -> Vec<String> {
+    let mut results = Vec::new();
+    eval_with(env, src, backend, |r| results.push(r.to_string()));
+    results
+}
+
+/// the printed result of each top level form in a new full session
+pub fn eval_all(src: &str) -> Vec<String> {
+    results(&session(Profile::Full), src, Backend::Tree)
+}
+
+/// the printed result of the last form
+pub fn last(src: &str) -> String {
+    eval_all(src).pop().unwrap()
+}
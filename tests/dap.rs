@@ -0,0 +1,148 @@
+// Drives the DAP server with a scripted client: every request is queued up
+// front, and the server reads the ones after configurationDone while the
+// program is paused at a breakpoint.
+
+use serde_json::{json, Value};
+use std::cell::RefCell;
+use std::fs;
+use std::io::{self, Cursor, Write};
+use std::path::PathBuf;
+use std::rc::Rc;
+
+const PROGRAM: &str = "\
+(defun sq (x)
+  (* x x))
+(print (sq 7))
+";
+
+/// output buffer the test can read after the server returns
+#[derive(Clone, Default)]
+struct Shared(Rc<RefCell<Vec<u8>>>);
+
+impl Write for Shared {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+fn frame(seq: usize, command: &str, arguments: Value) -> String {
+    let body = json!({ "seq": seq, "type": "request", "command": command, "arguments": arguments }).to_string();
+    format!("Content-Length: {}\r\n\r\n{}", body.len(), body)
+}
+
+fn parse_messages(bytes: &[u8]) -> Vec<Value> {
+    let mut text = std::str::from_utf8(bytes).unwrap();
+    let mut messages = Vec::new();
+    while let Some(rest) = text.strip_prefix("Content-Length: ") {
+        let (length, rest) = rest.split_once("\r\n\r\n").unwrap();
+        let length: usize = length.parse().unwrap();
+        messages.push(serde_json::from_str(&rest[..length]).unwrap());
+        text = &rest[length..];
+    }
+    messages
+}
+
+fn response<'a>(messages: &'a [Value], command: &str) -> &'a Value {
+    messages.iter()
+        .find(|m| m["type"] == "response" && m["command"] == command)
+        .unwrap_or_else(|| panic!("no {} response", command))
+}
+
+fn program_path(name: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("rustlisp-dap-{}-{}.l", name, std::process::id()));
+    fs::write(&path, PROGRAM).unwrap();
+    path
+}
+
+fn run_session(requests: &[(&str, Value)]) -> Vec<Value> {
+    let script: String = requests.iter().enumerate()
+        .map(|(i, (command, args))| frame(i + 1, command, args.clone()))
+        .collect();
+    let output = Shared::default();
+    rustlisp::dap::serve(Box::new(Cursor::new(script.into_bytes())), Box::new(output.clone())).unwrap();
+    let bytes = output.0.borrow();
+    parse_messages(&bytes)
+}
+
+#[test]
+fn stops_at_line_breakpoint_and_inspects_frame() {
+    let path = program_path("breakpoint");
+    let program = path.to_str().unwrap();
+    let messages = run_session(&[
+        ("initialize", json!({ "adapterID": "rustlisp" })),
+        ("launch", json!({ "program": program })),
+        ("setBreakpoints", json!({ "source": { "path": program }, "breakpoints": [{ "line": 2 }, { "line": 4 }] })),
+        ("configurationDone", json!({})),
+        ("threads", json!({})),
+        ("stackTrace", json!({ "threadId": 1 })),
+        ("scopes", json!({ "frameId": 0 })),
+        ("variables", json!({ "variablesReference": 2 })),
+        ("evaluate", json!({ "expression": "(+ x 1)", "frameId": 0 })),
+        ("continue", json!({ "threadId": 1 })),
+        ("disconnect", json!({})),
+    ]);
+    fs::remove_file(&path).unwrap();
+
+    assert!(messages.iter().all(|m| m["type"] != "response" || m["success"] == true));
+
+    let breakpoints = &response(&messages, "setBreakpoints")["body"]["breakpoints"];
+    assert_eq!(breakpoints[0]["verified"], true);
+    assert_eq!(breakpoints[1]["verified"], false);
+
+    let stopped = messages.iter().find(|m| m["event"] == "stopped").unwrap();
+    assert_eq!(stopped["body"]["reason"], "breakpoint");
+
+    let frames = &response(&messages, "stackTrace")["body"]["stackFrames"];
+    assert_eq!(frames[0]["name"], "sq");
+    assert_eq!(frames[0]["line"], 2);
+    assert_eq!(frames[1]["name"], "<top level>");
+    assert_eq!(frames[1]["line"], 3);
+
+    let scopes = &response(&messages, "scopes")["body"]["scopes"];
+    assert_eq!(scopes[0]["name"], "Locals");
+    let variables = &response(&messages, "variables")["body"]["variables"];
+    assert_eq!(variables[0]["name"], "x");
+    assert_eq!(variables[0]["value"], "7");
+
+    assert_eq!(response(&messages, "evaluate")["body"]["result"], "8");
+
+    let output = messages.iter().find(|m| m["event"] == "output").unwrap();
+    assert_eq!(output["body"]["output"], "49");
+    assert!(messages.iter().any(|m| m["event"] == "terminated"));
+}
+
+#[test]
+fn steps_from_entry() {
+    let path = program_path("entry");
+    let program = path.to_str().unwrap();
+    let messages = run_session(&[
+        ("initialize", json!({})),
+        ("launch", json!({ "program": program, "stopOnEntry": true })),
+        ("configurationDone", json!({})),
+        ("next", json!({ "threadId": 1 })),
+        ("stepIn", json!({ "threadId": 1 })),
+        ("stepIn", json!({ "threadId": 1 })),
+        ("stackTrace", json!({ "threadId": 1 })),
+        ("stepOut", json!({ "threadId": 1 })),
+        ("disconnect", json!({})),
+    ]);
+    fs::remove_file(&path).unwrap();
+
+    assert!(messages.iter().all(|m| m["type"] != "response" || m["success"] == true));
+    let reasons: Vec<&Value> = messages.iter()
+        .filter(|m| m["event"] == "stopped")
+        .map(|m| &m["body"]["reason"])
+        .collect();
+    // entry at the defun, then (print ...), (sq 7) and the body of sq
+    assert_eq!(reasons, ["entry", "step", "step", "step"]);
+
+    let frames = &response(&messages, "stackTrace")["body"]["stackFrames"];
+    assert_eq!(frames[0]["name"], "sq");
+    assert_eq!(frames[0]["line"], 2);
+    assert!(messages.iter().any(|m| m["event"] == "terminated"));
+}
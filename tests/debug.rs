@@ -0,0 +1,147 @@
+// Drives the debugger hooks with a scripted Debugger: each pause is logged
+// as its reason, form and frames, and answered with the next step command.
+
+use rustlisp::debug::{self, Debugger, Pause, Resume};
+use rustlisp::types::{Lenv, Lval};
+use rustlisp::{add_builtins, eval_source};
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::rc::Rc;
+
+const PROGRAM: &str = "
+(defun sq (x) (* x x))
+(defun f (n) (+ (sq n) 1))
+(defun h (n) (eq (break) (+ n 1)))
+(defun down (n) (cond ((eq n 0) (break)) (t (down (- n 1)))))
+";
+
+struct Script {
+    answers: VecDeque<Resume>,
+    log: Rc<RefCell<Vec<String>>>,
+}
+
+impl Debugger for Script {
+    fn pause(&mut self, pause: &Pause) -> Resume {
+        let frames: Vec<&str> = pause.frames.iter().map(|f| f.name.as_str()).collect();
+        self.log.borrow_mut().push(format!("{:?} {} [{}]", pause.reason, pause.expr, frames.join(" ")));
+        self.answers.pop_front().unwrap_or(Resume::Continue)
+    }
+}
+
+/// evaluates `src` after PROGRAM with a debugger answering `answers`, stepping
+/// from the first form when `step` is set. Returns the pauses and the last value
+fn run(src: &str, step: bool, answers: &[Resume]) -> (Vec<String>, String) {
+    let env = Rc::new(RefCell::new(Lenv::new()));
+    add_builtins(env.clone());
+    eval_source(&env, PROGRAM, |_| {}).unwrap();
+    let log = Rc::new(RefCell::new(Vec::new()));
+    debug::set_debugger(Some(Box::new(Script { answers: answers.iter().copied().collect(), log: log.clone() })));
+    if step { debug::step_next(); }
+    let mut last = Lval::NIL;
+    eval_source(&env, src, |v| last = v).unwrap();
+    debug::set_debugger(None);
+    debug::reset();
+    let log = log.borrow().clone();
+    (log, last.to_string())
+}
+
+#[test]
+fn step_into_pauses_inside_calls() {
+    let (log, value) = run("(f 3)", true, &[Resume::StepInto, Resume::StepInto, Resume::StepInto, Resume::Continue]);
+    assert_eq!(log, [
+        "Step (f 3) []",
+        "Step (+ (sq n) 1) [f]",
+        "Step (sq n) [f]",
+        "Step (* x x) [f sq]",
+    ]);
+    assert_eq!(value, "10");
+}
+
+#[test]
+fn step_over_skips_the_paused_form() {
+    let (log, value) = run("(f 3)", true, &[Resume::StepInto, Resume::StepInto, Resume::StepOver, Resume::Continue]);
+    assert_eq!(log, ["Step (f 3) []", "Step (+ (sq n) 1) [f]", "Step (sq n) [f]"]);
+    assert_eq!(value, "10");
+
+    let (log, value) = run("(+ (f 1) (f 2))", true, &[Resume::StepInto, Resume::StepOver, Resume::StepOver]);
+    assert_eq!(log, ["Step (+ (f 1) (f 2)) []", "Step (f 1) []", "Step (f 2) []"]);
+    assert_eq!(value, "7");
+}
+
+#[test]
+fn step_out_finishes_the_call() {
+    let answers = [Resume::StepInto, Resume::StepInto, Resume::StepOut, Resume::StepInto, Resume::Continue];
+    let (log, value) = run("(+ (sq 2) (f 3))", true, &answers);
+    assert_eq!(log, [
+        "Step (+ (sq 2) (f 3)) []",
+        "Step (sq 2) []",
+        "Step (* x x) [sq]",
+        "Step (f 3) []",
+        "Step (+ (sq n) 1) [f]",
+    ]);
+    assert_eq!(value, "14");
+}
+
+#[test]
+fn break_pauses_with_a_backtrace() {
+    let (log, value) = run("(down 2)", false, &[]);
+    assert_eq!(log, ["Break (break) [down down down]"]);
+    assert_eq!(value, "NIL");
+
+    // stepping on from a (break) pauses at the form after it
+    let (log, value) = run("(h 1)", false, &[Resume::StepOver, Resume::Continue]);
+    assert_eq!(log, ["Break (break) [h]", "Step (+ n 1) [h]"]);
+    assert_eq!(value, "NIL");
+
+    let (log, _) = run("(h 1)", false, &[Resume::Abort]);
+    assert_eq!(log, ["Break (break) [h]"]);
+
+    // break is an ordinary function, so it can be passed around
+    let (log, _) = run("(defun call (g) (g)) (call break)", false, &[]);
+    assert_eq!(log, ["Break (break) [call]"]);
+}
+
+#[test]
+fn breakpoints_pause_where_they_match() {
+    let env = Rc::new(RefCell::new(Lenv::new()));
+    add_builtins(env.clone());
+    eval_source(&env, PROGRAM, |_| {}).unwrap();
+    let log = Rc::new(RefCell::new(Vec::new()));
+    debug::set_debugger(Some(Box::new(Script { answers: VecDeque::new(), log: log.clone() })));
+    // an installed debugger only hooks evaluation while it has something to stop at
+    assert!(!debug::is_hooked());
+    debug::set_breakpoints(vec![rustlisp::read_source("(* x x)").unwrap().remove(0)]);
+    assert!(debug::is_hooked());
+    eval_source(&env, "(f 2) (f 5)", |_| {}).unwrap();
+    assert_eq!(*log.borrow(), ["Breakpoint (* x x) [f sq]", "Breakpoint (* x x) [f sq]"]);
+    debug::set_breakpoints(Vec::new());
+    assert!(!debug::is_hooked());
+    debug::set_debugger(None);
+}
+
+#[test]
+fn break_without_a_debugger_does_nothing() {
+    let env = Rc::new(RefCell::new(Lenv::new()));
+    add_builtins(env.clone());
+    eval_source(&env, PROGRAM, |_| {}).unwrap();
+    let mut last = Lval::NIL;
+    eval_source(&env, "(h 1)", |v| last = v).unwrap();
+    assert_eq!(last.to_string(), "NIL");
+}
+
+#[test]
+fn compiled_backends_refuse_to_step() {
+    let env = Rc::new(RefCell::new(Lenv::new()));
+    add_builtins(env.clone());
+    let log = Rc::new(RefCell::new(Vec::new()));
+    debug::set_debugger(Some(Box::new(Script { answers: VecDeque::new(), log: log.clone() })));
+    let refused = Err("the debugger needs the tree backend".to_string());
+    debug::step_next();
+    assert_eq!(rustlisp::ast::Evaluator::new(env.clone()).eval_source("(+ 1 2)", |_| {}), refused);
+    debug::step_next();
+    assert_eq!(rustlisp::vm::Vm::new(env.clone()).eval_source("(+ 1 2)", |_| {}), refused);
+    // refusing stops the stepping, so the next evaluation runs
+    assert!(!debug::is_hooked());
+    debug::set_debugger(None);
+    assert!(log.borrow().is_empty());
+}
@@ -0,0 +1,74 @@
+// Tests for format_source, the layout behind `rustlisp fmt` and pprint.
+
+use rustlisp::format::{format_source, DEFAULT_WIDTH};
+
+fn format(src: &str, width: usize) -> String {
+    format_source(src, width).unwrap()
+}
+
+const SOURCES: &[&str] = &[
+    ";; header\n\n\n(defun f (x) ;; trailing\n  (+ x 1))\n\n\n\n(f 2)\n",
+    "(cond ((eq 1 1) 'yes) ;; why\n      (t 'no))\n",
+    "(f 1 ;; last\n)\n",
+    "(setq numbers '(1 2 3)) (print numbers)\n",
+    "(defun classify (n) (cond ((eq n 0) 'zero) ((eq n 1) 'one) ((eq n 2) 'two) (t 'many)))\n",
+    "(setq l ' ;; the names\n  (a b))\n(print '\n  x)\n",
+];
+
+#[test]
+fn comments_are_kept() {
+    assert_eq!(
+        format("(defun f (x) ;; trailing\n  (+ x 1))\n", DEFAULT_WIDTH),
+        "(defun f (x) ;; trailing\n  (+ x 1))\n",
+    );
+    // a comment before the closing paren must not swallow it
+    assert_eq!(format("(f 1 ;; last\n)\n", DEFAULT_WIDTH), "(f 1 ;; last\n   )\n");
+    assert_eq!(format("(list ;; open\n a b ;; after b\n)", DEFAULT_WIDTH), "(list ;; open\n      a\n      b ;; after b\n      )\n");
+    assert_eq!(format(";; only a comment", DEFAULT_WIDTH), ";; only a comment\n");
+}
+
+#[test]
+fn space_after_a_quote() {
+    // the reader skips blanks and comments between a quote and its datum, so the formatter must too
+    assert_eq!(format("(f ' a '\n(a b))", DEFAULT_WIDTH), "(f 'a '(a b))\n");
+    assert_eq!(format("(setq l ' ;; the names\n  (a b))", DEFAULT_WIDTH), "(setq l\n      ' ;; the names\n       (a b))\n");
+}
+
+#[test]
+fn blank_lines_collapse_to_one() {
+    assert_eq!(format("(a)\n\n\n\n(b)\n(c)\n", DEFAULT_WIDTH), "(a)\n\n(b)\n(c)\n");
+    assert_eq!(format("\n\n(a)\n\n\n", DEFAULT_WIDTH), "(a)\n");
+    assert_eq!(format("(a\n\n\n b)", 4), "(a\n\n  b)\n");
+}
+
+#[test]
+fn long_forms_wrap_at_the_width() {
+    let src = "(defun classify (n) (cond ((eq n 0) 'zero) ((eq n 1) 'one) (t 'many)))";
+    assert_eq!(format(src, DEFAULT_WIDTH), format!("{}\n", src));
+    assert_eq!(format(src, 40), "\
+(defun classify (n)
+  (cond ((eq n 0) 'zero)
+        ((eq n 1) 'one)
+        (t 'many)))
+");
+    assert_eq!(format("(+ first-argument second-argument)", 20), "(+ first-argument\n   second-argument)\n");
+    for line in format(src, 40).lines() {
+        assert!(line.len() <= 40, "{:?} is wider than 40", line);
+    }
+}
+
+#[test]
+fn formatting_is_idempotent() {
+    for src in SOURCES {
+        for width in [20, 40, DEFAULT_WIDTH] {
+            let once = format(src, width);
+            assert_eq!(format(&once, width), once, "formatting {:?} at width {} again changed it", src, width);
+        }
+    }
+}
+
+#[test]
+fn unbalanced_source_is_an_error() {
+    assert!(format_source("(defun f (x)", DEFAULT_WIDTH).is_err());
+    assert!(format_source("(a))", DEFAULT_WIDTH).is_err());
+}
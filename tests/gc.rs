@@ -0,0 +1,107 @@
+// Tests for the cycle collector in gc.rs. Each test runs on its own thread,
+// so each has its own heap.
+
+mod common;
+
+use common::eval_all;
+use rustlisp::{cons, gc};
+use rustlisp::symbol::Symbol;
+use rustlisp::types::{Lenv, Lval};
+use std::cell::RefCell;
+use std::rc::Rc;
+
+/// an environment holding a closure whose environment has it as parent
+fn closure_cycle() -> Rc<RefCell<Lenv>> {
+    let env = gc::env(Lenv::new());
+    let captured = Lenv { par: Some(env.clone()), data: Default::default() };
+    let body = Lval::Qexpr(vec![Lval::Sym(Symbol::intern("x"))]);
+    let closure = Lval::Lambda(captured, Rc::new(Lval::Qexpr(Vec::new())), Rc::new(body));
+    env.borrow_mut().put(Symbol::intern("f"), closure);
+    env
+}
+
+#[test]
+fn collects_a_closure_stored_in_the_environment_it_captured() {
+    let env = closure_cycle();
+    let weak = Rc::downgrade(&env);
+    drop(env);
+    assert!(weak.upgrade().is_some(), "reference counting alone frees the cycle");
+    assert_eq!(gc::collect(), 1);
+    assert!(weak.upgrade().is_none());
+}
+
+#[test]
+fn keeps_cycles_that_are_still_referenced() {
+    let env = closure_cycle();
+    let inner = gc::env(Lenv { par: Some(env.clone()), data: Default::default() });
+    assert_eq!(gc::collect(), 0);
+    assert!(matches!(inner.borrow().get(Symbol::intern("f")), Some(Lval::Lambda(..))));
+    drop(inner);
+    drop(env);
+    assert_eq!(gc::collect(), 1);
+}
+
+#[test]
+fn collects_a_list_whose_last_cdr_is_its_first_cell() {
+    let list = cons::list(vec![Lval::Num(1), Lval::Num(2), Lval::Num(3)]);
+    let first = cons::cell(&list).unwrap();
+    cons::nth_cell(&first, 2).unwrap().borrow_mut().cdr = list.clone();
+    let weak = Rc::downgrade(&first);
+    drop((list, first));
+    assert!(weak.upgrade().is_some(), "reference counting alone frees the cycle");
+    assert_eq!(gc::collect(), 3);
+    assert!(weak.upgrade().is_none());
+}
+
+#[test]
+fn room_reports_live_objects_and_collections() {
+    let _live = closure_cycle();
+    drop(closure_cycle());
+    gc::collect();
+    let stats = gc::stats();
+    assert_eq!(stats.live[0], ("environments", 1));
+    assert!(stats.live[1..].iter().all(|&(_, n)| n == 0), "{:?}", stats.live);
+    assert_eq!((stats.collections, stats.freed), (1, 1));
+}
+
+#[test]
+fn room_counts_heap_objects_with_shared_cells_once() {
+    let results = eval_all("(setq l '(1 2 3)) (setq m (cons 0 l)) (setq h (make-hash-table)) (setq v (vector l)) (room)");
+    assert_eq!(results[4], "((environments 0) (conses 4) (hash-tables 1) (arrays 1) (structures 0) (instances 0) (generic-functions 0) (collections 0) (freed 0))");
+}
+
+#[test]
+fn collects_a_circular_list() {
+    let results = eval_all("
+        (setq l '(1 2 3))
+        (rplacd (cdr (cdr l)) l)
+        (setq l nil)
+        (gc)
+        (room)
+    ");
+    assert_eq!(results[1], "(3 1 2 . #<circular cons>)");
+    assert_eq!(results[3], "3");
+    assert!(results[4].starts_with("((environments 0) (conses 0) "), "{}", results[4]);
+}
+
+#[test]
+fn gc_and_room_from_lisp() {
+    let results = eval_all("
+        (defun count (n) (cond ((eq n 0) 0) (t (count (- n 1)))))
+        (count 50)
+        (gc)
+        (room)
+        (gc 1)
+    ");
+    // call environments are freed by reference counting as each call returns
+    assert_eq!(results[2], "0");
+    assert_eq!(results[3], "((environments 0) (conses 0) (hash-tables 0) (arrays 0) (structures 0) (instances 0) (generic-functions 0) (collections 1) (freed 0))");
+    assert_eq!(results[4], "Error: Function 'gc' passed incorrect number of arguments.");
+}
+
+#[test]
+fn gc_and_room_are_ordinary_functions() {
+    let results = eval_all("(defun twice (f) (+ (f) (f))) (twice gc) (length (room)) (defun shadow (room) (+ room 1)) (shadow 3)");
+    assert_eq!(results[1..3], ["0", "9"]);
+    assert_eq!(results[4], "4");
+}
@@ -0,0 +1,97 @@
+// Tests for hash tables and keywords.
+
+mod common;
+
+use common::{eval_all, last};
+
+#[test]
+fn get_put_and_remove() {
+    let results = eval_all("
+        (setq h (make-hash-table))
+        (puthash 'a 1 h)
+        (gethash 'a h)
+        (gethash 'b h)
+        (gethash 'b h 0)
+        (remhash 'a h)
+        (remhash 'a h)
+        (hash-table-count h)
+    ");
+    assert_eq!(results[1..], ["1", "1", "NIL", "0", "T", "NIL", "0"]);
+}
+
+#[test]
+fn eq_and_equal_tests() {
+    assert_eq!(last("(setq h (make-hash-table)) (puthash \"k\" 1 h) (gethash \"k\" h)"), "NIL");
+    assert_eq!(last("(setq h (make-hash-table :test equal)) (puthash \"k\" 1 h) (gethash \"k\" h)"), "1");
+    assert_eq!(last("(setq h (make-hash-table :test 'equal)) (puthash '(1 2) 'l h) (gethash '(1 2) h)"), "l");
+    assert_eq!(last("(make-hash-table :test =)"), "Error: Hash table test must be eq or equal");
+    assert_eq!(last("(make-hash-table :size 10)"), "Error: Unknown keyword argument :size to make-hash-table");
+}
+
+#[test]
+fn make_hash_table_is_an_ordinary_function() {
+    assert_eq!(last("(defun fresh (make) (make)) (fresh make-hash-table)"), "#S(hash-table :test eq :data ())");
+    assert_eq!(last("(setq test equal) (make-hash-table :test test)"), "#S(hash-table :test equal :data ())");
+    assert_eq!(last("(defun f (make-hash-table) (+ make-hash-table 1)) (f 1)"), "2");
+}
+
+#[test]
+fn setf_gethash_and_other_places() {
+    let results = eval_all("
+        (setq h (make-hash-table))
+        (setf (gethash 'x h) 5)
+        (incf (gethash 'x h))
+        (incf (gethash 'n h 10) 5)
+        (push 'a (gethash 'l h))
+        (hash-table-keys h)
+        h
+    ");
+    assert_eq!(results[1..], ["5", "6", "15", "(a)", "(x n l)", "#S(hash-table :test eq :data (x 6 n 15 l (a)))"]);
+}
+
+#[test]
+fn tables_are_shared_between_variables() {
+    assert_eq!(last("(setq a (make-hash-table)) (setq b a) (puthash 'k 1 b) (gethash 'k a)"), "1");
+    assert_eq!(last("(setq a (make-hash-table)) (setq b a) (eq a b)"), "T");
+    assert_eq!(last("(eq (make-hash-table) (make-hash-table))"), "NIL");
+}
+
+#[test]
+fn maphash_visits_every_entry() {
+    let results = eval_all("
+        (setq h (make-hash-table))
+        (puthash 'a 1 h)
+        (puthash 'b 2 h)
+        (maphash (\\ '(k v) '(print k v)) h)
+        (maphash 1 h)
+    ");
+    assert_eq!(results[3], "NIL");
+    assert_eq!(results[4], "Error: S-expression starts with incorrect type");
+}
+
+#[test]
+fn printed_tables_read_back() {
+    let src = "#S(hash-table :test equal :data (\"a\" 1 (1 2) (3 4) k v))";
+    assert_eq!(last(src), src);
+    assert_eq!(last(&format!("(gethash '(1 2) {})", src)), "(3 4)");
+    assert_eq!(last("#S(hash-table :test fuzzy)"), "Error: Malformed hash table literal");
+    assert_eq!(last("(setq h (make-hash-table)) (puthash 'me h h)"), "#S(hash-table :test eq :data (me #<circular hash-table>))");
+}
+
+#[test]
+fn keywords_evaluate_to_themselves() {
+    assert_eq!(eval_all(":test (eq :a :a) (symbol-name :key)"), [":test", "T", "\":key\""]);
+}
+
+#[test]
+fn removing_keeps_the_other_entries_reachable() {
+    let results = eval_all("
+        (setq h (make-hash-table))
+        (puthash 1 'one h) (puthash 2 'two h) (puthash 3 'three h)
+        (remhash 1 h)
+        (gethash 3 h)
+        (gethash 2 h)
+        (hash-table-keys h)
+    ");
+    assert_eq!(results[5..], ["three", "two", "(3 2)"]);
+}
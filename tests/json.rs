@@ -0,0 +1,49 @@
+// Tests for json-parse and json-stringify.
+
+mod common;
+
+use common::{eval_all, last};
+
+/// parses json and writes it back out
+fn round_trip(json: &str) -> String {
+    last(&format!("(json-stringify (json-parse {:?}))", json))
+}
+
+#[test]
+fn parsing() {
+    let results = eval_all(r#"(json-parse "{\"name\": \"x\", \"tags\": [1, 2], \"inner\": {\"ok\": true}}")
+        (json-parse "[true, false, null, -3]") (json-parse "\"a\\\"b\\n\"") (json-parse "2.0")"#);
+    assert_eq!(results, [r#"((name "x") (tags (1 2)) (inner ((ok T))))"#, "(T NIL NIL -3)", r#""a\"b\n""#, "2"]);
+}
+
+#[test]
+fn round_trips() {
+    assert_eq!(round_trip(r#"{"name":"x","tags":[1,2],"inner":{"ok":true,"none":null}}"#), r#""{\"name\":\"x\",\"tags\":[1,2],\"inner\":{\"ok\":true,\"none\":null}}""#);
+    assert_eq!(round_trip("[1,[2,[3]],true,null]"), r#""[1,[2,[3]],true,null]""#);
+    assert_eq!(round_trip(r#""tab\tquote\"slash\\""#), r#""\"tab\\tquote\\\"slash\\\\\"""#);
+    // a list of string pairs stays an array
+    assert_eq!(round_trip(r#"[["a",1],["b",2]]"#), r#""[[\"a\",1],[\"b\",2]]""#);
+}
+
+#[test]
+fn keys_that_are_not_symbols() {
+    let results = eval_all(r#"(json-parse "{\"a b\": 1, \"12\": 2, \"t\": 3, \"ok\": 4}") (json-stringify (json-parse "{\"a b\": 1, \"ok\": 2}"))"#);
+    assert_eq!(results, [r#"(("a b" 1) ("12" 2) ("t" 3) (ok 4))"#, r#""{\"a b\":1,\"ok\":2}""#]);
+}
+
+#[test]
+fn lossy_cases() {
+    // the empty object is the empty list, false is NIL, and an object without
+    // any symbol key is indistinguishable from a list of pairs
+    assert_eq!(round_trip("{}"), r#""[]""#);
+    assert_eq!(round_trip("[false]"), r#""[null]""#);
+    assert_eq!(round_trip(r#"{"a b":1}"#), r#""[[\"a b\",1]]""#);
+}
+
+#[test]
+fn errors() {
+    let results = eval_all(r#"(json-parse "1.5") (json-parse "[1, 2") (json-parse 1) (json-stringify (\ '(x) 'x))"#);
+    assert_eq!(results[0], "Error: json-parse: floats are unsupported, got 1.5 at line 1 column 3");
+    assert!(results[1].starts_with("Error: json-parse: EOF while parsing a list"), "{}", results[1]);
+    assert_eq!(results[2..], ["Error: Argument to json-parse must be a string", "Error: json-stringify: cannot serialize a function"]);
+}
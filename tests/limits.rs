@@ -0,0 +1,113 @@
+// Tests for the step, depth and memory limits on each top level form.
+
+mod common;
+
+use common::{results, session, Backend, BACKENDS};
+use rustlisp::capability::Profile;
+use rustlisp::cons::Cons;
+use rustlisp::limits::{set_limits, Limits};
+use rustlisp::types::Lval;
+use std::mem::size_of;
+
+/// the printed result of each top level form under the given limits
+fn run(src: &str, limits: Limits, backend: Backend) -> Vec<String> {
+    set_limits(limits);
+    let results = results(&session(Profile::Full), src, backend);
+    set_limits(Limits::default());
+    results
+}
+
+const COUNT: &str = "(defun count (n) (cond ((eq n 0) 'done) (t (count (- n 1)))))";
+
+#[test]
+fn step_limit() {
+    let limits = Limits { max_steps: Some(200), ..Limits::default() };
+    for backend in BACKENDS {
+        let results = run(&format!("{} (count 5) (count 1000)", COUNT), limits, backend);
+        assert_eq!(results[1..], ["'done", "Error: Step limit exceeded: more than 200 evaluation steps"], "{:?}", backend);
+    }
+}
+
+#[test]
+fn budget_resets_for_each_top_level_form() {
+    // each (count 10) fits the budget on its own, though together they do not
+    let limits = Limits { max_steps: Some(200), ..Limits::default() };
+    let src = format!("{} {}", COUNT, "(count 10) ".repeat(20));
+    for backend in BACKENDS {
+        let results = run(&src, limits, backend);
+        assert!(results[1..].iter().all(|r| r == "'done"), "{:?}: {:?}", backend, results);
+    }
+}
+
+#[test]
+fn depth_limit() {
+    let limits = Limits { max_depth: Some(50), ..Limits::default() };
+    for backend in BACKENDS {
+        let results = run("(defun down (n) (+ 1 (down n))) (down 1) (defun ok (n) (cond ((eq n 0) 0) (t (+ 1 (ok (- n 1)))))) (ok 40)", limits, backend);
+        assert_eq!(results[1..], ["Error: Recursion depth limit exceeded: more than 50 nested calls", "()", "40"], "{:?}", backend);
+    }
+}
+
+#[test]
+fn memory_limit() {
+    let cells = 100;
+    let limits = Limits { max_memory: Some(cells * size_of::<Lval>()), ..Limits::default() };
+    let error = format!("Error: Memory limit exceeded: more than {} bytes allocated", cells * size_of::<Lval>());
+    for backend in BACKENDS {
+        let results = run("(length (make-array 50)) (make-array 200)
+            (defun build (n acc) (cond ((eq n 0) (length acc)) (t (build (- n 1) (cons n acc)))))
+            (build 50 nil) (build 200 nil)", limits, backend);
+        assert_eq!(results, ["50".to_string(), error.clone(), "()".to_string(), "50".to_string(), error.clone()], "{:?}", backend);
+    }
+}
+
+#[test]
+fn load_shares_the_callers_budget() {
+    // a loaded file runs inside the form that loads it, so it cannot escape its budget
+    let path = std::env::temp_dir().join(format!("rustlisp-limits-{}.lisp", std::process::id()));
+    std::fs::write(&path, format!("{} {}", COUNT, "(count 10) ".repeat(20))).unwrap();
+    let limits = Limits { max_steps: Some(200), ..Limits::default() };
+    let results = run(&format!("(load {:?})", path.to_str().unwrap()), limits, Backend::Tree);
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(results, ["Error: Step limit exceeded: more than 200 evaluation steps"]);
+}
+
+#[test]
+fn new_symbol_names_count_against_memory() {
+    // interned names are never freed, so a program making them pays for them
+    let limits = Limits { max_memory: Some(100), ..Limits::default() };
+    let long = "n".repeat(120);
+    // the text of the json fits, but not with its key's name as well
+    let src = format!("(intern \"short\") (intern {0:?}) (intern {0:?}) (json-parse \"{{\\\"{1}\\\": 1}}\")", long, "k".repeat(60));
+    let results = run(&src, limits, Backend::Tree);
+    assert_eq!(results[0], "short");
+    assert_eq!(results[1], "Error: Memory limit exceeded: more than 100 bytes allocated");
+    // the name is known now, so interning it again is free
+    assert_eq!(results[2], long);
+    assert!(results[3].starts_with("Error: json-parse: Memory limit exceeded"), "{}", results[3]);
+}
+
+#[test]
+fn data_built_anywhere_counts_against_memory() {
+    let limits = Limits { max_memory: Some(2_000), ..Limits::default() };
+    let error = "Error: Memory limit exceeded: more than 2000 bytes allocated";
+    // each program builds a little at every step of a loop of 150
+    let programs = [
+        ("puthash", "(setq h (make-hash-table)) (defun fill (n) (cond ((eq n 0) 'done) (t (fill (- (puthash n n h) 1))))) (fill 150)"),
+        ("make-instance", "(defclass point () ((x :initarg :x))) (defun fill (n) (cond ((eq n 0) 'done) (t (fill (- (slot-value (make-instance 'point :x n) 'x) 1))))) (fill 150)"),
+        ("defstruct constructor", "(defstruct point x) (defun fill (n) (cond ((eq n 0) 'done) (t (fill (- (point-x (make-point :x n)) 1))))) (fill 150)"),
+        ("strings", "(defun fill (n) (cond ((eq n 0) 'done) (t (fill (- n (length (symbol-name 'twenty-letter-symbol))))))) (fill 3000)"),
+    ];
+    for (name, src) in programs {
+        for backend in BACKENDS {
+            assert_eq!(run(src, limits, backend).last().unwrap(), error, "{} on {:?}", name, backend);
+        }
+    }
+    // the list mapcar returns costs as much as the list it maps
+    let list = format!("'({})", "1 ".repeat(40));
+    let limits = Limits { max_memory: Some(60 * size_of::<Cons>()), ..Limits::default() };
+    for backend in BACKENDS {
+        let results = run(&format!("(length {0}) (length (mapcar (\\ '(x) '(x)) {0}))", list), limits, backend);
+        assert_eq!(results, ["40", &format!("Error: Memory limit exceeded: more than {} bytes allocated", 60 * size_of::<Cons>())], "{:?}", backend);
+    }
+}
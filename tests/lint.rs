@@ -0,0 +1,103 @@
+// Tests for the linter: for every rule, a fixture it accepts and one it
+// reports, and how rule settings and globals change the findings.
+
+use rustlisp::linter::{lint, LintConfig, Severity, RULES};
+
+/// rule, a fixture it has nothing to say about, a fixture it reports, and the report
+const FIXTURES: &[(&str, &str, &str, &str)] = &[
+    (
+        "cond-clause",
+        "(cond ((eq 1 1) 'yes) (t 'no))",
+        "(cond ((eq 1 1) 'yes) (t))",
+        "1:23: warning: cond clause needs a test and a body",
+    ),
+    (
+        "undefined-function",
+        "(defun helper (n) (+ n 1)) (helper 1)",
+        "(helper 1)",
+        "1:2: warning: call to undefined function 'helper'",
+    ),
+    (
+        "arity",
+        "(defun two (a b) (+ a b)) (two 1 2)",
+        "(defun two (a b) (+ a b)) (two 1)",
+        "1:27: warning: 'two' takes 2 arguments but is called with 1, which returns a partial application",
+    ),
+    (
+        "shadow-builtin",
+        "(defun first-of (l) (car l))",
+        "(defun car (l) l)",
+        "1:8: warning: function 'car' shadows the builtin of the same name",
+    ),
+    (
+        "unused-parameter",
+        "(defun f (x _y) x)",
+        "(defun f (x y) x)",
+        "1:13: warning: parameter 'y' of f is never used",
+    ),
+];
+
+fn findings(src: &str, config: &LintConfig) -> Vec<String> {
+    lint(src, config).unwrap().iter().map(|l| l.to_string()).collect()
+}
+
+#[test]
+fn every_rule_has_fixtures() {
+    for (rule, _) in RULES {
+        assert!(FIXTURES.iter().any(|(r, ..)| r == rule), "no fixture for {}", rule);
+    }
+}
+
+#[test]
+fn each_rule_accepts_its_passing_fixture() {
+    for (rule, passing, _, _) in FIXTURES {
+        assert_eq!(findings(passing, &LintConfig::default()), Vec::<String>::new(), "{}", rule);
+    }
+}
+
+#[test]
+fn each_rule_reports_its_failing_fixture() {
+    for (rule, _, failing, report) in FIXTURES {
+        assert_eq!(findings(failing, &LintConfig::default()), [format!("{} [{}]", report, rule)]);
+    }
+}
+
+#[test]
+fn rules_can_be_turned_off_or_made_errors() {
+    for (rule, _, failing, _) in FIXTURES {
+        let off = LintConfig::parse(&format!("{} = off", rule)).unwrap();
+        assert_eq!(findings(failing, &off), Vec::<String>::new(), "{}", rule);
+
+        let error = LintConfig::parse(&format!("{} = error # strict", rule)).unwrap();
+        let lints = lint(failing, &error).unwrap();
+        assert_eq!(lints.len(), 1, "{}", rule);
+        assert_eq!(lints[0].severity, Severity::Error);
+    }
+}
+
+#[test]
+fn globals_are_defined_elsewhere() {
+    let config = LintConfig::parse("globals = helper log").unwrap();
+    assert_eq!(findings("(helper 1) (log 'x)", &config), Vec::<String>::new());
+}
+
+#[test]
+fn unreadable_literals_are_errors() {
+    let lints = lint("(defun f (x) (+ x 99999999999999999999))\n'(1 -99999999999999999999)", &LintConfig::default()).unwrap();
+    let reports: Vec<String> = lints.iter().map(|l| l.to_string()).collect();
+    assert_eq!(reports, [
+        "1:19: error: integer 99999999999999999999 is out of range [read]",
+        "2:5: error: integer -99999999999999999999 is out of range [read]",
+    ]);
+    // no rule setting turns them off
+    let off = LintConfig::parse(&RULES.iter().map(|(rule, _)| format!("{} = off\n", rule)).collect::<String>()).unwrap();
+    assert_eq!(lint("99999999999999999999", &off).unwrap()[0].severity, Severity::Error);
+}
+
+#[test]
+fn bad_configs_and_sources_are_errors() {
+    assert_eq!(LintConfig::parse("no-such-rule = off").err(), Some("line 1: unknown rule 'no-such-rule'".to_string()));
+    assert_eq!(LintConfig::parse("\narity = loud").err(), Some("line 2: 'loud' should be off, warning or error".to_string()));
+    assert_eq!(LintConfig::parse("arity").err(), Some("line 1: expected 'name = value'".to_string()));
+    assert!(lint("(defun f (x)", &LintConfig::default()).is_err());
+}
@@ -0,0 +1,130 @@
+// Drives the language server in-process: a scripted client's messages are
+// read from memory and the server's replies are collected and checked.
+
+use serde_json::{json, Value};
+use std::cell::RefCell;
+use std::io::{self, Cursor, Write};
+use std::rc::Rc;
+
+const URI: &str = "file:///tmp/example.l";
+
+const SOURCE: &str = "\
+(defun sq (x)
+  (* x x))
+(def 'total (sq 3))
+(setq count (+ total undefined))
+";
+
+#[derive(Clone, Default)]
+struct Shared(Rc<RefCell<Vec<u8>>>);
+
+impl Write for Shared {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+fn frame(message: Value) -> String {
+    let body = message.to_string();
+    format!("Content-Length: {}\r\n\r\n{}", body.len(), body)
+}
+
+fn request(id: i64, method: &str, params: Value) -> String {
+    frame(json!({ "jsonrpc": "2.0", "id": id, "method": method, "params": params }))
+}
+
+fn notification(method: &str, params: Value) -> String {
+    frame(json!({ "jsonrpc": "2.0", "method": method, "params": params }))
+}
+
+fn at(line: usize, character: usize) -> Value {
+    json!({ "textDocument": { "uri": URI }, "position": { "line": line, "character": character } })
+}
+
+fn run(script: &[String]) -> Vec<Value> {
+    let output = Shared::default();
+    rustlisp::lsp::serve(Box::new(Cursor::new(script.concat().into_bytes())), Box::new(output.clone())).unwrap();
+    let bytes = output.0.borrow();
+    let mut text = std::str::from_utf8(&bytes).unwrap();
+    let mut messages = Vec::new();
+    while let Some(rest) = text.strip_prefix("Content-Length: ") {
+        let (length, rest) = rest.split_once("\r\n\r\n").unwrap();
+        let length: usize = length.parse().unwrap();
+        messages.push(serde_json::from_str(&rest[..length]).unwrap());
+        text = &rest[length..];
+    }
+    messages
+}
+
+fn result(messages: &[Value], id: i64) -> &Value {
+    &messages.iter().find(|m| m["id"] == id).unwrap()["result"]
+}
+
+fn open(text: &str) -> String {
+    notification("textDocument/didOpen", json!({
+        "textDocument": { "uri": URI, "languageId": "lisp", "version": 1, "text": text },
+    }))
+}
+
+#[test]
+fn answers_document_requests() {
+    let messages = run(&[
+        request(1, "initialize", json!({ "capabilities": {} })),
+        notification("initialized", json!({})),
+        open(SOURCE),
+        request(2, "textDocument/definition", at(2, 13)),
+        request(3, "textDocument/definition", at(1, 6)),
+        request(4, "textDocument/hover", at(2, 14)),
+        request(5, "textDocument/completion", at(3, 0)),
+        request(6, "textDocument/documentSymbol", json!({ "textDocument": { "uri": URI } })),
+        request(7, "shutdown", Value::Null),
+        notification("exit", Value::Null),
+    ]);
+
+    assert_eq!(result(&messages, 1)["capabilities"]["hoverProvider"], true);
+
+    let diagnostics = &messages.iter().find(|m| m["method"] == "textDocument/publishDiagnostics").unwrap()["params"]["diagnostics"];
+    assert_eq!(diagnostics.as_array().unwrap().len(), 1);
+    assert_eq!(diagnostics[0]["message"], "Unbound symbol 'undefined'");
+    assert_eq!(diagnostics[0]["range"]["start"], json!({ "line": 3, "character": 21 }));
+
+    // sq in (def 'total (sq 3)) goes to the defun's name
+    assert_eq!(result(&messages, 2)["range"]["start"], json!({ "line": 0, "character": 7 }));
+    // x in the body goes to the formal
+    assert_eq!(result(&messages, 3)["range"]["start"], json!({ "line": 0, "character": 11 }));
+
+    assert_eq!(result(&messages, 4)["contents"]["value"], "```lisp\n(sq x)\n```");
+
+    let labels: Vec<&str> = result(&messages, 5).as_array().unwrap().iter().map(|i| i["label"].as_str().unwrap()).collect();
+    assert!(labels.contains(&"cons"));
+    assert!(labels.contains(&"defun"));
+    assert!(labels.contains(&"total"));
+
+    let symbols: Vec<&str> = result(&messages, 6).as_array().unwrap().iter().map(|s| s["name"].as_str().unwrap()).collect();
+    assert_eq!(symbols, ["sq", "total", "count"]);
+
+    assert_eq!(result(&messages, 7), &Value::Null);
+}
+
+#[test]
+fn reports_syntax_errors_and_clears_them_on_change() {
+    let messages = run(&[
+        open("(defun f (x)\n  (+ x 1)"),
+        notification("textDocument/didChange", json!({
+            "textDocument": { "uri": URI, "version": 2 },
+            "contentChanges": [{ "text": "(defun f (x)\n  (+ x 1))" }],
+        })),
+        notification("exit", Value::Null),
+    ]);
+
+    let published: Vec<&Value> = messages.iter().map(|m| &m["params"]["diagnostics"]).collect();
+    assert_eq!(published.len(), 2);
+    assert!(published[0][0]["message"].as_str().unwrap().starts_with("Syntax error"));
+    assert_eq!(published[0][0]["severity"], 1);
+    assert_eq!(published[1], &json!([]));
+}
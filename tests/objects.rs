@@ -0,0 +1,114 @@
+// Tests for defclass, generic functions and method combination.
+
+mod common;
+
+use common::{eval_all, last, results, session, BACKENDS};
+use rustlisp::capability::Profile;
+
+const SHAPES: &str = "
+    (defclass shape () ((name :initarg :name :initform 'shape :reader shape-name)))
+    (defclass circle (shape) ((r :initarg :r :initform 1 :accessor circle-r)))
+    (defclass square (shape) ((side :initarg :side :accessor side)))
+    (defgeneric area (s))
+    (defmethod area ((c circle)) (* 3 (* (circle-r c) (circle-r c))))
+    (defmethod area ((s square)) (* (side s) (side s)))";
+
+#[test]
+fn instances_and_slots() {
+    let results = eval_all(&format!("{} (setq c (make-instance 'circle :r 2 :name 'ring)) c (slot-value c 'r) (shape-name c) (circle-r (make-instance 'circle)) (type-of c)", SHAPES));
+    assert_eq!(results[..3], ["shape", "circle", "square"]);
+    assert_eq!(results[7..], ["#<circle :name ring :r 2>", "2", "ring", "1", "circle"]);
+    assert_eq!(last(&format!("{} (make-instance 'square)", SHAPES)), "#<square :name shape :side NIL>");
+    assert_eq!(last(&format!("{} (make-instance 'circle :side 1)", SHAPES)), "Error: Unknown initarg :side to make-instance of circle");
+    assert_eq!(last(&format!("{} (slot-value (make-instance 'circle) 'side)", SHAPES)), "Error: Instance of circle has no slot side");
+    assert_eq!(last("(make-instance 'nothing)"), "Error: Unknown class nothing");
+}
+
+#[test]
+fn slots_are_places() {
+    let results = eval_all(&format!("{} (setq c (make-instance 'circle)) (setq d c) (setf (circle-r c) 5) (incf (slot-value c 'r)) (circle-r d) (setf (shape-name c) 'x) (shape-name d)", SHAPES));
+    assert_eq!(results[8..], ["5", "6", "6", "'x", "x"]);
+    assert_eq!(last(&format!("{} (setf (area (make-instance 'circle)) 1)", SHAPES)), "Error: (area (make-instance 'circle)) is not a place");
+    // an accessor is a place only where its method reads a slot
+    assert_eq!(last(&format!("{} (defmethod side ((c circle)) 0) (setf (side (make-instance 'circle)) 1)", SHAPES)), "Error: (side #<circle :name shape :r 1>) is not a place");
+}
+
+#[test]
+fn dispatch_on_class() {
+    let results = eval_all(&format!("{} (area (make-instance 'circle :r 2)) (area (make-instance 'square :side 3)) (area 5)", SHAPES));
+    assert_eq!(results[6..], ["12", "9", "Error: No applicable method for area with arguments (5)"]);
+    // builtin values dispatch on type-of, lists also on list, anything on t
+    let results = eval_all("(defmethod describe ((x integer)) 'int) (defmethod describe ((x list)) 'list) (defmethod describe (x) 'other)
+        (describe 1) (describe '(1 2)) (describe nil) (describe \"s\")");
+    assert_eq!(results[3..], ["'int", "'list", "'list", "'other"]);
+}
+
+#[test]
+fn a_lone_atom_body_is_its_value() {
+    // a function passed through is returned, not called
+    for backend in BACKENDS {
+        let results = results(&session(Profile::Full), "(defmethod id (x) x) (id car) ((id car) '(1 2)) (id gensym) (id 5)", backend);
+        assert_eq!(results[1..], ["<function>", "1", "<function>", "5"], "{:?}", backend);
+    }
+}
+
+#[test]
+fn multiple_dispatch() {
+    let results = eval_all("(defclass a () ()) (defclass b (a) ())
+        (defmethod meet ((x a) (y a)) 'a-a) (defmethod meet ((x b) (y a)) 'b-a) (defmethod meet ((x a) (y b)) 'a-b)
+        (setq ia (make-instance 'a)) (setq ib (make-instance 'b))
+        (meet ia ia) (meet ib ia) (meet ia ib) (meet ib ib) (meet ia 1)");
+    // the leftmost argument decides between b-a and a-b
+    assert_eq!(results[7..], ["'a-a", "'b-a", "'a-b", "'b-a", "Error: No applicable method for meet with arguments (#<a> 1)"]);
+}
+
+#[test]
+fn next_methods_and_qualifiers() {
+    let src = format!("{}
+        (defclass wheel (circle) ())
+        (defmethod area ((w wheel)) (+ 100 (call-next-method)))
+        (setq log (make-array 0 :fill-pointer 0))
+        (defmethod area :before ((s shape)) (vector-push-extend 'before-shape log))
+        (defmethod area :before ((c circle)) (vector-push-extend 'before-circle log))
+        (defmethod area :after ((s shape)) (vector-push-extend 'after-shape log))
+        (defmethod area :after ((w wheel)) (vector-push-extend 'after-wheel log))
+        (area (make-instance 'wheel :r 1))
+        log", SHAPES);
+    let results = eval_all(&src);
+    assert_eq!(results[13..], ["103", "#(before-circle before-shape after-shape after-wheel)"]);
+
+    let results = eval_all(&format!("{} (defmethod area :after ((c circle)) (call-next-method)) (area (make-instance 'circle)) (call-next-method)", SHAPES));
+    assert_eq!(results[7..], ["Error: No next method for area", "Error: call-next-method used outside a method"]);
+    // new arguments replace the ones the method was given
+    assert_eq!(last("(defmethod twice (x) (* 2 x)) (defmethod twice ((x integer)) (call-next-method (+ x 1))) (twice 4)"), "10");
+    // it is an ordinary function: a method may hand it to another, and a parameter may take its name
+    assert_eq!(last("(defun next (f) (f)) (defmethod twice (x) (* 2 x)) (defmethod twice ((x integer)) (next call-next-method)) (twice 4)"), "8");
+    assert_eq!(last("(defun f (call-next-method) (+ call-next-method 1)) (f 1)"), "2");
+}
+
+#[test]
+fn redefining_methods_and_generics() {
+    let results = eval_all(&format!("{} (defmethod area ((s square)) 'replaced) (area (make-instance 'square :side 2)) (defgeneric area (s)) (area (make-instance 'square :side 2)) area (area 1 2)", SHAPES));
+    assert_eq!(results[7..], ["'replaced", "area", "'replaced", "<generic-function area>", "Error: Function 'area' passed incorrect number of arguments."]);
+    // a generic function of another arity replaces the old one and its methods
+    assert_eq!(last(&format!("{} (defgeneric area (s scale)) (area (make-instance 'square :side 2) 1)", SHAPES)), "Error: No applicable method for area with arguments (#<square :name shape :side 2> 1)");
+}
+
+#[test]
+fn defmethod_errors() {
+    let results = eval_all("(defgeneric area (s)) (defmethod area ((s t) scale) 0) (defmethod area () 0) (defmethod area ((s t)) 1) (area 5)");
+    assert_eq!(results[1..], ["Error: Method for area takes 2 arguments, but the generic function takes 1",
+        "Error: Method for area takes 0 arguments, but the generic function takes 1", "area", "1"]);
+    let results = eval_all("(defmethod show ((a t)) (print a) (print a)) (defmethod show :around ((a t)) a) (defmethod show :before ((a t)) 1 2) (defmethod show)");
+    assert_eq!(results, ["Error: defmethod takes one body form, got 2", "Error: Unknown method qualifier :around",
+        "Error: defmethod takes one body form, got 2", "Error: Function 'defmethod' passed incorrect number of arguments."]);
+}
+
+#[test]
+fn redefining_a_superclass() {
+    let results = eval_all("(defclass a () ((x :initarg :x))) (defclass b (a) ()) (setq old (make-instance 'b :x 1))
+        (defclass a () ((x :initarg :x) (z :initform 3))) (slot-value (make-instance 'b) 'z) old
+        (defclass c () ()) (defclass a (c) ()) (defmethod kind ((v c)) 'c) (kind (make-instance 'b))");
+    assert_eq!(results[3..], ["a", "3", "#<b :x 1>", "c", "a", "kind", "'c"]);
+    assert_eq!(last("(defclass a () ()) (defclass b (a) ()) (defclass a (b) ())"), "Error: Class a cannot inherit from itself through b");
+}
@@ -0,0 +1,85 @@
+// Tests for setf and the place forms built on it.
+
+mod common;
+
+use common::{eval_all, last, results, session, BACKENDS};
+use rustlisp::capability::Profile;
+
+#[test]
+fn setf_variables_and_list_elements() {
+    assert_eq!(eval_all("(setf x 1 y (+ x 1)) x y"), ["2", "1", "2"]);
+    assert_eq!(last("(setq l '(1 2 3)) (setf (car l) 'a) l"), "'(a 2 3)");
+    assert_eq!(last("(setq l '(1 2 3)) (setf (cdr l) '(b)) l"), "'(1 b)");
+    assert_eq!(last("(setq l '(1 2 3)) (setf (nth 2 l) 30) l"), "'(1 2 30)");
+    assert_eq!(last("(setq m '((1 2) (3 4))) (setf (car (nth 1 m)) 30) m"), "'((1 2) (30 4))");
+}
+
+#[test]
+fn push_pop_incf_decf() {
+    assert_eq!(eval_all("(setq s '()) (push 1 s) (push 'b s) (pop s) (pop s) (pop s)"), ["()", "'(1)", "'(b 1)", "b", "1", "NIL"]);
+    assert_eq!(eval_all("(setq n 5) (incf n) (decf n 10) (incf n (* 2 3))"), ["()", "6", "-4", "2"]);
+    assert_eq!(last("(setq l '(1 2)) (incf (nth 1 l) 40) l"), "'(1 42)");
+}
+
+#[test]
+fn rplaca_and_rplacd() {
+    assert_eq!(eval_all("(setq l '(1 2 3)) (rplaca l 'a) l"), ["()", "'(a 2 3)", "'(a 2 3)"]);
+    assert_eq!(eval_all("(setq l '(1 2 3)) (rplacd l '(z)) l"), ["()", "'(1 z)", "'(1 z)"]);
+    assert_eq!(last("(rplaca '(1 2) 3)"), "'(3 2)");
+}
+
+#[test]
+fn variables_are_set_where_they_are_bound() {
+    let results = eval_all("(setq counter 0) (defun bump (by) (incf counter by)) (bump 1) (bump 2) counter
+        (setq l '(1 2)) (defun grow (x) (push x l)) (grow 0) l (defun shadow (counter) (incf counter)) (shadow 10) counter");
+    assert_eq!(results[2..], ["1", "3", "3", "()", "()", "'(0 1 2)", "'(0 1 2)", "()", "11", "3"]);
+    // a variable bound nowhere is bound where it is set, as setq binds it
+    assert_eq!(eval_all("(defun make (v) (setf fresh v)) (make 1) fresh")[1..], ["1", "Error: Unbound symbol 'fresh'"]);
+}
+
+#[test]
+fn lists_are_shared() {
+    // a change to a cell is seen through every variable holding the list
+    for backend in BACKENDS {
+        let run = |src| results(&session(Profile::Full), src, backend);
+        assert_eq!(run("(setq a '(1 2 3)) (setq b a) (rplaca a 9) a b")[3..], ["'(9 2 3)", "'(9 2 3)"], "{:?}", backend);
+        assert_eq!(run("(setq a '(1 2 3)) (setq b a) (setf (nth 1 b) 0) a b")[3..], ["'(1 0 3)", "'(1 0 3)"], "{:?}", backend);
+        assert_eq!(run("(setq a '(1 2 3)) (setq b (cdr a)) (setf (car b) 'x) (rplacd b nil) a")[4], "'(1 x)", "{:?}", backend);
+        // push and pop change the place, not the cells
+        assert_eq!(run("(setq a '(1 2)) (setq b a) (push 0 a) (pop b) a b")[4..], ["'(0 1 2)", "'(2)"], "{:?}", backend);
+    }
+}
+
+#[test]
+fn the_list_under_an_accessor_can_be_any_form() {
+    // the cells a call returns are changed, and seen through the variable holding them
+    for backend in BACKENDS {
+        let run = |src: &str| results(&session(Profile::Full), src, backend);
+        let defs = "(setq l '(1 2 3)) (defun f () (l))";
+        assert_eq!(run(&format!("{} (setf (car (f)) 5) l", defs))[2..], ["5", "'(5 2 3)"], "{:?}", backend);
+        assert_eq!(run(&format!("{} (push 0 (cdr (f))) l", defs))[2..], ["(0 2 3)", "'(1 0 2 3)"], "{:?}", backend);
+        assert_eq!(run(&format!("{} (incf (nth 2 (f)) 10) l", defs))[2..], ["13", "'(1 2 13)"], "{:?}", backend);
+        assert_eq!(run(&format!("{} (pop (cdr (f))) l", defs))[2..], ["2", "'(1 3)"], "{:?}", backend);
+    }
+    assert_eq!(last("(setf (car (+ 1 2)) 3)"), "Error: 3 is not a list");
+}
+
+#[test]
+fn each_evaluation_of_a_quoted_list_is_a_new_list() {
+    let results = eval_all("(defun fresh () ('(1 2))) (setq a (fresh)) (rplaca a 0) (fresh) (eq a (fresh)) a (setq b a) (eq a b)");
+    assert_eq!(results[3..], ["'(1 2)", "NIL", "'(0 2)", "()", "T"]);
+}
+
+#[test]
+fn place_errors() {
+    assert_eq!(last("(incf missing)"), "Error: Unbound symbol 'missing'");
+    assert_eq!(last("(setq s \"str\") (incf s)"), "Error: \"str\" is not a number");
+    assert_eq!(last("(setq l '(1)) (setf (nth 3 l) 0)"), "Error: Index 3 is out of range");
+    assert_eq!(last("(setf (+ 1 2) 3)"), "Error: (+ 1 2) is not a place");
+    assert_eq!(last("(setq e '()) (rplaca e 1)"), "Error: Cannot rplaca an empty list");
+    assert_eq!(last("(setf x)"), "Error: Function 'setf' passed incorrect number of arguments.");
+    // the place keeps its number when the step would overflow
+    assert_eq!(eval_all("(setq x 9223372036854775807) (incf x) x")[1..], ["Error: Integer overflow in incf", "9223372036854775807"]);
+    assert_eq!(last("(setq x -9223372036854775807) (decf x 2)"), "Error: Integer overflow in decf");
+    assert_eq!(last("(setq x 0) (decf x -9223372036854775808)"), "Error: Integer overflow in decf");
+}
@@ -0,0 +1,88 @@
+// Property tests for the printer: every value the reader can produce prints
+// as text that reads back to an equal value.
+
+use proptest::prelude::*;
+use rustlisp::symbol::Symbol;
+use rustlisp::types::{Lenv, Lval, Princ};
+use rustlisp::{add_builtins, eval_source, read_source, read_spans};
+use std::cell::RefCell;
+use std::rc::Rc;
+
+fn read_one(src: &str) -> Lval {
+    let mut forms = read_source(src).unwrap_or_else(|e| panic!("cannot read {:?}: {}", src, e));
+    assert_eq!(forms.len(), 1, "{:?} read as {:?}", src, forms);
+    forms.remove(0)
+}
+
+fn symbol() -> impl Strategy<Value = Lval> {
+    // a leading digit or minus sign would read as a number
+    "[A-Za-z_+*/\\\\=<>!&:][A-Za-z0-9_+*/\\\\=<>!&:-]{0,8}"
+        .prop_filter("constants read as T and NIL", |s| !matches!(s.as_str(), "t" | "T" | "nil" | "NIL"))
+        .prop_map(|s| Lval::Sym(Symbol::intern(&s)))
+}
+
+fn atom() -> impl Strategy<Value = Lval> {
+    prop_oneof![
+        any::<i64>().prop_map(Lval::Num),
+        symbol(),
+        // quotes, backslashes, line breaks and any other characters
+        "(\"|\\\\|\n|\t|;;|\\PC){0,12}".prop_map(Lval::Str),
+        any::<char>().prop_map(Lval::Char),
+        Just(Lval::T),
+        Just(Lval::NIL),
+    ]
+}
+
+fn value() -> impl Strategy<Value = Lval> {
+    atom().prop_recursive(4, 32, 6, |inner| prop_oneof![
+        prop::collection::vec(inner.clone(), 0..6).prop_map(Lval::Sexpr),
+        // the reader only builds one element Qexprs
+        inner.prop_map(|v| Lval::Qexpr(vec![v])),
+    ])
+}
+
+proptest! {
+    #[test]
+    fn read_print_round_trip(v in value()) {
+        let printed = v.to_string();
+        prop_assert_eq!(read_one(&printed), v, "printed as {}", printed);
+    }
+
+    #[test]
+    fn printed_text_is_stable(v in value()) {
+        let printed = v.to_string();
+        prop_assert_eq!(read_one(&printed).to_string(), printed);
+    }
+
+    #[test]
+    fn princ_shows_strings_verbatim(s in "\\PC{0,12}") {
+        prop_assert_eq!(Princ(&Lval::Str(s.clone())).to_string(), s);
+    }
+}
+
+#[test]
+fn printed_lambda_reads_back_as_an_equivalent_function() {
+    let env = Rc::new(RefCell::new(Lenv::new()));
+    add_builtins(env.clone());
+    let mut results = Vec::new();
+    eval_source(&env, "(defun add (x y) (+ x y)) add", |r| results.push(r)).unwrap();
+    let printed = results.pop().unwrap().to_string();
+    assert_eq!(printed, "(\\ '(x y) '(+ x y))");
+
+    results.clear();
+    eval_source(&env, &format!("(def 'add2 {}) (add2 2 3)", printed), |r| results.push(r)).unwrap();
+    assert_eq!(results.pop().unwrap(), Lval::Num(5));
+}
+
+#[test]
+fn integers_out_of_range_read_as_errors() {
+    let error = Lval::Err("Integer 99999999999999999999 is out of range".to_string());
+    assert_eq!(read_one("99999999999999999999"), error);
+    assert_eq!(read_spans("(nth 99999999999999999999 '(1))").unwrap()[0].form.to_string(), format!("(nth {} '(1))", error));
+
+    let env = Rc::new(RefCell::new(Lenv::new()));
+    add_builtins(env.clone());
+    let mut results = Vec::new();
+    eval_source(&env, "(nth 99999999999999999999 '(1)) (- 9223372036854775807)", |r| results.push(r)).unwrap();
+    assert_eq!(results, [error, Lval::Num(-9223372036854775807)]);
+}
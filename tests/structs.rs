@@ -0,0 +1,53 @@
+// Tests for defstruct and type-of.
+
+mod common;
+
+use common::{eval_all, last};
+
+const POINT: &str = "(defstruct point x (y (+ 1 1)))";
+
+#[test]
+fn constructor_accessors_and_predicate() {
+    let results = eval_all(&format!("{} (setq p (make-point :x 1)) p (point-x p) (point-y p) (point-p p) (point-p '(1 2))", POINT));
+    assert_eq!(results[0], "point");
+    assert_eq!(results[2..], ["#S(point :x 1 :y 2)", "1", "2", "T", "NIL"]);
+    assert_eq!(last(&format!("{} (make-point :y 'a :x '(1))", POINT)), "#S(point :x (1) :y a)");
+    // with no arguments every slot takes its default
+    assert_eq!(last(&format!("{} (make-point)", POINT)), "#S(point :x NIL :y 2)");
+}
+
+#[test]
+fn slots_are_places() {
+    let results = eval_all(&format!("{} (setq p (make-point :x 1)) (setf (point-x p) 5) (incf (point-y p)) (push 'a (point-x (copy-point p))) p", POINT));
+    assert_eq!(results[2..], ["5", "3", "Error: 5 is not a list", "#S(point :x 5 :y 3)"]);
+}
+
+#[test]
+fn copies_and_sharing() {
+    let results = eval_all(&format!("{} (setq p (make-point :x 1)) (setq q (copy-point p)) (setq r p) (setf (point-x q) 9) (setf (point-x r) 7) p q (eq p r) (eq p q)", POINT));
+    assert_eq!(results[6..], ["#S(point :x 7 :y 2)", "#S(point :x 9 :y 2)", "T", "NIL"]);
+}
+
+#[test]
+fn printed_instances_read_back() {
+    assert_eq!(last(&format!("{} (point-y #S(point :x 1 :y 4))", POINT)), "4");
+    assert_eq!(last(&format!("{} #S(point :y 4)", POINT)), "#S(point :x NIL :y 4)");
+    // a slot left out takes its default, as with the constructor
+    assert_eq!(eval_all(&format!("{} #S(point :x 3) (make-point :x 3)", POINT))[1..], ["#S(point :x 3 :y 2)", "#S(point :x 3 :y 2)"]);
+    assert_eq!(last("#S(nothing :a 1)"), "Error: Unknown structure type nothing");
+}
+
+#[test]
+fn type_of_names_types() {
+    let results = eval_all(&format!("{} (type-of (make-point :x 0)) (type-of 1) (type-of 'a) (type-of :k) (type-of \"s\") (type-of '(1)) (type-of nil) (type-of #(1)) (type-of point-x)", POINT));
+    assert_eq!(results[1..], ["point", "integer", "symbol", "keyword", "string", "cons", "null", "vector", "function"]);
+}
+
+#[test]
+fn defstruct_errors() {
+    assert_eq!(last(&format!("{} (make-point :z 1)", POINT)), "Error: Unknown keyword argument :z to make-point");
+    assert_eq!(last(&format!("{} (make-point :x)", POINT)), "Error: Function 'make-point' passed incorrect arguments.");
+    assert_eq!(last(&format!("{} (point-x 3)", POINT)), "Error: Argument to point-x must be a point");
+    assert_eq!(last("(defstruct pair a a)"), "Error: Slot a is given twice in defstruct");
+    assert_eq!(last("(defstruct pair (a))"), "Error: Malformed slot (a) in defstruct");
+}
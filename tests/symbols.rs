@@ -0,0 +1,45 @@
+// Tests for interned symbols shared between threads, and the special forms
+// the table interns first.
+
+mod common;
+
+use common::eval_all;
+use rustlisp::eval::SPECIAL_FORMS;
+use rustlisp::symbol::{self, gensym, Symbol};
+
+#[test]
+fn names_are_shared_between_threads() {
+    let here = Symbol::intern("made-on-the-test-thread");
+    let (there, name) = std::thread::spawn(move || (Symbol::intern("made-on-another-thread"), here.name())).join().unwrap();
+    assert_eq!(name, "made-on-the-test-thread");
+    assert_eq!(there.name(), "made-on-another-thread");
+    assert_eq!(Symbol::intern("made-on-another-thread"), there);
+    // a gensym made after this thread last looked names up is still found
+    let fresh = std::thread::spawn(|| gensym("T")).join().unwrap();
+    assert!(fresh.name().starts_with('T'));
+    assert_ne!(Symbol::intern(fresh.name()), fresh);
+}
+
+#[test]
+fn special_forms_are_known_by_their_symbols() {
+    for (name, _, _) in SPECIAL_FORMS {
+        assert!(Symbol::intern(name).special_form().is_some(), "{} is not a special form", name);
+        assert!(gensym(name).special_form().is_none());
+    }
+    for name in ["car", "T", "nil", ":quote", "quoted"] {
+        assert!(Symbol::intern(name).special_form().is_none(), "{} is a special form", name);
+    }
+    assert_eq!(symbol::QUOTE.name(), "quote");
+    assert_eq!(symbol::SETQ.name(), "setq");
+    assert_eq!(symbol::DEFUN.name(), "defun");
+    assert_eq!(symbol::COND.name(), "cond");
+}
+
+#[test]
+fn gensym_is_an_ordinary_builtin() {
+    let results = eval_all("(symbol-name (gensym \"tmp\")) (eq (gensym) (gensym)) (length (mapcar gensym '(\"a\" \"b\"))) (gensym 1)");
+    assert!(results[0].starts_with("\"tmp"), "{}", results[0]);
+    assert_eq!(results[1..], ["NIL", "2", "Error: Prefix given to gensym must be a string"]);
+    // a parameter may take its name
+    assert_eq!(eval_all("(defun f (gensym) (+ gensym 1)) (f 1)")[1], "2");
+}
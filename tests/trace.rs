@@ -0,0 +1,63 @@
+// Tests for trace and untrace: entry and exit lines, indented by how many
+// traced calls are in progress, on every backend.
+
+mod common;
+
+use common::{results, session, Backend, BACKENDS};
+use rustlisp::capability::Profile;
+use rustlisp::eval_source;
+use rustlisp::io::set_output;
+use std::cell::RefCell;
+use std::rc::Rc;
+
+const PROGRAM: &str = "
+(defun rev (l acc) (cond ((null l) acc) (t (rev (cdr l) (cons (car l) acc)))))
+(defun sq (x) (* x x))
+(defun sum-sq (a b) (+ (sq a) (sq b)))
+";
+
+/// the output printed while evaluating `src` after PROGRAM, and the printed
+/// result of each of its forms
+fn run(src: &str, backend: Backend) -> (String, Vec<String>) {
+    let env = session(Profile::Full);
+    eval_source(&env, PROGRAM, |_| {}).unwrap();
+    let output = Rc::new(RefCell::new(String::new()));
+    let sink = output.clone();
+    set_output(Some(Box::new(move |text| sink.borrow_mut().push_str(text))));
+    let results = results(&env, src, backend);
+    set_output(None);
+    let output = output.borrow().clone();
+    (output, results)
+}
+
+#[test]
+fn nested_calls_are_indented() {
+    for backend in BACKENDS {
+        let (output, results) = run("(trace rev) (rev '(a b) nil) (untrace)", backend);
+        assert_eq!(output, "  0: (rev '(a b) NIL)\n    1: (rev (b) (a))\n      2: (rev () (b a))\n      2: rev returned (b a)\n    1: rev returned (b a)\n  0: rev returned (b a)\n", "{:?}", backend);
+        assert_eq!(results, ["(rev)", "(b a)", "()"], "{:?}", backend);
+    }
+}
+
+#[test]
+fn only_traced_functions_print() {
+    for backend in BACKENDS {
+        let (output, _) = run("(trace sq) (sum-sq 2 3)", backend);
+        assert_eq!(output, "  0: (sq 2)\n  0: sq returned 4\n  0: (sq 3)\n  0: sq returned 9\n", "{:?}", backend);
+
+        let (output, results) = run("(trace 'sq sum-sq) (untrace sum-sq) (sum-sq 1 2) (untrace) (sq 5)", backend);
+        assert_eq!(output, "  0: (sq 1)\n  0: sq returned 1\n  0: (sq 2)\n  0: sq returned 4\n", "{:?}", backend);
+        assert_eq!(results, ["(sq sum-sq)", "(sq)", "5", "()", "25"], "{:?}", backend);
+        run("(untrace)", backend);
+    }
+}
+
+#[test]
+fn only_user_functions_can_be_traced() {
+    let (_, results) = run("(trace car) (trace missing) (trace 1)", Backend::Tree);
+    assert_eq!(results, [
+        "Error: trace: 'car' is not a user function",
+        "Error: trace: 'missing' is not defined",
+        "Error: Arguments to trace must be function names",
+    ]);
+}